@@ -0,0 +1,95 @@
+#[macro_use]
+extern crate bencher;
+
+use bencher::Bencher;
+use ruffd_core::frames::{write_frame, FrameWriter};
+use ruffd_types::tokio::io::{self, AsyncWriteExt};
+use ruffd_types::tokio::runtime::{Builder, Runtime};
+use ruffd_types::{lsp_types, serde_json, RpcMessage, RpcNotification};
+
+const FLOOD_SIZE: usize = 100;
+const DIAGNOSTICS_PER_MESSAGE: u32 = 200;
+
+fn runtime() -> Runtime {
+    Builder::new_current_thread().build().unwrap()
+}
+
+/// Diagnostics published for each of a hundred documents, as once a
+/// workspace is linted
+fn diagnostic_flood() -> Vec<RpcMessage> {
+    (0..FLOOD_SIZE)
+        .map(|idx| {
+            let uri = lsp_types::Url::parse(&format!("file:///tmp/module_{}.py", idx)).unwrap();
+            let diagnostics = (0..DIAGNOSTICS_PER_MESSAGE)
+                .map(|line| lsp_types::Diagnostic {
+                    range: lsp_types::Range::new(
+                        lsp_types::Position::new(line, 0),
+                        lsp_types::Position::new(line, 80),
+                    ),
+                    severity: Some(lsp_types::DiagnosticSeverity::WARNING),
+                    code: Some(lsp_types::NumberOrString::String("E501".to_string())),
+                    source: Some("ruff".to_string()),
+                    message: "Line too long (96 > 88 characters)".to_string(),
+                    ..Default::default()
+                })
+                .collect();
+            let params = lsp_types::PublishDiagnosticsParams::new(uri, diagnostics, Some(1));
+            RpcNotification::new(
+                "textDocument/publishDiagnostics".to_string(),
+                Some(serde_json::to_value(params).unwrap()),
+            )
+            .into()
+        })
+        .collect()
+}
+
+/// Frames written as before pooling, serializing each message to a fresh
+/// string and copying the header ahead of it
+fn flood_concat(bench: &mut Bencher) {
+    let runtime = runtime();
+    let messages = diagnostic_flood();
+    bench.iter(|| {
+        runtime.block_on(async {
+            let mut writer = io::sink();
+            for message in messages.iter() {
+                let body = serde_json::to_string(message).unwrap();
+                let header = format!("Content-Length: {}\r\n\r\n", body.len());
+                let bytes = [header.as_bytes(), body.as_bytes()].concat();
+                writer.write_all(&bytes).await.unwrap();
+                writer.flush().await.unwrap();
+            }
+        })
+    });
+}
+
+/// Frames of fresh strings written without copying the header
+fn flood_vectored(bench: &mut Bencher) {
+    let runtime = runtime();
+    let messages = diagnostic_flood();
+    bench.iter(|| {
+        runtime.block_on(async {
+            let mut writer = io::sink();
+            for message in messages.iter() {
+                let body = serde_json::to_vec(message).unwrap();
+                write_frame(&mut writer, &body).await.unwrap();
+            }
+        })
+    });
+}
+
+fn flood_pooled(bench: &mut Bencher) {
+    let runtime = runtime();
+    let messages = diagnostic_flood();
+    let mut frames = FrameWriter::new();
+    bench.iter(|| {
+        runtime.block_on(async {
+            let mut writer = io::sink();
+            for message in messages.iter() {
+                frames.write(&mut writer, message).await.unwrap();
+            }
+        })
+    });
+}
+
+benchmark_group!(benches, flood_concat, flood_vectored, flood_pooled);
+benchmark_main!(benches);
@@ -0,0 +1,153 @@
+//! Calls between the functions of a source, served by the call hierarchy
+//!
+//! Calls are only followed within the source: a name called is taken as
+//! calling the function its variable is defined as, such that calls to
+//! methods through attributes and calls from other files aren't found
+use crate::names::{NameKind, ResolvedNames};
+use ruffd_types::rustpython_ast::Location;
+
+/// Calls made from a function, or from the module outside of any function
+/// if `None`, to another function, by the indices of the names called
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Calls {
+    pub caller: Option<usize>,
+    pub callee: usize,
+    pub tokens: Vec<usize>,
+}
+
+/// Function called by the token at `idx`, being the latest definition of
+/// the variable called
+fn callee(names: &ResolvedNames, idx: usize) -> Option<usize> {
+    let token = names.token(idx);
+    if !token.called || token.kind != NameKind::Read {
+        return None;
+    }
+    names
+        .definitions(idx)
+        .into_iter()
+        .rev()
+        .find(|x| !names.is_class(*x))
+}
+
+/// Function defined or called at `location`
+pub fn function_at(names: &ResolvedNames, location: Location) -> Option<usize> {
+    let idx = names.token_at(location)?;
+    match names.defined_scope(idx) {
+        Some(x) if !names.is_class(x) => Some(x),
+        Some(_) => None,
+        None => callee(names, idx),
+    }
+}
+
+/// Calls of the source grouped by caller and callee, in the order each pair
+/// is first called
+fn calls(names: &ResolvedNames) -> Vec<Calls> {
+    let mut rv: Vec<Calls> = vec![];
+    for idx in 0..names.len() {
+        let callee = match callee(names, idx) {
+            Some(x) => x,
+            None => continue,
+        };
+        let caller = names.enclosing_function(idx);
+        match rv
+            .iter_mut()
+            .find(|x| x.caller == caller && x.callee == callee)
+        {
+            Some(calls) => calls.tokens.push(idx),
+            None => rv.push(Calls {
+                caller,
+                callee,
+                tokens: vec![idx],
+            }),
+        }
+    }
+    rv
+}
+
+/// Calls of `function`, by each of its callers
+pub fn incoming_calls(names: &ResolvedNames, function: usize) -> Vec<Calls> {
+    calls(names)
+        .into_iter()
+        .filter(|x| x.callee == function)
+        .collect()
+}
+
+/// Calls made by `function`, to each of its callees
+pub fn outgoing_calls(names: &ResolvedNames, function: usize) -> Vec<Calls> {
+    calls(names)
+        .into_iter()
+        .filter(|x| x.caller == Some(function))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::rustpython_parser::parser;
+
+    const SOURCE: &str = "\
+def helper():
+    pass
+
+def caller():
+    helper()
+    helper()
+    x.helper()
+
+class Widget:
+    def method(self):
+        return caller()
+
+caller()
+";
+
+    fn resolved(source: &str) -> ResolvedNames {
+        let suite = parser::parse_program(source, "<filename>").unwrap();
+        ResolvedNames::new(source, &suite)
+    }
+
+    fn name_of(names: &ResolvedNames, scope: Option<usize>) -> String {
+        match scope.and_then(|x| names.scope_name(x)) {
+            Some(x) => names.token(x).name.clone(),
+            None => "<module>".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_function_at() {
+        let names = resolved(SOURCE);
+        // at the definition and at a call
+        let helper = function_at(&names, Location::new(1, 5)).unwrap();
+        assert_eq!(function_at(&names, Location::new(5, 5)), Some(helper));
+        assert_eq!(name_of(&names, Some(helper)), "helper");
+        // attributes aren't resolved, nor are classes functions
+        assert_eq!(function_at(&names, Location::new(7, 7)), None);
+        assert_eq!(function_at(&names, Location::new(9, 7)), None);
+    }
+
+    #[test]
+    fn test_calls() {
+        let names = resolved(SOURCE);
+        let helper = function_at(&names, Location::new(1, 5)).unwrap();
+        let caller = function_at(&names, Location::new(4, 5)).unwrap();
+        let outgoing = outgoing_calls(&names, caller);
+        assert_eq!(outgoing.len(), 1);
+        assert_eq!(outgoing[0].callee, helper);
+        assert_eq!(outgoing[0].tokens.len(), 2);
+        let incoming = incoming_calls(&names, caller)
+            .into_iter()
+            .map(|x| name_of(&names, x.caller))
+            .collect::<Vec<_>>();
+        assert_eq!(incoming, vec!["method", "<module>"]);
+        assert!(outgoing_calls(&names, helper).is_empty());
+    }
+
+    #[test]
+    fn test_shadowed_calls() {
+        let source = "def f():\n    pass\n\ndef g(f):\n    f()\n";
+        let names = resolved(source);
+        let g = function_at(&names, Location::new(4, 5)).unwrap();
+        // the parameter shadows the function
+        assert!(outgoing_calls(&names, g).is_empty());
+    }
+}
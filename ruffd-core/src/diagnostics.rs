@@ -0,0 +1,46 @@
+//! Diagnostics of python sources for use outside of the server
+//!
+//! Checks are mapped to diagnostics exactly as the server publishes them,
+//! such that tools embedding ruffd report the same diagnostics without
+//! running a server
+use crate::ruff_utils::{check_with_settings, diagnostic_from_check};
+use ruffd_types::{lsp_types, sort_checks, RuntimeError};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub use crate::ruff_utils::{resolve_settings, SettingsScope, DEFAULT_SEVERITY};
+pub use ruffd_types::ruff::settings::Settings;
+
+/// Lints `text` as the contents of the file at `path` under `settings`,
+/// which [`resolve_settings`] resolves as the server does
+///
+/// Severities are those of ruff, as overrides of a project's
+/// `[tool.ruffd]` table are only applied by the server
+pub fn lint_source(
+    path: &Path,
+    text: &str,
+    settings: &Settings,
+) -> Result<Vec<lsp_types::Diagnostic>, RuntimeError> {
+    let mut checks = check_with_settings(path, text, settings, false)?;
+    sort_checks(&mut checks);
+    Ok(checks
+        .iter()
+        .map(|x| diagnostic_from_check(x, &BTreeMap::new()))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lint_source() {
+        let path = Path::new("/tmp/dummy.py");
+        let settings = resolve_settings(path, &SettingsScope::default()).unwrap();
+        let diagnostics = lint_source(path, "x = 1\nimport os\n", &settings).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, lsp_types::Position::new(1, 0));
+        assert_eq!(diagnostics[0].source.as_deref(), Some("ruff"));
+        assert_eq!(diagnostics[0].severity, Some(DEFAULT_SEVERITY));
+    }
+}
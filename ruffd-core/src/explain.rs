@@ -0,0 +1,237 @@
+//! Markdown explanations of rules, served by `ruffd/executeRuleExplain`
+//!
+//! ruff only describes a rule by its message, so examples of code flagged
+//! by a rule and the code it should be written as are kept here for the
+//! rules most commonly reported. Rules without an example are explained by
+//! their metadata alone
+use crate::ruff_utils::rule_info_from_code;
+use ruffd_types::extensions::RuleInfo;
+use ruffd_types::lsp_types;
+use std::collections::HashMap;
+
+/// Code flagged by a rule, and the same code once corrected
+struct Example {
+    before: &'static str,
+    after: &'static str,
+}
+
+lazy_static! {
+    static ref EXAMPLES: HashMap<&'static str, Example> = {
+        let examples = vec![
+            (
+                "E711",
+                Example {
+                    before: "if value == None:\n    pass\n",
+                    after: "if value is None:\n    pass\n",
+                },
+            ),
+            (
+                "E712",
+                Example {
+                    before: "if enabled == True:\n    pass\n",
+                    after: "if enabled:\n    pass\n",
+                },
+            ),
+            (
+                "E713",
+                Example {
+                    before: "if not key in mapping:\n    pass\n",
+                    after: "if key not in mapping:\n    pass\n",
+                },
+            ),
+            (
+                "E714",
+                Example {
+                    before: "if not value is None:\n    pass\n",
+                    after: "if value is not None:\n    pass\n",
+                },
+            ),
+            (
+                "E721",
+                Example {
+                    before: "if type(value) == type(1):\n    pass\n",
+                    after: "if isinstance(value, int):\n    pass\n",
+                },
+            ),
+            (
+                "E722",
+                Example {
+                    before: "try:\n    run()\nexcept:\n    pass\n",
+                    after: "try:\n    run()\nexcept Exception:\n    pass\n",
+                },
+            ),
+            (
+                "E731",
+                Example {
+                    before: "square = lambda x: x * x\n",
+                    after: "def square(x):\n    return x * x\n",
+                },
+            ),
+            (
+                "E741",
+                Example {
+                    before: "l = [1, 2, 3]\n",
+                    after: "lengths = [1, 2, 3]\n",
+                },
+            ),
+            (
+                "E999",
+                Example {
+                    before: "print(\"unclosed\"\n",
+                    after: "print(\"unclosed\")\n",
+                },
+            ),
+            (
+                "F401",
+                Example {
+                    before: "import os\nimport sys\n\nprint(sys.argv)\n",
+                    after: "import sys\n\nprint(sys.argv)\n",
+                },
+            ),
+            (
+                "F403",
+                Example {
+                    before: "from os.path import *\n\nprint(join(\"a\", \"b\"))\n",
+                    after: "from os.path import join\n\nprint(join(\"a\", \"b\"))\n",
+                },
+            ),
+            (
+                "F541",
+                Example {
+                    before: "print(f\"done\")\n",
+                    after: "print(\"done\")\n",
+                },
+            ),
+            (
+                "F632",
+                Example {
+                    before: "if name is \"main\":\n    pass\n",
+                    after: "if name == \"main\":\n    pass\n",
+                },
+            ),
+            (
+                "F811",
+                Example {
+                    before: "import json\nfrom simplejson import json\n",
+                    after: "from simplejson import json\n",
+                },
+            ),
+            (
+                "F821",
+                Example {
+                    before: "def area(radius):\n    return pi * radius ** 2\n",
+                    after:
+                        "from math import pi\n\n\ndef area(radius):\n    return pi * radius ** 2\n",
+                },
+            ),
+            (
+                "F841",
+                Example {
+                    before: "def run():\n    result = compute()\n",
+                    after: "def run():\n    compute()\n",
+                },
+            ),
+        ];
+        examples.into_iter().collect()
+    };
+}
+
+fn code_block(source: &str) -> String {
+    format!("```python\n{}\n```\n", source.trim_end())
+}
+
+fn severity_name(severity: lsp_types::DiagnosticSeverity) -> &'static str {
+    match severity {
+        lsp_types::DiagnosticSeverity::ERROR => "error",
+        lsp_types::DiagnosticSeverity::WARNING => "warning",
+        lsp_types::DiagnosticSeverity::INFORMATION => "information",
+        _ => "hint",
+    }
+}
+
+/// Lines of `source` spanned by `range`, clamped to the source
+fn reported_lines(source: &str, range: lsp_types::Range) -> String {
+    let start = range.start.line as usize;
+    let end = std::cmp::max(range.end.line as usize, start);
+    source
+        .lines()
+        .skip(start)
+        .take(end - start + 1)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn render(info: &RuleInfo, reported: Option<&str>) -> String {
+    let mut rv = format!("### {} `{}`\n\n", info.name, info.code);
+    rv.push_str(&format!(
+        "{} rule, reported as {} by default",
+        info.category,
+        severity_name(info.default_severity)
+    ));
+    if info.fixable {
+        rv.push_str(", fixable");
+    }
+    rv.push_str(&format!("\n\n{}\n", info.explanation));
+    if let Some(reported) = reported.filter(|x| !x.trim().is_empty()) {
+        rv.push_str("\n#### Reported\n\n");
+        rv.push_str(&code_block(reported));
+    }
+    if let Some(example) = EXAMPLES.get(info.code.as_str()) {
+        rv.push_str("\n#### Example\n\n");
+        rv.push_str(&code_block(example.before));
+        rv.push_str("\nUse instead:\n\n");
+        rv.push_str(&code_block(example.after));
+    }
+    rv
+}
+
+/// Explains the rule `code` as Markdown, quoting the lines of `source` it
+/// was reported at if given. Returns `None` if ruff doesn't recognise the
+/// code
+pub fn explain_rule(
+    code: &str,
+    source: Option<(&str, lsp_types::Range)>,
+) -> Option<lsp_types::MarkupContent> {
+    let info = rule_info_from_code(code)?;
+    let reported = source.map(|(source, range)| reported_lines(source, range));
+    Some(lsp_types::MarkupContent {
+        kind: lsp_types::MarkupKind::Markdown,
+        value: render(&info, reported.as_deref()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_explain_rule() {
+        let source = "import os\nimport sys\n\nprint(sys.argv)\n";
+        let range = lsp_types::Range::new(
+            lsp_types::Position::new(0, 7),
+            lsp_types::Position::new(0, 9),
+        );
+        let explanation = explain_rule("F401", Some((source, range))).unwrap();
+        assert_eq!(explanation.kind, lsp_types::MarkupKind::Markdown);
+        let value = explanation.value;
+        assert!(value.starts_with("### UnusedImport `F401`\n\n"));
+        assert!(value.contains("reported as warning by default, fixable"));
+        assert!(value.contains("#### Reported\n\n```python\nimport os\n```\n"));
+        assert!(value.contains("Use instead:\n\n```python\nimport sys\n\nprint(sys.argv)\n```\n"));
+        assert!(explain_rule("not a code", None).is_none());
+    }
+
+    #[test]
+    fn test_reported_lines() {
+        let source = "a = 1\nb = 2\nc = 3\n";
+        let range = |start, end| {
+            lsp_types::Range::new(
+                lsp_types::Position::new(start, 0),
+                lsp_types::Position::new(end, 0),
+            )
+        };
+        assert_eq!(reported_lines(source, range(1, 2)), "b = 2\nc = 3");
+        assert_eq!(reported_lines(source, range(2, 8)), "c = 3");
+        assert_eq!(reported_lines(source, range(8, 9)), "");
+    }
+}
@@ -0,0 +1,214 @@
+//! Framing of the messages written to the client
+//!
+//! Messages are serialized into buffers taken from a pool rather than
+//! freshly allocated strings, as floods of diagnostics would otherwise
+//! allocate a buffer the size of each message. Buffers are pooled by
+//! capacity in power of two size classes, such that a message is
+//! serialized into a buffer near its size. The header is written alongside
+//! the body in a single vectored write, rather than being copied ahead of it
+use bytes::BytesMut;
+use ruffd_types::serde::Serialize;
+use ruffd_types::serde_json;
+use ruffd_types::tokio::io::{self, AsyncWriteExt};
+use std::io::{IoSlice, Write};
+
+/// Capacity of the smallest size class
+const MIN_CLASS_SIZE: usize = 1 << 10;
+/// Size classes from 1KiB to 4MiB, larger buffers being dropped once written
+const NUM_CLASSES: usize = 13;
+/// Buffers kept of each size class
+const BUFFERS_PER_CLASS: usize = 4;
+/// Longest header, that of a 20 digit `Content-Length`
+const MAX_HEADER_SIZE: usize = 40;
+
+/// Size class holding buffers of at least `size` bytes
+fn size_class(size: usize) -> usize {
+    let class = size
+        .max(MIN_CLASS_SIZE)
+        .next_power_of_two()
+        .trailing_zeros()
+        - MIN_CLASS_SIZE.trailing_zeros();
+    class as usize
+}
+
+fn class_size(class: usize) -> usize {
+    MIN_CLASS_SIZE << class
+}
+
+/// Buffers reused across messages, by size class
+#[derive(Debug)]
+pub struct BufferPool {
+    classes: Vec<Vec<BytesMut>>,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self {
+            classes: vec![vec![]; NUM_CLASSES],
+        }
+    }
+}
+
+impl BufferPool {
+    /// Takes an empty buffer of at least `size` bytes, from the pool if it
+    /// has one
+    pub fn take(&mut self, size: usize) -> BytesMut {
+        let class = size_class(size);
+        self.classes
+            .get_mut(class..)
+            .into_iter()
+            .flatten()
+            .find_map(Vec::pop)
+            .unwrap_or_else(|| BytesMut::with_capacity(class_size(class)))
+    }
+
+    /// Returns a buffer to the pool, under the largest class it can hold
+    pub fn give(&mut self, mut buffer: BytesMut) {
+        buffer.clear();
+        if buffer.capacity() < MIN_CLASS_SIZE {
+            return;
+        }
+        // a buffer holds its class only if it reaches the class size
+        let class = size_class(buffer.capacity() / 2 + 1);
+        if let Some(buffers) = self.classes.get_mut(class) {
+            if buffers.len() < BUFFERS_PER_CLASS {
+                buffers.push(buffer);
+            }
+        }
+    }
+
+    /// Buffers currently pooled
+    pub fn len(&self) -> usize {
+        self.classes.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Serializes into a buffer by extending it, rather than through the
+/// generic `BufMut` writer copying chunk by chunk
+struct BodyWriter<'a>(&'a mut BytesMut);
+
+impl Write for BodyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes messages as frames of a header and a JSON body
+#[derive(Debug, Default)]
+pub struct FrameWriter {
+    pool: BufferPool,
+    /// Size of the last message written, taken as that of the next
+    last_size: usize,
+}
+
+impl FrameWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pool(&self) -> &BufferPool {
+        &self.pool
+    }
+
+    /// Serializes and writes `message` as a single frame
+    pub async fn write<W, T>(&mut self, writer: &mut W, message: &T) -> io::Result<()>
+    where
+        W: AsyncWriteExt + Unpin,
+        T: Serialize,
+    {
+        let mut body = self.pool.take(self.last_size);
+        serde_json::to_writer(BodyWriter(&mut body), message)?;
+        self.last_size = body.len();
+        let rv = write_frame(writer, &body).await;
+        self.pool.give(body);
+        rv
+    }
+}
+
+/// Writes a frame of `body`, sending its header and body in vectored writes
+pub async fn write_frame<W>(writer: &mut W, body: &[u8]) -> io::Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    let mut header = [0u8; MAX_HEADER_SIZE];
+    let header_len = {
+        let mut cursor = &mut header[..];
+        write!(cursor, "Content-Length: {}\r\n\r\n", body.len())?;
+        MAX_HEADER_SIZE - cursor.len()
+    };
+    let frame_len = header_len + body.len();
+    let mut written = 0;
+    while written < frame_len {
+        let slices = if written < header_len {
+            [
+                IoSlice::new(&header[written..header_len]),
+                IoSlice::new(body),
+            ]
+        } else {
+            [
+                IoSlice::new(&body[written - header_len..]),
+                IoSlice::new(&[]),
+            ]
+        };
+        match writer.write_vectored(&slices).await? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => written += n,
+        }
+    }
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::tokio;
+
+    #[test]
+    fn test_buffer_pool() {
+        assert_eq!(size_class(0), 0);
+        assert_eq!(size_class(MIN_CLASS_SIZE + 1), 1);
+        let mut pool = BufferPool::default();
+        let buffer = pool.take(3000);
+        assert!(buffer.capacity() >= 3000);
+        pool.give(buffer);
+        assert_eq!(pool.len(), 1);
+        // smaller messages reuse larger buffers
+        let buffer = pool.take(10);
+        assert!(buffer.capacity() >= 3000);
+        assert!(pool.is_empty());
+        pool.give(buffer);
+        // but larger messages don't take smaller buffers
+        assert!(pool.take(10_000).capacity() >= 10_000);
+        assert_eq!(pool.len(), 1);
+        // buffers too large to pool are dropped
+        pool.give(BytesMut::with_capacity(class_size(NUM_CLASSES)));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_frame_writer() {
+        let mut frames = FrameWriter::new();
+        let mut out = vec![];
+        let message = serde_json::json!({"jsonrpc": "2.0", "method": "exit"});
+        frames.write(&mut out, &message).await.unwrap();
+        frames.write(&mut out, &message).await.unwrap();
+        let body = message.to_string();
+        let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        assert_eq!(String::from_utf8(out).unwrap(), frame.repeat(2));
+        assert_eq!(frames.pool().len(), 1);
+    }
+}
@@ -0,0 +1,144 @@
+//! Access to the saved copies of documents on disk, and to the content of
+//! documents as the user sees it, unsaved changes included
+use ruffd_types::lsp_types;
+use ruffd_types::tokio::fs;
+use ruffd_types::uri;
+use ruffd_types::{DocumentBuffer, DocumentStatus, RuntimeError};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Byte order mark some editors write at the start of utf-8 files, which
+/// clients strip from the text they send
+const UTF8_BOM: char = '\u{feff}';
+
+/// Converts a `file` uri to a path on this platform as `uri::uri_to_path`
+/// does, erroring with the uri if it has none
+///
+/// A uri with a host other than `localhost` names a UNC path, which can
+/// only be expressed on windows
+pub fn uri_to_path_checked(uri: &lsp_types::Url) -> Result<PathBuf, RuntimeError> {
+    uri::uri_to_path(uri).ok_or_else(|| RuntimeError::UriToPathError(uri.clone()))
+}
+
+/// Reads the saved text of a document
+pub async fn read_document(uri: &lsp_types::Url) -> Result<String, RuntimeError> {
+    let path = uri_to_path_checked(uri)?;
+    let text = fs::read_to_string(&path)
+        .await
+        .map_err(|err| RuntimeError::FileReadError(path, err))?;
+    match text.strip_prefix(UTF8_BOM) {
+        Some(x) => Ok(x.to_string()),
+        None => Ok(text),
+    }
+}
+
+/// Text of the buffer of an open document, being what the user sees
+/// whether or not it's saved
+pub fn buffered_content(
+    uri: &lsp_types::Url,
+    open_buffers: &HashMap<lsp_types::Url, DocumentBuffer>,
+) -> Option<String> {
+    open_buffers.get(uri).map(|x| x.iter().collect())
+}
+
+/// Text of the buffer of a document open with unsaved changes, `None` if
+/// its saved text is what the user sees
+pub fn unsaved_content(
+    uri: &lsp_types::Url,
+    open_buffers: &HashMap<lsp_types::Url, DocumentBuffer>,
+    document_status: &HashMap<lsp_types::Url, DocumentStatus>,
+) -> Option<String> {
+    let dirty = matches!(document_status.get(uri), Some(x) if x.dirty);
+    open_buffers
+        .get(uri)
+        .filter(|_| dirty)
+        .map(|x| x.iter().collect())
+}
+
+/// Text of a document as the user sees it, being `buffered` as taken with
+/// [`buffered_content`] or [`unsaved_content`] if given, otherwise its
+/// saved text
+pub async fn effective_content(
+    uri: &lsp_types::Url,
+    buffered: Option<String>,
+) -> Result<String, RuntimeError> {
+    match buffered {
+        Some(x) => Ok(x),
+        None => read_document(uri).await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::tokio;
+    use std::path::Path;
+
+    #[test]
+    fn test_uri_to_path_checked() {
+        let uri = lsp_types::Url::parse("untitled:Untitled-1").unwrap();
+        assert!(uri_to_path_checked(&uri).is_err());
+        let uri = lsp_types::Url::from_file_path(
+            std::env::temp_dir().join("with space").join("caf\u{e9}.py"),
+        )
+        .unwrap();
+        assert!(uri.as_str().contains("with%20space"));
+        let path = uri_to_path_checked(&uri).unwrap();
+        assert!(path.ends_with(Path::new("with space").join("caf\u{e9}.py")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unc_uri_to_path_checked() {
+        let uri = lsp_types::Url::parse("file://localhost/src/a.py").unwrap();
+        assert_eq!(
+            uri_to_path_checked(&uri).unwrap(),
+            PathBuf::from("/src/a.py")
+        );
+        let uri = lsp_types::Url::parse("file://server/share/a.py").unwrap();
+        assert!(uri_to_path_checked(&uri).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_document() {
+        let path = std::env::temp_dir().join(format!("ruffd-fs-test-{}.py", std::process::id()));
+        std::fs::write(&path, "\u{feff}import os\n").unwrap();
+        let uri = lsp_types::Url::from_file_path(&path).unwrap();
+        assert_eq!(read_document(&uri).await.unwrap(), "import os\n");
+        std::fs::remove_file(&path).unwrap();
+        let err = read_document(&uri).await.unwrap_err();
+        assert!(matches!(err, RuntimeError::FileReadError(x, _) if x == path));
+    }
+
+    #[tokio::test]
+    async fn test_effective_content() {
+        let path = std::env::temp_dir().join(format!("ruffd-fs-unsaved-{}.py", std::process::id()));
+        std::fs::write(&path, "import os\n").unwrap();
+        let uri = lsp_types::Url::from_file_path(&path).unwrap();
+        let open_buffers = HashMap::from([(
+            uri.clone(),
+            DocumentBuffer::from_string("import sys\n".to_string()),
+        )]);
+        let mut document_status = HashMap::from([(uri.clone(), DocumentStatus::opened(1))]);
+        // a clean buffer matches its saved copy
+        let unsaved = unsaved_content(&uri, &open_buffers, &document_status);
+        assert_eq!(unsaved, None);
+        assert_eq!(
+            effective_content(&uri, unsaved).await.unwrap(),
+            "import os\n"
+        );
+        // whereas its buffer is what's seen regardless
+        let buffered = buffered_content(&uri, &open_buffers);
+        assert_eq!(
+            effective_content(&uri, buffered).await.unwrap(),
+            "import sys\n"
+        );
+        document_status.get_mut(&uri).unwrap().dirty = true;
+        let unsaved = unsaved_content(&uri, &open_buffers, &document_status);
+        assert_eq!(
+            effective_content(&uri, unsaved).await.unwrap(),
+            "import sys\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
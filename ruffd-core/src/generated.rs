@@ -0,0 +1,106 @@
+//! Detection of generated files, such as migrations or protobuf modules,
+//! whose checks are reported without fixes, as hints or suppressed as
+//! configured
+//!
+//! Detection runs as a filter stage of the lint pipeline, seeing the
+//! source each lint is of, such that every diagnostic run honours it
+use crate::lint::{CheckFilter, Linted};
+use ruffd_types::project::Severity;
+use ruffd_types::{GeneratedFileAction, GeneratedFilesConfig};
+
+/// Determines whether one of `markers` appears within the first `lines`
+/// lines of `source`, regardless of case
+pub fn is_generated(source: &str, markers: &[String], lines: usize) -> bool {
+    let markers = markers
+        .iter()
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect::<Vec<_>>();
+    if markers.is_empty() {
+        return false;
+    }
+    source
+        .lines()
+        .take(lines)
+        .map(str::to_lowercase)
+        .any(|line| markers.iter().any(|x| line.contains(x.as_str())))
+}
+
+impl CheckFilter for GeneratedFilesConfig {
+    fn filter(&self, source: &str, mut linted: Linted) -> Linted {
+        if self.action == GeneratedFileAction::Report
+            || linted.checks.is_empty()
+            || !is_generated(source, &self.markers, self.lines)
+        {
+            return linted;
+        }
+        match self.action {
+            GeneratedFileAction::Suppress => Linted::default(),
+            action => {
+                linted.checks.iter_mut().for_each(|x| x.fix = None);
+                if action == GeneratedFileAction::Hint {
+                    linted.severity = Some(Severity::Hint);
+                }
+                linted
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lint::lint;
+    use crate::ruff_utils::SettingsScope;
+    use ruffd_types::tokio;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_is_generated() {
+        let markers = GeneratedFilesConfig::default().markers;
+        let django = "# Generated by Django 4.1 on 2022-11-01\nimport os\n";
+        assert!(is_generated(django, &markers, 5));
+        assert!(!is_generated(django, &["@generated".to_string()], 5));
+        assert!(is_generated(
+            "\"\"\"\n@generated by protoc\n\"\"\"\n",
+            &markers,
+            5
+        ));
+        assert!(!is_generated("import os\n# @generated\n", &markers, 1));
+        assert!(!is_generated(django, &[String::new()], 5));
+        assert!(!is_generated("import os\n", &markers, 5));
+    }
+
+    #[tokio::test]
+    async fn test_generated_filter() {
+        let path = PathBuf::from("/tmp/dummy.py");
+        let source = "# @generated\nimport os\n".to_string();
+        let linted = lint(path.clone(), source.clone(), SettingsScope::default())
+            .await
+            .unwrap();
+        assert_eq!(linted.checks.len(), 1);
+        assert!(linted.checks[0].fix.is_none());
+        assert_eq!(linted.severity, None);
+        let mut scope = SettingsScope::default();
+        scope.generated_files.action = GeneratedFileAction::Report;
+        let linted = lint(path.clone(), source.clone(), scope.clone())
+            .await
+            .unwrap();
+        assert!(linted.checks[0].fix.is_some());
+        let mut config = GeneratedFilesConfig::default();
+        let filtered = config.filter(&source, linted.clone());
+        assert_eq!(filtered.checks.len(), 1);
+        assert!(filtered.checks[0].fix.is_none());
+        assert_eq!(config.filter("import os\n", linted.clone()), linted);
+        config.action = GeneratedFileAction::Hint;
+        let hinted = config.filter(&source, linted.clone());
+        assert_eq!(hinted.checks, filtered.checks);
+        assert_eq!(hinted.severity, Some(Severity::Hint));
+        assert_eq!(config.filter("import os\n", linted.clone()).severity, None);
+        scope.generated_files.action = GeneratedFileAction::Hint;
+        let linted = lint(path, source.clone(), scope).await.unwrap();
+        assert_eq!(linted, hinted);
+        config.action = GeneratedFileAction::Suppress;
+        assert_eq!(config.filter(&source, hinted), Linted::default());
+    }
+}
@@ -0,0 +1,280 @@
+use crate::positions::BufferPosition;
+use ruffd_types::lsp_types;
+use ruffd_types::rustpython_ast::{ExcepthandlerKind, Location, Stmt, StmtKind};
+use ruffd_types::rustpython_parser::parser;
+use std::path::{Component, Path};
+
+/// Resolves the dotted module path of a python file or package directory
+/// relative to `root`
+///
+/// Returns `None` for paths outside of `root` or that aren't valid modules
+pub fn module_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut parts = relative
+        .components()
+        .map(|x| match x {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let last = parts.pop()?;
+    let last = match last.rsplit_once('.') {
+        Some((stem, "py" | "pyi")) => stem,
+        Some(_) => return None,
+        None => last,
+    };
+    if last != "__init__" {
+        parts.push(last);
+    }
+    if parts.is_empty() || parts.iter().any(|x| x.is_empty() || x.contains('.')) {
+        return None;
+    }
+    Some(parts.join("."))
+}
+
+/// Maps `module` to its path after `old` has been renamed to `new`, returning
+/// `None` if `module` is unaffected
+fn renamed_module(module: &str, old: &str, new: &str) -> Option<String> {
+    let rest = module.strip_prefix(old)?;
+    if rest.is_empty() || rest.starts_with('.') {
+        Some(format!("{}{}", new, rest))
+    } else {
+        None
+    }
+}
+
+fn split_parent(module: &str) -> (Option<&str>, &str) {
+    match module.rsplit_once('.') {
+        Some((parent, name)) => (Some(parent), name),
+        None => (None, module),
+    }
+}
+
+pub(crate) fn flatten_body<'a>(body: &'a [Stmt], acc: &mut Vec<&'a Stmt>) {
+    for stmt in body {
+        acc.push(stmt);
+        match &stmt.node {
+            StmtKind::FunctionDef { body, .. }
+            | StmtKind::AsyncFunctionDef { body, .. }
+            | StmtKind::ClassDef { body, .. }
+            | StmtKind::With { body, .. }
+            | StmtKind::AsyncWith { body, .. } => flatten_body(body, acc),
+            StmtKind::For { body, orelse, .. }
+            | StmtKind::AsyncFor { body, orelse, .. }
+            | StmtKind::While { body, orelse, .. }
+            | StmtKind::If { body, orelse, .. } => {
+                flatten_body(body, acc);
+                flatten_body(orelse, acc);
+            }
+            StmtKind::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                flatten_body(body, acc);
+                for handler in handlers {
+                    let ExcepthandlerKind::ExceptHandler { body, .. } = &handler.node;
+                    flatten_body(body, acc);
+                }
+                flatten_body(orelse, acc);
+                flatten_body(finalbody, acc);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Creates an edit replacing `old_text` at `location`, provided the source
+/// holds exactly that text there
+fn replace_at(
+    lines: &[Vec<char>],
+    location: &Location,
+    old_text: &str,
+    new_text: &str,
+) -> Option<lsp_types::TextEdit> {
+    let start = BufferPosition::from(*location);
+    let end = BufferPosition::new(start.row, start.col + old_text.chars().count());
+    let line = lines.get(start.row)?;
+    let found = line.get(start.col..end.col)?.iter().collect::<String>();
+    if found != old_text {
+        return None;
+    }
+    Some(lsp_types::TextEdit {
+        range: lsp_types::Range {
+            start: start.into(),
+            end: end.into(),
+        },
+        new_text: new_text.to_string(),
+    })
+}
+
+/// Locates the module of a `from` import, which the AST has no location for
+fn from_module_location(lines: &[Vec<char>], stmt: &Stmt, module: &str) -> Option<Location> {
+    let line = lines.get(BufferPosition::from(stmt.location).row)?;
+    let line = line.iter().collect::<String>();
+    let after_from = line
+        .char_indices()
+        .nth(stmt.location.column() + "from".len())?
+        .0;
+    let byte_offset = after_from + line[after_from..].find(module)?;
+    let column = line[..byte_offset].chars().count();
+    Some(Location::new(stmt.location.row(), column))
+}
+
+/// Computes the edits updating absolute imports of `old` within `source` to
+/// import `new` instead
+///
+/// Relative imports and sources that fail to parse produce no edits
+pub fn import_rename_edits(source: &str, old: &str, new: &str) -> Vec<lsp_types::TextEdit> {
+    let suite = match parser::parse_program(source, "<filename>") {
+        Ok(x) => x,
+        Err(_) => return vec![],
+    };
+    let lines = source
+        .split('\n')
+        .map(|x| x.chars().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let mut stmts = vec![];
+    flatten_body(&suite, &mut stmts);
+    let (old_parent, old_name) = split_parent(old);
+    let (new_parent, new_name) = split_parent(new);
+    let mut rv = vec![];
+    for stmt in stmts {
+        match &stmt.node {
+            StmtKind::Import { names } => {
+                for alias in names {
+                    if let Some(renamed) = renamed_module(&alias.node.name, old, new) {
+                        rv.extend(replace_at(
+                            &lines,
+                            &alias.location,
+                            &alias.node.name,
+                            &renamed,
+                        ));
+                    }
+                }
+            }
+            StmtKind::ImportFrom {
+                module: Some(module),
+                names,
+                level,
+            } if level.unwrap_or(0) == 0 => {
+                if let Some(renamed) = renamed_module(module, old, new) {
+                    rv.extend(
+                        from_module_location(&lines, stmt, module)
+                            .and_then(|x| replace_at(&lines, &x, module, &renamed)),
+                    );
+                    continue;
+                }
+                // the renamed module may itself be imported from its package
+                if old_parent != Some(module.as_str()) {
+                    continue;
+                }
+                let aliases = names
+                    .iter()
+                    .filter(|x| x.node.name == old_name)
+                    .collect::<Vec<_>>();
+                if aliases.is_empty() {
+                    continue;
+                }
+                if new_parent == old_parent {
+                    for alias in aliases {
+                        rv.extend(replace_at(&lines, &alias.location, old_name, new_name));
+                    }
+                } else if let (Some(new_parent), [alias]) = (new_parent, names.as_slice()) {
+                    // moving packages can only be followed when the statement
+                    // imports nothing else from the old package
+                    rv.extend(
+                        from_module_location(&lines, stmt, module)
+                            .and_then(|x| replace_at(&lines, &x, module, new_parent)),
+                    );
+                    rv.extend(replace_at(&lines, &alias.location, old_name, new_name));
+                }
+            }
+            _ => {}
+        }
+    }
+    rv
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn apply_edits(source: &str, edits: &[lsp_types::TextEdit]) -> String {
+        let mut lines = source
+            .split('\n')
+            .map(|x| x.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut edits = edits.to_vec();
+        edits.sort_by_key(|x| (x.range.start.line, x.range.start.character));
+        for edit in edits.iter().rev() {
+            let line = &mut lines[edit.range.start.line as usize];
+            let start = edit.range.start.character as usize;
+            let end = edit.range.end.character as usize;
+            line.splice(start..end, edit.new_text.chars());
+        }
+        lines
+            .into_iter()
+            .map(|x| x.into_iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_module_path() {
+        let root = PathBuf::from("/project");
+        let cases = vec![
+            ("/project/a/b.py", Some("a.b")),
+            ("/project/a/b/__init__.py", Some("a.b")),
+            ("/project/a/b", Some("a.b")),
+            ("/project/stubs.pyi", Some("stubs")),
+            ("/project/__init__.py", None),
+            ("/project/a/b.txt", None),
+            ("/other/a.py", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(
+                module_path(&root, &PathBuf::from(path)).as_deref(),
+                expected,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_rename_edits() {
+        let source = [
+            "import pkg.old",
+            "import pkg.old.sub as sub, os",
+            "from pkg.old import thing",
+            "from pkg import old, other",
+            "from .old import relative",
+            "import pkg.older",
+            "    from pkg import old",
+        ]
+        .join("\n");
+        let expected = [
+            "import pkg.new",
+            "import pkg.new.sub as sub, os",
+            "from pkg.new import thing",
+            "from pkg import new, other",
+            "from .old import relative",
+            "import pkg.older",
+            "    from pkg import new",
+        ]
+        .join("\n");
+        let edits = import_rename_edits(&source, "pkg.old", "pkg.new");
+        assert_eq!(apply_edits(&source, &edits), expected);
+    }
+
+    #[test]
+    fn test_import_rename_edits_new_package() {
+        let source = ["from pkg import old", "from pkg import old, other"].join("\n");
+        let expected = ["from moved import new", "from pkg import old, other"].join("\n");
+        let edits = import_rename_edits(&source, "pkg.old", "moved.new");
+        assert_eq!(apply_edits(&source, &edits), expected);
+    }
+}
@@ -1,13 +1,42 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod calls;
+pub mod diagnostics;
+mod explain;
+pub mod frames;
+mod fs;
+mod generated;
+mod imports;
+pub mod lint;
+mod log_message;
+mod names;
+#[cfg(feature = "notebook")]
+mod notebook;
 mod notifications;
+mod outbound;
+mod positions;
+mod preview;
+mod profile;
+mod progress;
+mod references;
 mod requests;
 mod ruff_utils;
 pub mod server;
 mod server_ops;
 mod service;
+mod signatures;
+pub mod spill;
+mod symbols;
+#[cfg(feature = "telemetry")]
+mod telemetry;
+mod unwind;
+pub mod warm_cache;
+mod workspace;
 
 pub const PKG_NAME: &str = env!("CARGO_PKG_NAME");
 pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
-pub use service::Service;
+pub use service::{
+    InitializeHook, MessageHook, Middleware, Service, SessionOutcome, StateFactory,
+    TimingMiddleware, TracingMiddleware, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_SERVER_REQUEST_TIMEOUT,
+};
@@ -0,0 +1,248 @@
+//! Execution of lints off of the async runtime
+//!
+//! Linting is CPU bound, so runs on the blocking thread pool such that
+//! heavy analysis doesn't starve io. At most `lint_jobs` lints run at once,
+//! further lints queueing for a free slot
+use crate::positions::encode_checks;
+use crate::ruff_utils::{check_module, resolve_settings, SettingsScope};
+use ruffd_types::extensions::LintTiming;
+use ruffd_types::logging::{current_trace, in_trace};
+use ruffd_types::project::{FixSafety, Severity};
+use ruffd_types::ruff::checks::Check;
+use ruffd_types::rustpython_ast::Suite;
+use ruffd_types::tasks::spawn_blocking_named;
+use ruffd_types::tokio::sync::Semaphore;
+use ruffd_types::CheckRegistry;
+use ruffd_types::{log_debug, log_error, log_warn};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Number of lints run at once, 0 until set or first read
+static LINT_JOBS: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref LINT_SLOTS: Semaphore = Semaphore::new(lint_jobs());
+}
+
+/// Lints run at once when not set, being the available parallelism
+pub fn default_lint_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+}
+
+/// Sets the number of lints run at once, which must happen before the
+/// first lint to take effect
+pub fn set_lint_jobs(jobs: usize) {
+    LINT_JOBS.store(jobs.max(1), Ordering::Relaxed);
+}
+
+pub fn lint_jobs() -> usize {
+    match LINT_JOBS.load(Ordering::Relaxed) {
+        0 => {
+            let jobs = default_lint_jobs();
+            LINT_JOBS.store(jobs, Ordering::Relaxed);
+            jobs
+        }
+        x => x,
+    }
+}
+
+/// Checks of a source once through the pipeline, along with the severity
+/// each is reported at if a filter overrides it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Linted {
+    pub checks: Vec<Check>,
+    pub severity: Option<Severity>,
+}
+
+impl From<Linted> for CheckRegistry {
+    fn from(linted: Linted) -> Self {
+        CheckRegistry::from_iter(linted.checks).with_severity(linted.severity)
+    }
+}
+
+/// Stage of the pipeline adjusting the checks of a source once linted, as
+/// configured for the scope of the lint
+pub trait CheckFilter {
+    fn filter(&self, source: &str, linted: Linted) -> Linted;
+}
+
+/// Projects never offering fixes have their checks reported without them
+impl CheckFilter for FixSafety {
+    fn filter(&self, _source: &str, mut linted: Linted) -> Linted {
+        if *self == FixSafety::Never {
+            linted.checks.iter_mut().for_each(|x| x.fix = None);
+        }
+        linted
+    }
+}
+
+/// Ruff panicked while linting a source, which is then considered to have
+/// no checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LintPanicked;
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&'static str>() {
+        Some(x) => x,
+        None => payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .unwrap_or("unknown panic"),
+    }
+}
+
+/// Lints `source` as the contents of `path` on the blocking thread pool,
+/// under the settings resolved for `path` within `scope`, with columns in
+/// the encoding of `scope`
+///
+/// Sources failing to parse have their syntax error as their only check.
+/// A panic within ruff is logged along with the source it panicked on,
+/// rather than taking down the caller. Records logged linting, including
+/// those of ruff, are under the trace of the caller
+///
+/// Paths whose diagnostics are suppressed by `scope` have no checks, and
+/// aren't linted at all. The duration of the lint is recorded in the
+/// timings of `scope`
+pub async fn lint(
+    path: PathBuf,
+    source: String,
+    scope: SettingsScope,
+) -> Result<Linted, LintPanicked> {
+    lint_module(path, source, scope)
+        .await
+        .map(|(linted, _)| linted)
+}
+
+/// Lints `source` as `lint` does, along with the module it parsed to for
+/// sharing with the `AstCache`, being `None` if it failed to parse or
+/// wasn't linted at all
+pub async fn lint_module(
+    path: PathBuf,
+    source: String,
+    scope: SettingsScope,
+) -> Result<(Linted, Option<Arc<Suite>>), LintPanicked> {
+    if scope.suppresses(&path) {
+        return Ok((Linted::default(), None));
+    }
+    // the semaphore is never closed
+    let _slot = LINT_SLOTS.acquire().await.unwrap();
+    let trace = current_trace();
+    let start = Instant::now();
+    let timed = path.clone();
+    let timings = scope.lint_timings.clone();
+    let name = || format!("lint {}", timed.display());
+    let checks = spawn_blocking_named(name, move || {
+        in_trace(trace, || lint_blocking(path, source, scope))
+    });
+    let rv = match checks.await {
+        Ok(checks) => checks,
+        Err(err) => {
+            log_error!("lint failed: {}", err);
+            Ok((Linted::default(), None))
+        }
+    };
+    timings.record(LintTiming {
+        path: timed,
+        micros: start.elapsed().as_micros() as u64,
+    });
+    rv
+}
+
+fn lint_blocking(
+    path: PathBuf,
+    source: String,
+    scope: SettingsScope,
+) -> Result<(Linted, Option<Arc<Suite>>), LintPanicked> {
+    let settings = match resolve_settings(&path, &scope) {
+        Ok(x) => x,
+        Err(err) => {
+            log_warn!("failed resolving settings of {}: {}", path.display(), err);
+            return Ok((Linted::default(), None));
+        }
+    };
+    let checks = panic::catch_unwind(AssertUnwindSafe(|| {
+        check_module(&path, &source, &settings, true)
+    }));
+    match checks {
+        Ok(checks) => {
+            let (mut checks, module) = checks.unwrap_or_default();
+            encode_checks(&mut checks, &source, scope.encoding);
+            let linted = Linted {
+                checks,
+                severity: None,
+            };
+            let linted = scope
+                .filters()
+                .iter()
+                .fold(linted, |linted, x| x.filter(&source, linted));
+            Ok((linted, module.map(Arc::new)))
+        }
+        Err(payload) => {
+            log_error!(
+                "ruff panicked linting {} of {} lines: {}",
+                path.display(),
+                source.lines().count(),
+                panic_message(payload.as_ref())
+            );
+            log_debug!("source of {}:\n{}", path.display(), source);
+            Err(LintPanicked)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::tokio;
+    use ruffd_types::tokio::task;
+
+    #[tokio::test]
+    async fn test_concurrent_lints() {
+        let path = PathBuf::from("/tmp/dummy.py");
+        let lints = (0..lint_jobs() * 2 + 1)
+            .map(|_| {
+                task::spawn(lint(
+                    path.clone(),
+                    "import os\n".to_string(),
+                    SettingsScope::default(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        for handle in lints {
+            assert_eq!(handle.await.unwrap().unwrap().checks.len(), 1);
+        }
+        let check_vec = lint(path, "def (".to_string(), SettingsScope::default())
+            .await
+            .unwrap()
+            .checks;
+        assert_eq!(check_vec.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fix_safety_filter() {
+        let path = PathBuf::from("/tmp/dummy.py");
+        let mut scope = SettingsScope {
+            fix_safety: FixSafety::QuickFix,
+            ..Default::default()
+        };
+        let checks = lint(path.clone(), "import os\n".to_string(), scope.clone())
+            .await
+            .unwrap()
+            .checks;
+        assert!(checks[0].fix.is_some());
+        assert!(!scope.fixes_all());
+        scope.fix_safety = FixSafety::Never;
+        let checks = lint(path, "import os\n".to_string(), scope)
+            .await
+            .unwrap()
+            .checks;
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].fix.is_none());
+    }
+}
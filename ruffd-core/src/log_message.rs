@@ -0,0 +1,100 @@
+//! Failures of notification handlers and of the initialization options,
+//! logged to the client through `window/logMessage`
+//!
+//! Notifications have no response to carry an error, and error responses
+//! with a null id are mishandled or loudly reported by some clients. Each
+//! kind of error is instead logged at most once per interval, such that a
+//! client repeating a bad notification isn't flooded with messages
+use ruffd_types::tokio::sync::mpsc::Sender;
+use ruffd_types::tokio::sync::Mutex;
+use ruffd_types::{
+    lsp_types, serde_json, RpcErrors, RpcMessage, RpcNotification, RpcResponseError, ServerState,
+};
+use std::sync::Arc;
+
+/// Kind of an error as set by `RuntimeError`, falling back to its code for
+/// errors raised before reaching the handler
+fn error_kind(error: &RpcResponseError) -> String {
+    error
+        .data
+        .as_ref()
+        .and_then(|x| x.get("kind"))
+        .and_then(|x| x.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| error.code.to_string())
+}
+
+fn log_message(method: &str, error: &RpcResponseError, suppressed: usize) -> RpcNotification {
+    let typ = if error.code == RpcErrors::INTERNAL_ERROR.code {
+        lsp_types::MessageType::ERROR
+    } else {
+        lsp_types::MessageType::WARNING
+    };
+    let mut message = format!("{} failed: {}", method, error.message);
+    if suppressed > 0 {
+        message.push_str(&format!(" ({} similar errors suppressed)", suppressed));
+    }
+    window_log_message(typ, message)
+}
+
+fn window_log_message(typ: lsp_types::MessageType, message: String) -> RpcNotification {
+    RpcNotification::new(
+        "window/logMessage".to_string(),
+        Some(serde_json::to_value(lsp_types::LogMessageParams { typ, message }).unwrap()),
+    )
+}
+
+/// Warns the client that its `initializationOptions` failed to parse, such
+/// that the session runs with the default configuration
+pub fn malformed_options(err: &serde_json::Error) -> RpcNotification {
+    window_log_message(
+        lsp_types::MessageType::WARNING,
+        format!(
+            "malformed initializationOptions, defaults are used instead: {}",
+            err
+        ),
+    )
+}
+
+/// Logs to the client that the handler of notification `method` failed,
+/// unless the state's limiter admitted an error of the same kind recently
+pub async fn notification_error(
+    state: &Arc<Mutex<ServerState>>,
+    response_channel: &Sender<RpcMessage>,
+    method: &str,
+    error: &RpcResponseError,
+) {
+    let limiter = state.lock().await.log_limiter.clone();
+    let suppressed = limiter.write().await.admit(&error_kind(error));
+    if let Some(suppressed) = suppressed {
+        let message = log_message(method, error, suppressed);
+        response_channel.send(message.into()).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_log_message() {
+        let error = RpcResponseError {
+            code: RpcErrors::INVALID_PARAMS.code,
+            message: "Row out of bounds".to_string(),
+            data: Some(serde_json::json!({"kind": "DocumentError"})),
+        };
+        assert_eq!(error_kind(&error), "DocumentError");
+        let params = serde_json::from_value::<lsp_types::LogMessageParams>(
+            log_message("textDocument/didChange", &error, 2)
+                .params
+                .unwrap()
+                .into_value(),
+        )
+        .unwrap();
+        assert_eq!(params.typ, lsp_types::MessageType::WARNING);
+        assert_eq!(
+            params.message,
+            "textDocument/didChange failed: Row out of bounds (2 similar errors suppressed)"
+        );
+    }
+}
@@ -0,0 +1,779 @@
+//! Occurrences of the names of a source, served by
+//! `textDocument/documentHighlight`
+//!
+//! Names are found by walking the source's module, whose nodes tell whether
+//! each is bound or read where it occurs. Each is resolved to the scope
+//! binding it from the function and class definitions of the module, such
+//! that a local variable isn't taken for a global of the same name
+use crate::imports::flatten_body;
+use ruffd_types::rustpython_ast::{
+    Alias, Arguments, Comprehension, ExcepthandlerKind, Expr, ExprContext, ExprKind, Keyword,
+    Location, Stmt, StmtKind, Suite,
+};
+use ruffd_types::rustpython_parser::lexer::{make_tokenizer, Spanned};
+use ruffd_types::rustpython_parser::token::Tok;
+use std::cmp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    Read,
+    /// Binding of the name, by assignment, definition, import or as the
+    /// parameter of a function
+    Write,
+    /// Attribute following a `.`, only matched to attributes of the same
+    /// name as its object isn't known
+    Attribute,
+    /// Name of a keyword argument of a call
+    Keyword,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameToken {
+    pub name: String,
+    pub kind: NameKind,
+    pub location: Location,
+    pub end_location: Location,
+    /// Whether the name is followed by an opening parenthesis, as when it's
+    /// called
+    pub called: bool,
+    /// Whether the token names a function or class in its definition
+    definition: bool,
+    /// Whether the token is declared `global` or `nonlocal`, such that the
+    /// scope it's declared in doesn't bind it
+    declaration: bool,
+}
+
+/// Names of the source's tokens in the order they occur, placing the
+/// identifiers the AST has no location for
+struct NameLocations(Vec<Spanned>);
+
+impl NameLocations {
+    fn new(source: &str) -> Self {
+        let names = make_tokenizer(source)
+            .map_while(Result::ok)
+            .filter(|x| matches!(x.1, Tok::Name { .. }))
+            .collect();
+        Self(names)
+    }
+
+    /// Span of the first name `name` at or past `from`
+    fn find(&self, name: &str, from: Location) -> Option<(Location, Location)> {
+        let start = self.0.partition_point(|x| x.0 < from);
+        self.0[start..]
+            .iter()
+            .find(|x| matches!(&x.1, Tok::Name { name: x } if x == name))
+            .map(|x| (x.0, x.2))
+    }
+}
+
+/// Walks a module for its names, classifying each by the node it's part of
+struct Collector {
+    locations: NameLocations,
+    tokens: Vec<NameToken>,
+}
+
+impl Collector {
+    fn push(&mut self, name: &str, kind: NameKind, location: Location) -> &mut NameToken {
+        let end_location = Location::new(location.row(), location.column() + name.chars().count());
+        self.tokens.push(NameToken {
+            name: name.to_string(),
+            kind,
+            location,
+            end_location,
+            called: false,
+            definition: false,
+            declaration: false,
+        });
+        self.tokens.last_mut().unwrap()
+    }
+
+    /// Adds the first name `name` at or past `from`, returning the token
+    /// along with the location past it
+    fn find(
+        &mut self,
+        name: &str,
+        kind: NameKind,
+        from: Location,
+    ) -> Option<(&mut NameToken, Location)> {
+        let (location, end_location) = self.locations.find(name, from)?;
+        Some((self.push(name, kind, location), end_location))
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        stmts.iter().for_each(|x| self.stmt(x));
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        exprs.iter().for_each(|x| self.expr(x));
+    }
+
+    fn opt_expr(&mut self, expr: &Option<Box<Expr>>) {
+        if let Some(x) = expr {
+            self.expr(x);
+        }
+    }
+
+    /// Names the function or class defined by `stmt` as a binding of it,
+    /// which follows its decorators
+    fn definition(&mut self, stmt: &Stmt, name: &str, decorators: &[Expr]) {
+        self.exprs(decorators);
+        let from = decorators
+            .iter()
+            .filter_map(|x| x.end_location)
+            .fold(stmt.location, cmp::max);
+        if let Some((token, _)) = self.find(name, NameKind::Write, from) {
+            token.definition = true;
+        }
+    }
+
+    fn arguments(&mut self, args: &Arguments) {
+        let params = args
+            .posonlyargs
+            .iter()
+            .chain(args.args.iter())
+            .chain(args.vararg.as_deref())
+            .chain(args.kwonlyargs.iter())
+            .chain(args.kwarg.as_deref());
+        for param in params {
+            self.push(&param.node.arg, NameKind::Write, param.location);
+            self.opt_expr(&param.node.annotation);
+        }
+        self.exprs(&args.defaults);
+        self.exprs(&args.kw_defaults);
+    }
+
+    /// Names a module imported by `import`, or imported from by `from` if
+    /// `bound` is false, the first name of which is bound unless aliased
+    fn module(&mut self, module: &str, bound: bool, mut from: Location) -> Location {
+        for (idx, part) in module.split('.').enumerate() {
+            let kind = match idx {
+                0 if bound => NameKind::Write,
+                0 => NameKind::Read,
+                _ => NameKind::Attribute,
+            };
+            match self.find(part, kind, from) {
+                Some((_, end)) => from = end,
+                None => break,
+            }
+        }
+        from
+    }
+
+    fn alias(&mut self, alias: &Alias, from_import: bool) {
+        let bound = alias.node.asname.is_none();
+        let end = match from_import {
+            true => {
+                let kind = match bound {
+                    true => NameKind::Write,
+                    false => NameKind::Read,
+                };
+                self.find(&alias.node.name, kind, alias.location)
+                    .map_or(alias.location, |(_, end)| end)
+            }
+            false => self.module(&alias.node.name, bound, alias.location),
+        };
+        if let Some(asname) = &alias.node.asname {
+            self.find(asname, NameKind::Write, end);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.node {
+            StmtKind::FunctionDef {
+                name,
+                args,
+                body,
+                decorator_list,
+                returns,
+                ..
+            }
+            | StmtKind::AsyncFunctionDef {
+                name,
+                args,
+                body,
+                decorator_list,
+                returns,
+                ..
+            } => {
+                self.definition(stmt, name, decorator_list);
+                self.arguments(args);
+                self.opt_expr(returns);
+                self.stmts(body);
+            }
+            StmtKind::ClassDef {
+                name,
+                bases,
+                keywords,
+                body,
+                decorator_list,
+                ..
+            } => {
+                self.definition(stmt, name, decorator_list);
+                self.exprs(bases);
+                self.keywords(keywords);
+                self.stmts(body);
+            }
+            StmtKind::Return { value } => self.opt_expr(value),
+            StmtKind::Delete { targets } => self.exprs(targets),
+            StmtKind::Assign { targets, value, .. } => {
+                self.exprs(targets);
+                self.expr(value);
+            }
+            StmtKind::AugAssign { target, value, .. } => {
+                self.expr(target);
+                self.expr(value);
+            }
+            StmtKind::AnnAssign {
+                target,
+                annotation,
+                value,
+                ..
+            } => {
+                self.expr(target);
+                self.expr(annotation);
+                self.opt_expr(value);
+            }
+            StmtKind::For {
+                target,
+                iter,
+                body,
+                orelse,
+                ..
+            }
+            | StmtKind::AsyncFor {
+                target,
+                iter,
+                body,
+                orelse,
+                ..
+            } => {
+                self.expr(target);
+                self.expr(iter);
+                self.stmts(body);
+                self.stmts(orelse);
+            }
+            StmtKind::While { test, body, orelse } | StmtKind::If { test, body, orelse } => {
+                self.expr(test);
+                self.stmts(body);
+                self.stmts(orelse);
+            }
+            StmtKind::With { items, body, .. } | StmtKind::AsyncWith { items, body, .. } => {
+                for item in items {
+                    self.expr(&item.context_expr);
+                    self.opt_expr(&item.optional_vars);
+                }
+                self.stmts(body);
+            }
+            StmtKind::Raise { exc, cause } => {
+                self.opt_expr(exc);
+                self.opt_expr(cause);
+            }
+            StmtKind::Try {
+                body,
+                handlers,
+                orelse,
+                finalbody,
+            } => {
+                self.stmts(body);
+                for handler in handlers {
+                    let ExcepthandlerKind::ExceptHandler { type_, name, body } = &handler.node;
+                    self.opt_expr(type_);
+                    if let Some(name) = name {
+                        let from = type_
+                            .as_ref()
+                            .and_then(|x| x.end_location)
+                            .unwrap_or(handler.location);
+                        self.find(name, NameKind::Write, from);
+                    }
+                    self.stmts(body);
+                }
+                self.stmts(orelse);
+                self.stmts(finalbody);
+            }
+            StmtKind::Assert { test, msg } => {
+                self.expr(test);
+                self.opt_expr(msg);
+            }
+            StmtKind::Import { names } => names.iter().for_each(|x| self.alias(x, false)),
+            StmtKind::ImportFrom { module, names, .. } => {
+                if let Some(module) = module {
+                    self.module(module, false, stmt.location);
+                }
+                names.iter().for_each(|x| self.alias(x, true));
+            }
+            StmtKind::Global { names } | StmtKind::Nonlocal { names } => {
+                for name in names {
+                    if let Some((token, _)) = self.find(name, NameKind::Read, stmt.location) {
+                        token.declaration = true;
+                    }
+                }
+            }
+            StmtKind::Expr { value } => self.expr(value),
+            _ => {}
+        }
+    }
+
+    fn keywords(&mut self, keywords: &[Keyword]) {
+        for keyword in keywords {
+            if let Some(arg) = &keyword.node.arg {
+                self.find(arg, NameKind::Keyword, keyword.location);
+            }
+            self.expr(&keyword.node.value);
+        }
+    }
+
+    fn comprehensions(&mut self, generators: &[Comprehension]) {
+        for generator in generators {
+            self.expr(&generator.target);
+            self.expr(&generator.iter);
+            self.exprs(&generator.ifs);
+        }
+    }
+
+    /// Names the function called, marking the name or attribute it's
+    /// called by
+    fn callee(&mut self, func: &Expr) {
+        self.expr(func);
+        let name = match &func.node {
+            ExprKind::Name { id, .. } => id,
+            ExprKind::Attribute { attr, .. } => attr,
+            _ => return,
+        };
+        if let Some(token) = self.tokens.last_mut().filter(|x| &x.name == name) {
+            token.called = true;
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.node {
+            ExprKind::Name { id, ctx } => {
+                let kind = match ctx {
+                    ExprContext::Load => NameKind::Read,
+                    ExprContext::Store | ExprContext::Del => NameKind::Write,
+                };
+                self.push(id, kind, expr.location);
+            }
+            ExprKind::Attribute { value, attr, .. } => {
+                self.expr(value);
+                let from = value.end_location.unwrap_or(value.location);
+                self.find(attr, NameKind::Attribute, from);
+            }
+            ExprKind::Call {
+                func,
+                args,
+                keywords,
+            } => {
+                self.callee(func);
+                self.exprs(args);
+                self.keywords(keywords);
+            }
+            ExprKind::Lambda { args, body } => {
+                self.arguments(args);
+                self.expr(body);
+            }
+            ExprKind::BoolOp { values, .. } => self.exprs(values),
+            ExprKind::NamedExpr { target, value } => {
+                self.expr(target);
+                self.expr(value);
+            }
+            ExprKind::BinOp { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            ExprKind::UnaryOp { operand, .. } => self.expr(operand),
+            ExprKind::IfExp { test, body, orelse } => {
+                self.expr(test);
+                self.expr(body);
+                self.expr(orelse);
+            }
+            ExprKind::Dict { keys, values } => {
+                self.exprs(keys);
+                self.exprs(values);
+            }
+            ExprKind::Set { elts } | ExprKind::List { elts, .. } | ExprKind::Tuple { elts, .. } => {
+                self.exprs(elts)
+            }
+            ExprKind::ListComp { elt, generators }
+            | ExprKind::SetComp { elt, generators }
+            | ExprKind::GeneratorExp { elt, generators } => {
+                self.expr(elt);
+                self.comprehensions(generators);
+            }
+            ExprKind::DictComp {
+                key,
+                value,
+                generators,
+            } => {
+                self.expr(key);
+                self.expr(value);
+                self.comprehensions(generators);
+            }
+            ExprKind::Await { value }
+            | ExprKind::YieldFrom { value }
+            | ExprKind::Starred { value, .. } => self.expr(value),
+            ExprKind::Yield { value } => self.opt_expr(value),
+            ExprKind::Compare {
+                left, comparators, ..
+            } => {
+                self.expr(left);
+                self.exprs(comparators);
+            }
+            ExprKind::Subscript { value, slice, .. } => {
+                self.expr(value);
+                self.expr(slice);
+            }
+            ExprKind::Slice { lower, upper, step } => {
+                self.opt_expr(lower);
+                self.opt_expr(upper);
+                self.opt_expr(step);
+            }
+            // the expressions of f-strings aren't located within the source
+            ExprKind::JoinedStr { .. } | ExprKind::FormattedValue { .. } => {}
+            ExprKind::Constant { .. } => {}
+        }
+    }
+}
+
+/// Names of the module `suite` parsed from `source` in the order they
+/// occur, classified by how each is used
+pub fn name_tokens(source: &str, suite: &Suite) -> Vec<NameToken> {
+    let mut collector = Collector {
+        locations: NameLocations::new(source),
+        tokens: vec![],
+    };
+    collector.stmts(suite);
+    let mut rv = collector.tokens;
+    rv.sort_by_key(|x| x.location);
+    rv
+}
+
+/// Body of a function or class definition, in which names are bound
+#[derive(Debug, Clone)]
+struct Scope {
+    name: String,
+    class: bool,
+    location: Location,
+    end_location: Location,
+    parent: Option<usize>,
+}
+
+impl Scope {
+    fn contains(&self, location: Location) -> bool {
+        self.location <= location && location <= self.end_location
+    }
+}
+
+/// Scopes of the function and class definitions of `suite`, each following
+/// the scope containing it
+fn scopes(suite: &Suite) -> Vec<Scope> {
+    let mut stmts = vec![];
+    flatten_body(suite, &mut stmts);
+    let mut rv: Vec<Scope> = vec![];
+    for stmt in stmts {
+        let (name, class) = match &stmt.node {
+            StmtKind::FunctionDef { name, .. } | StmtKind::AsyncFunctionDef { name, .. } => {
+                (name, false)
+            }
+            StmtKind::ClassDef { name, .. } => (name, true),
+            _ => continue,
+        };
+        let parent = rv.iter().rposition(|x| x.contains(stmt.location));
+        rv.push(Scope {
+            name: name.clone(),
+            class,
+            location: stmt.location,
+            end_location: stmt.end_location.unwrap_or(stmt.location),
+            parent,
+        });
+    }
+    rv
+}
+
+/// Names of a source resolved to the scopes binding them
+pub struct ResolvedNames {
+    tokens: Vec<NameToken>,
+    scopes: Vec<Scope>,
+    /// Scope each token occurs in, the name of a definition occurring in
+    /// the scope containing the definition
+    token_scopes: Vec<Option<usize>>,
+}
+
+impl ResolvedNames {
+    pub fn new(source: &str, suite: &Suite) -> Self {
+        let tokens = name_tokens(source, suite);
+        let scopes = scopes(suite);
+        let token_scopes = tokens
+            .iter()
+            .map(|token| {
+                let innermost = scopes.iter().rposition(|x| x.contains(token.location));
+                match innermost {
+                    Some(x) if token.definition && scopes[x].name == token.name => scopes[x].parent,
+                    x => x,
+                }
+            })
+            .collect();
+        Self {
+            tokens,
+            scopes,
+            token_scopes,
+        }
+    }
+
+    /// Index of the token at `location`, a location just past a name taken
+    /// as being on it
+    pub fn token_at(&self, location: Location) -> Option<usize> {
+        self.tokens
+            .iter()
+            .position(|x| x.location <= location && location <= x.end_location)
+    }
+
+    fn binds(&self, scope: Option<usize>, name: &str) -> bool {
+        let mut bound = false;
+        for (token, token_scope) in self.tokens.iter().zip(self.token_scopes.iter()) {
+            if *token_scope != scope || token.name != name {
+                continue;
+            }
+            if token.declaration {
+                return false;
+            }
+            bound |= token.kind == NameKind::Write;
+        }
+        bound
+    }
+
+    /// Scope binding the name of the token at `idx`, `None` being the
+    /// module. Names within functions aren't resolved to the scope of a
+    /// class containing the function, as in python
+    pub fn resolve(&self, idx: usize) -> Option<usize> {
+        let name = self.tokens[idx].name.as_str();
+        let start = self.token_scopes[idx];
+        let mut scope = start;
+        while let Some(x) = scope {
+            let visible = !self.scopes[x].class || scope == start;
+            if visible && self.binds(scope, name) {
+                return scope;
+            }
+            scope = self.scopes[x].parent;
+        }
+        None
+    }
+
+    pub fn token(&self, idx: usize) -> &NameToken {
+        &self.tokens[idx]
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_class(&self, scope: usize) -> bool {
+        self.scopes[scope].class
+    }
+
+    /// Locations of the start and end of the definition of `scope`
+    pub fn scope_range(&self, scope: usize) -> (Location, Location) {
+        (self.scopes[scope].location, self.scopes[scope].end_location)
+    }
+
+    /// Index of the token naming `scope` in its definition
+    pub fn scope_name(&self, scope: usize) -> Option<usize> {
+        self.tokens
+            .iter()
+            .enumerate()
+            .find(|(_, x)| x.definition && self.scopes[scope].contains(x.location))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Scope defined by the definition named by the token at `idx`
+    pub fn defined_scope(&self, idx: usize) -> Option<usize> {
+        let token = &self.tokens[idx];
+        if !token.definition {
+            return None;
+        }
+        self.scopes
+            .iter()
+            .rposition(|x| x.contains(token.location))
+            .filter(|x| self.scopes[*x].name == token.name)
+    }
+
+    /// Innermost function containing the token at `idx`, classes being
+    /// skipped over
+    pub fn enclosing_function(&self, idx: usize) -> Option<usize> {
+        let mut scope = self.token_scopes[idx];
+        while let Some(x) = scope {
+            if !self.scopes[x].class {
+                return Some(x);
+            }
+            scope = self.scopes[x].parent;
+        }
+        None
+    }
+
+    /// Scopes of the functions and classes defined as the variable of the
+    /// token at `idx`, in the order they're defined
+    pub fn definitions(&self, idx: usize) -> Vec<usize> {
+        let token = &self.tokens[idx];
+        if !matches!(token.kind, NameKind::Read | NameKind::Write) {
+            return vec![];
+        }
+        let binding = self.resolve(idx);
+        self.scopes
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.name == token.name && x.parent == binding)
+            .map(|(scope, _)| scope)
+            .collect()
+    }
+
+    /// Tokens of variables named `name` bound by `scope`
+    pub fn variables(&self, name: &str, scope: Option<usize>) -> Vec<&NameToken> {
+        self.tokens
+            .iter()
+            .enumerate()
+            .filter(|(x, token)| {
+                token.name == name
+                    && matches!(token.kind, NameKind::Read | NameKind::Write)
+                    && self.resolve(*x) == scope
+            })
+            .map(|(_, token)| token)
+            .collect()
+    }
+
+    /// Attributes or keywords named `name`, as `kind` is either
+    pub fn named(&self, name: &str, kind: NameKind) -> Vec<&NameToken> {
+        self.tokens
+            .iter()
+            .filter(|x| x.name == name && x.kind == kind)
+            .collect()
+    }
+
+    /// Tokens referring to the same variable as the token at `idx`, or the
+    /// attributes or keywords of the same name if it's either
+    pub fn occurrences(&self, idx: usize) -> Vec<&NameToken> {
+        let target = &self.tokens[idx];
+        match target.kind {
+            NameKind::Read | NameKind::Write => self.variables(&target.name, self.resolve(idx)),
+            kind => self.named(&target.name, kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::rustpython_parser::parser;
+
+    fn kinds(source: &str) -> Vec<(String, NameKind)> {
+        let suite = parser::parse_program(source, "<filename>").unwrap();
+        name_tokens(source, &suite)
+            .into_iter()
+            .map(|x| (x.name, x.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_name_tokens() {
+        use NameKind::*;
+        let name = |x: &str, kind| (x.to_string(), kind);
+        assert_eq!(
+            kinds("a, b = c.d, e\nx: int = a\ny += x[i]\n"),
+            vec![
+                name("a", Write),
+                name("b", Write),
+                name("c", Read),
+                name("d", Attribute),
+                name("e", Read),
+                name("x", Write),
+                name("int", Read),
+                name("a", Read),
+                name("y", Write),
+                name("x", Read),
+                name("i", Read),
+            ]
+        );
+        assert_eq!(
+            kinds("import os.path as p\ndef f(a, b=c):\n    return g(a, key=b)\n"),
+            vec![
+                name("os", Read),
+                name("path", Attribute),
+                name("p", Write),
+                name("f", Write),
+                name("a", Write),
+                name("b", Write),
+                name("c", Read),
+                name("g", Read),
+                name("a", Read),
+                name("key", Keyword),
+                name("b", Read),
+            ]
+        );
+        assert_eq!(
+            kinds("for i, v in items:\n    k = lambda z: z\nif (n := v):\n    pass\n"),
+            vec![
+                name("i", Write),
+                name("v", Write),
+                name("items", Read),
+                name("k", Write),
+                name("z", Write),
+                name("z", Read),
+                name("n", Write),
+                name("v", Read),
+            ]
+        );
+        // subscripted targets only read their operands
+        assert_eq!(
+            kinds("x[i] = v\nwith open(p) as (f, g):\n    pass\n"),
+            vec![
+                name("x", Read),
+                name("i", Read),
+                name("v", Read),
+                name("open", Read),
+                name("p", Read),
+                name("f", Write),
+                name("g", Write),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let source = "\
+x = 1
+def f(y):
+    x = y
+    return x
+def g():
+    global x
+    x = 2
+print(x)
+";
+        let suite = parser::parse_program(source, "<filename>").unwrap();
+        let names = ResolvedNames::new(source, &suite);
+        let rows = |location: Location| {
+            let idx = names.token_at(location).unwrap();
+            names
+                .occurrences(idx)
+                .into_iter()
+                .map(|x| (x.location.row(), x.kind))
+                .collect::<Vec<_>>()
+        };
+        let module = vec![
+            (1, NameKind::Write),
+            (6, NameKind::Read),
+            (7, NameKind::Write),
+            (8, NameKind::Read),
+        ];
+        assert_eq!(rows(Location::new(1, 0)), module);
+        assert_eq!(rows(Location::new(8, 7)), module);
+        // the local of `f` is distinct from the global
+        assert_eq!(
+            rows(Location::new(4, 12)),
+            vec![(3, NameKind::Write), (4, NameKind::Read)]
+        );
+        assert_eq!(
+            rows(Location::new(2, 6)),
+            vec![(2, NameKind::Write), (3, NameKind::Read)]
+        );
+        assert!(names.token_at(Location::new(2, 0)).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::positions::BufferPosition;
+use ruffd_types::lsp_types;
+use ruffd_types::ruff::checks::Check;
+use ruffd_types::rustpython_ast::Location;
+use std::collections::HashMap;
+
+/// Rows of the concatenated source taken by a cell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellSpan {
+    pub uri: lsp_types::Url,
+    /// First row of the cell, 0-indexed
+    pub start_row: usize,
+    pub row_count: usize,
+}
+
+/// Source of a notebook's code cells concatenated such that it can be
+/// linted as a single module, as cells share a namespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotebookSource {
+    pub source: String,
+    pub cells: Vec<CellSpan>,
+}
+
+/// Code of a line without its trailing comment, a `#` within a string
+/// literal not starting one
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '#' => return &line[..idx],
+            None => {}
+        }
+    }
+    line
+}
+
+/// Determines whether a line is an IPython line magic, shell escape or help
+/// query, none of which are valid python
+///
+/// A help query's `?` follows the last code token, so code whose trailing
+/// comment ends in `?` isn't one
+fn is_magic(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.starts_with('%')
+        || trimmed.starts_with('!')
+        || strip_comment(trimmed).trim_end().ends_with('?')
+}
+
+/// Comments out a line, keeping its indentation
+fn comment_out(line: &str) -> String {
+    let indent = line.len() - line.trim_start().len();
+    format!("{}#{}", &line[..indent], &line[indent..])
+}
+
+impl NotebookSource {
+    /// Concatenates the text of code cells in order, commenting out magics
+    ///
+    /// Cells whose first line is a cell magic such as `%%bash` may not hold
+    /// python, so are commented out entirely
+    pub fn from_cells<'a, I>(cells: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a lsp_types::Url, String)>,
+    {
+        let mut source = String::new();
+        let mut spans = vec![];
+        let mut start_row = 0;
+        for (uri, text) in cells {
+            let lines = text.lines().collect::<Vec<_>>();
+            let cell_magic = lines
+                .first()
+                .map(|x| x.trim_start().starts_with("%%"))
+                .unwrap_or(false);
+            for line in lines.iter() {
+                if cell_magic || is_magic(line) {
+                    source.push_str(&comment_out(line));
+                } else {
+                    source.push_str(line);
+                }
+                source.push('\n');
+            }
+            spans.push(CellSpan {
+                uri: uri.clone(),
+                start_row,
+                row_count: lines.len(),
+            });
+            start_row += lines.len();
+        }
+        Self {
+            source,
+            cells: spans,
+        }
+    }
+
+    /// Finds the cell containing the 0-indexed `row` of the concatenated
+    /// source
+    pub fn cell_of_row(&self, row: usize) -> Option<&CellSpan> {
+        self.cells
+            .iter()
+            .find(|x| x.start_row <= row && row < x.start_row + x.row_count)
+    }
+
+    /// Splits checks on the concatenated source into checks on each cell,
+    /// with locations relative to the cell
+    ///
+    /// Every cell has an entry, such that cells without checks are cleared
+    pub fn split_checks(&self, checks: Vec<Check>) -> HashMap<lsp_types::Url, Vec<Check>> {
+        let mut rv = self
+            .cells
+            .iter()
+            .map(|x| (x.uri.clone(), vec![]))
+            .collect::<HashMap<_, _>>();
+        for mut check in checks {
+            let span = match self.cell_of_row(BufferPosition::from(check.location).row) {
+                Some(x) => x,
+                None => continue,
+            };
+            check.location = cell_location(span, check.location).unwrap();
+            check.end_location = cell_location(span, check.end_location)
+                .unwrap_or_else(|| BufferPosition::new(span.row_count, 0).into());
+            // a fix reaching into another cell can't be applied to this one
+            check.fix = check.fix.and_then(|mut fix| {
+                fix.patch.location = cell_location(span, fix.patch.location)?;
+                fix.patch.end_location = cell_location(span, fix.patch.end_location)?;
+                Some(fix)
+            });
+            rv.get_mut(&span.uri).unwrap().push(check);
+        }
+        rv
+    }
+}
+
+/// Offsets a location of the concatenated source to be relative to the
+/// cell, returning `None` if it lies outside of the cell
+///
+/// The start of the row following the cell is the end of the cell, and so
+/// considered within it
+fn cell_location(span: &CellSpan, location: Location) -> Option<Location> {
+    let position = BufferPosition::from(location);
+    let row = position.row.checked_sub(span.start_row)?;
+    if row < span.row_count || (row == span.row_count && position.col == 0) {
+        Some(BufferPosition::new(row, position.col).into())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::ruff::check;
+    use std::path::PathBuf;
+
+    fn cell_uri(idx: usize) -> lsp_types::Url {
+        lsp_types::Url::parse(&format!("vscode-notebook-cell:/nb.ipynb#c{}", idx)).unwrap()
+    }
+
+    #[test]
+    fn test_concatenate_magics() {
+        let uris = (0..3).map(cell_uri).collect::<Vec<_>>();
+        let cells = vec![
+            (
+                &uris[0],
+                "%matplotlib inline\nimport os\n  !ls\nhelp?".to_string(),
+            ),
+            (&uris[1], "%%bash\necho hi".to_string()),
+            (
+                &uris[2],
+                "# why?\nx = 1  # why?\nif x:  # ok?\n    os.path?  # docs".to_string(),
+            ),
+        ];
+        let notebook = NotebookSource::from_cells(cells);
+        let expected = [
+            "#%matplotlib inline",
+            "import os",
+            "  #!ls",
+            "#help?",
+            "#%%bash",
+            "#echo hi",
+            "# why?",
+            "x = 1  # why?",
+            "if x:  # ok?",
+            "    #os.path?  # docs",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(notebook.source, expected);
+        assert_eq!(notebook.cell_of_row(3).unwrap().uri, uris[0]);
+        assert_eq!(notebook.cell_of_row(4).unwrap().uri, uris[1]);
+        assert_eq!(notebook.cell_of_row(9).unwrap().uri, uris[2]);
+        assert!(notebook.cell_of_row(10).is_none());
+    }
+
+    #[test]
+    fn test_is_magic() {
+        assert!(is_magic("os.path??"));
+        assert!(is_magic("  x?  # docs"));
+        // the `?` ends a comment or string rather than the code
+        assert!(!is_magic("x = 1  # why?"));
+        assert!(!is_magic("s = '# why?'"));
+        assert!(!is_magic("s = \"it's\"  # ok?"));
+        assert!(!is_magic("# why?"));
+    }
+
+    #[test]
+    fn test_empty_cells() {
+        let uris = (0..4).map(cell_uri).collect::<Vec<_>>();
+        let cells = vec![
+            (&uris[0], "".to_string()),
+            (&uris[1], "x = 1\n".to_string()),
+            (&uris[2], "".to_string()),
+            (&uris[3], "y = 2".to_string()),
+        ];
+        let notebook = NotebookSource::from_cells(cells);
+        assert_eq!(notebook.source, "x = 1\ny = 2\n");
+        assert_eq!(notebook.cell_of_row(0).unwrap().uri, uris[1]);
+        assert_eq!(notebook.cell_of_row(1).unwrap().uri, uris[3]);
+        assert_eq!(notebook.cells[2].row_count, 0);
+    }
+
+    #[test]
+    fn test_split_checks() {
+        let uris = (0..3).map(cell_uri).collect::<Vec<_>>();
+        let cells = vec![
+            (&uris[0], "%load_ext autoreload\nimport os".to_string()),
+            (&uris[1], "".to_string()),
+            (&uris[2], "x = 1\nimport sys".to_string()),
+        ];
+        let notebook = NotebookSource::from_cells(cells);
+        let checks = check(&PathBuf::from("/nb.ipynb"), &notebook.source, true).unwrap();
+        let split = notebook.split_checks(checks);
+        assert_eq!(split.len(), 3);
+        assert!(split[&uris[1]].is_empty());
+        let first = &split[&uris[0]];
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].location, Location::new(2, 0));
+        let last = &split[&uris[2]];
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].location, Location::new(2, 0));
+        let fix = last[0].fix.as_ref().unwrap();
+        assert_eq!(fix.patch.location.row(), 2);
+    }
+}
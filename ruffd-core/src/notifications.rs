@@ -1,88 +1,829 @@
-use crate::server_ops::run_diagnostic_op;
+use crate::fs::{buffered_content, effective_content};
+use crate::progress::{self, WorkspaceStatus};
+use crate::ruff_utils::{registry_diagnostics, SettingsScope};
+#[cfg(feature = "notebook")]
+use crate::server_ops::run_notebook_diagnostic_op;
+use crate::server_ops::{
+    apply_fix_all, fix_all_in_place, lint_in_place, pulls_diagnostics, reload_project_config,
+    remove_spill, replace_config, run_configuration_pull_op, run_diagnostic_op,
+    run_extend_index_op, run_publish_diagnostics_op, run_register_capability_op,
+    run_saved_diagnostic_op, schedule_relint, shift_checks, ScheduleDiagnostics,
+};
+use crate::workspace::{collect_python_files, renamed_uri};
+#[cfg(feature = "watch")]
+use crate::workspace::{is_pyproject_uri, is_python_uri, is_under};
 use ruffd_macros::notification;
+#[cfg(feature = "watch")]
+use ruffd_types::capabilities::supports_watched_files_registration;
+use ruffd_types::capabilities::{
+    supports_apply_edit, supports_document_changes, supports_work_done_progress,
+};
+use ruffd_types::extensions::IndexingStage;
+#[cfg(feature = "watch")]
+use ruffd_types::extensions::WatcherStatus;
+use ruffd_types::layered::ResolvedConfig;
 use ruffd_types::lsp_types;
-use ruffd_types::tokio::task;
-use ruffd_types::{DocumentBuffer, Notification, RuntimeError, ScheduledTask, ServerInitiated};
+#[cfg(feature = "watch")]
+use ruffd_types::lsp_types::notification::DidChangeWatchedFiles;
+use ruffd_types::lsp_types::notification::{
+    DidChangeConfiguration, DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    DidRenameFiles, DidSaveTextDocument, Initialized, WillSaveTextDocument, WorkDoneProgressCancel,
+};
+#[cfg(feature = "notebook")]
+use ruffd_types::notebook::{
+    DidChangeNotebookDocument, DidChangeNotebookDocumentParams, DidCloseNotebookDocument,
+    DidCloseNotebookDocumentParams, DidOpenNotebookDocument, DidOpenNotebookDocumentParams,
+    NotebookCellKind,
+};
+use ruffd_types::project::RunMode;
+#[cfg(feature = "watch")]
+use ruffd_types::serde_json;
+use ruffd_types::tasks::{spawn_blocking_named, spawn_named, spawn_or_inline};
+use ruffd_types::uri::{normalize_uri, path_to_uri, uri_to_path};
+#[cfg(feature = "notebook")]
+use ruffd_types::Notebook;
+use ruffd_types::{
+    evict_checks, notification_entry, prune_closed_checks, DocumentBuffer, DocumentStatus,
+    FixOnSave, Notification, PositionBounds, RuntimeError, Scheduler, ServerConfig,
+    ServerInitiated,
+};
+use ruffd_types::{log_debug, log_error, log_warn};
+#[cfg(feature = "watch")]
+use ruffd_types::{CheckRegistry, ConfigSnapshot};
+#[cfg(feature = "notebook")]
+use std::cmp;
 use std::collections::HashMap;
+use std::time::Duration;
 
-#[notification]
-fn initialized_notif() -> Result<(), RuntimeError> {
-    Ok(())
+fn supports_configuration_pull(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|x| x.configuration)
+        .unwrap_or(false)
+}
+
+fn supports_configuration_registration(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|x| x.did_change_configuration.as_ref())
+        .and_then(|x| x.dynamic_registration)
+        .unwrap_or(false)
 }
 
-#[notification(mut open_buffers)]
-fn document_did_open(doc_info: lsp_types::DidOpenTextDocumentParams) -> Result<(), RuntimeError> {
-    let key = doc_info.text_document.uri;
-    let key_clone = key.clone();
-    let val = DocumentBuffer::from_string(doc_info.text_document.text);
-    open_buffers.insert(key, val);
-    task::spawn(async move {
-        let diagnostic_op = run_diagnostic_op(key_clone);
-        _scheduler_channel
-            .send(ScheduledTask::Server(ServerInitiated::Notification(
-                diagnostic_op,
-            )))
+/// Registers for configuration and watched file changes and indexes the
+/// workspace, unless its index was restored from a warm cache
+///
+/// Deterministic sessions index inline, without creating a progress token
+/// as that awaits the client
+#[notification(client_capabilities, workspace_index, mut config_snapshot)]
+async fn initialized_notif(scheduler: Scheduler) -> Result<(), RuntimeError> {
+    if let Err(notification) = reload_project_config(&config_snapshot) {
+        scheduler.notify_client(notification);
+    }
+    let pull_config = supports_configuration_pull(&client_capabilities);
+    let mut registrations = vec![];
+    // clients using the pull model only notify of configuration changes
+    // once registered for them
+    if pull_config && supports_configuration_registration(&client_capabilities) {
+        registrations.push(lsp_types::Registration {
+            id: "ruffd/didChangeConfiguration".to_string(),
+            method: "workspace/didChangeConfiguration".to_string(),
+            register_options: None,
+        });
+    }
+    #[cfg(feature = "watch")]
+    if supports_watched_files_registration(&client_capabilities) {
+        config_snapshot.update(|x| x.watcher = WatcherStatus::Pending);
+        registrations.push(lsp_types::Registration {
+            id: "ruffd/didChangeWatchedFiles".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: Some(serde_json::json!({
+                "watchers": [
+                    {"globPattern": "**/*.{py,pyi}"},
+                    {"globPattern": "**/pyproject.toml"},
+                ]
+            })),
+        });
+    }
+    let root_path = match workspace_index.is_empty() {
+        true => config_snapshot
+            .load()
+            .project_root
+            .as_ref()
+            .and_then(uri_to_path),
+        false => {
+            log_debug!(
+                "indexed {} files from the warm cache",
+                workspace_index.len()
+            );
+            None
+        }
+    };
+    drop(workspace_index);
+    let deterministic = config_snapshot.load().deterministic;
+    let progress = supports_work_done_progress(&client_capabilities) && !deterministic;
+    let index = async move {
+        let mut tasks: Vec<ServerInitiated> = vec![];
+        if let Some(root_path) = root_path {
+            let token = match progress {
+                true => progress::create_token(&scheduler, "index").await,
+                false => None,
+            };
+            let status = WorkspaceStatus::begin(scheduler.clone(), IndexingStage::Indexing, token);
+            let index_files = spawn_blocking_named(
+                || "collect files".to_string(),
+                move || {
+                    collect_python_files(&root_path)
+                        .into_iter()
+                        .filter_map(|x| path_to_uri(&x))
+                        .collect::<Vec<_>>()
+                },
+            )
             .await
-            .ok()
-            .unwrap();
-    });
+            .unwrap_or_default();
+            let count = index_files.len();
+            tasks.push(run_extend_index_op(index_files).into());
+            status.end(count, count, format!("indexed {} files", count));
+        }
+        if !registrations.is_empty() {
+            tasks.push(run_register_capability_op(registrations).into());
+        }
+        if pull_config {
+            tasks.push(run_configuration_pull_op().into());
+        }
+        scheduler.schedule_all(tasks);
+    };
+    spawn_or_inline(deterministic, || "index workspace".to_string(), index).await;
     Ok(())
 }
 
-#[notification(mut open_buffers)]
+#[notification(client_capabilities, mut checks, mut config_snapshot, mut relint_pending)]
+fn workspace_did_change_configuration(
+    scheduler: Scheduler,
+    params: lsp_types::DidChangeConfigurationParams,
+) -> Result<(), RuntimeError> {
+    if params.settings.is_null() || supports_configuration_pull(&client_capabilities) {
+        // settings in the notification are unreliable when the client
+        // supports pulling, so they are always re-pulled
+        scheduler.schedule(run_configuration_pull_op());
+    } else {
+        let mut new_config =
+            ServerConfig::from_value(params.settings).map_err(RuntimeError::InvalidSettings)?;
+        new_config.inherit_profile(&config_snapshot.load().config);
+        replace_config(new_config, &mut checks, &config_snapshot);
+        schedule_relint(&scheduler, &mut relint_pending);
+    }
+    Ok(())
+}
+
+/// Re-lints a document once saved, reading the saved text from disk if
+/// neither the client included it nor the document is buffered
+///
+/// The text is read inline if `inline`, as in deterministic sessions
+async fn schedule_saved_diagnostic_op(
+    scheduler: &Scheduler,
+    uri: lsp_types::Url,
+    text: Option<String>,
+    inline: bool,
+) {
+    let scheduler = scheduler.clone();
+    let name = format!("saved diagnostics {}", uri);
+    let read = async move {
+        let text = match effective_content(&uri, text).await {
+            Ok(x) => x,
+            Err(err) => {
+                log_error!("{}", err);
+                return;
+            }
+        };
+        scheduler.schedule(run_saved_diagnostic_op(uri, text));
+    };
+    spawn_or_inline(inline, || name, read).await;
+}
+
+/// Opens a document, or replaces its content if it's already open, as some
+/// clients resend `didOpen` on reconnecting or renaming
+///
+/// The content of a reopened document is replaced as an edit of its buffer,
+/// and the checks it had are kept until it's linted again
+#[notification(
+    mut open_buffers,
+    mut document_status,
+    mut cached_diagnostics,
+    mut ast_cache,
+    config_snapshot
+)]
+async fn document_did_open(
+    scheduler: Scheduler,
+    doc_info: lsp_types::DidOpenTextDocumentParams,
+) -> Result<(), RuntimeError> {
+    let key = normalize_uri(&doc_info.text_document.uri);
+    let version = doc_info.text_document.version;
+    let text = doc_info.text_document.text;
+    let previous = document_status.remove(&key);
+    if previous.is_some() {
+        log_warn!("{} opened again, replacing its content", key);
+        // the module of the replaced content may be cached at this version
+        ast_cache.invalidate(&key);
+    }
+    if text.chars().count() > config_snapshot.config.max_document_size {
+        log_warn!("{} is too large to lint as it's edited", key);
+        open_buffers.remove(&key);
+        let mut status = DocumentStatus::opened(version);
+        status.oversized = true;
+        document_status.insert(key.clone(), status);
+        let inline = config_snapshot.deterministic;
+        schedule_saved_diagnostic_op(&scheduler, key, None, inline).await;
+        return Ok(());
+    }
+    let mut diagnostic_ops = vec![];
+    match (open_buffers.get_mut(&key), previous) {
+        (Some(buffer), Some(mut status)) => {
+            let content_hash = buffer.content_hash();
+            buffer.replace(text);
+            if buffer.content_hash() != content_hash {
+                status.changed(version);
+            } else {
+                status.version = version;
+            }
+            document_status.insert(key.clone(), status);
+        }
+        _ => {
+            let val = DocumentBuffer::from_string(text);
+            // diagnostics from a warm cache are published while the document
+            // is linted, provided it hasn't changed since they were computed
+            let cached = cached_diagnostics
+                .remove(&key)
+                .filter(|x| x.content_hash == val.content_hash());
+            open_buffers.insert(key.clone(), val);
+            document_status.insert(key.clone(), DocumentStatus::opened(version));
+            if let Some(cached) = cached {
+                diagnostic_ops.push(run_publish_diagnostics_op(
+                    key.clone(),
+                    cached.diagnostics,
+                    Some(version),
+                ));
+            }
+        }
+    }
+    diagnostic_ops.push(run_diagnostic_op(key));
+    scheduler.schedule_all(diagnostic_ops);
+    Ok(())
+}
+
+/// Closes a document, keeping its checks while it's among the
+/// `recently_closed` most recently closed documents, such that reopening it
+/// is instant
+///
+/// Checks of unsaved changes are cleared, as the changes are discarded, as
+/// are those of documents closed less recently. Everything else kept for
+/// the document, down to its spill and any diagnostics restored from a warm
+/// cache, is dropped
+#[notification(
+    mut open_buffers,
+    mut document_status,
+    mut shadow_buffers,
+    mut ast_cache,
+    mut checks,
+    mut spilled,
+    mut cached_diagnostics,
+    config_snapshot
+)]
+fn document_did_close(
+    scheduler: Scheduler,
+    params: lsp_types::DidCloseTextDocumentParams,
+) -> Result<(), RuntimeError> {
+    let uri = normalize_uri(&params.text_document.uri);
+    let config = &config_snapshot.config;
+    open_buffers.remove(&uri);
+    remove_spill(&uri, config_snapshot.spill_dir.as_deref(), &mut spilled);
+    let unsaved = matches!(document_status.remove(&uri), Some(x) if x.dirty);
+    shadow_buffers.remove(&uri);
+    cached_diagnostics.remove(&uri);
+    ast_cache.invalidate(&uri);
+    let mut cleared = vec![];
+    if unsaved {
+        if let Some(registry) = checks.remove(&uri) {
+            if !registry.is_empty() || registry.is_evicted() {
+                cleared.push(uri);
+            }
+        }
+    } else {
+        checks.close(&uri);
+    }
+    cleared.extend(prune_closed_checks(
+        &mut checks,
+        &open_buffers,
+        config.recently_closed,
+    ));
+    let evicted = evict_checks(&mut checks, &open_buffers, config.checks_memory_budget);
+    if evicted > 0 {
+        log_debug!("evicted checks of {} documents", evicted);
+    }
+    if !cleared.is_empty() {
+        schedule_publish_ops(
+            &scheduler,
+            cleared.into_iter().map(|x| (x, vec![])).collect(),
+        );
+    }
+    Ok(())
+}
+
+#[notification(
+    mut open_buffers,
+    mut document_status,
+    mut ast_cache,
+    mut checks,
+    capabilities,
+    config_snapshot
+)]
 fn document_did_change(
+    scheduler: Scheduler,
     doc_info: lsp_types::DidChangeTextDocumentParams,
 ) -> Result<(), RuntimeError> {
-    if let Some(buffer) = open_buffers.get_mut(&doc_info.text_document.uri) {
-        for change in doc_info.content_changes.iter() {
-            let range = change.range.ok_or(RuntimeError::UnexpectedNone)?;
-            let start = (range.start.line as usize, range.start.character as usize);
-            let end = (range.end.line as usize, range.end.character as usize);
-            buffer.delete_range(start, end)?;
-            buffer.insert_text(change.text.as_str(), start)?;
-        }
-        let uri = doc_info.text_document.uri;
-        task::spawn(async move {
-            let diagnostic_op = run_diagnostic_op(uri);
-            _scheduler_channel
-                .send(ScheduledTask::Server(ServerInitiated::Notification(
-                    diagnostic_op,
-                )))
-                .await
-                .ok()
-                .unwrap();
-        });
+    let uri = normalize_uri(&doc_info.text_document.uri);
+    ast_cache.invalidate(&uri);
+    if let Some(status) = document_status.get_mut(&uri) {
+        status.changed(doc_info.text_document.version);
+        // oversized documents are only linted once saved
+        if status.oversized {
+            return Ok(());
+        }
+    }
+    if let Some(buffer) = open_buffers.get_mut(&uri) {
+        buffer.apply_content_changes(
+            &doc_info.content_changes,
+            config_snapshot.position_encoding,
+            PositionBounds::Clamp,
+        )?;
+        if buffer.len() > config_snapshot.config.max_document_size {
+            log_warn!("{} is too large to lint as it's edited", uri);
+            open_buffers.remove(&uri);
+            if let Some(status) = document_status.get_mut(&uri) {
+                status.oversized = true;
+            }
+            return Ok(());
+        }
+        let publish = !pulls_diagnostics(&capabilities);
+        if let Some(notification) = shift_checks(
+            &uri,
+            &doc_info.content_changes,
+            config_snapshot.position_encoding,
+            Some(doc_info.text_document.version),
+            publish,
+            &config_snapshot.project_config.severity_overrides,
+            &mut checks,
+        ) {
+            scheduler.notify_client(notification);
+        }
+        let version = doc_info.text_document.version;
+        let project_config = &config_snapshot.project_config;
+        let resolved = ResolvedConfig::resolve(&config_snapshot.config.layer, project_config);
+        match resolved.run_mode.value {
+            // checks are shifted along with edits until the next save
+            RunMode::OnSave => {}
+            // debouncing would order diagnostics by the wall clock
+            RunMode::OnType if project_config.debounce > 0 && !config_snapshot.deterministic => {
+                let delay = Duration::from_millis(project_config.debounce);
+                scheduler.schedule_debounced_diagnostics(uri, version, delay);
+            }
+            RunMode::OnType => scheduler.schedule_diagnostics(uri),
+        }
         Ok(())
     } else {
-        Err(RuntimeError::EditUnopenedDocument(
-            doc_info.text_document.uri,
-        ))
+        Err(RuntimeError::EditUnopenedDocument(uri))
+    }
+}
+
+#[notification(
+    open_buffers,
+    capabilities,
+    config_snapshot,
+    mut document_status,
+    mut checks,
+    mut ast_cache
+)]
+async fn document_will_save(
+    scheduler: Scheduler,
+    doc_info: lsp_types::WillSaveTextDocumentParams,
+) -> Result<(), RuntimeError> {
+    let uri = normalize_uri(&doc_info.text_document.uri);
+    if let Some(status) = document_status.get_mut(&uri) {
+        status.will_save();
+        if status.oversized {
+            return Ok(());
+        }
     }
+    match open_buffers.get(&uri) {
+        // linted while holding the checks, such that code actions requested
+        // as part of saving wait on the lint rather than a scheduled op
+        Some(buffer) if config_snapshot.config.lint_on_will_save => {
+            let scope = SettingsScope::from_snapshot(&config_snapshot);
+            let publish = !pulls_diagnostics(&capabilities);
+            let notification = lint_in_place(
+                &uri,
+                buffer,
+                scope,
+                publish,
+                &mut document_status,
+                &mut checks,
+                &mut ast_cache,
+            )
+            .await;
+            if let Some(notification) = notification {
+                scheduler.notify_client(notification);
+            }
+        }
+        _ => scheduler.schedule_diagnostics(uri),
+    }
+    Ok(())
+}
+
+/// Lints the saved document, applying its fixes through
+/// `workspace/applyEdit` if `fixOnSave` is `applyEdit`
+///
+/// The fixes carry the saved version, such that clients accepting
+/// `documentChanges` don't apply them to the document once edited again
+#[notification(
+    open_buffers,
+    capabilities,
+    client_capabilities,
+    config_snapshot,
+    mut document_status,
+    mut checks,
+    mut ast_cache,
+    mut spilled
+)]
+async fn document_did_save(
+    scheduler: Scheduler,
+    doc_info: lsp_types::DidSaveTextDocumentParams,
+) -> Result<(), RuntimeError> {
+    let uri = normalize_uri(&doc_info.text_document.uri);
+    remove_spill(&uri, config_snapshot.spill_dir.as_deref(), &mut spilled);
+    if let Some(status) = document_status.get_mut(&uri) {
+        let announced = status.pending_save;
+        if !status.saved() {
+            log_warn!(
+                "{} saved at version {} but announced at version {}",
+                uri,
+                status.version,
+                announced.unwrap()
+            );
+        }
+    }
+    let buffer = open_buffers
+        .get(&uri)
+        .filter(|_| config_snapshot.config.fix_on_save == FixOnSave::ApplyEdit);
+    if let Some(buffer) = buffer {
+        if supports_apply_edit(&client_capabilities) {
+            let scope = SettingsScope::from_snapshot(&config_snapshot);
+            let publish = !pulls_diagnostics(&capabilities);
+            let (edits, notification) = fix_all_in_place(
+                &uri,
+                buffer,
+                scope,
+                publish,
+                &mut document_status,
+                &mut checks,
+                &mut ast_cache,
+            )
+            .await;
+            if let Some(notification) = notification {
+                scheduler.notify_client(notification);
+            }
+            if !edits.is_empty() {
+                let document = lsp_types::OptionalVersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: document_status.get(&uri).map(|x| x.version),
+                };
+                let document_changes = supports_document_changes(&client_capabilities);
+                spawn_named(
+                    || format!("fix on save {}", uri),
+                    apply_fix_all(scheduler.clone(), document, edits, document_changes, false),
+                );
+            }
+        } else {
+            log_warn!("fixOnSave is applyEdit but the client doesn't apply edits");
+        }
+    }
+    // the buffer of a saved document is its saved text
+    let text = doc_info
+        .text
+        .or_else(|| buffered_content(&uri, &open_buffers));
+    schedule_saved_diagnostic_op(&scheduler, uri, text, config_snapshot.deterministic).await;
+    Ok(())
+}
+
+/// Sends diagnostic publish ops for each uri, diagnostics pair
+fn schedule_publish_ops(
+    scheduler: &Scheduler,
+    mut publish: Vec<(lsp_types::Url, Vec<lsp_types::Diagnostic>)>,
+) {
+    // collected from maps, so ordered such that publishing is reproducible
+    publish.sort_by(|a, b| a.0.cmp(&b.0));
+    scheduler.schedule_all(
+        publish
+            .into_iter()
+            .map(|(uri, diagnostics)| run_publish_diagnostics_op(uri, diagnostics, None)),
+    );
+}
+
+#[cfg(feature = "watch")]
+#[notification(
+    open_buffers,
+    mut checks,
+    mut workspace_index,
+    capabilities,
+    mut config_snapshot,
+    mut relint_pending
+)]
+fn workspace_did_change_watched_files(
+    scheduler: Scheduler,
+    params: lsp_types::DidChangeWatchedFilesParams,
+) -> Result<(), RuntimeError> {
+    let mut reload_settings = false;
+    let mut layout_changed = false;
+    let mut publish = vec![];
+    for mut event in params.changes {
+        event.uri = normalize_uri(&event.uri);
+        if is_pyproject_uri(&event.uri) {
+            reload_settings = true;
+            continue;
+        }
+        // first-party roots are inferred from the packages of a file
+        if event.uri.path().ends_with("/__init__.py")
+            && event.typ != lsp_types::FileChangeType::CHANGED
+        {
+            layout_changed = true;
+        }
+        match event.typ {
+            lsp_types::FileChangeType::CREATED if is_python_uri(&event.uri) => {
+                workspace_index.insert(event.uri);
+            }
+            lsp_types::FileChangeType::DELETED => {
+                // deletion of a folder removes everything below it
+                let removed = workspace_index
+                    .iter()
+                    .filter(|x| is_under(x, &event.uri))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                removed.iter().for_each(|x| {
+                    workspace_index.remove(x);
+                });
+                // open documents still hold content, and so keep diagnostics
+                let cleared = checks
+                    .keys()
+                    .filter(|x| is_under(x, &event.uri) && !open_buffers.contains_key(x))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for uri in cleared {
+                    let registry = checks.remove(&uri).unwrap();
+                    if !registry.is_empty() || registry.is_evicted() {
+                        publish.push((uri, vec![]));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if reload_settings || layout_changed {
+        config_snapshot.update(ConfigSnapshot::settings_changed);
+    }
+    if reload_settings {
+        match reload_project_config(&config_snapshot) {
+            // diagnostics of unchanged checks are republished under the new
+            // severities, ahead of the relint updating them
+            Ok(true) if !pulls_diagnostics(&capabilities) => {
+                let overrides = &config_snapshot.load().project_config.severity_overrides;
+                let republish = checks
+                    .iter()
+                    .filter(|(_, x)| !x.is_empty())
+                    .map(|(uri, x)| (uri.clone(), registry_diagnostics(x, overrides)))
+                    .collect();
+                schedule_publish_ops(&scheduler, republish);
+            }
+            Ok(_) => {}
+            Err(notification) => scheduler.notify_client(notification),
+        }
+        // checks of unchanged content may differ under the new settings
+        checks.values_mut().for_each(CheckRegistry::invalidate);
+        schedule_relint(&scheduler, &mut relint_pending);
+    }
+    schedule_publish_ops(&scheduler, publish);
+    Ok(())
+}
+
+#[notification(open_buffers, mut checks, mut workspace_index, config_snapshot)]
+fn workspace_did_rename_files(
+    scheduler: Scheduler,
+    params: lsp_types::RenameFilesParams,
+) -> Result<(), RuntimeError> {
+    let mut publish = vec![];
+    for file_rename in params.files {
+        let (old, new) = match (
+            lsp_types::Url::parse(&file_rename.old_uri).map(|x| normalize_uri(&x)),
+            lsp_types::Url::parse(&file_rename.new_uri).map(|x| normalize_uri(&x)),
+        ) {
+            (Ok(old), Ok(new)) => (old, new),
+            _ => continue,
+        };
+        let index_renames = workspace_index
+            .iter()
+            .filter_map(|x| renamed_uri(x, &old, &new).map(|y| (x.clone(), y)))
+            .collect::<Vec<_>>();
+        for (from, to) in index_renames {
+            workspace_index.remove(&from);
+            workspace_index.insert(to);
+        }
+        let registry_renames = checks
+            .keys()
+            .filter_map(|x| renamed_uri(x, &old, &new).map(|y| (x.clone(), y)))
+            .collect::<Vec<_>>();
+        for (from, to) in registry_renames {
+            let mut registry = checks.remove(&from).unwrap();
+            // settings may apply differently at the new path
+            registry.invalidate();
+            if !registry.is_empty() {
+                publish.push((from, vec![]));
+            }
+            // a registry from linting the opened renamed document is newer
+            if checks.contains_key(&to) || open_buffers.contains_key(&to) {
+                continue;
+            }
+            let diagnostics = registry_diagnostics(
+                &registry,
+                &config_snapshot.project_config.severity_overrides,
+            );
+            publish.push((to.clone(), diagnostics));
+            checks.insert(to, registry);
+        }
+    }
+    schedule_publish_ops(&scheduler, publish);
+    Ok(())
+}
+
+#[cfg(feature = "notebook")]
+#[notification(mut open_buffers, mut notebooks)]
+fn notebook_did_open(
+    scheduler: Scheduler,
+    params: DidOpenNotebookDocumentParams,
+) -> Result<(), RuntimeError> {
+    let notebook = Notebook::new(
+        params.notebook_document.version,
+        params.notebook_document.cells,
+    );
+    for item in params.cell_text_documents {
+        if notebook.code_cells().any(|x| x == &item.uri) {
+            open_buffers.insert(item.uri, DocumentBuffer::from_string(item.text));
+        }
+    }
+    let uri = normalize_uri(&params.notebook_document.uri);
+    notebooks.insert(uri.clone(), notebook);
+    scheduler.schedule(run_notebook_diagnostic_op(uri));
+    Ok(())
+}
+
+#[cfg(feature = "notebook")]
+#[notification(mut open_buffers, mut notebooks, mut checks, config_snapshot)]
+fn notebook_did_change(
+    scheduler: Scheduler,
+    params: DidChangeNotebookDocumentParams,
+) -> Result<(), RuntimeError> {
+    let uri = normalize_uri(&params.notebook_document.uri);
+    let notebook = notebooks
+        .get_mut(&uri)
+        .ok_or_else(|| RuntimeError::EditUnopenedDocument(uri.clone()))?;
+    notebook.version = params.notebook_document.version;
+    let cells = match params.change.cells {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let mut publish = vec![];
+    if let Some(structure) = cells.structure {
+        let start = cmp::min(structure.array.start as usize, notebook.cells.len());
+        let end = cmp::min(
+            start + structure.array.delete_count as usize,
+            notebook.cells.len(),
+        );
+        notebook
+            .cells
+            .splice(start..end, structure.array.cells.unwrap_or_default());
+        for closed in structure.did_close.unwrap_or_default() {
+            open_buffers.remove(&closed.uri);
+            if let Some(registry) = checks.remove(&closed.uri) {
+                if !registry.is_empty() {
+                    publish.push((closed.uri, vec![]));
+                }
+            }
+        }
+        for item in structure.did_open.unwrap_or_default() {
+            if notebook.code_cells().any(|x| x == &item.uri) {
+                open_buffers.insert(item.uri, DocumentBuffer::from_string(item.text));
+            }
+        }
+    }
+    // markup cells have no buffer, so a cell changing to code is only
+    // linted once reopened, and a cell changing to markup is no longer
+    // linted at all
+    for cell in cells.data.unwrap_or_default() {
+        if let Some(existing) = notebook
+            .cells
+            .iter_mut()
+            .find(|x| x.document == cell.document)
+        {
+            if cell.kind == NotebookCellKind::Markup {
+                open_buffers.remove(&cell.document);
+                if let Some(registry) = checks.remove(&cell.document) {
+                    if !registry.is_empty() {
+                        publish.push((cell.document.clone(), vec![]));
+                    }
+                }
+            }
+            *existing = cell;
+        }
+    }
+    for text_change in cells.text_content.unwrap_or_default() {
+        let cell_uri = text_change.document.uri;
+        if let Some(buffer) = open_buffers.get_mut(&cell_uri) {
+            for change in text_change.changes.iter() {
+                buffer.apply_content_change(
+                    change,
+                    config_snapshot.position_encoding,
+                    PositionBounds::Clamp,
+                )?;
+            }
+        }
+    }
+    // cells share a namespace, so any change may alter every cell's checks
+    schedule_publish_ops(&scheduler, publish);
+    scheduler.schedule(run_notebook_diagnostic_op(uri));
+    Ok(())
+}
+
+#[cfg(feature = "notebook")]
+#[notification(mut open_buffers, mut notebooks, mut checks)]
+fn notebook_did_close(
+    scheduler: Scheduler,
+    params: DidCloseNotebookDocumentParams,
+) -> Result<(), RuntimeError> {
+    let mut cell_uris = params
+        .cell_text_documents
+        .into_iter()
+        .map(|x| x.uri)
+        .collect::<Vec<_>>();
+    if let Some(notebook) = notebooks.remove(&normalize_uri(&params.notebook_document.uri)) {
+        cell_uris.extend(notebook.cells.into_iter().map(|x| x.document));
+    }
+    cell_uris.sort();
+    cell_uris.dedup();
+    let mut publish = vec![];
+    for uri in cell_uris {
+        open_buffers.remove(&uri);
+        if let Some(registry) = checks.remove(&uri) {
+            if !registry.is_empty() {
+                publish.push((uri, vec![]));
+            }
+        }
+    }
+    schedule_publish_ops(&scheduler, publish);
+    Ok(())
 }
 
 #[notification]
-fn document_will_save(doc_info: lsp_types::WillSaveTextDocumentParams) -> Result<(), RuntimeError> {
-    let uri = doc_info.text_document.uri;
-    task::spawn(async move {
-        let diagnostic_op = run_diagnostic_op(uri);
-        _scheduler_channel
-            .send(ScheduledTask::Server(ServerInitiated::Notification(
-                diagnostic_op,
-            )))
-            .await
-            .ok()
-            .unwrap();
-    });
+fn work_done_progress_cancel(
+    params: lsp_types::WorkDoneProgressCancelParams,
+) -> Result<(), RuntimeError> {
+    // progress may have ended before the cancellation arrived
+    if !progress::cancel(&params.token) {
+        log_warn!("no progress to cancel for {:?}", params.token);
+    }
     Ok(())
 }
 
 lazy_static! {
     pub(crate) static ref NOTIFICATION_REGISTRY: HashMap<&'static str, Notification> = {
         let pairs = vec![
-            ("initialized", initialized_notif),
-            ("textDocument/didOpen", document_did_open),
-            ("textDocument/didChange", document_did_change),
-            ("textDocument/willSave", document_will_save),
+            notification_entry::<Initialized>(initialized_notif::typed()),
+            notification_entry::<DidOpenTextDocument>(document_did_open::typed()),
+            notification_entry::<DidChangeTextDocument>(document_did_change::typed()),
+            notification_entry::<DidCloseTextDocument>(document_did_close::typed()),
+            notification_entry::<WillSaveTextDocument>(document_will_save::typed()),
+            notification_entry::<DidSaveTextDocument>(document_did_save::typed()),
+            notification_entry::<DidChangeConfiguration>(
+                workspace_did_change_configuration::typed(),
+            ),
+            #[cfg(feature = "watch")]
+            notification_entry::<DidChangeWatchedFiles>(workspace_did_change_watched_files::typed()),
+            notification_entry::<DidRenameFiles>(workspace_did_rename_files::typed()),
+            #[cfg(feature = "notebook")]
+            notification_entry::<DidOpenNotebookDocument>(notebook_did_open::typed()),
+            #[cfg(feature = "notebook")]
+            notification_entry::<DidChangeNotebookDocument>(notebook_did_change::typed()),
+            #[cfg(feature = "notebook")]
+            notification_entry::<DidCloseNotebookDocument>(notebook_did_close::typed()),
+            notification_entry::<WorkDoneProgressCancel>(work_done_progress_cancel::typed()),
         ];
         pairs
             .into_iter()
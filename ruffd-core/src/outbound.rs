@@ -0,0 +1,185 @@
+//! Ordering of the messages written to the client
+//!
+//! Every outgoing message is queued on a single channel drained by the
+//! sender task, such that frames are written whole and in the order they're
+//! queued. Lints of successive versions of a document run concurrently, so
+//! their diagnostics may still be queued out of order. Diagnostics of a
+//! version older than the last published for the document are dropped
+//! rather than overwriting newer ones on the client.
+//!
+//! Diagnostics equal to those last published for the document are dropped
+//! too, such that handlers needn't track what the client was sent
+use ruffd_types::uri::normalize_uri;
+use ruffd_types::{content_hash, lsp_types, serde_json, Params, RpcMessage, RpcNotification};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub type SharedPublishedVersions = Arc<Mutex<PublishedVersions>>;
+
+/// Version and digest of the diagnostics last published for each document
+#[derive(Debug, Default)]
+pub struct PublishedVersions {
+    versions: HashMap<lsp_types::Url, i32>,
+    digests: HashMap<lsp_types::Url, u64>,
+}
+
+fn message_uri(params: &serde_json::Value) -> Option<lsp_types::Url> {
+    serde_json::from_value::<lsp_types::Url>(params.get("uri")?.clone())
+        .ok()
+        .map(|x| normalize_uri(&x))
+}
+
+impl PublishedVersions {
+    /// Determines whether an outgoing message is to be sent, recording the
+    /// version and digest of the diagnostics it publishes
+    ///
+    /// Diagnostics without a version aren't tied to a document's edits, so
+    /// are only dropped if unchanged. Documents yet to be published for are
+    /// treated as having no diagnostics, such that clean documents don't
+    /// publish on open
+    pub fn admit(&mut self, message: &RpcMessage) -> bool {
+        let params = match message {
+            RpcMessage::Notification(x) if x.method == "textDocument/publishDiagnostics" => {
+                x.params.as_ref().map(Params::to_value)
+            }
+            _ => return true,
+        };
+        let params = params.as_deref();
+        let uri = match params.and_then(message_uri) {
+            Some(x) => x,
+            None => return true,
+        };
+        let version = params
+            .and_then(|x| x.get("version"))
+            .and_then(|x| x.as_i64())
+            .map(|x| x as i32);
+        if let Some(version) = version {
+            match self.versions.get(&uri) {
+                Some(last) if *last > version => return false,
+                _ => {
+                    self.versions.insert(uri.clone(), version);
+                }
+            }
+        }
+        let digest = params
+            .and_then(|x| x.get("diagnostics"))
+            .filter(|x| !matches!(x.as_array(), Some(x) if x.is_empty()))
+            .map(|x| content_hash(&x.to_string()));
+        if self.digests.get(&uri).copied() == digest {
+            return false;
+        }
+        match digest {
+            Some(digest) => self.digests.insert(uri, digest),
+            None => self.digests.remove(&uri),
+        };
+        true
+    }
+
+    /// Forgets the versions published for a document, as once it's closed
+    /// or opened again and its versions may restart
+    pub fn reset(&mut self, uri: &lsp_types::Url) {
+        self.versions.remove(uri);
+    }
+}
+
+/// Document opened or closed by a notification, whose published versions
+/// are reset once the notification is handled
+pub fn synced_uri(notification: &RpcNotification) -> Option<lsp_types::Url> {
+    if notification.method != "textDocument/didOpen"
+        && notification.method != "textDocument/didClose"
+    {
+        return None;
+    }
+    notification
+        .params
+        .as_ref()
+        .map(Params::to_value)
+        .as_deref()
+        .and_then(|x| x.get("textDocument"))
+        .and_then(message_uri)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn notification(method: &str, params: serde_json::Value) -> RpcMessage {
+        RpcNotification::new(method.to_string(), Some(params)).into()
+    }
+
+    fn diagnostic(message: &str) -> serde_json::Value {
+        serde_json::json!({
+            "range": {
+                "start": {"line": 0, "character": 0},
+                "end": {"line": 0, "character": 1}
+            },
+            "message": message
+        })
+    }
+
+    fn publish(version: Option<i32>, message: &str) -> RpcMessage {
+        notification(
+            "textDocument/publishDiagnostics",
+            serde_json::json!({
+                "uri": "file:///tmp/a.py",
+                "diagnostics": [diagnostic(message)],
+                "version": version
+            }),
+        )
+    }
+
+    fn publish_empty(version: Option<i32>) -> RpcMessage {
+        notification(
+            "textDocument/publishDiagnostics",
+            serde_json::json!({
+                "uri": "file:///tmp/a.py", "diagnostics": [], "version": version
+            }),
+        )
+    }
+
+    #[test]
+    fn test_published_versions() {
+        let mut published = PublishedVersions::default();
+        assert!(published.admit(&publish(Some(2), "a")));
+        // stale diagnostics finishing after newer ones are dropped
+        assert!(!published.admit(&publish(Some(1), "b")));
+        assert!(published.admit(&publish(Some(2), "b")));
+        assert!(published.admit(&publish(None, "c")));
+        assert!(published.admit(&publish(Some(3), "d")));
+        assert!(published.admit(&notification("window/logMessage", serde_json::json!({}))));
+        let opened = RpcNotification::new(
+            "textDocument/didOpen".to_string(),
+            Some(serde_json::json!({"textDocument": {
+                "uri": "file:///tmp/a.py", "languageId": "python",
+                "version": 1, "text": ""
+            }})),
+        );
+        published.reset(&synced_uri(&opened).unwrap());
+        assert!(published.admit(&publish(Some(1), "e")));
+        let closed = RpcNotification::new(
+            "textDocument/didClose".to_string(),
+            Some(serde_json::json!({"textDocument": {"uri": "file:///tmp/a.py"}})),
+        );
+        published.reset(&synced_uri(&closed).unwrap());
+        assert!(published.versions.is_empty());
+        let saved = RpcNotification::new(
+            "textDocument/didSave".to_string(),
+            Some(serde_json::json!({"textDocument": {"uri": "file:///tmp/a.py"}})),
+        );
+        assert_eq!(synced_uri(&saved), None);
+    }
+
+    #[test]
+    fn test_published_digests() {
+        let mut published = PublishedVersions::default();
+        // nothing published is equal to publishing no diagnostics
+        assert!(!published.admit(&publish_empty(Some(1))));
+        assert!(published.admit(&publish(Some(1), "a")));
+        assert!(!published.admit(&publish(Some(2), "a")));
+        // the version of dropped diagnostics is still recorded
+        assert!(!published.admit(&publish(Some(1), "b")));
+        assert!(published.admit(&publish(None, "b")));
+        assert!(published.admit(&publish_empty(Some(3))));
+        assert!(!published.admit(&publish_empty(None)));
+    }
+}
@@ -0,0 +1,288 @@
+//! Conversions between the coordinates of ruff, the protocol and buffers
+//!
+//! - ruff's `Location` has 1-indexed rows and 0-indexed char columns
+//! - `lsp_types::Position` has 0-indexed lines and columns, the columns
+//!   being converted to the negotiated encoding by `encode_checks` once a
+//!   source is linted, such that checks are held as the client counts them
+//! - `BufferPosition` has 0-indexed rows and char columns, indexing
+//!   `DocumentBuffer` and the lines of a source
+//!
+//! Each is a distinct type, such that passing one in place of another
+//! doesn't compile, and rows are only ever offset by the conversions here
+use ruffd_types::lsp_types;
+use ruffd_types::ruff::checks::Check;
+use ruffd_types::rustpython_ast::Location;
+use ruffd_types::{EditDelta, PositionEncoding};
+
+/// Row and char column of a source, both 0-indexed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct BufferPosition {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl BufferPosition {
+    pub fn new(row: usize, col: usize) -> Self {
+        Self { row, col }
+    }
+}
+
+impl From<Location> for BufferPosition {
+    fn from(location: Location) -> Self {
+        // rows of parsed locations start at 1, row 0 is clamped to the
+        // first row rather than wrapping
+        Self::new(location.row().saturating_sub(1), location.column())
+    }
+}
+
+impl From<BufferPosition> for Location {
+    fn from(position: BufferPosition) -> Self {
+        Location::new(position.row + 1, position.col)
+    }
+}
+
+impl From<(usize, usize)> for BufferPosition {
+    fn from(row_col: (usize, usize)) -> Self {
+        Self::new(row_col.0, row_col.1)
+    }
+}
+
+impl From<BufferPosition> for (usize, usize) {
+    fn from(position: BufferPosition) -> Self {
+        (position.row, position.col)
+    }
+}
+
+impl From<lsp_types::Position> for BufferPosition {
+    fn from(position: lsp_types::Position) -> Self {
+        Self::new(position.line as usize, position.character as usize)
+    }
+}
+
+impl From<BufferPosition> for lsp_types::Position {
+    fn from(position: BufferPosition) -> Self {
+        lsp_types::Position {
+            line: position.row as u32,
+            character: position.col as u32,
+        }
+    }
+}
+
+pub fn position_from_location(location: Location) -> lsp_types::Position {
+    BufferPosition::from(location).into()
+}
+
+pub fn location_from_position(position: lsp_types::Position) -> Location {
+    BufferPosition::from(position).into()
+}
+
+/// Range spanning from `start` to `end` of a ruff check or fix
+pub fn range_from_locations(start: Location, end: Location) -> lsp_types::Range {
+    lsp_types::Range {
+        start: position_from_location(start),
+        end: position_from_location(end),
+    }
+}
+
+/// Converts the char column of `location` within `lines` to `encoding`,
+/// columns past the end of their line being kept as is
+fn encode_location(lines: &[&str], location: Location, encoding: PositionEncoding) -> Location {
+    let position = BufferPosition::from(location);
+    let line = match lines.get(position.row) {
+        Some(x) if !x.is_ascii() => x,
+        _ => return location,
+    };
+    let (chars, units) = line
+        .chars()
+        .take(position.col)
+        .fold((0, 0), |(chars, units), c| {
+            (chars + 1, units + encoding.char_len(c))
+        });
+    BufferPosition::new(position.row, units + position.col - chars).into()
+}
+
+/// Range from `start` to `end` of the char columns of `lines`, converted to
+/// `encoding`
+pub fn encode_range(
+    lines: &[&str],
+    start: Location,
+    end: Location,
+    encoding: PositionEncoding,
+) -> lsp_types::Range {
+    range_from_locations(
+        encode_location(lines, start, encoding),
+        encode_location(lines, end, encoding),
+    )
+}
+
+/// Converts the char columns ruff reports the checks of `source` at, along
+/// with those of their fixes, to `encoding`
+///
+/// Only lines holding non-ASCII text differ between encodings, such as
+/// those with emoji or CJK text, so other lines are left untouched
+pub fn encode_checks(checks: &mut [Check], source: &str, encoding: PositionEncoding) {
+    if encoding == PositionEncoding::Utf32 || checks.is_empty() || source.is_ascii() {
+        return;
+    }
+    let lines = source.lines().collect::<Vec<_>>();
+    for check in checks.iter_mut() {
+        check.location = encode_location(&lines, check.location, encoding);
+        check.end_location = encode_location(&lines, check.end_location, encoding);
+        if let Some(fix) = check.fix.as_mut() {
+            fix.patch.location = encode_location(&lines, fix.patch.location, encoding);
+            fix.patch.end_location = encode_location(&lines, fix.patch.end_location, encoding);
+        }
+    }
+}
+
+/// Position of the end of `text` inserted at `start`, columns counted in
+/// the units of `encoding`
+fn text_end(start: BufferPosition, text: &str, encoding: PositionEncoding) -> BufferPosition {
+    let mut rv = start;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                rv.row += 1;
+                rv.col = 0;
+            }
+            _ => rv.col += encoding.char_len(c),
+        }
+    }
+    rv
+}
+
+/// Delta of a change as sent in `textDocument/didChange`, in the units the
+/// client counts, `None` for a change replacing the whole document
+///
+/// Checks are held in the same units once encoded, such that they're
+/// shifted by the delta without the text
+pub fn edit_delta_from_change(
+    change: &lsp_types::TextDocumentContentChangeEvent,
+    encoding: PositionEncoding,
+) -> Option<EditDelta> {
+    let range = change.range?;
+    let start = BufferPosition::from(range.start);
+    let end = BufferPosition::from(range.end);
+    let new_end = text_end(start, &change.text, encoding);
+    Some(EditDelta::new(start.into(), end.into(), new_end.into()))
+}
+
+/// Shifts a check along with the text it was reported at, dropping its fix
+/// if the change edits the text the fix replaces. Returns false if the
+/// change removed the text of the check
+pub fn shift_check(check: &mut Check, delta: &EditDelta) -> bool {
+    let start = BufferPosition::from(check.location).into();
+    let end = BufferPosition::from(check.end_location).into();
+    if delta.removes(start, end) && start < end {
+        return false;
+    }
+    let shift = |x: Location| -> Location {
+        BufferPosition::from(delta.map(BufferPosition::from(x).into())).into()
+    };
+    check.location = shift(check.location);
+    check.end_location = shift(check.end_location);
+    let fix_edited = check.fix.as_ref().map(|x| {
+        delta.overlaps(
+            BufferPosition::from(x.patch.location).into(),
+            BufferPosition::from(x.patch.end_location).into(),
+        )
+    });
+    match (fix_edited, check.fix.as_mut()) {
+        (Some(true), _) => check.fix = None,
+        (_, Some(fix)) => {
+            fix.patch.location = shift(fix.patch.location);
+            fix.patch.end_location = shift(fix.patch.end_location);
+        }
+        _ => {}
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        for row in 0..4 {
+            for col in 0..4 {
+                let buffer = BufferPosition::new(row, col);
+                let location = Location::from(buffer);
+                let position = lsp_types::Position::from(buffer);
+                assert_eq!(location, Location::new(row + 1, col));
+                assert_eq!(position.line as usize, row);
+                assert_eq!(position.character as usize, col);
+                assert_eq!(BufferPosition::from(location), buffer);
+                assert_eq!(BufferPosition::from(position), buffer);
+                assert_eq!(position_from_location(location), position);
+                assert_eq!(location_from_position(position), location);
+            }
+        }
+        assert_eq!(
+            BufferPosition::from(Location::new(0, 3)),
+            BufferPosition::new(0, 3)
+        );
+        let range = range_from_locations(Location::new(1, 0), Location::new(2, 4));
+        assert_eq!(range.start, lsp_types::Position::new(0, 0));
+        assert_eq!(range.end, lsp_types::Position::new(1, 4));
+    }
+
+    #[test]
+    fn test_encode_checks() {
+        use ruffd_types::ruff::checks::CheckKind;
+        let source = "import os\nx = '🐍'; import sys\n";
+        let check = |row, col, end_col| Check {
+            kind: CheckKind::UnusedImport(vec!["sys".to_string()]),
+            location: Location::new(row, col),
+            end_location: Location::new(row, end_col),
+            fix: None,
+        };
+        let mut checks = vec![check(1, 7, 9), check(2, 17, 20), check(2, 17, 40)];
+        encode_checks(&mut checks, source, PositionEncoding::Utf32);
+        assert_eq!(checks[1].location, Location::new(2, 17));
+        encode_checks(&mut checks, source, PositionEncoding::Utf16);
+        let columns = checks
+            .iter()
+            .map(|x| (x.location.column(), x.end_location.column()))
+            .collect::<Vec<_>>();
+        // the snake is a surrogate pair, shifting the columns after it
+        assert_eq!(columns, vec![(7, 9), (18, 21), (18, 41)]);
+        let mut checks = vec![check(2, 17, 20)];
+        encode_checks(&mut checks, source, PositionEncoding::Utf8);
+        assert_eq!(checks[0].location, Location::new(2, 20));
+    }
+
+    #[test]
+    fn test_edit_delta_from_change() {
+        let change = |start: (u32, u32), end: (u32, u32), text: &str| {
+            lsp_types::TextDocumentContentChangeEvent {
+                range: Some(lsp_types::Range::new(
+                    lsp_types::Position::new(start.0, start.1),
+                    lsp_types::Position::new(end.0, end.1),
+                )),
+                range_length: None,
+                text: text.to_string(),
+            }
+        };
+        let delta = |change, encoding| edit_delta_from_change(&change, encoding).unwrap();
+        // the snake is a surrogate pair, counting two UTF-16 units
+        let typed = change((1, 4), (1, 4), "ab🐍");
+        assert_eq!(
+            delta(typed.clone(), PositionEncoding::Utf16).new_end,
+            (1, 8)
+        );
+        assert_eq!(delta(typed, PositionEncoding::Utf32).new_end, (1, 7));
+        let broken = delta(change((2, 1), (2, 3), "x\r\ny"), PositionEncoding::Utf16);
+        assert_eq!(broken, EditDelta::new((2, 1), (2, 3), (3, 1)));
+        let joined = delta(change((0, 9), (1, 0), ""), PositionEncoding::Utf16);
+        assert_eq!(joined, EditDelta::deletion((0, 9), (1, 0)));
+        let replaced = lsp_types::TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: String::new(),
+        };
+        assert!(edit_delta_from_change(&replaced, PositionEncoding::Utf16).is_none());
+    }
+}
@@ -0,0 +1,316 @@
+//! Previews of fixes, served by the `ruffd.previewFix` command
+//!
+//! The fix is applied to a copy of the document's buffer, which is linted as
+//! the document would be, such that clients can show the change a fix makes
+//! and the diagnostics it clears or introduces before applying it
+use crate::lint::lint;
+use crate::positions::range_from_locations;
+use crate::ruff_utils::{diagnostic_from_check, fix_all_edits, SettingsScope};
+use ruffd_types::extensions::FixPreview;
+use ruffd_types::ruff::checks::Check;
+use ruffd_types::{
+    lsp_types, sort_checks, CheckRegistry, DocumentBuffer, PositionBounds, PositionEncoding,
+    RuntimeError,
+};
+use std::collections::HashMap;
+
+/// Unchanged lines shown around the changed lines of a diff
+const CONTEXT_LINES: usize = 3;
+
+/// Unified diff of `old` and `new`, empty if they're equal
+///
+/// Lines common to the start and end of both are taken as unchanged and
+/// the lines between them as replaced, such that the diff of a single fix
+/// is one hunk
+pub fn unified_diff(label: &str, old: &str, new: &str) -> String {
+    let old_lines = old.lines().collect::<Vec<_>>();
+    let new_lines = new.lines().collect::<Vec<_>>();
+    let prefix = old_lines
+        .iter()
+        .zip(new_lines.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if prefix == old_lines.len() && prefix == new_lines.len() {
+        return String::new();
+    }
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let start = prefix.saturating_sub(CONTEXT_LINES);
+    let trailing = std::cmp::min(suffix, CONTEXT_LINES);
+    let old_end = old_lines.len() - suffix + trailing;
+    let new_end = new_lines.len() - suffix + trailing;
+    // an empty range is numbered by the line before it
+    let hunk_start = |len: usize| if len == 0 { start } else { start + 1 };
+    let mut rv = format!("--- {}\n+++ {}\n", label, label);
+    rv.push_str(&format!(
+        "@@ -{},{} +{},{} @@\n",
+        hunk_start(old_end - start),
+        old_end - start,
+        hunk_start(new_end - start),
+        new_end - start
+    ));
+    let unchanged = |line: &&str| format!(" {}\n", line);
+    old_lines[start..prefix]
+        .iter()
+        .map(unchanged)
+        .chain(
+            old_lines[prefix..old_lines.len() - suffix]
+                .iter()
+                .map(|x| format!("-{}\n", x)),
+        )
+        .chain(
+            new_lines[prefix..new_lines.len() - suffix]
+                .iter()
+                .map(|x| format!("+{}\n", x)),
+        )
+        .chain(
+            old_lines[old_lines.len() - suffix..old_end]
+                .iter()
+                .map(unchanged),
+        )
+        .for_each(|x| rv.push_str(&x));
+    rv
+}
+
+fn diagnostic_key(diagnostic: &lsp_types::Diagnostic) -> (String, String) {
+    let code = match &diagnostic.code {
+        Some(lsp_types::NumberOrString::String(x)) => x.clone(),
+        Some(lsp_types::NumberOrString::Number(x)) => x.to_string(),
+        None => String::new(),
+    };
+    (code, diagnostic.message.clone())
+}
+
+/// Diagnostics of `from` without a counterpart in `to`
+///
+/// Diagnostics are matched by code and message alone, as a fix moves the
+/// diagnostics following it
+fn unmatched(
+    from: &[lsp_types::Diagnostic],
+    to: &[lsp_types::Diagnostic],
+) -> Vec<lsp_types::Diagnostic> {
+    let mut remaining = HashMap::<_, usize>::new();
+    for diagnostic in to {
+        *remaining.entry(diagnostic_key(diagnostic)).or_default() += 1;
+    }
+    from.iter()
+        .filter(|x| match remaining.get_mut(&diagnostic_key(x)) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Diagnostics cleared and introduced going from `before` to `after`
+pub fn diagnostic_delta(
+    before: &[lsp_types::Diagnostic],
+    after: &[lsp_types::Diagnostic],
+) -> (Vec<lsp_types::Diagnostic>, Vec<lsp_types::Diagnostic>) {
+    (unmatched(before, after), unmatched(after, before))
+}
+
+/// Unified diff of the document at `uri` before and after fixing all the
+/// checks of `registry`
+///
+/// The registry must be of the buffer's current content, as the ranges of
+/// checks of an earlier version don't locate their fixes
+pub fn fix_all_diff(
+    uri: &lsp_types::Url,
+    buffer: &DocumentBuffer,
+    registry: &CheckRegistry,
+    encoding: PositionEncoding,
+) -> Result<String, RuntimeError> {
+    if registry.content_hash() != Some(buffer.content_hash()) {
+        return Err(RuntimeError::StaleChecks(uri.clone()));
+    }
+    let edits = fix_all_edits(registry.iter());
+    let source = buffer.iter().collect::<String>();
+    let mut fixed = DocumentBuffer::from_string(source.clone());
+    // edits are in document order, applying the last first keeps the
+    // ranges of those before it valid
+    for edit in edits.iter().rev() {
+        let change = lsp_types::TextDocumentContentChangeEvent {
+            range: Some(edit.range),
+            range_length: None,
+            text: edit.new_text.clone(),
+        };
+        fixed.apply_content_change(&change, encoding, PositionBounds::Strict)?;
+    }
+    let fixed = fixed.iter().collect::<String>();
+    Ok(unified_diff(uri.as_str(), &source, &fixed))
+}
+
+/// Applies the fix of `check` to a copy of the document at `uri` and lints
+/// the copy, comparing its diagnostics to those of `checks`
+pub async fn preview_fix<'a, I>(
+    uri: &lsp_types::Url,
+    buffer: &DocumentBuffer,
+    check: &Check,
+    checks: I,
+    scope: SettingsScope,
+) -> Result<FixPreview, RuntimeError>
+where
+    I: IntoIterator<Item = &'a Check>,
+{
+    let patch = match &check.fix {
+        Some(x) => &x.patch,
+        None => {
+            return Err(RuntimeError::InvalidCommandArguments(format!(
+                "{} has no fix",
+                check.kind.code().as_ref()
+            )))
+        }
+    };
+    let overrides = scope.severity_overrides.clone();
+    let before = checks
+        .into_iter()
+        .map(|x| diagnostic_from_check(x, &overrides))
+        .collect::<Vec<_>>();
+    let source = buffer.iter().collect::<String>();
+    let mut fixed = DocumentBuffer::from_string(source.clone());
+    let change = lsp_types::TextDocumentContentChangeEvent {
+        range: Some(range_from_locations(patch.location, patch.end_location)),
+        range_length: None,
+        text: patch.content.clone(),
+    };
+    fixed.apply_content_change(&change, scope.encoding, PositionBounds::Strict)?;
+    let fixed = fixed.iter().collect::<String>();
+    let path = scope
+        .lint_path(uri)
+        .ok_or_else(|| RuntimeError::UriToPathError(uri.clone()))?;
+    let mut fixed_checks = lint(path, fixed.clone(), scope)
+        .await
+        .map(|x| x.checks)
+        .unwrap_or_default();
+    sort_checks(&mut fixed_checks);
+    let after = fixed_checks
+        .iter()
+        .map(|x| diagnostic_from_check(x, &overrides))
+        .collect::<Vec<_>>();
+    let (removed, added) = diagnostic_delta(&before, &after);
+    Ok(FixPreview {
+        diff: unified_diff(uri.as_str(), &source, &fixed),
+        removed,
+        added,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::ruff::check;
+    use ruffd_types::ruff::checks::CheckKind;
+    use ruffd_types::rustpython_ast::Location;
+    use ruffd_types::tokio;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\n";
+        assert_eq!(
+            unified_diff("a.py", old, new),
+            "--- a.py\n+++ a.py\n@@ -2,7 +2,7 @@\n b\n c\n d\n-e\n+E\n f\n g\n h\n"
+        );
+        assert_eq!(
+            unified_diff("a.py", "import os\nx = 1\n", "x = 1\n"),
+            "--- a.py\n+++ a.py\n@@ -1,2 +1,1 @@\n-import os\n x = 1\n"
+        );
+        assert_eq!(
+            unified_diff("a.py", "import os\n", ""),
+            "--- a.py\n+++ a.py\n@@ -1,1 +0,0 @@\n-import os\n"
+        );
+        assert_eq!(unified_diff("a.py", old, old), "");
+    }
+
+    #[test]
+    fn test_fix_all_diff() {
+        let uri = lsp_types::Url::parse("file:///tmp/a.py").unwrap();
+        let source = "import os\nimport sys\nx = 1\n";
+        let buffer = DocumentBuffer::from_string(source.to_string());
+        let check_vec = check(Path::new("/tmp/a.py"), source, true).unwrap();
+        let registry =
+            CheckRegistry::from_iter(check_vec).with_content_hash(Some(buffer.content_hash()));
+        let diff = fix_all_diff(&uri, &buffer, &registry, PositionEncoding::Utf16).unwrap();
+        assert_eq!(
+            diff,
+            "--- file:///tmp/a.py\n+++ file:///tmp/a.py\n@@ -1,3 +1,1 @@\n-import os\n-import sys\n x = 1\n"
+        );
+        let clean = CheckRegistry::from_iter(vec![]).with_content_hash(Some(buffer.content_hash()));
+        assert_eq!(
+            fix_all_diff(&uri, &buffer, &clean, PositionEncoding::Utf16).unwrap(),
+            ""
+        );
+        // checks of another version of the document aren't diffed
+        let stale = registry.with_content_hash(Some(buffer.content_hash() + 1));
+        assert!(matches!(
+            fix_all_diff(&uri, &buffer, &stale, PositionEncoding::Utf16),
+            Err(RuntimeError::StaleChecks(_))
+        ));
+    }
+
+    #[test]
+    fn test_diagnostic_delta() {
+        let diagnostic = |code: &str, line| lsp_types::Diagnostic {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(line, 0),
+                lsp_types::Position::new(line, 1),
+            ),
+            code: Some(lsp_types::NumberOrString::String(code.to_string())),
+            message: code.to_string(),
+            ..Default::default()
+        };
+        let before = vec![diagnostic("F401", 0), diagnostic("F401", 1)];
+        // the fix moves the remaining diagnostic up a line
+        let after = vec![diagnostic("F401", 0), diagnostic("F841", 2)];
+        let (removed, added) = diagnostic_delta(&before, &after);
+        assert_eq!(removed, vec![diagnostic("F401", 1)]);
+        assert_eq!(added, vec![diagnostic("F841", 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_preview_fix() {
+        let uri = lsp_types::Url::from_file_path(std::env::temp_dir().join("a.py")).unwrap();
+        let buffer = DocumentBuffer::from_string("import os\nimport sys\n".to_string());
+        let checks = lint(
+            uri.to_file_path().unwrap(),
+            buffer.iter().collect(),
+            SettingsScope::default(),
+        )
+        .await
+        .unwrap()
+        .checks;
+        assert_eq!(checks.len(), 2);
+        let preview = preview_fix(&uri, &buffer, &checks[0], &checks, SettingsScope::default())
+            .await
+            .unwrap();
+        assert!(preview
+            .diff
+            .ends_with("@@ -1,2 +1,1 @@\n-import os\n import sys\n"));
+        assert_eq!(
+            preview.removed,
+            vec![diagnostic_from_check(&checks[0], &BTreeMap::new())]
+        );
+        assert!(preview.added.is_empty());
+        let unfixable = Check {
+            kind: CheckKind::UnusedVariable("x".to_string()),
+            location: Location::new(1, 0),
+            end_location: Location::new(1, 1),
+            fix: None,
+        };
+        assert!(
+            preview_fix(&uri, &buffer, &unfixable, &checks, SettingsScope::default())
+                .await
+                .is_err()
+        );
+    }
+}
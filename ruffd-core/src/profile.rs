@@ -0,0 +1,135 @@
+//! Timing of the stages of the lint pipeline, served by `ruffd/profileLint`
+//!
+//! Each stage runs as it does when linting an edited document, only
+//! repeated such that timings are stable enough to compare between reports
+use crate::ruff_utils::{check_parsed, diagnostic_from_check};
+use ruffd_types::extensions::StageTiming;
+use ruffd_types::project::Severity;
+use ruffd_types::ruff::settings::Settings;
+use ruffd_types::rustpython_parser::parser;
+use ruffd_types::{anyhow, lsp_types, serde_json, DocumentBuffer};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Runs when the number of iterations isn't given
+pub const DEFAULT_ITERATIONS: u32 = 10;
+
+/// Most runs of a single request, bounding the time it holds a worker
+pub const MAX_ITERATIONS: u32 = 1000;
+
+/// Durations of each run of a stage
+#[derive(Debug, Clone)]
+pub struct Samples {
+    stage: &'static str,
+    durations: Vec<Duration>,
+}
+
+impl Samples {
+    pub fn new(stage: &'static str) -> Self {
+        Self {
+            stage,
+            durations: vec![],
+        }
+    }
+
+    /// Runs `f`, recording its duration
+    pub fn time<T, F: FnOnce() -> T>(&mut self, f: F) -> T {
+        let start = Instant::now();
+        let rv = f();
+        self.durations.push(start.elapsed());
+        rv
+    }
+
+    pub fn timing(&self) -> StageTiming {
+        let micros = |x: Duration| x.as_micros() as u64;
+        let total = self.durations.iter().sum::<Duration>();
+        let runs = std::cmp::max(self.durations.len(), 1) as u32;
+        StageTiming {
+            stage: self.stage.to_string(),
+            total_micros: micros(total),
+            mean_micros: micros(total / runs),
+            min_micros: self.durations.iter().copied().min().map_or(0, micros),
+            max_micros: self.durations.iter().copied().max().map_or(0, micros),
+        }
+    }
+}
+
+/// Copies the buffer's content `iterations` times, as done before each lint
+pub fn profile_snapshot(buffer: &DocumentBuffer, iterations: u32) -> (String, Samples) {
+    let mut samples = Samples::new("snapshot");
+    let mut source = String::new();
+    for _ in 0..iterations {
+        source = samples.time(|| buffer.iter().collect::<String>());
+    }
+    (source, samples)
+}
+
+/// Runs the stages following the snapshot `iterations` times, returning
+/// the number of checks along with the samples of each stage
+///
+/// Sources failing to parse are rejected, as the later stages are skipped
+/// for them when linting
+pub fn profile_pipeline(
+    path: PathBuf,
+    source: String,
+    settings: Arc<Settings>,
+    overrides: &BTreeMap<String, Severity>,
+    iterations: u32,
+) -> anyhow::Result<(usize, Vec<Samples>)> {
+    let mut parse = Samples::new("parse");
+    let mut check = Samples::new("check");
+    let mut diagnostics = Samples::new("diagnostics");
+    let mut serialize = Samples::new("serialize");
+    let mut check_count = 0;
+    for _ in 0..iterations {
+        parse.time(|| parser::parse_program(&source, "<filename>"))?;
+        let check_vec = check.time(|| check_parsed(&path, &source, &settings, true))?;
+        check_count = check_vec.len();
+        let diagnostic_vec = diagnostics.time(|| {
+            check_vec
+                .iter()
+                .map(|x| diagnostic_from_check(x, overrides))
+                .collect::<Vec<lsp_types::Diagnostic>>()
+        });
+        serialize.time(|| serde_json::to_string(&diagnostic_vec))?;
+    }
+    Ok((check_count, vec![parse, check, diagnostics, serialize]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ruff_utils::{resolve_settings, SettingsScope};
+
+    #[test]
+    fn test_profile_pipeline() {
+        let path = PathBuf::from("/tmp/dummy.py");
+        let buffer = DocumentBuffer::from_string("import os\nimport sys\n".to_string());
+        let (source, snapshot) = profile_snapshot(&buffer, 3);
+        assert_eq!(source, "import os\nimport sys\n");
+        let settings = resolve_settings(&path, &SettingsScope::default()).unwrap();
+        let (checks, samples) =
+            profile_pipeline(path.clone(), source, settings, &BTreeMap::new(), 3).unwrap();
+        assert_eq!(checks, 2);
+        let stages = std::iter::once(&snapshot)
+            .chain(samples.iter())
+            .map(|x| {
+                let timing = x.timing();
+                assert_eq!(x.durations.len(), 3);
+                assert!(timing.min_micros <= timing.mean_micros);
+                assert!(timing.mean_micros <= timing.max_micros);
+                timing.stage
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stages,
+            vec!["snapshot", "parse", "check", "diagnostics", "serialize"]
+        );
+        let settings = resolve_settings(&path, &SettingsScope::default()).unwrap();
+        assert!(
+            profile_pipeline(path, "x = (\n".to_string(), settings, &BTreeMap::new(), 1).is_err()
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Work done progress of long running server operations
+//!
+//! Operations check whether they were cancelled between units of work, the
+//! flag being set by `window/workDoneProgress/cancel` through the registry
+//! of tokens in progress
+//!
+//! Indexing and linting the workspace are also published as
+//! `ruffd/indexingStatus`, which clients may show without a progress token
+use ruffd_types::extensions::{IndexingStage, IndexingStatus, IndexingStatusNotification};
+use ruffd_types::lsp_types::notification::Notification;
+use ruffd_types::{lsp_types, serde_json};
+use ruffd_types::{RpcNotification, Scheduler};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref IN_PROGRESS: Mutex<HashMap<lsp_types::NumberOrString, Arc<AtomicBool>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Creates a token for progress initiated by the server, unique to the
+/// process
+pub fn server_token(operation: &str) -> lsp_types::NumberOrString {
+    let idx = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    lsp_types::NumberOrString::String(format!("ruffd/{}/{}", operation, idx))
+}
+
+/// Creates a token for `operation` with the client, returning `None` if the
+/// client rejected it
+pub async fn create_token(
+    scheduler: &Scheduler,
+    operation: &str,
+) -> Option<lsp_types::NumberOrString> {
+    let token = server_token(operation);
+    let params = lsp_types::WorkDoneProgressCreateParams {
+        token: token.clone(),
+    };
+    let resp = scheduler
+        .request_client(
+            "window/workDoneProgress/create",
+            Some(serde_json::to_value(params).unwrap()),
+        )
+        .await
+        .ok()?;
+    resp.into_result().ok().map(|_| token)
+}
+
+fn percentage(done: usize, total: usize) -> u32 {
+    match total {
+        0 => 100,
+        _ => (done * 100 / total) as u32,
+    }
+}
+
+/// Cancels the operation reporting progress under `token`, returning
+/// whether there was such an operation
+pub fn cancel(token: &lsp_types::NumberOrString) -> bool {
+    match IN_PROGRESS.lock().unwrap().get(token) {
+        Some(cancelled) => {
+            cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Cancels every operation in progress, as once the client it reports to
+/// is lost
+#[cfg(feature = "tcp")]
+pub fn cancel_all() {
+    for (_, cancelled) in IN_PROGRESS.lock().unwrap().drain() {
+        cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Progress of a cancellable operation, reported to the client through
+/// `$/progress` until ended or dropped
+pub struct Progress {
+    token: lsp_types::NumberOrString,
+    cancelled: Arc<AtomicBool>,
+    scheduler: Scheduler,
+    percentage: u32,
+}
+
+impl Progress {
+    /// Begins reporting progress under `token`, which the client must
+    /// already know of
+    pub fn begin(scheduler: Scheduler, token: lsp_types::NumberOrString, title: &str) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        IN_PROGRESS
+            .lock()
+            .unwrap()
+            .insert(token.clone(), cancelled.clone());
+        let rv = Self {
+            token,
+            cancelled,
+            scheduler,
+            percentage: 0,
+        };
+        rv.notify(lsp_types::WorkDoneProgress::Begin(
+            lsp_types::WorkDoneProgressBegin {
+                title: title.to_string(),
+                cancellable: Some(true),
+                message: None,
+                percentage: Some(0),
+            },
+        ));
+        rv
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Reports `done` of `total` units of work, only notifying the client
+    /// once the percentage changes
+    pub fn report(&mut self, done: usize, total: usize) {
+        let percentage = percentage(done, total);
+        if percentage == self.percentage {
+            return;
+        }
+        self.percentage = percentage;
+        self.notify(lsp_types::WorkDoneProgress::Report(
+            lsp_types::WorkDoneProgressReport {
+                cancellable: Some(true),
+                message: Some(format!("{}/{}", done, total)),
+                percentage: Some(percentage),
+            },
+        ));
+    }
+
+    pub fn end(self, message: impl Into<String>) {
+        self.notify(lsp_types::WorkDoneProgress::End(
+            lsp_types::WorkDoneProgressEnd {
+                message: Some(message.into()),
+            },
+        ));
+    }
+
+    fn notify(&self, value: lsp_types::WorkDoneProgress) {
+        let params = lsp_types::ProgressParams {
+            token: self.token.clone(),
+            value: lsp_types::ProgressParamsValue::WorkDone(value),
+        };
+        self.scheduler.notify_client(RpcNotification::new(
+            "$/progress".to_string(),
+            Some(serde_json::to_value(params).unwrap()),
+        ));
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // the token may since have been reused by another operation, once
+        // this one was cancelled along with its session
+        let mut in_progress = IN_PROGRESS.lock().unwrap();
+        if matches!(in_progress.get(&self.token), Some(x) if Arc::ptr_eq(x, &self.cancelled)) {
+            in_progress.remove(&self.token);
+        }
+    }
+}
+
+/// Status of indexing or linting the workspace, published through
+/// `ruffd/indexingStatus` along with `$/progress` if there's a token
+pub struct WorkspaceStatus {
+    stage: IndexingStage,
+    scheduler: Scheduler,
+    progress: Option<Progress>,
+    /// Percentage last published, `None` until the total is known
+    percentage: Option<u32>,
+}
+
+impl WorkspaceStatus {
+    pub fn begin(
+        scheduler: Scheduler,
+        stage: IndexingStage,
+        token: Option<lsp_types::NumberOrString>,
+    ) -> Self {
+        let title = match stage {
+            IndexingStage::Indexing => "Indexing workspace",
+            IndexingStage::Linting => "Linting workspace",
+        };
+        let progress = token.map(|x| Progress::begin(scheduler.clone(), x, title));
+        let rv = Self {
+            stage,
+            scheduler,
+            progress,
+            percentage: None,
+        };
+        rv.notify(0, None, false);
+        rv
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(&self.progress, Some(x) if x.is_cancelled())
+    }
+
+    /// Reports `done` of `total` files, only notifying the client once the
+    /// percentage changes
+    pub fn report(&mut self, done: usize, total: usize) {
+        if let Some(progress) = self.progress.as_mut() {
+            progress.report(done, total);
+        }
+        let percentage = Some(percentage(done, total));
+        if percentage == self.percentage {
+            return;
+        }
+        self.percentage = percentage;
+        self.notify(done, Some(total), false);
+    }
+
+    pub fn end(self, done: usize, total: usize, message: impl Into<String>) {
+        self.notify(done, Some(total), true);
+        if let Some(progress) = self.progress {
+            progress.end(message);
+        }
+    }
+
+    fn notify(&self, done: usize, total: Option<usize>, finished: bool) {
+        let params = IndexingStatus {
+            stage: self.stage,
+            done,
+            total,
+            finished,
+        };
+        self.scheduler.notify_client(RpcNotification::new(
+            IndexingStatusNotification::METHOD.to_string(),
+            Some(serde_json::to_value(params).unwrap()),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::tokio;
+    use ruffd_types::tokio::sync::mpsc::{channel, Receiver};
+    use ruffd_types::{
+        server_state_handles_from_locks, RpcMessage, ScheduledTask, ServerInitiated,
+        ServerStateLocks,
+    };
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let (sender, _receiver) = channel(8);
+        let token = server_token("test");
+        assert!(!cancel(&token));
+        let progress = Progress::begin(Scheduler::new(sender), token.clone(), "Testing");
+        assert!(!progress.is_cancelled());
+        assert!(cancel(&token));
+        assert!(progress.is_cancelled());
+        progress.end("cancelled");
+        assert!(!cancel(&token));
+    }
+
+    /// Method and params of the next notification sent to the client
+    async fn next_notification(receiver: &mut Receiver<ScheduledTask>) -> RpcNotification {
+        let notification = match receiver.recv().await.unwrap() {
+            ScheduledTask::Server(ServerInitiated::Notification(x), _) => x,
+            _ => panic!("expected a notification"),
+        };
+        let locks = ServerStateLocks::default();
+        let handles = server_state_handles_from_locks(&locks).await;
+        let (sender, _) = channel(1);
+        match (notification.exec)(handles, sender).await {
+            Some(RpcMessage::Notification(x)) => x,
+            _ => panic!("expected a notification to the client"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_workspace_status() {
+        let (sender, mut receiver) = channel(16);
+        let mut status =
+            WorkspaceStatus::begin(Scheduler::new(sender), IndexingStage::Linting, None);
+        for done in [0, 1, 1, 2] {
+            status.report(done, 200);
+        }
+        status.end(200, 200, "done");
+        let mut statuses = vec![];
+        for _ in 0..4 {
+            let notification = next_notification(&mut receiver).await;
+            assert_eq!(notification.method, "ruffd/indexingStatus");
+            statuses.push(
+                serde_json::from_value::<IndexingStatus>(notification.params.unwrap().into_value())
+                    .unwrap(),
+            );
+        }
+        let counts = statuses
+            .iter()
+            .map(|x| (x.done, x.total))
+            .collect::<Vec<_>>();
+        // reports within the same percentage aren't published
+        assert_eq!(
+            counts,
+            vec![(0, None), (0, Some(200)), (2, Some(200)), (200, Some(200))]
+        );
+        assert!(statuses.iter().all(|x| x.stage == IndexingStage::Linting));
+        assert_eq!(
+            statuses.iter().map(|x| x.finished).collect::<Vec<_>>(),
+            vec![false, false, false, true]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! References to the name under the cursor, served by `textDocument/references`
+//!
+//! Variables of functions and classes are only referenced within their own
+//! file. Variables of a module are followed into the files importing them by
+//! name, as in `from module import name`, which requires knowing the module
+//! path of each file, and so a project root
+use crate::imports::{flatten_body, module_path};
+use crate::names::{NameKind, NameToken, ResolvedNames};
+use ruffd_types::rustpython_ast::{Location, StmtKind, Suite};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Source of a file along with the module it is
+pub struct SourceFile {
+    pub source: String,
+    pub suite: Arc<Suite>,
+    module: Option<String>,
+    /// Whether the file is the `__init__` of its module
+    package: bool,
+}
+
+impl SourceFile {
+    pub fn new(
+        source: String,
+        suite: Arc<Suite>,
+        root: Option<&Path>,
+        path: Option<&Path>,
+    ) -> Self {
+        let module = root
+            .zip(path)
+            .and_then(|(root, path)| module_path(root, path));
+        let package = matches!(
+            path.and_then(|x| x.file_stem()),
+            Some(x) if x == "__init__"
+        );
+        Self {
+            source,
+            suite,
+            module,
+            package,
+        }
+    }
+}
+
+/// What a name refers to, determining the files it may be referenced from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Symbol {
+    /// Variable bound by `scope` of the file the name is in, `None` being
+    /// the module when the file's module path isn't known
+    Local { name: String, scope: Option<usize> },
+    /// Variable bound by the module at `module`
+    Module { module: String, name: String },
+    /// Attribute or keyword argument, matched by name alone
+    Member { name: String, kind: NameKind },
+}
+
+impl Symbol {
+    /// Whether the symbol may be referenced from files other than its own
+    pub fn is_local(&self) -> bool {
+        matches!(self, Self::Local { .. })
+    }
+}
+
+/// Absolute path of the module imported by an import of `module` at `level`
+/// from the module `importer`
+pub fn resolve_import(
+    importer: &str,
+    package: bool,
+    module: Option<&str>,
+    level: usize,
+) -> Option<String> {
+    if level == 0 {
+        return module.map(str::to_string);
+    }
+    let mut parts = importer.split('.').collect::<Vec<_>>();
+    // the package of a module is its parent, that of an `__init__` itself
+    let parents = if package { level - 1 } else { level };
+    if parents >= parts.len() {
+        return None;
+    }
+    parts.truncate(parts.len() - parents);
+    parts.extend(module);
+    Some(parts.join("."))
+}
+
+/// Names bound by `from ... import ...` statements of `file`, as tuples of
+/// the imported module, the imported name and the name it's bound to
+fn imported_names(file: &SourceFile) -> Vec<(String, String, String)> {
+    let importer = match &file.module {
+        Some(x) => x,
+        None => return vec![],
+    };
+    let mut stmts = vec![];
+    flatten_body(&file.suite, &mut stmts);
+    let mut rv = vec![];
+    for stmt in stmts {
+        if let StmtKind::ImportFrom {
+            module,
+            names,
+            level,
+        } = &stmt.node
+        {
+            let resolved = resolve_import(
+                importer,
+                file.package,
+                module.as_deref(),
+                level.unwrap_or(0),
+            );
+            if let Some(resolved) = resolved {
+                rv.extend(names.iter().map(|x| {
+                    let bound = x.node.asname.as_ref().unwrap_or(&x.node.name);
+                    (resolved.clone(), x.node.name.clone(), bound.clone())
+                }));
+            }
+        }
+    }
+    rv
+}
+
+/// Symbol the name at `at` within `file` refers to
+pub fn symbol_at(file: &SourceFile, at: Location) -> Option<Symbol> {
+    let names = ResolvedNames::new(&file.source, &file.suite);
+    let idx = names.token_at(at)?;
+    let token = names.token(idx);
+    if !matches!(token.kind, NameKind::Read | NameKind::Write) {
+        return Some(Symbol::Member {
+            name: token.name.clone(),
+            kind: token.kind,
+        });
+    }
+    let scope = names.resolve(idx);
+    let module = match (&file.module, scope) {
+        (Some(x), None) => x,
+        _ => {
+            return Some(Symbol::Local {
+                name: token.name.clone(),
+                scope,
+            })
+        }
+    };
+    // names imported from another module refer to the variable there
+    let imported = imported_names(file)
+        .into_iter()
+        .find(|(_, _, bound)| *bound == token.name);
+    Some(match imported {
+        Some((module, name, _)) => Symbol::Module { module, name },
+        None => Symbol::Module {
+            module: module.clone(),
+            name: token.name.clone(),
+        },
+    })
+}
+
+/// Tokens of `file` referring to `symbol`, in order of occurrence
+///
+/// The declaration of a variable is the first binding of it within the file
+/// of its scope, such as its definition, parameter or first assignment
+pub fn references_in(
+    file: &SourceFile,
+    symbol: &Symbol,
+    include_declaration: bool,
+) -> Vec<NameToken> {
+    let names = ResolvedNames::new(&file.source, &file.suite);
+    let (mut rv, declared) = match symbol {
+        Symbol::Member { name, kind } => return owned(names.named(name, *kind)),
+        Symbol::Local { name, scope } => (owned(names.variables(name, *scope)), true),
+        Symbol::Module { module, name } => {
+            let home = file.module.as_ref() == Some(module);
+            let mut bound = match home {
+                true => vec![name.clone()],
+                false => vec![],
+            };
+            bound.extend(
+                imported_names(file)
+                    .into_iter()
+                    .filter(|x| x.0 == *module && x.1 == *name)
+                    .map(|x| x.2),
+            );
+            let mut rv = bound
+                .iter()
+                .flat_map(|x| owned(names.variables(x, None)))
+                .collect::<Vec<_>>();
+            rv.sort_by_key(|x| x.location);
+            rv.dedup();
+            (rv, home)
+        }
+    };
+    if !include_declaration && declared {
+        if let Some(idx) = rv.iter().position(|x| x.kind == NameKind::Write) {
+            rv.remove(idx);
+        }
+    }
+    rv
+}
+
+fn owned(tokens: Vec<&NameToken>) -> Vec<NameToken> {
+    tokens.into_iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::rustpython_parser::parser;
+    use std::path::PathBuf;
+
+    fn source_file(source: &str, path: &str) -> SourceFile {
+        let suite = parser::parse_program(source, "<filename>").unwrap();
+        let root = PathBuf::from("/root");
+        SourceFile::new(
+            source.to_string(),
+            Arc::new(suite),
+            Some(&root),
+            Some(&root.join(path)),
+        )
+    }
+
+    fn rows(tokens: &[NameToken]) -> Vec<(usize, usize)> {
+        tokens
+            .iter()
+            .map(|x| (x.location.row(), x.location.column()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_import() {
+        assert_eq!(
+            resolve_import("a.b", false, Some("c"), 0),
+            Some("c".to_string())
+        );
+        assert_eq!(
+            resolve_import("a.b", false, Some("c"), 1),
+            Some("a.c".to_string())
+        );
+        assert_eq!(
+            resolve_import("a.b", true, Some("c"), 1),
+            Some("a.b.c".to_string())
+        );
+        assert_eq!(resolve_import("a.b", false, None, 2), None);
+        assert_eq!(resolve_import("a.b", true, None, 2), Some("a".to_string()));
+        // top-level modules have no package to import relative to
+        assert_eq!(resolve_import("a", false, Some("b"), 1), None);
+    }
+
+    #[test]
+    fn test_references() {
+        let home = source_file("def f(x):\n    return x\n\n\nf(1)\n", "pkg/a.py");
+        let importer = source_file("from .a import f as g\n\ng(2)\nf = 3\n", "pkg/b.py");
+        let unrelated = source_file("def f():\n    pass\n", "pkg/c.py");
+        let symbol = symbol_at(&importer, Location::new(3, 0)).unwrap();
+        assert_eq!(
+            symbol,
+            Symbol::Module {
+                module: "pkg.a".to_string(),
+                name: "f".to_string()
+            }
+        );
+        assert_eq!(symbol_at(&home, Location::new(1, 4)).unwrap(), symbol);
+        assert_eq!(
+            rows(&references_in(&home, &symbol, true)),
+            vec![(1, 4), (5, 0)]
+        );
+        assert_eq!(rows(&references_in(&home, &symbol, false)), vec![(5, 0)]);
+        assert_eq!(
+            rows(&references_in(&importer, &symbol, false)),
+            vec![(1, 20), (3, 0)]
+        );
+        assert!(references_in(&unrelated, &symbol, true).is_empty());
+        // parameters are only referenced within their function
+        let parameter = symbol_at(&home, Location::new(2, 11)).unwrap();
+        assert!(parameter.is_local());
+        assert_eq!(
+            rows(&references_in(&home, &parameter, true)),
+            vec![(1, 6), (2, 11)]
+        );
+    }
+}
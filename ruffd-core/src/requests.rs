@@ -1,24 +1,113 @@
-use crate::ruff_utils::action_from_check;
+use crate::calls::{function_at, incoming_calls, outgoing_calls, Calls};
+use crate::explain::explain_rule;
+use crate::fs::{effective_content, read_document, unsaved_content};
+use crate::imports::{import_rename_edits, module_path};
+use crate::lint::lint;
+use crate::names::{NameKind, ResolvedNames};
+use crate::positions::{encode_range, location_from_position, BufferPosition};
+use crate::preview::{fix_all_diff, preview_fix};
+use crate::profile;
+use crate::progress::{self, WorkspaceStatus};
+use crate::references::{references_in, symbol_at, SourceFile};
+use crate::ruff_utils::{
+    action_from_check, diagnostic_from_check, find_check, registry_diagnostics, resolve_action,
+    resolve_settings, rule_info_from_code, settings_root, SettingsScope,
+};
+use crate::server_ops::{
+    apply_fix_all, checks_or_mark_failed, fix_all_in_place, lint_in_place, pulls_diagnostics,
+    run_select_profile_op, run_update_checks_op,
+};
+use crate::signatures::{call_at, signature_for};
+use crate::symbols::{file_symbols, matches_query, suite_symbols};
+use crate::PKG_VERSION;
 use ruffd_macros::request;
-use ruffd_types::lsp_types;
-use ruffd_types::{Request, RuntimeError};
-use std::collections::HashMap;
+use ruffd_types::capabilities::{
+    supports_document_changes, supports_edit_resolve, supports_show_document,
+    supports_work_done_progress,
+};
+use ruffd_types::contention::lock_waits;
+use ruffd_types::extensions::{
+    DocumentStatusReport, DocumentStatusRequest, FixAllFormat, FixAllParams, IndexingStage,
+    LintWorkspaceParams, LintWorkspaceRequest, PreviewFixParams, ProfileLintParams,
+    ProfileLintReport, ProfileLintRequest, RuleExplainParams, RuleExplainRequest, RuleInfo,
+    RuleInfoParams, RuleInfoRequest, ServerStatus, ServerStatusRequest, FIX_ALL_COMMAND,
+    PREVIEW_FIX_COMMAND, SELECT_PROFILE_COMMAND,
+};
+use ruffd_types::layered::ResolvedConfig;
+use ruffd_types::lsp_types::request::{
+    CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+    CodeActionRequest, CodeActionResolveRequest, DocumentDiagnosticRequest,
+    DocumentHighlightRequest, ExecuteCommand, References, SignatureHelpRequest, WillRenameFiles,
+    WillSaveWaitUntil,
+};
+use ruffd_types::project::FixSafety;
+use ruffd_types::rustpython_ast::{Location, Suite};
+use ruffd_types::rustpython_parser::parser;
+use ruffd_types::tasks::{spawn_blocking_named, spawn_named, spawn_or_inline};
+use ruffd_types::uri::{normalize_uri, uri_to_path};
+use ruffd_types::{anyhow, content_hash, log_warn, lsp_types, serde_json};
+use ruffd_types::{
+    request_entry, AstCache, CheckRegistry, DocumentBuffer, DocumentStatus, FixOnSave,
+    PositionBounds, PositionEncoding, Request, RuntimeError, Scheduler, RUFF_VERSION,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
 
-#[request(checks)]
+/// Determines whether actions of `kind` are requested by the `only` filter
+///
+/// Kinds are hierarchical, so requesting `source` includes actions of kind
+/// `source.fixAll`
+fn kind_requested(
+    kind: &lsp_types::CodeActionKind,
+    only: Option<&[lsp_types::CodeActionKind]>,
+) -> bool {
+    match only {
+        None => true,
+        Some(only) => only.iter().any(|requested| {
+            let requested = requested.as_str();
+            match kind.as_str().strip_prefix(requested) {
+                Some(rest) => requested.is_empty() || rest.is_empty() || rest.starts_with('.'),
+                None => false,
+            }
+        }),
+    }
+}
+
+#[request(checks, client_capabilities, config_snapshot)]
 fn doc_code_action(
     action_params: lsp_types::CodeActionParams,
 ) -> Result<Option<Vec<lsp_types::CodeActionOrCommand>>, RuntimeError> {
-    let uri = action_params.text_document.uri;
+    let only = action_params.context.only.as_deref();
+    // quick fixes are the only actions offered
+    if !kind_requested(&lsp_types::CodeActionKind::QUICKFIX, only) {
+        return Ok(None);
+    }
+    let uri = normalize_uri(&action_params.text_document.uri);
+    let lazy = supports_edit_resolve(&client_capabilities);
+    let overrides = &config_snapshot.project_config.severity_overrides;
+    checks.touch(&uri);
     if let Some(registry) = checks.get(&uri) {
-        let start_line = action_params.range.start.line as usize;
-        let start_col = action_params.range.start.character as usize;
-        let end_line = action_params.range.end.line as usize;
-        let end_col = action_params.range.end.character as usize;
-        let start = (start_line, start_col);
-        let end = (end_line, end_col);
-        let rv = registry
-            .iter_range(start..end)
-            .map(|check| action_from_check(check, &uri))
+        let context_diagnostics = action_params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|x| x.source.as_deref() == Some("ruff"))
+            .collect::<Vec<_>>();
+        let candidates: Box<dyn Iterator<Item = _>> = if context_diagnostics.is_empty() {
+            let start = location_from_position(action_params.range.start);
+            let end = location_from_position(action_params.range.end);
+            Box::new(registry.iter_range(start..end))
+        } else {
+            // the client knows which diagnostics the user is acting on
+            Box::new(registry.iter().filter(move |check| {
+                let diagnostic = diagnostic_from_check(check, overrides);
+                context_diagnostics
+                    .iter()
+                    .any(|x| x.range == diagnostic.range && x.code == diagnostic.code)
+            }))
+        };
+        let rv = candidates
+            .map(|check| action_from_check(check, &uri, lazy, overrides))
             .filter(Option::is_some)
             .flatten()
             .map(lsp_types::CodeActionOrCommand::CodeAction)
@@ -29,11 +118,1122 @@ fn doc_code_action(
     }
 }
 
+#[request(checks)]
+fn code_action_resolve(
+    action: lsp_types::CodeAction,
+) -> Result<lsp_types::CodeAction, RuntimeError> {
+    Ok(resolve_action(action, &checks))
+}
+
+#[request(
+    open_buffers,
+    shadow_buffers,
+    config_snapshot,
+    mut document_status,
+    mut checks
+)]
+async fn doc_diagnostic(
+    params: lsp_types::DocumentDiagnosticParams,
+) -> Result<lsp_types::DocumentDiagnosticReportResult, RuntimeError> {
+    let uri = normalize_uri(&params.text_document.uri);
+    let buffer = open_buffers.get(&uri).or_else(|| shadow_buffers.get(&uri));
+    let scope = SettingsScope::from_snapshot(&config_snapshot);
+    let overrides = &config_snapshot.project_config.severity_overrides;
+    // evicted checks of documents that aren't buffered are recomputed from
+    // their saved text
+    let evicted = matches!(checks.get(&uri), Some(x) if x.is_evicted());
+    let saved = match buffer {
+        None if evicted => match read_document(&uri).await {
+            Ok(x) => Some(x),
+            Err(err) => {
+                log_warn!("{}", err);
+                None
+            }
+        },
+        _ => None,
+    };
+    let content_hash = buffer
+        .map(DocumentBuffer::content_hash)
+        .or_else(|| saved.as_deref().map(content_hash));
+    // notebook cells have no path as they're linted with their notebook,
+    // so their last checks are reported as is
+    if let (Some(content_hash), Some(path)) = (content_hash, scope.lint_path(&uri)) {
+        let generation = config_snapshot.generation;
+        // checks of previous settings are recomputed, as the client would
+        // otherwise be told they're unchanged
+        let current = checks
+            .get(&uri)
+            .filter(|x| x.content_hash() == Some(content_hash) && x.generation() == generation);
+        if current.is_none() {
+            let doc = saved.unwrap_or_else(|| buffer.iter().flat_map(|x| x.iter()).collect());
+            let result = lint(path, doc, scope).await;
+            let linted = checks_or_mark_failed(&uri, result, &mut document_status);
+            let registry = CheckRegistry::from(linted)
+                .with_content_hash(Some(content_hash))
+                .with_generation(generation);
+            checks.insert(uri.clone(), registry);
+        }
+    }
+    let registry = checks.get(&uri);
+    let result_id = registry.and_then(CheckRegistry::result_id);
+    let report = match (result_id, params.previous_result_id) {
+        (Some(result_id), Some(previous)) if result_id == previous => {
+            lsp_types::DocumentDiagnosticReport::Unchanged(
+                lsp_types::RelatedUnchangedDocumentDiagnosticReport {
+                    related_documents: None,
+                    unchanged_document_diagnostic_report:
+                        lsp_types::UnchangedDocumentDiagnosticReport { result_id },
+                },
+            )
+        }
+        (result_id, _) => {
+            let items = registry
+                .map(|x| registry_diagnostics(x, overrides))
+                .unwrap_or_default();
+            lsp_types::DocumentDiagnosticReport::Full(
+                lsp_types::RelatedFullDocumentDiagnosticReport {
+                    related_documents: None,
+                    full_document_diagnostic_report: lsp_types::FullDocumentDiagnosticReport {
+                        result_id,
+                        items,
+                    },
+                },
+            )
+        }
+    };
+    Ok(lsp_types::DocumentDiagnosticReportResult::Report(report))
+}
+
+#[request]
+fn rule_info(params: RuleInfoParams) -> Result<Option<RuleInfo>, RuntimeError> {
+    Ok(rule_info_from_code(params.code.as_str()))
+}
+
+/// Explains a rule as Markdown, quoting the lines it was reported at when
+/// the document is open
+#[request(open_buffers)]
+fn rule_explain(
+    params: RuleExplainParams,
+) -> Result<Option<lsp_types::MarkupContent>, RuntimeError> {
+    let code = match params.rule_code() {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let source = params
+        .text_document
+        .as_ref()
+        .and_then(|x| open_buffers.get(&normalize_uri(&x.uri)))
+        .map(|x| x.iter().collect::<String>());
+    let reported = source.as_deref().zip(params.reported_range());
+    Ok(explain_rule(&code, reported))
+}
+
+/// Times each stage of linting an open document over repeated runs
+#[request(open_buffers, config_snapshot)]
+async fn profile_lint(params: ProfileLintParams) -> Result<ProfileLintReport, RuntimeError> {
+    let uri = normalize_uri(&params.text_document.uri);
+    let text = open_buffers
+        .get(&uri)
+        .ok_or_else(|| RuntimeError::EditUnopenedDocument(uri.clone()))?
+        .iter()
+        .collect::<String>();
+    // runs are timed on a copy of the document, such that it can be edited
+    // while they run
+    drop(open_buffers);
+    let scope = SettingsScope::from_snapshot(&config_snapshot);
+    let path = scope
+        .lint_path(&uri)
+        .ok_or_else(|| RuntimeError::UriToPathError(uri.clone()))?;
+    let iterations = params
+        .iterations
+        .unwrap_or(profile::DEFAULT_ITERATIONS)
+        .clamp(1, profile::MAX_ITERATIONS);
+    let settings = resolve_settings(&path, &scope)?;
+    let overrides = scope.severity_overrides;
+    let (checks, stages) = spawn_blocking_named(
+        || "profile lint".to_string(),
+        move || {
+            let buffer = DocumentBuffer::from_string(text);
+            let (source, snapshot) = profile::profile_snapshot(&buffer, iterations);
+            let (checks, samples) =
+                profile::profile_pipeline(path, source, settings, &overrides, iterations)?;
+            let stages = std::iter::once(snapshot).chain(samples).collect::<Vec<_>>();
+            Ok::<_, anyhow::Error>((checks, stages))
+        },
+    )
+    .await
+    .map_err(anyhow::Error::from)??;
+    Ok(ProfileLintReport {
+        iterations,
+        checks,
+        stages: stages.iter().map(profile::Samples::timing).collect(),
+    })
+}
+
+#[request(document_status)]
+fn document_status_report() -> Result<Vec<DocumentStatusReport>, RuntimeError> {
+    let mut rv = document_status
+        .iter()
+        .map(|(uri, status)| DocumentStatusReport {
+            uri: uri.clone(),
+            version: status.version,
+            saved_version: status.saved_version,
+            dirty: status.dirty,
+            pending_save: status.pending_save,
+            oversized: status.oversized,
+            lint_failed: status.lint_failed,
+        })
+        .collect::<Vec<_>>();
+    rv.sort_by(|a, b| a.uri.cmp(&b.uri));
+    Ok(rv)
+}
+
+/// Reports the state of the server as a whole, for status panes and bug
+/// reports
+#[request(document_status, config_snapshot)]
+fn server_status(scheduler: Scheduler) -> Result<ServerStatus, RuntimeError> {
+    let root_path = config_snapshot.project_root.as_ref().and_then(uri_to_path);
+    // settings of each document are resolved from the pyproject of its root
+    let config_paths = document_status
+        .keys()
+        .filter_map(uri_to_path)
+        .filter_map(|x| settings_root(&x, root_path.as_deref()))
+        .map(|x| x.join("pyproject.toml"))
+        .filter(|x| x.is_file())
+        .collect::<BTreeSet<_>>();
+    Ok(ServerStatus {
+        server_version: PKG_VERSION.to_string(),
+        ruff_version: RUFF_VERSION.to_string(),
+        config_paths: config_paths.into_iter().collect(),
+        open_documents: document_status.len(),
+        pending_tasks: scheduler.pending(),
+        recent_lints: config_snapshot.lint_timings.recent(),
+        lock_waits: lock_waits(),
+        watcher: config_snapshot.watcher,
+        resolved_config: ResolvedConfig::resolve(
+            &config_snapshot.config.layer,
+            &config_snapshot.project_config,
+        ),
+    })
+}
+
+#[request(open_buffers, config_snapshot)]
+fn workspace_will_rename_files(
+    params: lsp_types::RenameFilesParams,
+) -> Result<Option<lsp_types::WorkspaceEdit>, RuntimeError> {
+    let root_path = match config_snapshot.project_root.as_ref().and_then(uri_to_path) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let module_renames = params
+        .files
+        .iter()
+        .filter_map(|x| {
+            let old_path = uri_to_path(&lsp_types::Url::parse(&x.old_uri).ok()?)?;
+            let new_path = uri_to_path(&lsp_types::Url::parse(&x.new_uri).ok()?)?;
+            Some((
+                module_path(&root_path, &old_path)?,
+                module_path(&root_path, &new_path)?,
+            ))
+        })
+        .filter(|(old, new)| old != new)
+        .collect::<Vec<_>>();
+    if module_renames.is_empty() {
+        return Ok(None);
+    }
+    let mut changes = HashMap::new();
+    for (uri, buffer) in open_buffers.iter() {
+        let source = buffer.iter().collect::<String>();
+        let edits = module_renames
+            .iter()
+            .flat_map(|(old, new)| import_rename_edits(&source, old, new))
+            .collect::<Vec<_>>();
+        if !edits.is_empty() {
+            changes.insert(uri.clone(), edits);
+        }
+    }
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(lsp_types::WorkspaceEdit {
+        changes: Some(changes),
+        ..Default::default()
+    }))
+}
+
+/// Lints each of `files` in turn from disk, publishing its status and
+/// reporting progress under the token if there is one, stopping between
+/// files once cancelled
+async fn lint_files(
+    scheduler: Scheduler,
+    files: Vec<(lsp_types::Url, Option<String>)>,
+    scope: SettingsScope,
+    token: Option<lsp_types::NumberOrString>,
+) {
+    let mut status = WorkspaceStatus::begin(scheduler.clone(), IndexingStage::Linting, token);
+    let total = files.len();
+    let mut done = 0;
+    for (uri, unsaved) in files {
+        if status.is_cancelled() {
+            break;
+        }
+        status.report(done, total);
+        done += 1;
+        let (path, text) = match (uri_to_path(&uri), effective_content(&uri, unsaved).await) {
+            (Some(path), Ok(text)) => (path, text),
+            (_, Err(err)) => {
+                log_warn!("{}", err);
+                continue;
+            }
+            _ => continue,
+        };
+        let hash = content_hash(&text);
+        let result = lint(path, text, scope.clone()).await;
+        scheduler.schedule(run_update_checks_op(uri, result, hash));
+    }
+    if status.is_cancelled() {
+        status.end(done, total, "cancelled");
+    } else {
+        status.end(done, total, format!("linted {} files", total));
+    }
+}
+
+#[request(
+    workspace_index,
+    open_buffers,
+    document_status,
+    client_capabilities,
+    config_snapshot
+)]
+async fn lint_workspace(
+    scheduler: Scheduler,
+    params: LintWorkspaceParams,
+) -> Result<usize, RuntimeError> {
+    // open documents with unsaved changes are linted from their buffers,
+    // such that their diagnostics match the editor, the rest being linted
+    // as they're opened
+    let files = workspace_index
+        .iter()
+        .map(|x| (x, unsaved_content(x, &open_buffers, &document_status)))
+        .filter(|(x, unsaved)| unsaved.is_some() || !open_buffers.contains_key(*x))
+        .map(|(x, unsaved)| (x.clone(), unsaved))
+        .collect::<Vec<_>>();
+    drop(workspace_index);
+    drop(open_buffers);
+    drop(document_status);
+    let count = files.len();
+    // deterministic sessions lint inline, only reporting progress under a
+    // token of the client's as creating one awaits the client
+    let deterministic = config_snapshot.deterministic;
+    let token = params.work_done_progress_params.work_done_token;
+    let create_token =
+        token.is_none() && !deterministic && supports_work_done_progress(&client_capabilities);
+    let scope = SettingsScope::from_snapshot(&config_snapshot);
+    let lint = async move {
+        let token = match token {
+            Some(x) => Some(x),
+            None if create_token => progress::create_token(&scheduler, "lintWorkspace").await,
+            None => None,
+        };
+        lint_files(scheduler, files, scope, token).await;
+    };
+    spawn_or_inline(deterministic, || "lint workspace".to_string(), lint).await;
+    Ok(count)
+}
+
+/// Runs the server's commands, `ruffd.fixAll`, `ruffd.previewFix` and
+/// `ruffd.selectProfile`
+///
+/// Edits of fixing all are applied once the command has returned, such
+/// that the client isn't waiting on the command while applying them. The
+/// document is linted first if edited since its checks were, such that the
+/// edits apply to its current text
+#[request(
+    open_buffers,
+    capabilities,
+    client_capabilities,
+    config_snapshot,
+    mut document_status,
+    mut checks,
+    mut ast_cache
+)]
+async fn execute_command(
+    scheduler: Scheduler,
+    params: lsp_types::ExecuteCommandParams,
+) -> Result<Option<serde_json::Value>, RuntimeError> {
+    let command = params.command;
+    let argument = params.arguments.into_iter().next();
+    match command.as_str() {
+        FIX_ALL_COMMAND => {}
+        PREVIEW_FIX_COMMAND => {
+            let params = argument
+                .and_then(|x| serde_json::from_value::<PreviewFixParams>(x).ok())
+                .ok_or(RuntimeError::InvalidCommandArguments(command))?;
+            let uri = normalize_uri(&params.uri);
+            let buffer = open_buffers
+                .get(&uri)
+                .ok_or_else(|| RuntimeError::EditUnopenedDocument(uri.clone()))?;
+            // the check is looked up among the checks of the current text
+            let scope = SettingsScope::from_snapshot(&config_snapshot);
+            let publish = !pulls_diagnostics(&capabilities);
+            if let Some(notification) = lint_in_place(
+                &uri,
+                buffer,
+                scope.clone(),
+                publish,
+                &mut document_status,
+                &mut checks,
+                &mut ast_cache,
+            )
+            .await
+            {
+                scheduler.notify_client(notification);
+            }
+            let registry = checks.get(&uri);
+            let code = lsp_types::NumberOrString::String(params.code);
+            let check = registry
+                .and_then(|x| find_check(x, &code, params.range))
+                .ok_or_else(|| {
+                    RuntimeError::InvalidCommandArguments(format!("no check at {}", uri))
+                })?;
+            let registry_checks = registry.into_iter().flat_map(CheckRegistry::iter);
+            let preview = preview_fix(&uri, buffer, check, registry_checks, scope).await?;
+            return Ok(Some(serde_json::to_value(preview).unwrap()));
+        }
+        SELECT_PROFILE_COMMAND => {
+            let profile = match argument {
+                None | Some(serde_json::Value::Null) => None,
+                Some(serde_json::Value::String(x)) => Some(x),
+                Some(_) => return Err(RuntimeError::InvalidCommandArguments(command)),
+            };
+            if let Some(x) = profile
+                .as_ref()
+                .filter(|x| !config_snapshot.config.profiles.contains_key(*x))
+            {
+                return Err(RuntimeError::InvalidCommandArguments(format!(
+                    "unknown profile {}",
+                    x
+                )));
+            }
+            scheduler.schedule(run_select_profile_op(profile));
+            return Ok(None);
+        }
+        _ => return Err(RuntimeError::UnknownCommand(command)),
+    }
+    // the argument is either the document's uri or `FixAllParams`
+    let params = argument
+        .and_then(
+            |x| match serde_json::from_value::<lsp_types::Url>(x.clone()) {
+                Ok(uri) => Some(FixAllParams {
+                    uri,
+                    format: FixAllFormat::default(),
+                }),
+                Err(_) => serde_json::from_value::<FixAllParams>(x).ok(),
+            },
+        )
+        .ok_or(RuntimeError::InvalidCommandArguments(command))?;
+    let uri = normalize_uri(&params.uri);
+    if config_snapshot.project_config.fix_safety != FixSafety::All {
+        log_warn!("fixing all is disabled by the project's fixSafety");
+        return Ok(None);
+    }
+    let buffer = open_buffers
+        .get(&uri)
+        .ok_or_else(|| RuntimeError::EditUnopenedDocument(uri.clone()))?;
+    let version = document_status.get(&uri).map(|x| x.version);
+    let scope = SettingsScope::from_snapshot(&config_snapshot);
+    let publish = !pulls_diagnostics(&capabilities);
+    let (edits, notification) = fix_all_in_place(
+        &uri,
+        buffer,
+        scope,
+        publish,
+        &mut document_status,
+        &mut checks,
+        &mut ast_cache,
+    )
+    .await;
+    if let Some(notification) = notification {
+        scheduler.notify_client(notification);
+    }
+    if params.format == FixAllFormat::Diff {
+        let registry = checks
+            .get(&uri)
+            .ok_or_else(|| RuntimeError::StaleChecks(uri.clone()))?;
+        let diff = fix_all_diff(&uri, buffer, registry, config_snapshot.position_encoding)?;
+        return Ok(Some(serde_json::Value::String(diff)));
+    }
+    if edits.is_empty() {
+        return Ok(None);
+    }
+    let show_document = config_snapshot.config.show_document_after_fix
+        && supports_show_document(&client_capabilities);
+    let document_changes = supports_document_changes(&client_capabilities);
+    let document = lsp_types::OptionalVersionedTextDocumentIdentifier { uri, version };
+    let name = format!("fix all {}", document.uri);
+    let fix_all = apply_fix_all(scheduler, document, edits, document_changes, show_document);
+    spawn_named(|| name, fix_all);
+    Ok(None)
+}
+
+/// Returns the edits fixing all fixable checks of a document about to be
+/// saved, if `fixOnSave` is `willSaveWaitUntil`
+///
+/// The document is linted first if edited since its checks were, such that
+/// the edits apply to the text being saved
+#[request(
+    open_buffers,
+    capabilities,
+    config_snapshot,
+    mut document_status,
+    mut checks,
+    mut ast_cache
+)]
+async fn will_save_wait_until(
+    scheduler: Scheduler,
+    params: lsp_types::WillSaveTextDocumentParams,
+) -> Result<Option<Vec<lsp_types::TextEdit>>, RuntimeError> {
+    if config_snapshot.config.fix_on_save != FixOnSave::WillSaveWaitUntil {
+        return Ok(None);
+    }
+    let uri = normalize_uri(&params.text_document.uri);
+    // documents too large to buffer aren't fixed
+    let buffer = match open_buffers.get(&uri) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let scope = SettingsScope::from_snapshot(&config_snapshot);
+    let publish = !pulls_diagnostics(&capabilities);
+    let (edits, notification) = fix_all_in_place(
+        &uri,
+        buffer,
+        scope,
+        publish,
+        &mut document_status,
+        &mut checks,
+        &mut ast_cache,
+    )
+    .await;
+    if let Some(notification) = notification {
+        scheduler.notify_client(notification);
+    }
+    Ok(Some(edits))
+}
+
+/// Searches the top-level definitions of the indexed files, reading files
+/// that aren't open from disk
+///
+/// Open documents are copied along with their cached modules, such that
+/// the state is released before anything is parsed or read. Symbols of
+/// documents not yet parsed are kept in the shared `SymbolCache`
+#[request(
+    open_buffers,
+    document_status,
+    ast_cache,
+    workspace_index,
+    symbol_cache
+)]
+async fn workspace_symbol(
+    params: lsp_types::WorkspaceSymbolParams,
+) -> Result<Option<Vec<lsp_types::SymbolInformation>>, RuntimeError> {
+    let files = workspace_index
+        .iter()
+        .map(|uri| {
+            let open =
+                open_buffers
+                    .get(uri)
+                    .zip(document_status.get(uri))
+                    .map(|(buffer, status)| {
+                        let source = buffer.iter().collect::<String>();
+                        (source, ast_cache.get(uri, status.version))
+                    });
+            (uri.clone(), open)
+        })
+        .collect::<Vec<_>>();
+    let cache = symbol_cache.clone();
+    drop(open_buffers);
+    drop(document_status);
+    drop(ast_cache);
+    drop(workspace_index);
+    drop(symbol_cache);
+    let mut rv = vec![];
+    for (uri, open) in files {
+        let symbols = match open {
+            Some((_, Some(suite))) => Arc::new(suite_symbols(&suite)),
+            Some((source, None)) => file_symbols(&cache, &uri, source).await,
+            None => match read_document(&uri).await {
+                Ok(source) => file_symbols(&cache, &uri, source).await,
+                Err(err) => {
+                    log_warn!("{}", err);
+                    continue;
+                }
+            },
+        };
+        rv.extend(
+            symbols
+                .iter()
+                .filter(|x| matches_query(&x.name, &params.query))
+                .map(|x| x.information(uri.clone())),
+        );
+    }
+    Ok(Some(rv))
+}
+
+/// Highlights the occurrences of the variable under the cursor, or of the
+/// attribute or keyword argument of its name
+///
+/// Documents failing to parse, as while they're typed, have their names
+/// resolved without the scopes of their definitions
+///
+/// The document is copied along with its cached module, such that the
+/// state is released before it's parsed
+#[request(open_buffers, document_status, ast_cache, config_snapshot)]
+async fn document_highlight(
+    params: lsp_types::DocumentHighlightParams,
+) -> Result<Option<Vec<lsp_types::DocumentHighlight>>, RuntimeError> {
+    let position = params.text_document_position_params;
+    let uri = normalize_uri(&position.text_document.uri);
+    let (buffer, version) = match open_buffers
+        .get(&uri)
+        .zip(document_status.get(&uri).map(|x| x.version))
+    {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let source = buffer.iter().collect::<String>();
+    let cached = ast_cache.get(&uri, version);
+    let at = buffer.row_col_from_position(
+        &position.position,
+        config_snapshot.position_encoding,
+        PositionBounds::Clamp,
+    )?;
+    drop(open_buffers);
+    drop(document_status);
+    drop(ast_cache);
+    let (source, suite) = parsed_source(&uri, source, cached).await;
+    let names = ResolvedNames::new(&source, &suite);
+    let idx = match names.token_at(BufferPosition::from(at).into()) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let lines = source.lines().collect::<Vec<_>>();
+    let highlights = names
+        .occurrences(idx)
+        .into_iter()
+        .map(|x| lsp_types::DocumentHighlight {
+            range: encode_range(
+                &lines,
+                x.location,
+                x.end_location,
+                config_snapshot.position_encoding,
+            ),
+            kind: Some(match x.kind {
+                NameKind::Read => lsp_types::DocumentHighlightKind::READ,
+                NameKind::Write => lsp_types::DocumentHighlightKind::WRITE,
+                _ => lsp_types::DocumentHighlightKind::TEXT,
+            }),
+        })
+        .collect();
+    Ok(Some(highlights))
+}
+
+/// Source of a document, parsed on the blocking thread pool unless its
+/// module was already cached. Sources failing to parse have an empty module
+async fn parsed_source(
+    uri: &lsp_types::Url,
+    source: String,
+    cached: Option<Arc<Suite>>,
+) -> (String, Arc<Suite>) {
+    if let Some(suite) = cached {
+        return (source, suite);
+    }
+    let parsed = source.clone();
+    let suite = spawn_blocking_named(
+        || format!("parse {}", uri),
+        move || parser::parse_program(&parsed, "<filename>").unwrap_or_default(),
+    )
+    .await
+    .unwrap_or_default();
+    (source, Arc::new(suite))
+}
+
+/// Finds the references to the name under the cursor
+///
+/// Variables of modules are also searched for in the other open documents
+/// and the indexed files, reading files that aren't open from disk
+///
+/// Open documents are copied along with their cached modules, such that
+/// the state is released before anything is parsed or read
+#[request(
+    open_buffers,
+    document_status,
+    ast_cache,
+    workspace_index,
+    config_snapshot
+)]
+async fn references(
+    params: lsp_types::ReferenceParams,
+) -> Result<Option<Vec<lsp_types::Location>>, RuntimeError> {
+    let position = params.text_document_position;
+    let uri = normalize_uri(&position.text_document.uri);
+    let root_path = config_snapshot.project_root.as_ref().and_then(uri_to_path);
+    let open_source = |x: &lsp_types::Url| {
+        let buffer = open_buffers.get(x)?;
+        let version = document_status.get(x)?.version;
+        Some((buffer.iter().collect::<String>(), ast_cache.get(x, version)))
+    };
+    let (source, cached) = match open_source(&uri) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let at = open_buffers[&uri].row_col_from_position(
+        &position.position,
+        config_snapshot.position_encoding,
+        PositionBounds::Clamp,
+    )?;
+    let mut others = open_buffers
+        .keys()
+        .filter(|x| **x != uri)
+        .map(|x| (x.clone(), open_source(x)))
+        .collect::<Vec<_>>();
+    others.sort_by(|a, b| a.0.cmp(&b.0));
+    others.extend(
+        workspace_index
+            .iter()
+            .filter(|x| !open_buffers.contains_key(*x))
+            .map(|x| (x.clone(), None)),
+    );
+    drop(open_buffers);
+    drop(document_status);
+    drop(ast_cache);
+    drop(workspace_index);
+    let (source, suite) = parsed_source(&uri, source, cached).await;
+    let file = SourceFile::new(
+        source,
+        suite,
+        root_path.as_deref(),
+        uri_to_path(&uri).as_deref(),
+    );
+    let symbol = match symbol_at(&file, BufferPosition::from(at).into()) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let mut files = vec![(uri.clone(), file)];
+    if !symbol.is_local() {
+        for (other, open) in others {
+            let (source, suite) = match open {
+                Some((source, cached)) => parsed_source(&other, source, cached).await,
+                None => match read_document(&other).await {
+                    Ok(source) => parsed_source(&other, source, None).await,
+                    Err(err) => {
+                        log_warn!("{}", err);
+                        continue;
+                    }
+                },
+            };
+            let path = uri_to_path(&other);
+            let file = SourceFile::new(source, suite, root_path.as_deref(), path.as_deref());
+            files.push((other, file));
+        }
+    }
+    let mut rv = vec![];
+    for (uri, file) in files {
+        let lines = file.source.lines().collect::<Vec<_>>();
+        let tokens = references_in(&file, &symbol, params.context.include_declaration);
+        rv.extend(tokens.into_iter().map(|x| {
+            let range = encode_range(
+                &lines,
+                x.location,
+                x.end_location,
+                config_snapshot.position_encoding,
+            );
+            lsp_types::Location::new(uri.clone(), range)
+        }));
+    }
+    Ok(Some(rv))
+}
+
+/// Open document copied for the call hierarchy, along with its cached
+/// module and where `position` is in it, such that the state can be
+/// released before it's parsed
+fn function_document(
+    uri: &lsp_types::Url,
+    position: lsp_types::Position,
+    open_buffers: &HashMap<lsp_types::Url, DocumentBuffer>,
+    document_status: &HashMap<lsp_types::Url, DocumentStatus>,
+    ast_cache: &AstCache,
+    position_encoding: PositionEncoding,
+) -> Result<Option<(String, Option<Arc<Suite>>, Location)>, RuntimeError> {
+    let (buffer, version) = match open_buffers
+        .get(uri)
+        .zip(document_status.get(uri).map(|x| x.version))
+    {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let source = buffer.iter().collect::<String>();
+    let at = buffer.row_col_from_position(&position, position_encoding, PositionBounds::Clamp)?;
+    Ok(Some((
+        source,
+        ast_cache.get(uri, version),
+        BufferPosition::from(at).into(),
+    )))
+}
+
+/// Document copied by [`function_document`] parsed for the call hierarchy,
+/// along with the function defined or called where its position is
+async fn function_source(
+    uri: &lsp_types::Url,
+    document: Option<(String, Option<Arc<Suite>>, Location)>,
+) -> Option<(String, ResolvedNames, usize)> {
+    let (source, cached, at) = document?;
+    let (source, suite) = parsed_source(uri, source, cached).await;
+    let names = ResolvedNames::new(&source, &suite);
+    function_at(&names, at).map(|x| (source, names, x))
+}
+
+/// Item of the call hierarchy for `function`, or for the module of `uri`
+/// where calls are made outside of any function
+fn call_hierarchy_item(
+    uri: &lsp_types::Url,
+    names: &ResolvedNames,
+    lines: &[&str],
+    function: Option<usize>,
+    encoding: PositionEncoding,
+) -> lsp_types::CallHierarchyItem {
+    let name_token = function.and_then(|x| names.scope_name(x));
+    let (name, kind, range, selection_range) = match (function, name_token) {
+        (Some(function), Some(token)) => {
+            let (start, end) = names.scope_range(function);
+            let token = names.token(token);
+            (
+                token.name.clone(),
+                lsp_types::SymbolKind::FUNCTION,
+                encode_range(lines, start, end, encoding),
+                encode_range(lines, token.location, token.end_location, encoding),
+            )
+        }
+        _ => {
+            let name = uri
+                .path_segments()
+                .and_then(|mut x| x.next_back())
+                .unwrap_or_default()
+                .to_string();
+            let start = Location::new(1, 0);
+            let end = Location::new(
+                lines.len().max(1),
+                lines.last().map(|x| x.chars().count()).unwrap_or(0),
+            );
+            (
+                name,
+                lsp_types::SymbolKind::MODULE,
+                encode_range(lines, start, end, encoding),
+                encode_range(lines, start, start, encoding),
+            )
+        }
+    };
+    lsp_types::CallHierarchyItem {
+        name,
+        kind,
+        tags: None,
+        detail: None,
+        uri: uri.clone(),
+        range,
+        selection_range,
+        data: None,
+    }
+}
+
+/// Ranges of the names called by `calls`
+fn call_ranges(
+    names: &ResolvedNames,
+    lines: &[&str],
+    calls: &Calls,
+    encoding: PositionEncoding,
+) -> Vec<lsp_types::Range> {
+    calls
+        .tokens
+        .iter()
+        .map(|x| {
+            let token = names.token(*x);
+            encode_range(lines, token.location, token.end_location, encoding)
+        })
+        .collect()
+}
+
+/// Finds the function defined or called at the cursor, as the root of the
+/// call hierarchy
+#[request(open_buffers, document_status, ast_cache, config_snapshot)]
+async fn prepare_call_hierarchy(
+    params: lsp_types::CallHierarchyPrepareParams,
+) -> Result<Option<Vec<lsp_types::CallHierarchyItem>>, RuntimeError> {
+    let position = params.text_document_position_params;
+    let uri = normalize_uri(&position.text_document.uri);
+    let document = function_document(
+        &uri,
+        position.position,
+        &open_buffers,
+        &document_status,
+        &ast_cache,
+        config_snapshot.position_encoding,
+    )?;
+    drop(open_buffers);
+    drop(document_status);
+    drop(ast_cache);
+    let (source, names, function) = match function_source(&uri, document).await {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let lines = source.lines().collect::<Vec<_>>();
+    let item = call_hierarchy_item(
+        &uri,
+        &names,
+        &lines,
+        Some(function),
+        config_snapshot.position_encoding,
+    );
+    Ok(Some(vec![item]))
+}
+
+/// Finds the callers of a function within its document
+#[request(open_buffers, document_status, ast_cache, config_snapshot)]
+async fn incoming_calls_request(
+    params: lsp_types::CallHierarchyIncomingCallsParams,
+) -> Result<Option<Vec<lsp_types::CallHierarchyIncomingCall>>, RuntimeError> {
+    let uri = normalize_uri(&params.item.uri);
+    let document = function_document(
+        &uri,
+        params.item.selection_range.start,
+        &open_buffers,
+        &document_status,
+        &ast_cache,
+        config_snapshot.position_encoding,
+    )?;
+    drop(open_buffers);
+    drop(document_status);
+    drop(ast_cache);
+    let (source, names, function) = match function_source(&uri, document).await {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let lines = source.lines().collect::<Vec<_>>();
+    let rv = incoming_calls(&names, function)
+        .iter()
+        .map(|x| lsp_types::CallHierarchyIncomingCall {
+            from: call_hierarchy_item(
+                &uri,
+                &names,
+                &lines,
+                x.caller,
+                config_snapshot.position_encoding,
+            ),
+            from_ranges: call_ranges(&names, &lines, x, config_snapshot.position_encoding),
+        })
+        .collect();
+    Ok(Some(rv))
+}
+
+/// Finds the functions of its document a function calls
+#[request(open_buffers, document_status, ast_cache, config_snapshot)]
+async fn outgoing_calls_request(
+    params: lsp_types::CallHierarchyOutgoingCallsParams,
+) -> Result<Option<Vec<lsp_types::CallHierarchyOutgoingCall>>, RuntimeError> {
+    let uri = normalize_uri(&params.item.uri);
+    let document = function_document(
+        &uri,
+        params.item.selection_range.start,
+        &open_buffers,
+        &document_status,
+        &ast_cache,
+        config_snapshot.position_encoding,
+    )?;
+    drop(open_buffers);
+    drop(document_status);
+    drop(ast_cache);
+    let (source, names, function) = match function_source(&uri, document).await {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let lines = source.lines().collect::<Vec<_>>();
+    let rv = outgoing_calls(&names, function)
+        .iter()
+        .map(|x| lsp_types::CallHierarchyOutgoingCall {
+            to: call_hierarchy_item(
+                &uri,
+                &names,
+                &lines,
+                Some(x.callee),
+                config_snapshot.position_encoding,
+            ),
+            from_ranges: call_ranges(&names, &lines, x, config_snapshot.position_encoding),
+        })
+        .collect();
+    Ok(Some(rv))
+}
+
+/// Shows the signature of the function called at the cursor, with the
+/// parameter the cursor's argument is passed to
+#[request(open_buffers, config_snapshot)]
+fn signature_help(
+    params: lsp_types::SignatureHelpParams,
+) -> Result<Option<lsp_types::SignatureHelp>, RuntimeError> {
+    let position = params.text_document_position_params;
+    let uri = normalize_uri(&position.text_document.uri);
+    let buffer = match open_buffers.get(&uri) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let source = buffer.iter().collect::<String>();
+    let at = buffer.row_col_from_position(
+        &position.position,
+        config_snapshot.position_encoding,
+        PositionBounds::Clamp,
+    )?;
+    let at = BufferPosition::from(at).into();
+    let call = match call_at(&source, at) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    Ok(signature_for(&source, &call, at).map(|signature| {
+        let information = signature.information(&call);
+        lsp_types::SignatureHelp {
+            active_parameter: information.active_parameter,
+            signatures: vec![information],
+            active_signature: Some(0),
+        }
+    }))
+}
+
 lazy_static! {
     pub(crate) static ref REQUEST_REGISTRY: HashMap<&'static str, Request> = {
-        let pairs = vec![("textDocument/codeAction", doc_code_action)];
+        let pairs = vec![
+            request_entry::<CodeActionRequest>(doc_code_action::typed()),
+            request_entry::<CodeActionResolveRequest>(code_action_resolve::typed()),
+            request_entry::<DocumentDiagnosticRequest>(doc_diagnostic::typed()),
+            request_entry::<RuleInfoRequest>(rule_info::typed()),
+            request_entry::<RuleExplainRequest>(rule_explain::typed()),
+            request_entry::<ProfileLintRequest>(profile_lint::typed()),
+            request_entry::<DocumentStatusRequest>(document_status_report::typed()),
+            request_entry::<ServerStatusRequest>(server_status::typed()),
+            request_entry::<LintWorkspaceRequest>(lint_workspace::typed()),
+            request_entry::<WillRenameFiles>(workspace_will_rename_files::typed()),
+            // the type of `workspace/symbol` is named differently across
+            // versions of lsp_types
+            request_entry::<lsp_types::lsp_request!("workspace/symbol")>(
+                workspace_symbol::typed(),
+            ),
+            request_entry::<ExecuteCommand>(execute_command::typed()),
+            request_entry::<WillSaveWaitUntil>(will_save_wait_until::typed()),
+            request_entry::<DocumentHighlightRequest>(document_highlight::typed()),
+            request_entry::<References>(references::typed()),
+            request_entry::<SignatureHelpRequest>(signature_help::typed()),
+            request_entry::<CallHierarchyPrepare>(prepare_call_hierarchy::typed()),
+            request_entry::<CallHierarchyIncomingCalls>(incoming_calls_request::typed()),
+            request_entry::<CallHierarchyOutgoingCalls>(outgoing_calls_request::typed()),
+        ];
         pairs
             .into_iter()
             .collect::<HashMap<&'static str, Request>>()
     };
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::tokio;
+    use ruffd_types::tokio::sync::mpsc::{channel, Receiver};
+    use ruffd_types::tokio::task;
+    use ruffd_types::{RpcResponseMessage, ScheduledTask, ServerInitiated};
+
+    #[test]
+    fn test_kind_requested() {
+        let quickfix = lsp_types::CodeActionKind::QUICKFIX;
+        let fix_all = lsp_types::CodeActionKind::SOURCE_FIX_ALL;
+        let cases = vec![
+            (vec![], false),
+            (vec![lsp_types::CodeActionKind::QUICKFIX], true),
+            (vec![lsp_types::CodeActionKind::EMPTY], true),
+            (vec![lsp_types::CodeActionKind::SOURCE], false),
+            (vec![lsp_types::CodeActionKind::from("quick")], false),
+        ];
+        for (only, expected) in cases {
+            assert_eq!(
+                kind_requested(&quickfix, Some(&only)),
+                expected,
+                "{:?}",
+                only
+            );
+        }
+        assert!(kind_requested(&quickfix, None));
+        assert!(kind_requested(
+            &fix_all,
+            Some(&[lsp_types::CodeActionKind::SOURCE])
+        ));
+        assert!(!kind_requested(
+            &lsp_types::CodeActionKind::SOURCE,
+            Some(&[lsp_types::CodeActionKind::SOURCE_FIX_ALL])
+        ));
+    }
+
+    #[test]
+    fn test_checks_at_position() {
+        let path = std::path::PathBuf::from("/tmp/dummy.py");
+        let doc = "x = 1\nimport os\n";
+        let registry =
+            CheckRegistry::from_iter(ruffd_types::ruff::check(&path, doc, true).unwrap());
+        // cursor on the second line, 1 in protocol positions
+        let at = |line| {
+            let location = location_from_position(lsp_types::Position::new(line, 2));
+            registry.iter_range(location..=location).count()
+        };
+        assert_eq!(at(0), 0);
+        assert_eq!(at(1), 1);
+    }
+
+    /// Answers the next request the server makes of the client
+    async fn respond(receiver: &mut Receiver<ScheduledTask>, result: serde_json::Value) {
+        let request = match receiver.recv().await.unwrap() {
+            ScheduledTask::Server(ServerInitiated::Request(x), _) => x,
+            _ => panic!("expected a server request"),
+        };
+        let id = lsp_types::NumberOrString::Number(1);
+        (request.on_response)(RpcResponseMessage::from_result(id, result));
+    }
+
+    #[tokio::test]
+    async fn test_apply_fix_all() {
+        let uri = lsp_types::Url::parse("file:///tmp/dummy.py").unwrap();
+        let edits = vec![lsp_types::TextEdit {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(1, 0),
+                lsp_types::Position::new(2, 0),
+            ),
+            new_text: String::new(),
+        }];
+        for (applied, show_document, shown) in [
+            (true, true, true),
+            (true, false, false),
+            (false, true, false),
+        ] {
+            let (sender, mut receiver) = channel(8);
+            let document = lsp_types::OptionalVersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: Some(1),
+            };
+            let fix_all = task::spawn(apply_fix_all(
+                Scheduler::new(sender),
+                document,
+                edits.clone(),
+                true,
+                show_document,
+            ));
+            respond(&mut receiver, serde_json::json!({ "applied": applied })).await;
+            if shown {
+                // the document is shown once the edit is applied
+                respond(&mut receiver, serde_json::json!({ "success": true })).await;
+            }
+            fix_all.await.unwrap();
+            assert!(receiver.try_recv().is_err());
+        }
+    }
+}
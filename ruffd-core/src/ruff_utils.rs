@@ -1,24 +1,48 @@
-use ruffd_types::lsp_types;
-use ruffd_types::ruff::checks::Check;
-use std::collections::HashMap;
-
-pub fn diagnostic_from_check(check: &Check) -> lsp_types::Diagnostic {
-    let range = {
-        // diagnostic is zero indexed, but message rows are 1-indexed
-        let row_start = check.location.row() as u32 - 1;
-        let col_start = check.location.column() as u32;
-        let row_end = check.end_location.row() as u32 - 1;
-        let col_end = check.end_location.column() as u32;
-        let start = lsp_types::Position {
-            line: row_start,
-            character: col_start,
-        };
-        let end = lsp_types::Position {
-            line: row_end,
-            character: col_end,
-        };
-        lsp_types::Range { start, end }
-    };
+use crate::lint::CheckFilter;
+use crate::positions::range_from_locations;
+use glob::{MatchOptions, Pattern};
+use ruffd_types::anyhow;
+use ruffd_types::extensions::RuleInfo;
+use ruffd_types::project::{severity_override, FixSafety, ProjectConfig, Severity};
+use ruffd_types::ruff::checks::{Check, CheckCode, CheckKind};
+use ruffd_types::ruff::directives::{self, extract_directives};
+use ruffd_types::ruff::linter::{check_path, tokenize};
+use ruffd_types::ruff::settings::configuration::Configuration;
+use ruffd_types::ruff::settings::Settings;
+use ruffd_types::ruff::source_code_locator::SourceCodeLocator;
+use ruffd_types::rustpython_ast::Suite;
+use ruffd_types::rustpython_parser::error::ParseError;
+use ruffd_types::rustpython_parser::parser;
+use ruffd_types::uri::uri_to_path;
+use ruffd_types::{
+    log_warn, lsp_types, serde_json, CheckRegistries, CheckRegistry, ConfigSnapshot,
+    GeneratedFilesConfig, LintConfig, LintTimings, PositionEncoding, ServerConfig, SettingsCache,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Severity attached to diagnostics produced from ruff checks
+pub const DEFAULT_SEVERITY: lsp_types::DiagnosticSeverity = lsp_types::DiagnosticSeverity::WARNING;
+
+/// Severity of the diagnostics of a rule, syntax errors being the only
+/// rule to stop a source from being linted at all
+fn default_severity(code: &CheckCode) -> lsp_types::DiagnosticSeverity {
+    match code {
+        CheckCode::E999 => lsp_types::DiagnosticSeverity::ERROR,
+        _ => DEFAULT_SEVERITY,
+    }
+}
+
+/// Creates the diagnostic of a check, where severities overridden by the
+/// project in `overrides` take precedence over those of ruff
+pub fn diagnostic_from_check(
+    check: &Check,
+    overrides: &BTreeMap<String, Severity>,
+) -> lsp_types::Diagnostic {
+    let range = range_from_locations(check.location, check.end_location);
     let code = Some(lsp_types::NumberOrString::String(
         check.kind.code().as_ref().to_string(),
     ));
@@ -29,7 +53,10 @@ pub fn diagnostic_from_check(check: &Check) -> lsp_types::Diagnostic {
         code,
         source,
         message,
-        severity: Some(lsp_types::DiagnosticSeverity::WARNING),
+        severity: Some(
+            severity_override(overrides, check.kind.code().as_ref())
+                .map_or_else(|| default_severity(check.kind.code()), Into::into),
+        ),
         code_description: None,
         tags: None,
         related_information: None,
@@ -37,39 +64,731 @@ pub fn diagnostic_from_check(check: &Check) -> lsp_types::Diagnostic {
     }
 }
 
+/// Creates the diagnostics of the checks of a registry in its order, under
+/// the severity of the registry if it has one
+pub fn registry_diagnostics(
+    registry: &CheckRegistry,
+    overrides: &BTreeMap<String, Severity>,
+) -> Vec<lsp_types::Diagnostic> {
+    let severity = registry.severity().map(Into::into);
+    registry
+        .iter()
+        .map(|x| {
+            let mut diagnostic = diagnostic_from_check(x, overrides);
+            diagnostic.severity = severity.or(diagnostic.severity);
+            diagnostic
+        })
+        .collect()
+}
+
+fn edit_from_check(
+    check: &Check,
+    document_uri: &lsp_types::Url,
+) -> Option<lsp_types::WorkspaceEdit> {
+    check.fix.as_ref().map(|fix| lsp_types::WorkspaceEdit {
+        changes: Some(HashMap::from_iter(vec![(
+            document_uri.clone(),
+            vec![lsp_types::TextEdit {
+                range: range_from_locations(fix.patch.location, fix.patch.end_location),
+                new_text: fix.patch.content.clone(),
+            }],
+        )])),
+        ..Default::default()
+    })
+}
+
+/// Edit of a document changing it by `edits`
+///
+/// Clients accepting `documentChanges` are sent the edits along with the
+/// document's version, such that they reject them once the document has
+/// changed since the edits were computed
+pub fn document_edit(
+    document: lsp_types::OptionalVersionedTextDocumentIdentifier,
+    edits: Vec<lsp_types::TextEdit>,
+    document_changes: bool,
+) -> lsp_types::WorkspaceEdit {
+    if !document_changes {
+        return lsp_types::WorkspaceEdit {
+            changes: Some(HashMap::from([(document.uri, edits)])),
+            ..Default::default()
+        };
+    }
+    let edit = lsp_types::TextDocumentEdit {
+        text_document: document,
+        edits: edits.into_iter().map(lsp_types::OneOf::Left).collect(),
+    };
+    lsp_types::WorkspaceEdit {
+        document_changes: Some(lsp_types::DocumentChanges::Edits(vec![edit])),
+        ..Default::default()
+    }
+}
+
+/// Edits applying every fix of `checks` at once, in document order
+///
+/// Fixes overlapping one earlier in the document are left out, as the
+/// edits of a single change can't overlap. They're fixed by a later pass,
+/// once the document is linted again
+pub fn fix_all_edits<'a, I>(checks: I) -> Vec<lsp_types::TextEdit>
+where
+    I: IntoIterator<Item = &'a Check>,
+{
+    let mut patches = checks
+        .into_iter()
+        .filter_map(|x| x.fix.as_ref())
+        .map(|x| &x.patch)
+        .collect::<Vec<_>>();
+    patches.sort_by_key(|x| {
+        (
+            x.location.row(),
+            x.location.column(),
+            x.end_location.row(),
+            x.end_location.column(),
+        )
+    });
+    let mut rv: Vec<lsp_types::TextEdit> = vec![];
+    for patch in patches {
+        let range = range_from_locations(patch.location, patch.end_location);
+        if rv.last().filter(|x| x.range.end > range.start).is_some() {
+            continue;
+        }
+        rv.push(lsp_types::TextEdit {
+            range,
+            new_text: patch.content.clone(),
+        });
+    }
+    rv
+}
+
+/// Creates a quick fix for a check if it's fixable
+///
+/// When `lazy` the edit is left for `codeAction/resolve` to compute, with
+/// the action instead carrying the data required to find the check again
 pub fn action_from_check(
     check: &Check,
     document_uri: &lsp_types::Url,
+    lazy: bool,
+    overrides: &BTreeMap<String, Severity>,
 ) -> Option<lsp_types::CodeAction> {
-    check.fix.as_ref().map(|fix| {
-        let row_start = fix.patch.location.row() as u32 - 1;
-        let row_end = fix.patch.end_location.row() as u32 - 1;
-        let col_start = fix.patch.location.column() as u32;
-        let col_end = fix.patch.end_location.column() as u32;
-        lsp_types::CodeAction {
-            title: format!("fix {}", check.kind.code().as_ref()),
-            kind: Some(lsp_types::CodeActionKind::QUICKFIX),
-            diagnostics: Some(vec![diagnostic_from_check(check)]),
-            edit: Some(lsp_types::WorkspaceEdit {
-                changes: Some(HashMap::from_iter(vec![(
-                    document_uri.clone(),
-                    vec![lsp_types::TextEdit {
-                        range: lsp_types::Range {
-                            start: lsp_types::Position {
-                                line: row_start,
-                                character: col_start,
-                            },
-                            end: lsp_types::Position {
-                                line: row_end,
-                                character: col_end,
-                            },
-                        },
-                        new_text: fix.patch.content.clone(),
-                    }],
-                )])),
-                ..Default::default()
-            }),
+    check.fix.as_ref()?;
+    let diagnostic = diagnostic_from_check(check, overrides);
+    let (edit, data) = if lazy {
+        let data = serde_json::json!({
+            "uri": document_uri,
+            "code": diagnostic.code,
+            "range": diagnostic.range,
+        });
+        (None, Some(data))
+    } else {
+        (edit_from_check(check, document_uri), None)
+    };
+    Some(lsp_types::CodeAction {
+        title: format!("fix {}", check.kind.code().as_ref()),
+        kind: Some(lsp_types::CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic]),
+        edit,
+        data,
+        ..Default::default()
+    })
+}
+
+/// Computes the edit of a lazily created quick fix
+///
+/// The action is returned unchanged if its check no longer exists, as is
+/// the case when the document changed since the action was offered
+pub fn resolve_action(
+    mut action: lsp_types::CodeAction,
+    checks: &CheckRegistries,
+) -> lsp_types::CodeAction {
+    if action.edit.is_some() {
+        return action;
+    }
+    let data = match action.data.as_ref() {
+        Some(x) => x,
+        None => return action,
+    };
+    let uri = serde_json::from_value::<lsp_types::Url>(data["uri"].clone());
+    let code = serde_json::from_value::<lsp_types::NumberOrString>(data["code"].clone());
+    let range = serde_json::from_value::<lsp_types::Range>(data["range"].clone());
+    if let (Ok(uri), Ok(code), Ok(range)) = (uri, code, range) {
+        action.edit = checks
+            .get(&uri)
+            .and_then(|registry| find_check(registry, &code, range))
+            .and_then(|check| edit_from_check(check, &uri));
+    }
+    action
+}
+
+/// Finds the check reported with `code` at `range`
+pub fn find_check<'a>(
+    registry: &'a CheckRegistry,
+    code: &lsp_types::NumberOrString,
+    range: lsp_types::Range,
+) -> Option<&'a Check> {
+    registry.iter().find(|check| {
+        range_from_locations(check.location, check.end_location) == range
+            && matches!(code, lsp_types::NumberOrString::String(x) if x == check.kind.code().as_ref())
+    })
+}
+
+/// Looks up metadata for a rule code, returning `None` if ruff doesn't
+/// recognise the code
+pub fn rule_info_from_code(code: &str) -> Option<RuleInfo> {
+    let check_code = CheckCode::from_str(code).ok()?;
+    // explanation and name are taken from the representative kind of the code
+    let kind = check_code.kind();
+    Some(RuleInfo {
+        code: check_code.as_ref().to_string(),
+        name: kind.as_ref().to_string(),
+        category: check_code.category().title().to_string(),
+        explanation: kind.body(),
+        fixable: kind.fixable(),
+        default_severity: default_severity(&check_code),
+    })
+}
+
+/// Determines whether a `pyproject.toml` configures ruff, as opposed to
+/// only describing a package
+fn configures_ruff(pyproject: &Path) -> bool {
+    fs::read_to_string(pyproject)
+        .map(|x| {
+            x.lines().any(|line| {
+                let line = line.trim();
+                line == "[tool.ruff]" || line.starts_with("[tool.ruff.")
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Directory whose `pyproject.toml` configures ruff for the file at `path`
+///
+/// The nearest ancestor configuring ruff is taken, such that packages
+/// nested in the workspace may carry their own settings while packaging
+/// only pyprojects don't hide the workspace's. Falls back to the project
+/// root, relative to which default settings are resolved
+pub fn settings_root(path: &Path, project_root: Option<&Path>) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .find(|x| configures_ruff(&x.join("pyproject.toml")))
+        .or(project_root)
+        .map(Path::to_path_buf)
+}
+
+/// Workspace wide inputs to resolving the settings of a file, and to
+/// reporting its checks
+#[derive(Debug, Clone, Default)]
+pub struct SettingsScope {
+    pub project_root: Option<PathBuf>,
+    /// First-party roots configured for the server, taking the place of
+    /// inferred roots when not empty
+    pub src: Vec<PathBuf>,
+    /// Rule selection of the editor, layered over each pyproject's
+    pub lint: LintConfig,
+    /// Encoding the columns of checks are converted to once linted
+    pub encoding: PositionEncoding,
+    /// Paths whose checks aren't reported, relative to the project root
+    pub suppressed: Vec<Pattern>,
+    pub generated_files: GeneratedFilesConfig,
+    /// How fixes are offered, as set by the project
+    pub fix_safety: FixSafety,
+    /// Severities of rules overridden by the project
+    pub severity_overrides: BTreeMap<String, Severity>,
+    /// Generation of the settings the scope was taken from
+    pub generation: u64,
+    /// Ruff's settings resolved under the scope
+    pub settings_cache: SettingsCache,
+    /// Durations of lints run under the scope, excluding the time spent
+    /// waiting for a free slot
+    pub lint_timings: LintTimings,
+}
+
+/// Parses glob patterns, skipping those that are invalid
+fn glob_patterns(patterns: &[String]) -> Vec<Pattern> {
+    patterns
+        .iter()
+        .filter_map(|x| match Pattern::new(x) {
+            Ok(pattern) => Some(pattern),
+            Err(err) => {
+                log_warn!("ignoring invalid pattern {}: {}", x, err);
+                None
+            }
+        })
+        .collect()
+}
+
+impl SettingsScope {
+    pub fn new(project_root: Option<&lsp_types::Url>, config: &ServerConfig) -> Self {
+        let project_root = project_root.and_then(uri_to_path);
+        let src = config
+            .src
+            .iter()
+            .map(|x| match &project_root {
+                Some(root) => root.join(x),
+                None => x.clone(),
+            })
+            .collect();
+        Self {
+            project_root,
+            src,
+            lint: config.lint_config(),
+            encoding: PositionEncoding::default(),
+            suppressed: glob_patterns(&config.suppress_diagnostics),
+            generated_files: config.generated_files.clone(),
+            fix_safety: FixSafety::default(),
+            severity_overrides: BTreeMap::new(),
+            generation: 0,
+            settings_cache: SettingsCache::default(),
+            lint_timings: LintTimings::default(),
+        }
+    }
+
+    pub fn with_encoding(mut self, encoding: PositionEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Takes the settings of the project's `[tool.ruffd]` table
+    pub fn with_project(mut self, project: &ProjectConfig) -> Self {
+        self.fix_safety = project.fix_safety;
+        self.severity_overrides = project.severity_overrides.clone();
+        self
+    }
+
+    /// Scope of the settings as of `snapshot`
+    pub fn from_snapshot(snapshot: &ConfigSnapshot) -> Self {
+        Self {
+            generation: snapshot.generation,
+            settings_cache: snapshot.settings_cache.clone(),
+            lint_timings: snapshot.lint_timings.clone(),
+            ..Self::new(snapshot.project_root.as_ref(), &snapshot.config)
+                .with_encoding(snapshot.position_encoding)
+                .with_project(&snapshot.project_config)
+        }
+    }
+
+    /// Whether fixes are applied in bulk, by fixing all or on save
+    pub fn fixes_all(&self) -> bool {
+        self.fix_safety == FixSafety::All
+    }
+
+    /// Path the document at `uri` is linted as
+    ///
+    /// Unsaved `untitled:` buffers have no path, so are linted as a file of
+    /// the same name at the project root, resolving the settings of the
+    /// project. Documents of other schemes, such as notebook cells linted
+    /// along with their notebook, have none
+    pub fn lint_path(&self, uri: &lsp_types::Url) -> Option<PathBuf> {
+        if uri.scheme() != "untitled" {
+            return uri_to_path(uri);
+        }
+        let root = match &self.project_root {
+            Some(x) => x.clone(),
+            None => std::env::current_dir().ok()?,
+        };
+        let name = uri
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| match c.is_alphanumeric() || "-_.".contains(c) {
+                true => c,
+                false => '_',
+            })
+            .collect::<String>();
+        let name = match name.trim_matches('.') {
+            "" => "untitled",
+            x => x,
+        };
+        let path = root.join(name);
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("py" | "pyi" | "ipynb") => Some(path),
+            _ => Some(root.join(format!("{}.py", name))),
+        }
+    }
+
+    /// Filter stages run over the checks of each lint, in order
+    pub fn filters(&self) -> [&dyn CheckFilter; 2] {
+        [&self.generated_files, &self.fix_safety]
+    }
+
+    /// Determines whether the checks of the file at `path` go unreported
+    ///
+    /// Paths outside of the project root are matched whole. `*` doesn't
+    /// match across directories, whereas `**` does
+    pub fn suppresses(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
             ..Default::default()
+        };
+        let path = self
+            .project_root
+            .as_ref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        self.suppressed
+            .iter()
+            .any(|x| x.matches_path_with(path, options))
+    }
+}
+
+/// Infers the first-party roots of the file at `path` from the layout of
+/// its project
+///
+/// These are the settings root itself and its `src` directory as used by
+/// src-layout projects, along with the directory holding the outermost
+/// package of the file. Namespace packages lack an `__init__.py`, so are
+/// only found below one of the former
+pub fn infer_src(path: &Path, root: Option<&Path>) -> Vec<PathBuf> {
+    let mut src = vec![];
+    if let Some(root) = root {
+        src.push(root.to_path_buf());
+        let src_dir = root.join("src");
+        if src_dir.is_dir() {
+            src.push(src_dir);
         }
-    })
+    }
+    let package_parent = path
+        .ancestors()
+        .skip(1)
+        .take_while(|x| x.join("__init__.py").is_file())
+        .last()
+        .and_then(Path::parent);
+    if let Some(package_parent) = package_parent {
+        if !src.iter().any(|x| x == package_parent) {
+            src.push(package_parent.to_path_buf());
+        }
+    }
+    src
+}
+
+/// Resolves ruff's settings for the file at `path`, such that per-file
+/// ignores, source roots and the target version are relative to the
+/// pyproject configuring it rather than the workspace root
+///
+/// First-party roots of the scope, or those inferred if it has none, are
+/// added to any configured in the pyproject. Settings are cached by the
+/// scope for the file's directory, as they're the same for its other files
+pub fn resolve_settings(path: &Path, scope: &SettingsScope) -> anyhow::Result<Arc<Settings>> {
+    let dir = path.parent().unwrap_or(path);
+    scope
+        .settings_cache
+        .get_or_resolve(dir, || resolve_uncached_settings(path, scope))
+}
+
+fn resolve_uncached_settings(path: &Path, scope: &SettingsScope) -> anyhow::Result<Settings> {
+    let root = settings_root(path, scope.project_root.as_deref());
+    let pyproject = root
+        .as_ref()
+        .map(|x| x.join("pyproject.toml"))
+        .filter(|x| x.is_file());
+    let mut configuration = Configuration::from_pyproject(&pyproject, &root)?;
+    scope.lint.apply(&mut configuration);
+    let mut settings = Settings::from_configuration(configuration);
+    let src = if scope.src.is_empty() {
+        infer_src(path, root.as_deref())
+    } else {
+        scope.src.clone()
+    };
+    for dir in src {
+        if !settings.src.contains(&dir) {
+            settings.src.push(dir);
+        }
+    }
+    Ok(settings)
+}
+
+/// Check reporting where a source failed to parse
+fn syntax_error_check(err: &ParseError) -> Check {
+    Check {
+        kind: CheckKind::SyntaxError(err.error.to_string()),
+        location: err.location,
+        end_location: err.location,
+        fix: None,
+    }
+}
+
+/// Lints `contents` as the file at `path` under the given settings, as
+/// `ruff::check` does under the settings it finds itself
+///
+/// A source failing to parse has the syntax error as its only check,
+/// whether or not the settings select it, as no other check is reliable
+pub fn check_with_settings(
+    path: &Path,
+    contents: &str,
+    settings: &Settings,
+    autofix: bool,
+) -> anyhow::Result<Vec<Check>> {
+    check_module(path, contents, settings, autofix).map(|(checks, _)| checks)
+}
+
+/// Lints `contents` as `check_with_settings` does, along with the module
+/// it parsed to such that it needn't be parsed again, being `None` for a
+/// source failing to parse
+pub fn check_module(
+    path: &Path,
+    contents: &str,
+    settings: &Settings,
+    autofix: bool,
+) -> anyhow::Result<(Vec<Check>, Option<Suite>)> {
+    match parser::parse_program(contents, "<filename>") {
+        Ok(suite) => Ok((
+            check_parsed(path, contents, settings, autofix)?,
+            Some(suite),
+        )),
+        Err(err) => Ok((vec![syntax_error_check(&err)], None)),
+    }
+}
+
+/// Lints `contents` known to parse, being the stages of
+/// `check_with_settings` following parsing
+pub fn check_parsed(
+    path: &Path,
+    contents: &str,
+    settings: &Settings,
+    autofix: bool,
+) -> anyhow::Result<Vec<Check>> {
+    let tokens = tokenize(contents);
+    let locator = SourceCodeLocator::new(contents);
+    let directives = extract_directives(
+        &tokens,
+        &locator,
+        &directives::Flags::from_settings(settings),
+    );
+    check_path(
+        path,
+        contents,
+        tokens,
+        &locator,
+        &directives,
+        settings,
+        autofix,
+        false,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::ruff::checks_gen::CheckCodePrefix;
+
+    #[test]
+    fn test_lint_path() {
+        let root = std::env::temp_dir().join("project");
+        let scope = SettingsScope {
+            project_root: Some(root.clone()),
+            ..Default::default()
+        };
+        let url = |x: &str| lsp_types::Url::parse(x).unwrap();
+        let cases = [
+            ("untitled:Untitled-1", Some(root.join("Untitled-1.py"))),
+            (
+                "untitled:Untitled-2.ipynb",
+                Some(root.join("Untitled-2.ipynb")),
+            ),
+            ("untitled:/tmp/../scratch", Some(root.join("scratch.py"))),
+            ("untitled:..", Some(root.join("untitled.py"))),
+            ("vscode-notebook-cell:/tmp/a.ipynb#W0sZmlsZQ", None),
+        ];
+        for (uri, expected) in cases {
+            assert_eq!(scope.lint_path(&url(uri)), expected, "{}", uri);
+        }
+        let path = std::env::temp_dir().join("a.py");
+        let mut uri = lsp_types::Url::from_file_path(&path).unwrap();
+        uri.set_query(Some("version=2"));
+        uri.set_fragment(Some("L3"));
+        assert_eq!(scope.lint_path(&uri), Some(path));
+    }
+
+    #[test]
+    fn test_suppresses() {
+        let root = std::env::temp_dir().join("project");
+        let config = ServerConfig {
+            suppress_diagnostics: vec![
+                "**/migrations/*.py".to_string(),
+                "generated_*.py".to_string(),
+                "[".to_string(),
+            ],
+            ..Default::default()
+        };
+        let root_uri = lsp_types::Url::from_directory_path(&root).unwrap();
+        let scope = SettingsScope::new(Some(&root_uri), &config);
+        assert_eq!(scope.suppressed.len(), 2);
+        let cases = [
+            ("migrations/0001_initial.py", true),
+            ("app/migrations/0001_initial.py", true),
+            ("app/migrations/nested/0001_initial.py", false),
+            ("generated_models.py", true),
+            ("app/generated_models.py", false),
+            ("app/models.py", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(scope.suppresses(&root.join(path)), expected, "{}", path);
+        }
+        assert!(!SettingsScope::default().suppresses(&root.join("generated_models.py")));
+    }
+
+    #[test]
+    fn test_rule_info() {
+        let info = rule_info_from_code("F401").unwrap();
+        assert_eq!(info.code, "F401");
+        assert_eq!(info.name, "UnusedImport");
+        assert!(info.fixable);
+        assert!(rule_info_from_code("not a code").is_none());
+    }
+
+    #[test]
+    fn test_syntax_error_diagnostic() {
+        let path = PathBuf::from("/tmp/dummy.py");
+        let settings = resolve_settings(&path, &SettingsScope::default()).unwrap();
+        let check_vec = check_with_settings(&path, "import os\nx = (\n", &settings, true).unwrap();
+        assert_eq!(check_vec.len(), 1);
+        let diagnostic = diagnostic_from_check(&check_vec[0], &BTreeMap::new());
+        assert_eq!(
+            diagnostic.severity,
+            Some(lsp_types::DiagnosticSeverity::ERROR)
+        );
+        assert_eq!(diagnostic.range.start.line, 1);
+        assert!(diagnostic.message.contains("SyntaxError"));
+        let overrides = BTreeMap::from([("E9".to_string(), Severity::Hint)]);
+        let diagnostic = diagnostic_from_check(&check_vec[0], &overrides);
+        assert_eq!(
+            diagnostic.severity,
+            Some(lsp_types::DiagnosticSeverity::HINT)
+        );
+    }
+
+    #[test]
+    fn test_fix_all_edits() {
+        let path = PathBuf::from("/tmp/dummy.py");
+        let settings = resolve_settings(&path, &SettingsScope::default()).unwrap();
+        let check_vec =
+            check_with_settings(&path, "import sys\nimport os\n", &settings, true).unwrap();
+        // checks repeated overlap with themselves, so are fixed once
+        let edits = fix_all_edits(check_vec.iter().rev().chain(check_vec.iter()));
+        let starts = edits.iter().map(|x| x.range.start).collect::<Vec<_>>();
+        assert_eq!(
+            starts,
+            vec![
+                lsp_types::Position::new(0, 0),
+                lsp_types::Position::new(1, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_overrides() {
+        let mut configuration = Configuration::from_pyproject(&None, &None).unwrap();
+        let pyproject_select = configuration.select.clone();
+        let lint = LintConfig {
+            extend_select: vec!["F401".to_string(), "X000".to_string()],
+            ignore: vec!["E9".to_string()],
+            ..LintConfig::default()
+        };
+        lint.apply(&mut configuration);
+        // unset overrides keep the pyproject's selection, unknown codes are
+        // skipped
+        assert_eq!(configuration.select, pyproject_select);
+        assert_eq!(configuration.extend_select, vec![CheckCodePrefix::F401]);
+        assert_eq!(configuration.ignore, vec![CheckCodePrefix::E9]);
+        let lint = LintConfig {
+            select: vec!["F".to_string()],
+            ..LintConfig::default()
+        };
+        lint.apply(&mut configuration);
+        assert_eq!(configuration.select, vec![CheckCodePrefix::F]);
+        assert_eq!(configuration.ignore, vec![CheckCodePrefix::E9]);
+    }
+
+    #[test]
+    fn test_settings_root_nested() {
+        let root = std::env::temp_dir().join(format!("ruffd-settings-{}", std::process::id()));
+        let package = root.join("packages").join("package");
+        let configured = root.join("packages").join("configured");
+        fs::create_dir_all(package.join("src")).unwrap();
+        fs::create_dir_all(configured.join("src")).unwrap();
+        fs::write(
+            root.join("pyproject.toml"),
+            "[tool.ruff]\nline-length = 100\n",
+        )
+        .unwrap();
+        // packaging only, so the workspace's settings apply
+        fs::write(
+            package.join("pyproject.toml"),
+            "[project]\nname = \"package\"\n",
+        )
+        .unwrap();
+        fs::write(
+            configured.join("pyproject.toml"),
+            "[tool.ruff.per-file-ignores]\n\"__init__.py\" = [\"F401\"]\n",
+        )
+        .unwrap();
+        let module = |dir: &Path| dir.join("src").join("module.py");
+        assert_eq!(settings_root(&module(&package), None), Some(root.clone()));
+        assert_eq!(
+            settings_root(&module(&configured), Some(&root)),
+            Some(configured.clone())
+        );
+        assert_eq!(
+            settings_root(&root.join("module.py"), None),
+            Some(root.clone())
+        );
+        fs::remove_file(root.join("pyproject.toml")).unwrap();
+        assert_eq!(
+            settings_root(&module(&package), Some(&root)),
+            Some(root.clone())
+        );
+        assert_eq!(settings_root(&module(&package), None), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_settings_cached() {
+        let dir = std::env::temp_dir();
+        let scope = SettingsScope::default();
+        let settings = resolve_settings(&dir.join("a.py"), &scope).unwrap();
+        // files of the same directory share settings
+        let cached = resolve_settings(&dir.join("b.py"), &scope).unwrap();
+        assert!(Arc::ptr_eq(&settings, &cached));
+        let nested = resolve_settings(&dir.join("package").join("a.py"), &scope).unwrap();
+        assert!(!Arc::ptr_eq(&settings, &nested));
+        // a scope taken after the settings changed resolves them again
+        let mut snapshot = ConfigSnapshot {
+            settings_cache: scope.settings_cache.clone(),
+            ..Default::default()
+        };
+        snapshot.settings_changed();
+        let changed = SettingsScope::from_snapshot(&snapshot);
+        let resolved = resolve_settings(&dir.join("a.py"), &changed).unwrap();
+        assert!(!Arc::ptr_eq(&settings, &resolved));
+    }
+
+    #[test]
+    fn test_infer_src() {
+        let root = std::env::temp_dir().join(format!("ruffd-src-{}", std::process::id()));
+        let package = root.join("src").join("package");
+        let namespace = root.join("libs").join("namespace");
+        fs::create_dir_all(package.join("sub")).unwrap();
+        fs::create_dir_all(&namespace).unwrap();
+        fs::write(package.join("__init__.py"), "").unwrap();
+        fs::write(package.join("sub").join("__init__.py"), "").unwrap();
+        let src_dir = root.join("src");
+        assert_eq!(
+            infer_src(&package.join("sub").join("module.py"), Some(&root)),
+            vec![root.clone(), src_dir.clone()]
+        );
+        assert_eq!(
+            infer_src(&namespace.join("module.py"), Some(&root)),
+            vec![root.clone(), src_dir.clone()]
+        );
+        assert_eq!(
+            infer_src(&package.join("module.py"), None),
+            vec![src_dir.clone()]
+        );
+        fs::write(namespace.join("__init__.py"), "").unwrap();
+        assert_eq!(
+            infer_src(&namespace.join("module.py"), Some(&root)),
+            vec![root.clone(), src_dir, root.join("libs")]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
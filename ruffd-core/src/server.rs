@@ -1,11 +1,29 @@
+#[cfg(feature = "tcp")]
+use crate::progress;
 use crate::service::Service;
-use ruffd_types::tokio::io::{self, AsyncRead, AsyncWrite};
+#[cfg(feature = "tcp")]
+use crate::service::SessionOutcome;
+#[cfg(feature = "tcp")]
+use ruffd_types::contention;
+#[cfg(feature = "tcp")]
+use ruffd_types::log_info;
+use ruffd_types::log_warn;
+#[cfg(feature = "tcp")]
+use ruffd_types::tokio::io::AsyncRead;
+use ruffd_types::tokio::io::{self, AsyncWrite};
+#[cfg(feature = "tcp")]
 use ruffd_types::tokio::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "tcp")]
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "tcp")]
 use std::sync::{Arc, Mutex};
+#[cfg(feature = "tcp")]
+use std::time::Duration;
 
-type StdioService = Service<io::BufReader<io::Stdin>, io::Stdout>;
+type StdioWriter = Box<dyn AsyncWrite + Send + Unpin>;
+type StdioService = Service<io::BufReader<io::Stdin>, StdioWriter>;
+#[cfg(feature = "tcp")]
 type TcpService = Service<io::BufReader<TcpReader>, TcpWriter>;
 
 static STDIO_SERVER_COUNT: AtomicUsize = AtomicUsize::new(0);
@@ -32,23 +50,49 @@ impl Default for StdioServer {
         if prev_count != 0 {
             panic!("Cannot instantiate more than one StdioServer")
         }
-        let stdout = io::stdout();
+        let stdout = claim_stdout();
         let stdin = io::BufReader::new(io::stdin());
         let inner = Service::new(stdin, stdout);
         Self { inner }
     }
 }
 
+/// Takes exclusive ownership of stdout for the protocol, redirecting the
+/// process's stdout to stderr such that stray prints can't corrupt messages
+#[cfg(unix)]
+fn claim_stdout() -> StdioWriter {
+    use std::os::unix::io::FromRawFd;
+    // SAFETY: only standard descriptors are duplicated, and the duplicate
+    // of stdout is owned solely by the returned file
+    let protocol_fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if protocol_fd < 0 || unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        log_warn!("failed redirecting stdout, stray prints may corrupt the protocol");
+        if protocol_fd >= 0 {
+            unsafe { libc::close(protocol_fd) };
+        }
+        return Box::new(io::stdout());
+    }
+    let file = unsafe { std::fs::File::from_raw_fd(protocol_fd) };
+    Box::new(ruffd_types::tokio::fs::File::from_std(file))
+}
+
+#[cfg(not(unix))]
+fn claim_stdout() -> StdioWriter {
+    Box::new(io::stdout())
+}
+
 impl StdioServer {
     pub fn get_service_mut(&mut self) -> &mut StdioService {
         &mut self.inner
     }
 }
 
+#[cfg(feature = "tcp")]
 pub struct TcpReader {
     inner: Arc<Mutex<TcpStream>>,
 }
 
+#[cfg(feature = "tcp")]
 impl AsyncRead for TcpReader {
     fn poll_read(
         self: Pin<&mut Self>,
@@ -61,10 +105,12 @@ impl AsyncRead for TcpReader {
     }
 }
 
+#[cfg(feature = "tcp")]
 pub struct TcpWriter {
     inner: Arc<Mutex<TcpStream>>,
 }
 
+#[cfg(feature = "tcp")]
 impl AsyncWrite for TcpWriter {
     fn poll_write(
         self: Pin<&mut Self>,
@@ -110,10 +156,12 @@ impl AsyncWrite for TcpWriter {
 /// type capable of producing a service communicating to a client,
 /// over a TcpSocket, however the connection is initialized from this side,
 /// rather than binding to a port and listening, hence behaving more like a client
+#[cfg(feature = "tcp")]
 pub struct TcpServer {
     inner: TcpService,
 }
 
+#[cfg(feature = "tcp")]
 impl TcpServer {
     pub async fn connect<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
         let stream = TcpStream::connect(addr).await?;
@@ -128,4 +176,187 @@ impl TcpServer {
     pub fn get_service_mut(&mut self) -> &mut TcpService {
         &mut self.inner
     }
+
+    /// Serves the client at `addr` until it asks the server to exit or the
+    /// server fails fatally, connecting again whenever the connection is
+    /// lost, such as when the editor restarts
+    ///
+    /// Each connection is served by a fresh service, and so begins with a
+    /// fresh server state, configured by `setup`, what the process keeps of
+    /// a session being reset once it's lost. Failed connections are retried
+    /// as given by `backoff`, returning the last error once it gives up
+    pub async fn run_reconnecting<A, F>(
+        addr: A,
+        backoff: Backoff,
+        mut setup: F,
+    ) -> std::io::Result<SessionOutcome>
+    where
+        A: ToSocketAddrs + Clone,
+        F: FnMut(&mut TcpService),
+    {
+        let mut attempt = 0;
+        loop {
+            let mut server = match Self::connect(addr.clone()).await {
+                Ok(x) => x,
+                Err(err) => {
+                    let delay = backoff.delay(attempt).ok_or(err)?;
+                    log_warn!("failed connecting to client, retrying in {:?}", delay);
+                    ruffd_types::tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+            };
+            attempt = 0;
+            let service = server.get_service_mut();
+            setup(service);
+            match service.run().await {
+                SessionOutcome::Disconnected => {
+                    log_info!("lost the client, reconnecting");
+                    reset_session();
+                }
+                outcome => return Ok(outcome),
+            }
+        }
+    }
+}
+
+/// Clears what the process keeps of a lost session, such that the next
+/// session begins afresh: operations in progress are cancelled, as their
+/// client is gone, and the lock waits of the status are forgotten
+///
+/// Spills of the lost session are left for recovery, as each session spills
+/// to its own directory
+#[cfg(feature = "tcp")]
+fn reset_session() {
+    progress::cancel_all();
+    contention::reset();
+}
+
+/// Delays between attempts to connect, doubling from `initial` up to `max`
+#[cfg(feature = "tcp")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// Failed attempts in a row after which connecting is given up
+    pub attempts: u32,
+}
+
+#[cfg(feature = "tcp")]
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+            attempts: 12,
+        }
+    }
+}
+
+#[cfg(feature = "tcp")]
+impl Backoff {
+    /// Delay following the failure of the `attempt`th attempt in a row,
+    /// counting from 0, `None` once connecting is to be given up
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt + 1 >= self.attempts {
+            return None;
+        }
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
+
+#[cfg(all(test, feature = "tcp"))]
+mod test {
+    use super::*;
+    use ruffd_types::serde_json;
+    use ruffd_types::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use ruffd_types::tokio::net::TcpListener;
+    use ruffd_types::tokio::{self, task};
+
+    async fn send(stream: &mut TcpStream, value: serde_json::Value) {
+        let body = value.to_string();
+        let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        stream.write_all(frame.as_bytes()).await.unwrap();
+    }
+
+    /// Reads until the end of the first frame, which is the only one sent
+    /// in response to initializing
+    async fn recv_frame(stream: &mut TcpStream) {
+        let mut received = vec![];
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&received);
+            if let Some((header, body)) = text.split_once("\r\n\r\n") {
+                let len = header["Content-Length: ".len()..].parse::<usize>().unwrap();
+                if body.len() >= len {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn initialize(stream: &mut TcpStream) {
+        send(
+            stream,
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": {"capabilities": {}}
+            }),
+        )
+        .await;
+        recv_frame(stream).await;
+    }
+
+    #[tokio::test]
+    async fn test_run_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let backoff = Backoff {
+            initial: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let mut sessions = 0;
+        let server_task = task::spawn(async move {
+            let rv = TcpServer::run_reconnecting(addr, backoff, |_| sessions += 1).await;
+            (rv, sessions)
+        });
+        let (mut stream, _) = listener.accept().await.unwrap();
+        initialize(&mut stream).await;
+        drop(stream);
+        // the server connects again once the client is lost, and exits only
+        // when asked to
+        let (mut stream, _) = listener.accept().await.unwrap();
+        initialize(&mut stream).await;
+        send(
+            &mut stream,
+            serde_json::json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+        )
+        .await;
+        send(
+            &mut stream,
+            serde_json::json!({"jsonrpc": "2.0", "method": "exit"}),
+        )
+        .await;
+        let (rv, sessions) = server_task.await.unwrap();
+        assert_eq!(rv.unwrap(), SessionOutcome::Exit);
+        assert_eq!(sessions, 2);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            attempts: 40,
+        };
+        assert_eq!(backoff.delay(0), Some(Duration::from_millis(100)));
+        assert_eq!(backoff.delay(3), Some(Duration::from_millis(800)));
+        assert_eq!(backoff.delay(4), Some(Duration::from_secs(1)));
+        // doubling past the range of the factor stays at the maximum
+        assert_eq!(backoff.delay(38), Some(Duration::from_secs(1)));
+        assert_eq!(backoff.delay(39), None);
+    }
 }
@@ -1,60 +1,1077 @@
-use crate::ruff_utils::diagnostic_from_check;
-use ruffd_types::ruff::check;
+use crate::fs::uri_to_path_checked;
+use crate::lint::{lint, lint_module, LintPanicked, Linted};
+#[cfg(feature = "notebook")]
+use crate::notebook::NotebookSource;
+use crate::positions::{edit_delta_from_change, shift_check};
+use crate::ruff_utils::{document_edit, fix_all_edits, registry_diagnostics, SettingsScope};
+use crate::spill;
+use ruffd_types::capabilities::supports_diagnostic_refresh;
+use ruffd_types::extensions::WatcherStatus;
+use ruffd_types::layered::ResolvedConfig;
+use ruffd_types::project::{ProjectConfig, Severity};
+use ruffd_types::tasks::{spawn_blocking_named, spawn_named};
 use ruffd_types::tokio::sync::mpsc::Sender;
-use ruffd_types::{create_locks_fut, unwrap_state_handles};
-use ruffd_types::{lsp_types, serde_json};
+use ruffd_types::tokio::time;
+use ruffd_types::uri::uri_to_path;
 use ruffd_types::{
-    CheckRegistry, CreateLocksFn, RpcNotification, ScheduledTask, ServerNotification,
-    ServerNotificationExec, ServerStateHandles,
+    content_hash, evict_checks, AstCache, CheckRegistries, CheckRegistry, ConfigSnapshot,
+    CreateLocksFn, DocumentBuffer, DocumentStatus, PositionEncoding, ResponseHandler,
+    RpcNotification, RpcRequest, RpcResponseMessage, ScheduledTask, Scheduler, ServerConfig,
+    ServerInitiated, ServerNotification, ServerNotificationExec, ServerRequest, ServerRequestExec,
+    ServerStateHandles, ServerWork, ServerWorkExec, Snapshot, CONFIG_SECTION,
 };
+use ruffd_types::{create_locks_fut, unwrap_state_handles};
+use ruffd_types::{log_debug, log_error, log_warn};
+use ruffd_types::{lsp_types, serde_json};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::time::Duration;
+
+/// Determines whether clients pull diagnostics, in which case they aren't
+/// published
+pub fn pulls_diagnostics(capabilities: &lsp_types::ServerCapabilities) -> bool {
+    capabilities.diagnostic_provider.is_some()
+}
+
+/// Determines whether the registry of the document was computed from
+/// content with the given hash, such that linting it again is redundant
+fn checks_current(
+    document_uri: &lsp_types::Url,
+    content_hash: u64,
+    checks: &CheckRegistries,
+) -> bool {
+    checks
+        .get(document_uri)
+        .and_then(CheckRegistry::content_hash)
+        == Some(content_hash)
+}
+
+/// Unwraps the checks of a lint of the document, recording in its status
+/// whether ruff panicked such that the failure is surfaced with no checks
+pub fn checks_or_mark_failed(
+    document_uri: &lsp_types::Url,
+    result: Result<Linted, LintPanicked>,
+    document_status: &mut HashMap<lsp_types::Url, DocumentStatus>,
+) -> Linted {
+    if let Some(status) = document_status.get_mut(document_uri) {
+        status.lint_failed = result.is_err();
+    }
+    result.unwrap_or_default()
+}
+
+/// Replaces the registry of the document with `registry`, creating the
+/// publish notification of its diagnostics if `publish` is set
+///
+/// `version` is the version of the document linted, allowing clients to
+/// drop diagnostics of a version they've since edited. Diagnostics equal to
+/// those last published are dropped as they're sent. The checks are
+/// recorded as computed under the settings of `scope`
+fn update_checks(
+    document_uri: lsp_types::Url,
+    registry: CheckRegistry,
+    version: Option<i32>,
+    publish: bool,
+    scope: &SettingsScope,
+    checks: &mut CheckRegistries,
+) -> Option<RpcNotification> {
+    let registry = registry.with_generation(scope.generation);
+    // published in the registry's order
+    let diagnostics = registry_diagnostics(&registry, &scope.severity_overrides);
+    checks.insert(document_uri.clone(), registry);
+    if !publish {
+        return None;
+    }
+    Some(publish_diagnostics_notification(
+        document_uri,
+        diagnostics,
+        version,
+    ))
+}
+
+fn publish_diagnostics_notification(
+    document_uri: lsp_types::Url,
+    diagnostics: Vec<lsp_types::Diagnostic>,
+    version: Option<i32>,
+) -> RpcNotification {
+    RpcNotification::new(
+        "textDocument/publishDiagnostics".to_string(),
+        Some(
+            serde_json::to_value(lsp_types::PublishDiagnosticsParams {
+                uri: document_uri,
+                diagnostics,
+                version,
+            })
+            .unwrap(),
+        ),
+    )
+}
 
+/// Shifts the checks of a document through the changes of a
+/// `textDocument/didChange`, creating the publish notification of the
+/// shifted diagnostics if `publish` is set
+///
+/// Published diagnostics track the text they were reported at while the
+/// document's lint is pending, rather than lagging at stale positions. The
+/// registry is invalidated, as it no longer matches any linted content.
+/// Changes replacing the whole document can't be tracked, leaving the
+/// checks as they were
+pub fn shift_checks(
+    document_uri: &lsp_types::Url,
+    changes: &[lsp_types::TextDocumentContentChangeEvent],
+    encoding: PositionEncoding,
+    version: Option<i32>,
+    publish: bool,
+    overrides: &BTreeMap<String, Severity>,
+    checks: &mut CheckRegistries,
+) -> Option<RpcNotification> {
+    let deltas = changes
+        .iter()
+        .map(|x| edit_delta_from_change(x, encoding))
+        .collect::<Option<Vec<_>>>()?;
+    let registry = checks
+        .get_mut(document_uri)
+        .filter(|x| !x.is_evicted() && !x.is_empty())?;
+    for delta in deltas.iter() {
+        registry.retain_mut(|x| shift_check(x, delta));
+    }
+    registry.invalidate();
+    if !publish {
+        return None;
+    }
+    let diagnostics = registry_diagnostics(registry, overrides);
+    Some(publish_diagnostics_notification(
+        document_uri.clone(),
+        diagnostics,
+        version,
+    ))
+}
+
+/// Scheduling of diagnostic ops through the handlers' `Scheduler`
+pub trait ScheduleDiagnostics {
+    /// Lints the open document, publishing its diagnostics if changed
+    fn schedule_diagnostics(&self, document_uri: lsp_types::Url);
+
+    /// Lints the open document once `delay` has passed, unless it's been
+    /// edited past `version` by then, as the later edit schedules its own
+    fn schedule_debounced_diagnostics(
+        &self,
+        document_uri: lsp_types::Url,
+        version: i32,
+        delay: Duration,
+    );
+}
+
+impl ScheduleDiagnostics for Scheduler {
+    fn schedule_diagnostics(&self, document_uri: lsp_types::Url) {
+        self.schedule(run_diagnostic_op(document_uri));
+    }
+
+    fn schedule_debounced_diagnostics(
+        &self,
+        document_uri: lsp_types::Url,
+        version: i32,
+        delay: Duration,
+    ) {
+        let scheduler = self.clone();
+        let name = {
+            let uri = document_uri.clone();
+            move || format!("debounce {}", uri)
+        };
+        spawn_named(name, async move {
+            time::sleep(delay).await;
+            scheduler.schedule(diagnostic_op(document_uri, Some(version)));
+        });
+    }
+}
+
+/// Lints a buffered document within the handler holding its state rather
+/// than through an op, returning the publish notification of its
+/// diagnostics
+///
+/// Content unchanged since it was last linted isn't linted again. The
+/// module parsed linting is cached in `ast_cache` for the version linted
+pub async fn lint_in_place(
+    document_uri: &lsp_types::Url,
+    buffer: &DocumentBuffer,
+    scope: SettingsScope,
+    publish: bool,
+    document_status: &mut HashMap<lsp_types::Url, DocumentStatus>,
+    checks: &mut CheckRegistries,
+    ast_cache: &mut AstCache,
+) -> Option<RpcNotification> {
+    let content_hash = buffer.content_hash();
+    if checks_current(document_uri, content_hash, checks) {
+        return None;
+    }
+    let version = document_status.get(document_uri).map(|x| x.version);
+    let registry = match scope.lint_path(document_uri) {
+        Some(path) => {
+            let source = buffer.iter().collect::<String>();
+            let result = lint_module(path, source, scope.clone()).await;
+            let result = result.map(|(linted, module)| {
+                if let Some((module, version)) = module.zip(version) {
+                    ast_cache.insert(document_uri.clone(), version, module, buffer.len());
+                }
+                linted
+            });
+            checks_or_mark_failed(document_uri, result, document_status).into()
+        }
+        None => CheckRegistry::from_iter(vec![]),
+    };
+    update_checks(
+        document_uri.clone(),
+        registry.with_content_hash(Some(content_hash)),
+        version,
+        publish,
+        &scope,
+        checks,
+    )
+}
+
+/// Lints a document in place as `lint_in_place` does, returning the edits
+/// fixing all its fixable checks along with the publish notification
+pub async fn fix_all_in_place(
+    document_uri: &lsp_types::Url,
+    buffer: &DocumentBuffer,
+    scope: SettingsScope,
+    publish: bool,
+    document_status: &mut HashMap<lsp_types::Url, DocumentStatus>,
+    checks: &mut CheckRegistries,
+    ast_cache: &mut AstCache,
+) -> (Vec<lsp_types::TextEdit>, Option<RpcNotification>) {
+    let fixes_all = scope.fixes_all();
+    let notification = lint_in_place(
+        document_uri,
+        buffer,
+        scope,
+        publish,
+        document_status,
+        checks,
+        ast_cache,
+    )
+    .await;
+    if !fixes_all {
+        return (vec![], notification);
+    }
+    let edits = fix_all_edits(
+        checks
+            .get(document_uri)
+            .into_iter()
+            .flat_map(CheckRegistry::iter),
+    );
+    (edits, notification)
+}
+
+/// Applies every fix of a document through `workspace/applyEdit`, then
+/// focuses the first location changed if configured to
+///
+/// The edit carries the version the fixes were computed for when the client
+/// accepts `documentChanges`, such that it isn't applied to a later version
+pub async fn apply_fix_all(
+    scheduler: Scheduler,
+    document: lsp_types::OptionalVersionedTextDocumentIdentifier,
+    edits: Vec<lsp_types::TextEdit>,
+    document_changes: bool,
+    show_document: bool,
+) {
+    let uri = document.uri.clone();
+    let first_changed = edits.first().map(|x| x.range.start);
+    let params = lsp_types::ApplyWorkspaceEditParams {
+        label: Some("Fix all".to_string()),
+        edit: document_edit(document, edits, document_changes),
+    };
+    let applied = scheduler
+        .request_client(
+            "workspace/applyEdit",
+            Some(serde_json::to_value(params).unwrap()),
+        )
+        .await
+        .ok()
+        .and_then(|x| x.into_result().ok().flatten())
+        .and_then(|x| serde_json::from_value::<lsp_types::ApplyWorkspaceEditResponse>(x).ok())
+        .map(|x| x.applied)
+        .unwrap_or(false);
+    if !applied {
+        log_warn!("client didn't apply fixes of {}", uri);
+        return;
+    }
+    if let Some(position) = first_changed.filter(|_| show_document) {
+        let params = lsp_types::ShowDocumentParams {
+            uri,
+            external: None,
+            take_focus: Some(true),
+            selection: Some(lsp_types::Range::new(position, position)),
+        };
+        // the response only tells whether the document was shown
+        scheduler
+            .request_client(
+                "window/showDocument",
+                Some(serde_json::to_value(params).unwrap()),
+            )
+            .await
+            .ok();
+    }
+}
+
+/// Lints the buffer of a document, falling back to its saved text if it
+/// isn't buffered. Documents too large to be buffered are skipped, being
+/// linted as they're saved
+///
+/// Content unchanged since it was last linted isn't linted again
 pub fn run_diagnostic_op(document_uri: lsp_types::Url) -> ServerNotification {
+    diagnostic_op(document_uri, None)
+}
+
+/// Diagnostic op of the document, skipped if `version` is given and the
+/// document is no longer at it
+fn diagnostic_op(document_uri: lsp_types::Url, version: Option<i32>) -> ServerNotification {
     let exec: ServerNotificationExec = Box::new(
         move |state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
             Box::pin(async move {
-                unwrap_state_handles!(state_handles, open_buffers, mut checks);
+                unwrap_state_handles!(
+                    state_handles,
+                    open_buffers,
+                    shadow_buffers,
+                    mut document_status,
+                    capabilities,
+                    config_snapshot,
+                    mut checks,
+                    mut ast_cache
+                );
+                let current = document_status.get(&document_uri).map(|x| x.version);
+                if version.is_some() && current != version {
+                    return None;
+                }
+                // documents too large to be buffered are only linted from
+                // their saved text as they're saved
+                if matches!(document_status.get(&document_uri), Some(x) if x.oversized) {
+                    return None;
+                }
+                let buffer = open_buffers
+                    .get(&document_uri)
+                    .or_else(|| shadow_buffers.get(&document_uri));
+                let publish = !pulls_diagnostics(&capabilities);
+                let notification = match buffer {
+                    Some(buffer) => {
+                        let scope = SettingsScope::from_snapshot(&config_snapshot);
+                        lint_in_place(
+                            &document_uri,
+                            buffer,
+                            scope,
+                            publish,
+                            &mut document_status,
+                            &mut checks,
+                            &mut ast_cache,
+                        )
+                        .await
+                    }
+                    None => {
+                        let version = document_status.get(&document_uri).map(|x| x.version);
+                        let scope = SettingsScope::from_snapshot(&config_snapshot);
+                        update_checks(
+                            document_uri,
+                            CheckRegistry::from_iter(vec![]),
+                            version,
+                            publish,
+                            &scope,
+                            &mut checks,
+                        )
+                    }
+                };
+                notification.map(Into::into)
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(
+        open_buffers,
+        shadow_buffers,
+        mut document_status,
+        capabilities,
+        config_snapshot,
+        mut checks,
+        mut ast_cache
+    );
+    ServerNotification { exec, create_locks }
+}
 
-                let check_vec = {
-                    if let Some(buffer) = open_buffers.get(&document_uri) {
+/// Records `text` as the saved text of a document and re-lints it
+///
+/// Documents too large to be buffered are linted from `text`, which isn't
+/// kept, as a copy of it would defeat the limit on their size
+pub fn run_saved_diagnostic_op(document_uri: lsp_types::Url, text: String) -> ServerNotification {
+    let exec: ServerNotificationExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(
+                    state_handles,
+                    open_buffers,
+                    mut shadow_buffers,
+                    mut document_status,
+                    capabilities,
+                    config_snapshot,
+                    mut checks
+                );
+                let path = match uri_to_path_checked(&document_uri) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        log_error!("{}", err);
+                        return None;
+                    }
+                };
+                let doc = match open_buffers.get(&document_uri) {
+                    Some(buffer) => {
                         let doc = buffer.iter().collect::<String>();
-                        if let Ok(path) = document_uri.to_file_path() {
-                            check(&path, doc.as_str(), true).unwrap_or_default()
-                        } else {
-                            vec![]
+                        if doc != text {
+                            log_warn!("{} differs from its saved copy", document_uri);
                         }
-                    } else {
-                        vec![]
+                        doc
+                    }
+                    None => text.clone(),
+                };
+                let oversized =
+                    matches!(document_status.get(&document_uri), Some(x) if x.oversized);
+                if !oversized {
+                    shadow_buffers.insert(document_uri.clone(), DocumentBuffer::from_string(text));
+                }
+                let hash = content_hash(&doc);
+                if checks_current(&document_uri, hash, &checks) {
+                    return None;
+                }
+                let scope = SettingsScope::from_snapshot(&config_snapshot);
+                let result = lint(path, doc, scope.clone()).await;
+                let linted = checks_or_mark_failed(&document_uri, result, &mut document_status);
+                let publish = !pulls_diagnostics(&capabilities);
+                let version = document_status.get(&document_uri).map(|x| x.version);
+                update_checks(
+                    document_uri,
+                    CheckRegistry::from(linted).with_content_hash(Some(hash)),
+                    version,
+                    publish,
+                    &scope,
+                    &mut checks,
+                )
+                .map(Into::into)
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(
+        open_buffers,
+        mut shadow_buffers,
+        mut document_status,
+        capabilities,
+        config_snapshot,
+        mut checks
+    );
+    ServerNotification { exec, create_locks }
+}
+
+/// Stores checks linted outside of an op, such as those of files linted
+/// across the workspace, unless the document was opened or edited since
+///
+/// Checks of documents that aren't open are then evicted down to the
+/// configured budget. Whether ruff panicked is recorded in the status of the
+/// document, as it is for lints in place
+pub fn run_update_checks_op(
+    document_uri: lsp_types::Url,
+    result: Result<Linted, LintPanicked>,
+    content_hash: u64,
+) -> ServerNotification {
+    let exec: ServerNotificationExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(
+                    state_handles,
+                    open_buffers,
+                    mut document_status,
+                    capabilities,
+                    config_snapshot,
+                    mut checks
+                );
+                let version = match open_buffers.get(&document_uri) {
+                    Some(buffer) if buffer.content_hash() != content_hash => return None,
+                    Some(_) => document_status.get(&document_uri).map(|x| x.version),
+                    None => None,
+                };
+                let linted = checks_or_mark_failed(&document_uri, result, &mut document_status);
+                let publish = !pulls_diagnostics(&capabilities);
+                let rv = update_checks(
+                    document_uri,
+                    CheckRegistry::from(linted).with_content_hash(Some(content_hash)),
+                    version,
+                    publish,
+                    &SettingsScope::from_snapshot(&config_snapshot),
+                    &mut checks,
+                );
+                let evicted = evict_checks(
+                    &mut checks,
+                    &open_buffers,
+                    config_snapshot.config.checks_memory_budget,
+                );
+                if evicted > 0 {
+                    log_debug!("evicted checks of {} documents", evicted);
+                }
+                rv.map(Into::into)
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(
+        open_buffers,
+        mut document_status,
+        capabilities,
+        config_snapshot,
+        mut checks
+    );
+    ServerNotification { exec, create_locks }
+}
+
+/// Lints the code cells of a notebook as a single module, publishing the
+/// diagnostics of each cell
+///
+/// Cells are always linted, as a cell's checks depend on the other cells
+#[cfg(feature = "notebook")]
+pub fn run_notebook_diagnostic_op(notebook_uri: lsp_types::Url) -> ServerWork {
+    let exec: ServerWorkExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(
+                    state_handles,
+                    open_buffers,
+                    notebooks,
+                    mut document_status,
+                    capabilities,
+                    config_snapshot,
+                    mut checks
+                );
+                let notebook = match notebooks.get(&notebook_uri) {
+                    Some(x) => x,
+                    None => return,
+                };
+                let source = NotebookSource::from_cells(notebook.code_cells().map(|uri| {
+                    let text = open_buffers
+                        .get(uri)
+                        .map(|x| x.iter().collect::<String>())
+                        .unwrap_or_default();
+                    (uri, text)
+                }));
+                let scope = SettingsScope::from_snapshot(&config_snapshot);
+                let linted = match scope.lint_path(&notebook_uri) {
+                    Some(path) => {
+                        let result = lint(path, source.source.clone(), scope).await;
+                        // a panic on the notebook fails the lint of each of its cells
+                        for cell_uri in notebook.code_cells() {
+                            if let Some(status) = document_status.get_mut(cell_uri) {
+                                status.lint_failed = result.is_err();
+                            }
+                        }
+                        result.unwrap_or_default()
+                    }
+                    None => Linted::default(),
+                };
+                let pull = pulls_diagnostics(&capabilities);
+                let mut publish = vec![];
+                for (cell_uri, cell_checks) in source.split_checks(linted.checks) {
+                    let registry = CheckRegistry::from(Linted {
+                        checks: cell_checks,
+                        severity: linted.severity,
+                    })
+                    .with_generation(config_snapshot.generation);
+                    let diagnostics = registry_diagnostics(
+                        &registry,
+                        &config_snapshot.project_config.severity_overrides,
+                    );
+                    if !pull {
+                        publish.push(run_publish_diagnostics_op(
+                            cell_uri.clone(),
+                            diagnostics,
+                            None,
+                        ));
+                    }
+                    checks.insert(cell_uri, registry);
+                }
+                if publish.is_empty() {
+                    return;
+                }
+                Scheduler::new(_scheduler_channel).schedule_all(publish);
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(
+        open_buffers,
+        notebooks,
+        mut document_status,
+        capabilities,
+        config_snapshot,
+        mut checks
+    );
+    ServerWork { exec, create_locks }
+}
+
+/// Publishes precomputed diagnostics, such as clearing diagnostics of a
+/// removed document, along with the version of the document they apply to
+/// if known
+pub fn run_publish_diagnostics_op(
+    document_uri: lsp_types::Url,
+    diagnostics: Vec<lsp_types::Diagnostic>,
+    version: Option<i32>,
+) -> ServerNotification {
+    let exec: ServerNotificationExec = Box::new(
+        move |_state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                Some(publish_diagnostics_notification(document_uri, diagnostics, version).into())
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!();
+    ServerNotification { exec, create_locks }
+}
+
+/// Schedules a re-lint of open documents under changed settings, unless
+/// one is already pending
+///
+/// The re-lint acquires its locks after those of the change scheduling it,
+/// so changes made before it runs are seen without scheduling another
+pub fn schedule_relint(scheduler: &Scheduler, relint_pending: &mut bool) {
+    if !*relint_pending {
+        *relint_pending = true;
+        scheduler.schedule(run_relint_op());
+    }
+}
+
+/// Re-lints every open document and notebook, as their checks may differ
+/// under changed settings even though their content is unchanged
+///
+/// Documents too large to be buffered are only linted once saved, as when
+/// edited. Clients pulling diagnostics are asked to pull them again once
+/// the documents are linted, if they support being asked
+pub fn run_relint_op() -> ServerWork {
+    let exec: ServerWorkExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(
+                    state_handles,
+                    mut relint_pending,
+                    open_buffers,
+                    notebooks,
+                    capabilities,
+                    client_capabilities,
+                    mut checks
+                );
+                *relint_pending = false;
+                // cells are linted as part of their notebook
+                let cells = notebooks
+                    .values()
+                    .flat_map(|x| x.cells.iter().map(|y| &y.document))
+                    .collect::<BTreeSet<_>>();
+                let documents = open_buffers
+                    .keys()
+                    .filter(|x| !cells.contains(x))
+                    .collect::<BTreeSet<_>>();
+                let mut tasks: Vec<ServerInitiated> = vec![];
+                for uri in documents {
+                    if let Some(registry) = checks.get_mut(uri) {
+                        registry.invalidate();
                     }
+                    tasks.push(run_diagnostic_op(uri.clone()).into());
+                }
+                #[cfg(feature = "notebook")]
+                {
+                    let mut notebook_uris = notebooks.keys().cloned().collect::<Vec<_>>();
+                    notebook_uris.sort();
+                    tasks.extend(
+                        notebook_uris
+                            .into_iter()
+                            .map(|x| run_notebook_diagnostic_op(x).into()),
+                    );
+                }
+                if pulls_diagnostics(&capabilities)
+                    && supports_diagnostic_refresh(&client_capabilities)
+                {
+                    tasks.push(run_diagnostic_refresh_op().into());
+                }
+                Scheduler::new(scheduler_channel).schedule_all(tasks);
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(
+        mut relint_pending,
+        open_buffers,
+        notebooks,
+        capabilities,
+        client_capabilities,
+        mut checks
+    );
+    ServerWork { exec, create_locks }
+}
+
+/// Asks the client to pull the diagnostics of every document again, the
+/// response is disregarded
+pub fn run_diagnostic_refresh_op() -> ServerRequest {
+    let exec: ServerRequestExec = Box::new(
+        move |_state_handles: ServerStateHandles<'_>,
+              _scheduler_channel: Sender<ScheduledTask>,
+              id: lsp_types::NumberOrString| {
+            Box::pin(async move {
+                RpcRequest::new(id, "workspace/diagnostic/refresh".to_string(), None)
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!();
+    let on_response: ResponseHandler = Box::new(|_| None);
+    ServerRequest {
+        exec,
+        create_locks,
+        on_response,
+    }
+}
+
+/// Adds the given files to the workspace index
+pub fn run_extend_index_op(files: Vec<lsp_types::Url>) -> ServerWork {
+    let exec: ServerWorkExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(state_handles, mut workspace_index);
+                workspace_index.extend(files);
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(mut workspace_index);
+    ServerWork { exec, create_locks }
+}
+
+/// Writes open buffers with unsaved changes to the spill directory when
+/// opted in, otherwise clears any previous spills
+///
+/// Spills of buffers since saved or closed are removed, as their text is
+/// on disk or was discarded
+pub fn run_spill_op() -> ServerWork {
+    let exec: ServerWorkExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(
+                    state_handles,
+                    open_buffers,
+                    document_status,
+                    config_snapshot,
+                    mut spilled
+                );
+                let dir = match &config_snapshot.spill_dir {
+                    Some(x) => x.clone(),
+                    None => return,
                 };
-                let diagnostics = check_vec
+                if !config_snapshot.config.spill {
+                    if !spilled.is_empty() {
+                        spilled.clear();
+                        spawn_blocking_named(
+                            || "clear spills".to_string(),
+                            move || spill::clear(&dir),
+                        )
+                        .await
+                        .unwrap()
+                        .unwrap_or_else(|err| log_error!("failed clearing spills: {}", err));
+                    }
+                    return;
+                }
+                // unedited buffers match what the client opened, and saved
+                // ones what's on disk, so only unsaved edits are worth
+                // recovering. Notebook cells have no status of their own
+                let unsaved =
+                    |uri: &lsp_types::Url| !matches!(document_status.get(uri), Some(x) if !x.dirty);
+                let stale = spilled
+                    .keys()
+                    .filter(|x| !open_buffers.contains_key(*x) || !unsaved(x))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                stale.iter().for_each(|x| {
+                    spilled.remove(x);
+                });
+                let pending = open_buffers
                     .iter()
-                    .map(diagnostic_from_check)
+                    .filter(|(uri, buffer)| {
+                        buffer.revision() > 0
+                            && unsaved(uri)
+                            && spilled.get(*uri) != Some(&buffer.revision())
+                    })
+                    .map(|(uri, buffer)| {
+                        (
+                            uri.clone(),
+                            buffer.revision(),
+                            buffer.iter().collect::<String>(),
+                        )
+                    })
                     .collect::<Vec<_>>();
-                // for now, recreate the registry every op
-                let registry = CheckRegistry::from_iter(check_vec);
-                checks.insert(document_uri.clone(), registry);
-                RpcNotification::new(
-                    "textDocument/publishDiagnostics".to_string(),
-                    Some(
-                        serde_json::to_value(lsp_types::PublishDiagnosticsParams {
-                            uri: document_uri,
-                            diagnostics,
-                            version: None,
-                        })
-                        .unwrap(),
-                    ),
+                if pending.is_empty() && stale.is_empty() {
+                    return;
+                }
+                let written = spawn_blocking_named(
+                    || "spill".to_string(),
+                    move || {
+                        for uri in stale {
+                            if let Err(err) = spill::remove_buffer(&dir, &uri) {
+                                log_error!("failed removing spill of {}: {}", uri, err);
+                            }
+                        }
+                        pending
+                            .into_iter()
+                            .filter_map(|(uri, revision, text)| {
+                                match spill::write_buffer(&dir, &uri, &text) {
+                                    Ok(()) => Some((uri, revision)),
+                                    Err(err) => {
+                                        log_error!("failed spilling {}: {}", uri, err);
+                                        None
+                                    }
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    },
                 )
-                .into()
+                .await
+                .unwrap();
+                spilled.extend(written);
             })
         },
     );
-    let create_locks: CreateLocksFn = create_locks_fut!(open_buffers, mut checks);
-    ServerNotification { exec, create_locks }
+    let create_locks: CreateLocksFn =
+        create_locks_fut!(open_buffers, document_status, config_snapshot, mut spilled);
+    ServerWork { exec, create_locks }
+}
+
+/// Removes the spill of a document from the session's spill directory once
+/// saved or closed, as its text is then on disk or discarded
+pub fn remove_spill(
+    document_uri: &lsp_types::Url,
+    spill_dir: Option<&Path>,
+    spilled: &mut HashMap<lsp_types::Url, usize>,
+) {
+    let dir = match spill_dir {
+        Some(x) if spilled.remove(document_uri).is_some() => x.to_path_buf(),
+        _ => return,
+    };
+    let document_uri = document_uri.clone();
+    spawn_blocking_named(
+        || "remove spill".to_string(),
+        move || {
+            if let Err(err) = spill::remove_buffer(&dir, &document_uri) {
+                log_error!("failed removing spill of {}: {}", document_uri, err);
+            }
+        },
+    );
+}
+
+/// Pulls the server's settings section from the client with
+/// `workspace/configuration`, scoped to the project root
+pub fn run_configuration_pull_op() -> ServerRequest {
+    let exec: ServerRequestExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>,
+              _scheduler_channel: Sender<ScheduledTask>,
+              id: lsp_types::NumberOrString| {
+            Box::pin(async move {
+                unwrap_state_handles!(state_handles, config_snapshot);
+                let params = lsp_types::ConfigurationParams {
+                    items: vec![lsp_types::ConfigurationItem {
+                        scope_uri: config_snapshot.project_root.clone(),
+                        section: Some(CONFIG_SECTION.to_string()),
+                    }],
+                };
+                RpcRequest::new(
+                    id,
+                    "workspace/configuration".to_string(),
+                    Some(serde_json::to_value(params).unwrap()),
+                )
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(config_snapshot);
+    let on_response: ResponseHandler = Box::new(|resp: RpcResponseMessage| {
+        let result = resp.into_result().ok().flatten()?;
+        let items: Vec<serde_json::Value> = serde_json::from_value(result).ok()?;
+        // null indicates the client holds no settings for the section,
+        // in which case the current settings are kept
+        let value = items.into_iter().next().filter(|x| !x.is_null())?;
+        let new_config = ServerConfig::from_value(value).ok()?;
+        Some(ServerInitiated::Work(run_update_config_op(new_config)))
+    });
+    ServerRequest {
+        exec,
+        create_locks,
+        on_response,
+    }
+}
+
+/// Replaces the server's config
+///
+/// Checks of unchanged content may differ under a new rule selection,
+/// including that of the active profile, so are invalidated such that the
+/// relint following doesn't skip them. The log level is resolved again, as
+/// the client may have set one
+pub fn replace_config(
+    new_config: ServerConfig,
+    checks: &mut CheckRegistries,
+    config_snapshot: &Snapshot<ConfigSnapshot>,
+) {
+    let current = config_snapshot.load();
+    let lint_changed = new_config.lint_config() != current.config.lint_config();
+    ResolvedConfig::resolve(&new_config.layer, &current.project_config).apply_log_level();
+    if lint_changed {
+        checks.values_mut().for_each(CheckRegistry::invalidate);
+    }
+    config_snapshot.update(|x| {
+        x.config = new_config;
+        x.settings_changed();
+    });
+}
+
+fn show_message_notification(typ: lsp_types::MessageType, message: String) -> RpcNotification {
+    RpcNotification::new(
+        "window/showMessage".to_string(),
+        Some(serde_json::to_value(lsp_types::ShowMessageParams { typ, message }).unwrap()),
+    )
+}
+
+/// Reads the `[tool.ruffd]` table of the project's pyproject into the
+/// config snapshot, applying the resolved logging level and the
+/// severities of diagnostics, returning whether the severities changed
+///
+/// The pyproject is that at the project root unless another is given by a
+/// layer of the server's config. Invalid settings leave the previous ones
+/// in place, returning instead the `window/showMessage` notification
+/// telling the user where the error is
+pub fn reload_project_config(
+    config_snapshot: &Snapshot<ConfigSnapshot>,
+) -> Result<bool, RpcNotification> {
+    let current = config_snapshot.load();
+    let root_path = current.project_root.as_ref().and_then(uri_to_path);
+    let pyproject = match ResolvedConfig::resolve(&current.config.layer, &current.project_config)
+        .pyproject(root_path.as_deref())
+    {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+    let new_config = ProjectConfig::from_pyproject(&pyproject).map_err(|err| {
+        log_error!("invalid [tool.ruffd] settings: {}", err);
+        show_message_notification(
+            lsp_types::MessageType::ERROR,
+            format!("Invalid [tool.ruffd] settings, keeping previous: {}", err),
+        )
+    })?;
+    ResolvedConfig::resolve(&current.config.layer, &new_config).apply_log_level();
+    let changed = new_config.severity_overrides != current.project_config.severity_overrides;
+    config_snapshot.update(|x| {
+        x.project_config = new_config;
+        x.settings_changed();
+    });
+    Ok(changed)
+}
+
+/// Replaces the server's config with settings of the client, keeping the
+/// active profile unless they name one
+pub fn run_update_config_op(mut new_config: ServerConfig) -> ServerWork {
+    let exec: ServerWorkExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(
+                    state_handles,
+                    mut checks,
+                    mut config_snapshot,
+                    mut relint_pending
+                );
+                new_config.inherit_profile(&config_snapshot.load().config);
+                replace_config(new_config, &mut checks, &config_snapshot);
+                schedule_relint(&Scheduler::new(scheduler_channel), &mut relint_pending);
+            })
+        },
+    );
+    let create_locks: CreateLocksFn =
+        create_locks_fut!(mut checks, mut config_snapshot, mut relint_pending);
+    ServerWork { exec, create_locks }
+}
+
+/// Activates the configuration profile `profile`, or deactivates profiles
+/// if `None`, re-linting under the resulting rule selection
+pub fn run_select_profile_op(profile: Option<String>) -> ServerWork {
+    let exec: ServerWorkExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(
+                    state_handles,
+                    mut checks,
+                    mut config_snapshot,
+                    mut relint_pending
+                );
+                let new_config = ServerConfig {
+                    profile,
+                    ..config_snapshot.load().config.clone()
+                };
+                replace_config(new_config, &mut checks, &config_snapshot);
+                schedule_relint(&Scheduler::new(scheduler_channel), &mut relint_pending);
+            })
+        },
+    );
+    let create_locks: CreateLocksFn =
+        create_locks_fut!(mut checks, mut config_snapshot, mut relint_pending);
+    ServerWork { exec, create_locks }
+}
+
+/// Records whether the client watches files on the server's behalf
+fn run_watcher_status_op(status: WatcherStatus) -> ServerWork {
+    let exec: ServerWorkExec = Box::new(
+        move |state_handles: ServerStateHandles<'_>, _scheduler_channel: Sender<ScheduledTask>| {
+            Box::pin(async move {
+                unwrap_state_handles!(state_handles, mut config_snapshot);
+                config_snapshot.update(|x| x.watcher = status);
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!(mut config_snapshot);
+    ServerWork { exec, create_locks }
+}
+
+/// Dynamically registers capabilities with the client
+///
+/// The response is disregarded, other than to record whether a
+/// registration for watched files was accepted
+pub fn run_register_capability_op(registrations: Vec<lsp_types::Registration>) -> ServerRequest {
+    let watches_files = registrations
+        .iter()
+        .any(|x| x.method == "workspace/didChangeWatchedFiles");
+    let exec: ServerRequestExec = Box::new(
+        move |_state_handles: ServerStateHandles<'_>,
+              _scheduler_channel: Sender<ScheduledTask>,
+              id: lsp_types::NumberOrString| {
+            Box::pin(async move {
+                let params = lsp_types::RegistrationParams { registrations };
+                RpcRequest::new(
+                    id,
+                    "client/registerCapability".to_string(),
+                    Some(serde_json::to_value(params).unwrap()),
+                )
+            })
+        },
+    );
+    let create_locks: CreateLocksFn = create_locks_fut!();
+    let on_response: ResponseHandler = Box::new(move |resp: RpcResponseMessage| {
+        if !watches_files {
+            return None;
+        }
+        let status = match resp.into_result() {
+            Ok(_) => WatcherStatus::Registered,
+            Err(_) => WatcherStatus::Failed,
+        };
+        Some(ServerInitiated::Work(run_watcher_status_op(status)))
+    });
+    ServerRequest {
+        exec,
+        create_locks,
+        on_response,
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::ruff_utils::diagnostic_from_check;
+    use ruffd_types::ruff::check;
+    use ruffd_types::tokio;
+    use ruffd_types::tokio::sync::mpsc::channel;
 
     #[test]
     fn test_diagnostic_gen_position() {
@@ -70,7 +1087,7 @@ def bar():
         let diagnostics = check(&path, doc, true)
             .unwrap()
             .iter()
-            .map(diagnostic_from_check)
+            .map(|x| diagnostic_from_check(x, &BTreeMap::new()))
             .collect::<Vec<_>>();
         let expected_range = lsp_types::Range {
             start: lsp_types::Position {
@@ -85,4 +1102,252 @@ def bar():
         assert_eq!(diagnostics.len(), 1);
         assert_eq!(diagnostics[0].range, expected_range);
     }
+
+    #[test]
+    fn test_update_checks_version() {
+        let uri = lsp_types::Url::parse("file:///tmp/dummy.py").unwrap();
+        let path = uri.to_file_path().unwrap();
+        let check_vec = check(&path, "import os\n", true).unwrap();
+        let mut checks = CheckRegistries::default();
+        let scope = SettingsScope {
+            severity_overrides: BTreeMap::from([("F4".to_string(), Severity::Hint)]),
+            ..Default::default()
+        };
+        let msg = update_checks(
+            uri.clone(),
+            CheckRegistry::from_iter(check_vec.clone()),
+            Some(3),
+            true,
+            &scope,
+            &mut checks,
+        );
+        let params = msg.unwrap().params.unwrap().into_value();
+        let params = serde_json::from_value::<lsp_types::PublishDiagnosticsParams>(params).unwrap();
+        assert_eq!(params.uri, uri);
+        assert_eq!(params.version, Some(3));
+        assert_eq!(
+            params.diagnostics[0].severity,
+            Some(lsp_types::DiagnosticSeverity::HINT)
+        );
+        // the severity of the registry takes precedence over overrides
+        let registry = CheckRegistry::from_iter(check_vec).with_severity(Some(Severity::Error));
+        let msg = update_checks(uri, registry, Some(4), true, &scope, &mut checks);
+        let params = msg.unwrap().params.unwrap().into_value();
+        let params = serde_json::from_value::<lsp_types::PublishDiagnosticsParams>(params).unwrap();
+        assert_eq!(
+            params.diagnostics[0].severity,
+            Some(lsp_types::DiagnosticSeverity::ERROR)
+        );
+    }
+
+    #[test]
+    fn test_shift_checks() {
+        let uri = lsp_types::Url::parse("file:///tmp/dummy.py").unwrap();
+        let path = uri.to_file_path().unwrap();
+        let check_vec = check(&path, "import os\nimport sys\n", true).unwrap();
+        let mut checks = CheckRegistries::default();
+        let overrides = BTreeMap::new();
+        update_checks(
+            uri.clone(),
+            CheckRegistry::from_iter(check_vec).with_content_hash(Some(1)),
+            None,
+            false,
+            &SettingsScope::default(),
+            &mut checks,
+        );
+        let change = |start: (u32, u32), end: (u32, u32), text: &str| {
+            lsp_types::TextDocumentContentChangeEvent {
+                range: Some(lsp_types::Range::new(
+                    lsp_types::Position::new(start.0, start.1),
+                    lsp_types::Position::new(end.0, end.1),
+                )),
+                range_length: None,
+                text: text.to_string(),
+            }
+        };
+        // a line inserted above, then the first import deleted
+        let changes = vec![
+            change((0, 0), (0, 0), "x = 1\n"),
+            change((1, 0), (2, 0), ""),
+        ];
+        let msg = shift_checks(
+            &uri,
+            &changes,
+            PositionEncoding::Utf16,
+            Some(5),
+            true,
+            &overrides,
+            &mut checks,
+        );
+        let params = serde_json::from_value::<lsp_types::PublishDiagnosticsParams>(
+            msg.unwrap().params.unwrap().into_value(),
+        )
+        .unwrap();
+        assert_eq!(params.version, Some(5));
+        assert_eq!(params.diagnostics.len(), 1);
+        assert_eq!(
+            params.diagnostics[0].range.start,
+            lsp_types::Position::new(1, 0)
+        );
+        let registry = checks.get(&uri).unwrap();
+        assert!(registry.content_hash().is_none());
+        let patch = &registry.iter().next().unwrap().fix.as_ref().unwrap().patch;
+        assert_eq!(patch.location.row(), 2);
+        // the fix of a check is dropped once the text it replaces is edited
+        let changes = vec![change((1, 0), (1, 1), "")];
+        shift_checks(
+            &uri,
+            &changes,
+            PositionEncoding::Utf16,
+            None,
+            false,
+            &overrides,
+            &mut checks,
+        );
+        let registry = checks.get(&uri).unwrap();
+        assert!(registry.iter().next().unwrap().fix.is_none());
+        let replaced = lsp_types::TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: String::new(),
+        };
+        assert!(shift_checks(
+            &uri,
+            &[replaced],
+            PositionEncoding::Utf16,
+            None,
+            true,
+            &overrides,
+            &mut checks
+        )
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lint_in_place() {
+        let uri = lsp_types::Url::parse("file:///tmp/dummy.py").unwrap();
+        let buffer = DocumentBuffer::from_string("import os\n".to_string());
+        let mut document_status = HashMap::from([(uri.clone(), DocumentStatus::opened(2))]);
+        let mut checks = CheckRegistries::default();
+        let mut ast_cache = AstCache::default();
+        let msg = lint_in_place(
+            &uri,
+            &buffer,
+            SettingsScope::default(),
+            true,
+            &mut document_status,
+            &mut checks,
+            &mut ast_cache,
+        )
+        .await;
+        let params = serde_json::from_value::<lsp_types::PublishDiagnosticsParams>(
+            msg.unwrap().params.unwrap().into_value(),
+        )
+        .unwrap();
+        assert_eq!(params.diagnostics.len(), 1);
+        assert_eq!(params.version, Some(2));
+        assert!(checks_current(&uri, buffer.content_hash(), &checks));
+        // the module parsed linting is shared with the ast cache
+        assert!(ast_cache.get(&uri, 2).is_some());
+        // unchanged content isn't linted again
+        assert!(lint_in_place(
+            &uri,
+            &buffer,
+            SettingsScope::default(),
+            true,
+            &mut document_status,
+            &mut checks,
+            &mut ast_cache,
+        )
+        .await
+        .is_none());
+    }
+
+    #[test]
+    fn test_checks_or_mark_failed() {
+        let uri = lsp_types::Url::parse("file:///tmp/dummy.py").unwrap();
+        let path = uri.to_file_path().unwrap();
+        let mut document_status = HashMap::from([(uri.clone(), DocumentStatus::opened(1))]);
+        let linted = checks_or_mark_failed(&uri, Err(LintPanicked), &mut document_status);
+        assert!(linted.checks.is_empty());
+        assert!(document_status[&uri].lint_failed);
+        let result = Ok(Linted {
+            checks: check(&path, "import os\n", true).unwrap(),
+            severity: None,
+        });
+        let linted = checks_or_mark_failed(&uri, result, &mut document_status);
+        assert_eq!(linted.checks.len(), 1);
+        assert!(!document_status[&uri].lint_failed);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_relint_coalesces() {
+        let (sender, mut receiver) = channel(8);
+        let scheduler = Scheduler::new(sender);
+        let mut relint_pending = false;
+        for _ in 0..3 {
+            schedule_relint(&scheduler, &mut relint_pending);
+        }
+        assert!(relint_pending);
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ScheduledTask::Server(ServerInitiated::Work(_), _))
+        ));
+        drop(scheduler);
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_evict_checks() {
+        let path = std::path::PathBuf::from("/tmp/dummy.py");
+        let uri = |x: &str| lsp_types::Url::parse(&format!("file:///tmp/{}.py", x)).unwrap();
+        let registry = || CheckRegistry::from_iter(check(&path, "import os\n", true).unwrap());
+        let cost = registry().memory_size();
+        let mut checks = CheckRegistries::default();
+        for name in ["a", "b", "c", "open"] {
+            checks.insert(uri(name), registry().with_content_hash(Some(1)));
+        }
+        let open_buffers = HashMap::from([(uri("open"), DocumentBuffer::new())]);
+        // a is used after b, so b is the least recently used
+        checks.touch(&uri("a"));
+        assert_eq!(evict_checks(&mut checks, &open_buffers, cost * 3), 0);
+        assert_eq!(evict_checks(&mut checks, &open_buffers, cost * 2), 1);
+        assert!(checks[&uri("b")].is_evicted());
+        assert!(checks[&uri("b")].is_empty());
+        assert_eq!(checks[&uri("b")].content_hash(), None);
+        // open documents are never evicted
+        assert_eq!(evict_checks(&mut checks, &open_buffers, 0), 2);
+        assert!(!checks[&uri("open")].is_evicted());
+    }
+
+    #[test]
+    fn test_prune_closed_checks() {
+        use ruffd_types::prune_closed_checks;
+        let path = std::path::PathBuf::from("/tmp/dummy.py");
+        let uri = |x: &str| lsp_types::Url::parse(&format!("file:///tmp/{}.py", x)).unwrap();
+        let registry = || CheckRegistry::from_iter(check(&path, "import os\n", true).unwrap());
+        let mut checks = CheckRegistries::default();
+        for name in ["a", "b", "c", "linted", "reopened"] {
+            checks.insert(uri(name), registry());
+        }
+        for name in ["b", "a", "reopened", "c"] {
+            checks.close(&uri(name));
+        }
+        let open_buffers = HashMap::from([(uri("reopened"), DocumentBuffer::new())]);
+        assert!(prune_closed_checks(&mut checks, &open_buffers, 3).is_empty());
+        // b is the least recently closed
+        assert_eq!(
+            prune_closed_checks(&mut checks, &open_buffers, 2),
+            vec![uri("b")]
+        );
+        assert!(!checks.contains_key(&uri("b")));
+        checks.get_mut(&uri("a")).unwrap().evict();
+        assert_eq!(
+            prune_closed_checks(&mut checks, &open_buffers, 0),
+            vec![uri("c"), uri("a")]
+        );
+        // checks of open documents and those never closed are kept
+        assert!(checks.contains_key(&uri("reopened")));
+        assert!(checks.contains_key(&uri("linted")));
+    }
 }
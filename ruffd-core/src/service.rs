@@ -1,20 +1,159 @@
+use crate::frames::{write_frame, FrameWriter};
+use crate::log_message;
 use crate::notifications::NOTIFICATION_REGISTRY;
+use crate::outbound::{synced_uri, SharedPublishedVersions};
 use crate::requests::REQUEST_REGISTRY;
+use crate::server_ops::run_spill_op;
+use crate::spill;
+#[cfg(feature = "telemetry")]
+use crate::telemetry;
+use crate::unwind::{self, PanicPayload};
+use crate::warm_cache;
 use crate::{PKG_NAME, PKG_VERSION};
 use regex::Regex;
+#[cfg(feature = "notebook")]
+use ruffd_types::capabilities::notebook_document_sync;
+use ruffd_types::contention::handling;
+use ruffd_types::layered::ResolvedConfig;
+use ruffd_types::logging::{traced, TraceId};
+use ruffd_types::tasks::spawn_named;
 use ruffd_types::tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 use ruffd_types::tokio::sync::mpsc::{channel, Receiver, Sender};
 use ruffd_types::tokio::sync::{Mutex, Notify, RwLock};
-use ruffd_types::tokio::task;
-use ruffd_types::{lsp_types, serde_json, ServerInitiated, ServerNotification};
+use ruffd_types::tokio::{self, task, time};
+use ruffd_types::tracing::{self, field, Instrument, Span};
+use ruffd_types::{log_debug, log_error, log_info, log_warn};
 use ruffd_types::{
-    server_state_handles_from_locks, RpcErrors, RpcMessage, RpcNotification, RpcRequest,
-    RpcResponseMessage, RpcResult, RuntimeError, ScheduledTask, ServerState,
+    lsp_types, serde_json, ServerInitiated, ServerNotification, ServerRequest, ServerWork,
+};
+use ruffd_types::{
+    server_state_handles_from_locks, Notification, Params, PositionEncoding, Request,
+    ResponseHandler, RpcErrors, RpcMessage, RpcNotification, RpcRequest, RpcResponseError,
+    RpcResponseMessage, RpcResult, RuntimeError, ScheduledTask, ServerConfig, ServerState,
 };
 use std::collections::HashMap;
 use std::future::Future;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default limit on the `Content-Length` of client messages
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Default time the client has to answer a server request
+pub const DEFAULT_SERVER_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a session with the client ended, from which embedders derive the
+/// exit code of the process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionOutcome {
+    /// The client shut the server down and asked it to exit
+    Exit,
+    /// The client asked the server to exit without shutting it down first
+    ExitWithoutShutdown,
+    /// The client closed the connection without asking the server to exit
+    Disconnected,
+    /// The server couldn't serve the client, such as on failing to
+    /// initialize, described by the message
+    Fatal(String),
+}
+
+type PendingResponses = Arc<Mutex<HashMap<lsp_types::NumberOrString, ResponseHandler>>>;
+
+/// Called with the client's initialization parameters and the result to be
+/// sent in response, such that embedders can advertise capabilities of
+/// their own methods
+pub type InitializeHook =
+    Box<dyn Fn(&lsp_types::InitializeParams, &mut serde_json::Value) + Send + Sync>;
+
+/// Called with each client message before it's dispatched
+pub type MessageHook = Box<dyn Fn(&RpcMessage) + Send + Sync>;
+
+/// Creates the server state from the client's initialization parameters
+pub type StateFactory =
+    Box<dyn Fn(&lsp_types::InitializeParams) -> Result<ServerState, RuntimeError> + Send + Sync>;
+
+/// Intercepts messages passing between the client and the dispatcher,
+/// allowing cross-cutting behaviour to be composed without changing the
+/// dispatcher
+///
+/// Inbound messages pass through middlewares in the order they were added
+/// and outbound messages in reverse, such that the first middleware added
+/// is the outermost. Messages exchanged during initialization aren't
+/// intercepted
+pub trait Middleware: Send + Sync {
+    /// Inspects or rewrites a client message before it's dispatched
+    ///
+    /// Rejecting a request answers it with the error, rejected
+    /// notifications and responses are dropped
+    fn inbound(&self, message: RpcMessage) -> RpcResult<RpcMessage> {
+        Ok(message)
+    }
+
+    /// Inspects or rewrites a message before it's written to the client
+    fn outbound(&self, message: RpcMessage) -> RpcMessage {
+        message
+    }
+}
+
+type MiddlewareChain = Arc<Vec<Box<dyn Middleware>>>;
+
+/// Logs the method and id of every message
+#[derive(Debug, Default)]
+pub struct TracingMiddleware;
+
+fn describe_message(message: &RpcMessage) -> String {
+    match message {
+        RpcMessage::Request(x) => format!("request {} ({:?})", x.method, x.id),
+        RpcMessage::Notification(x) => format!("notification {}", x.method),
+        RpcMessage::Response(x) => format!("response ({:?})", x.id()),
+    }
+}
+
+impl Middleware for TracingMiddleware {
+    fn inbound(&self, message: RpcMessage) -> RpcResult<RpcMessage> {
+        log_info!("--> {}", describe_message(&message));
+        Ok(message)
+    }
+
+    fn outbound(&self, message: RpcMessage) -> RpcMessage {
+        log_info!("<-- {}", describe_message(&message));
+        message
+    }
+}
+
+/// Logs the time taken to respond to each client request
+#[derive(Debug, Default)]
+pub struct TimingMiddleware {
+    started: std::sync::Mutex<HashMap<lsp_types::NumberOrString, (String, Instant)>>,
+}
+
+impl Middleware for TimingMiddleware {
+    fn inbound(&self, message: RpcMessage) -> RpcResult<RpcMessage> {
+        if let RpcMessage::Request(req) = &message {
+            self.started
+                .lock()
+                .unwrap()
+                .insert(req.id.clone(), (req.method.clone(), Instant::now()));
+        }
+        Ok(message)
+    }
+
+    fn outbound(&self, message: RpcMessage) -> RpcMessage {
+        if let RpcMessage::Response(resp) = &message {
+            let started = resp
+                .id()
+                .and_then(|id| self.started.lock().unwrap().remove(id));
+            if let Some((method, start)) = started {
+                log_info!("{} took {:?}", method, start.elapsed());
+            }
+        }
+        message
+    }
+}
 
 lazy_static! {
     static ref PAYLOAD_START_PATTERN: Regex =
@@ -34,6 +173,24 @@ where
     writer: Option<W>,
     state: Arc<Mutex<Option<Arc<Mutex<ServerState>>>>>,
     user_tasks: Arc<RwLock<HashMap<lsp_types::NumberOrString, task::JoinHandle<()>>>>,
+    pending_responses: PendingResponses,
+    server_request_count: Arc<AtomicI32>,
+    server_request_timeout: Option<Duration>,
+    server_request_retries: usize,
+    ignored_methods: HashMap<String, usize>,
+    max_message_size: usize,
+    requests: HashMap<String, Request>,
+    notifications: HashMap<String, Notification>,
+    initialize_hook: Option<InitializeHook>,
+    message_hook: Option<MessageHook>,
+    state_factory: StateFactory,
+    middlewares: MiddlewareChain,
+    warm_cache_dir: Option<PathBuf>,
+    deterministic: bool,
+    profile: Option<String>,
+    /// Whether the client shut the server down, after which it only
+    /// awaits the client asking it to exit
+    shutdown: bool,
 }
 
 impl<R, W> Service<R, W>
@@ -48,34 +205,219 @@ where
             writer: Some(writer),
             state: Arc::new(Mutex::new(None)),
             user_tasks: Arc::new(RwLock::new(HashMap::new())),
+            pending_responses: Arc::new(Mutex::new(HashMap::new())),
+            server_request_count: Arc::new(AtomicI32::new(0)),
+            server_request_timeout: Some(DEFAULT_SERVER_REQUEST_TIMEOUT),
+            server_request_retries: 0,
+            ignored_methods: HashMap::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            requests: HashMap::new(),
+            notifications: HashMap::new(),
+            initialize_hook: None,
+            message_hook: None,
+            state_factory: Box::new(ServerState::from_init),
+            middlewares: Arc::new(vec![]),
+            warm_cache_dir: None,
+            deterministic: false,
+            profile: None,
+            shutdown: false,
         }
     }
 
+    /// Sets the largest `Content-Length` in bytes accepted from the client
+    ///
+    /// Larger messages are discarded without being buffered, and answered
+    /// with a `REQUEST_FAILED` error
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Registers requests in addition to those handled by ruffd, taking
+    /// precedence over ruffd's handler of the same method
+    pub fn add_requests<I, S>(&mut self, requests: I)
+    where
+        I: IntoIterator<Item = (S, Request)>,
+        S: Into<String>,
+    {
+        self.requests
+            .extend(requests.into_iter().map(|(k, v)| (k.into(), v)));
+    }
+
+    /// Registers notifications in addition to those handled by ruffd,
+    /// taking precedence over ruffd's handler of the same method
+    pub fn add_notifications<I, S>(&mut self, notifications: I)
+    where
+        I: IntoIterator<Item = (S, Notification)>,
+        S: Into<String>,
+    {
+        self.notifications
+            .extend(notifications.into_iter().map(|(k, v)| (k.into(), v)));
+    }
+
+    pub fn set_initialize_hook(&mut self, hook: InitializeHook) {
+        self.initialize_hook = Some(hook);
+    }
+
+    pub fn set_message_hook(&mut self, hook: MessageHook) {
+        self.message_hook = Some(hook);
+    }
+
+    /// Replaces the creation of the server state on initialization, which
+    /// defaults to `ServerState::from_init`
+    pub fn set_state_factory(&mut self, factory: StateFactory) {
+        self.state_factory = factory;
+    }
+
+    /// Adds a middleware, which is inside of those previously added
+    ///
+    /// # Panics
+    /// If called once the service is running
+    pub fn add_middleware(&mut self, middleware: Box<dyn Middleware>) {
+        Arc::get_mut(&mut self.middlewares)
+            .expect("middlewares added to a running service")
+            .push(middleware);
+    }
+
+    /// Restores the workspace index and diagnostics from a snapshot in
+    /// `dir` on initialization, and snapshots them there on shutdown
+    pub fn set_warm_cache_dir(&mut self, dir: PathBuf) {
+        self.warm_cache_dir = Some(dir);
+    }
+
+    /// Sets the time the client has to answer a server request before it's
+    /// cancelled, or `None` to wait indefinitely
+    ///
+    /// The response handler of a request out of retries is called with a
+    /// `RESPONSE_TIMED_OUT` error
+    pub fn set_server_request_timeout(&mut self, timeout: Option<Duration>) {
+        self.server_request_timeout = timeout;
+    }
+
+    /// Sets the number of times a server request is resent under a new id
+    /// once it times out
+    pub fn set_server_request_retries(&mut self, retries: usize) {
+        self.server_request_retries = retries;
+    }
+
+    /// Handles messages one at a time, such that replaying a session yields
+    /// identical output
+    ///
+    /// Each message is handled to completion before the next, with the
+    /// work it schedules handled before further client messages. Periodic
+    /// spilling of buffers and timing out of server requests are disabled,
+    /// as they depend on timing
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Activates the configuration profile `profile` on initialization,
+    /// unless the client's settings name one
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.profile = profile;
+    }
+
+    fn find_request(&self, method: &str) -> Option<Request> {
+        self.requests
+            .get(method)
+            .or_else(|| REQUEST_REGISTRY.get(method))
+            .copied()
+    }
+
+    fn find_notification(&self, method: &str) -> Option<Notification> {
+        self.notifications
+            .get(method)
+            .or_else(|| NOTIFICATION_REGISTRY.get(method))
+            .copied()
+    }
+
     async fn init(
         &mut self,
         init_params: &lsp_types::InitializeParams,
+        position_encoding: PositionEncoding,
     ) -> Result<lsp_types::ServerCapabilities, RuntimeError> {
         let capabilities_lock = {
             let mut state_handle = self.state.lock().await;
-            let new_state = ServerState::from_init(init_params)?;
+            let new_state = (self.state_factory)(init_params)?;
+            let deterministic = self.deterministic;
+            new_state.config_snapshot.update(|x| {
+                x.position_encoding = position_encoding;
+                x.deterministic = deterministic;
+                x.spill_dir = Some(spill::session_spill_dir());
+            });
+            // the level of the previous session's settings is reset to that
+            // of the new session's
+            let config = new_state.config_snapshot.load();
+            ResolvedConfig::resolve(&config.config.layer, &config.project_config).apply_log_level();
+            if let Some(profile) = &self.profile {
+                select_initial_profile(&new_state, profile);
+            }
             let rv = new_state.capabilities.clone();
             *state_handle = Some(Arc::new(Mutex::new(new_state)));
             rv
         };
         // FIXME erroneous lock here
+        if let Some(dir) = &self.warm_cache_dir {
+            let state = self.state.lock().await.clone().unwrap();
+            let state = state.lock().await;
+            if warm_cache::restore(dir, &state).await {
+                log_info!("restored warm cache from {}", dir.display());
+            }
+        }
         let capabilities = capabilities_lock.read().await;
         Ok(capabilities.clone())
     }
 
+    /// Spill directory of the session, once initialized
+    async fn spill_dir(&self) -> Option<PathBuf> {
+        let state = self.state.lock().await.clone()?;
+        let snapshot = state.lock().await.config_snapshot.load();
+        snapshot.spill_dir.clone()
+    }
+
+    async fn save_warm_cache(&self) {
+        let dir = match &self.warm_cache_dir {
+            Some(x) => x,
+            None => return,
+        };
+        let state = match self.state.lock().await.clone() {
+            Some(x) => x,
+            None => return,
+        };
+        let state = state.lock().await;
+        if let Err(err) = warm_cache::save(dir, &state).await {
+            log_error!("failed saving warm cache: {}", err);
+        }
+    }
+
+    /// Counts a client method the server has no handler for, logging the
+    /// first occurrence of each
+    fn record_ignored_method(&mut self, method: &str) {
+        let count = self.ignored_methods.entry(method.to_string()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            // the spec permits dropping `$/` methods, others hint at a
+            // client expecting an unsupported feature
+            if is_optional_method(method) {
+                log_debug!("ignoring optional method {}", method);
+            } else {
+                log_warn!("ignoring unknown method {}", method);
+            }
+        }
+    }
+
     /// Handles arbitrary client messages
     ///
-    /// Returns false if server should shut down
+    /// Breaks if the client asked the server to exit, otherwise continues
+    /// with the task handling the message if there is one
     async fn handle_client_msg(
         &mut self,
         rpc_message: RpcMessage,
+        span: Span,
+        trace: TraceId,
         scheduler_channel: Sender<ScheduledTask>,
         response_channel: Sender<RpcMessage>,
-    ) -> bool {
+        published: &SharedPublishedVersions,
+    ) -> ControlFlow<(), Option<task::JoinHandle<()>>> {
         let curr_state = self.state.lock().await.clone();
         // below code path should never be reached
         if curr_state.is_none() {
@@ -89,16 +431,47 @@ where
             };
             let resp = RpcResponseMessage::from_error(id, RpcErrors::SERVER_NOT_INITIALIZED);
             let response_channel = response_channel.clone();
-            task::spawn(async move {
+            spawn_named(|| "respond".to_string(), async move {
                 response_channel.send(resp.into()).await.unwrap();
             });
-            return true;
+            return ControlFlow::Continue(None);
         }
         let curr_state = curr_state.unwrap();
+        let rpc_message = match apply_inbound(&self.middlewares, rpc_message, &response_channel) {
+            Some(x) => x,
+            None => return ControlFlow::Continue(None),
+        };
+        if let Some(hook) = &self.message_hook {
+            hook(&rpc_message);
+        }
         match rpc_message {
             RpcMessage::Request(req) => {
-                if req.method.eq("exit") {
-                    return false;
+                // the server only answers shutdown once, any request after
+                // it being invalid
+                let resp = match (self.shutdown, req.method.as_str()) {
+                    (true, _) => Some(RpcResponseMessage::from_error(
+                        Some(req.id.clone()),
+                        RpcErrors::INVALID_REQUEST,
+                    )),
+                    (false, "shutdown") => {
+                        self.shutdown = true;
+                        Some(RpcResponseMessage::from_result(
+                            req.id.clone(),
+                            serde_json::Value::Null,
+                        ))
+                    }
+                    _ => None,
+                };
+                if let Some(resp) = resp {
+                    let response_channel = response_channel.clone();
+                    spawn_named(|| "respond".to_string(), async move {
+                        response_channel.send(resp.into()).await.unwrap();
+                    });
+                    return ControlFlow::Continue(None);
+                }
+                let request = self.find_request(&req.method);
+                if request.is_none() {
+                    self.record_ignored_method(&req.method);
                 }
                 let user_tasks = self.user_tasks.clone();
                 let id = req.id.clone();
@@ -112,154 +485,575 @@ where
                 });
                 let task_handle = schedule_request(
                     curr_state.clone(),
+                    request,
                     req,
+                    span,
+                    trace,
                     scheduler_channel,
                     response_channel,
                     Some(fut_cleanup),
                 )
                 .await;
+                // requests handled serially can't be cancelled
+                if self.deterministic {
+                    return ControlFlow::Continue(Some(task_handle));
+                }
                 let tasks_lock = self.user_tasks.clone();
                 let mut tasks_lg = tasks_lock.write().await;
                 tasks_lg.insert(id, task_handle);
+                ControlFlow::Continue(None)
             }
             RpcMessage::Notification(notif) => {
-                schedule_notification(
+                if notif.method == "exit" {
+                    return ControlFlow::Break(());
+                }
+                let notification = match self.find_notification(&notif.method) {
+                    Some(x) => x,
+                    None => {
+                        // notifications can't be responded to, so unknown
+                        // ones are dropped regardless of prefix
+                        self.record_ignored_method(&notif.method);
+                        return ControlFlow::Continue(None);
+                    }
+                };
+                // reset once the document is opened or closed, such that
+                // diagnostics of the reopened document are never compared
+                // against those of its previous versions still being
+                // published, and closed documents aren't tracked
+                let reset = synced_uri(&notif).map(|uri| {
+                    let published = published.clone();
+                    Box::pin(async move { published.lock().unwrap().reset(&uri) })
+                        as Pin<Box<dyn Future<Output = ()> + Send>>
+                });
+                let task_handle = schedule_notification(
                     curr_state.clone(),
+                    notification,
                     notif,
+                    span,
+                    trace,
                     scheduler_channel,
                     response_channel,
-                    None,
+                    reset,
                 )
                 .await;
+                ControlFlow::Continue(Some(task_handle))
+            }
+            RpcMessage::Response(resp) => {
+                self.handle_client_response(resp, scheduler_channel).await;
+                ControlFlow::Continue(None)
+            }
+        }
+    }
+
+    /// Routes a client response to the handler registered when the
+    /// corresponding server request was sent
+    async fn handle_client_response(
+        &mut self,
+        resp: RpcResponseMessage,
+        scheduler_channel: Sender<ScheduledTask>,
+    ) {
+        let handler = match resp.id() {
+            Some(id) => self.pending_responses.lock().await.remove(id),
+            None => None,
+        };
+        // responses without a pending request are dropped
+        if let Some(handler) = handler {
+            if let Some(follow_up) = handler(resp) {
+                // sent from a separate task as this loop is the consumer
+                spawn_named(|| "response follow up".to_string(), async move {
+                    // the session may have ended while the client responded
+                    if scheduler_channel
+                        .send(ScheduledTask::server(follow_up))
+                        .await
+                        .is_err()
+                    {
+                        log_debug!("session ended before the follow-up of a response was sent");
+                    }
+                });
             }
-            // TODO implement handler for server triggered request responses
-            _ => unimplemented!(),
         }
-        true
     }
 
     async fn handle_server_notification(
         &mut self,
         notification: ServerNotification,
+        trace: TraceId,
         scheduler_channel: Sender<ScheduledTask>,
         response_channel: Sender<RpcMessage>,
         cleanup_fut: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
-    ) {
-        let curr_state = self.state.lock().await.clone();
-        if curr_state.is_none() {
-            return;
-        }
-        let state = curr_state.unwrap();
+    ) -> Option<task::JoinHandle<()>> {
+        let state = self.state.lock().await.clone()?;
         let locks = (notification.create_locks)(state.clone()).await;
         let notify = Arc::new(Notify::new());
         let notify_clone = notify.clone();
         let fut = async move {
-            let handles = server_state_handles_from_locks(&locks).await;
+            let handles = handling(
+                "server notification",
+                server_state_handles_from_locks(&locks),
+            )
+            .await;
             notify_clone.notify_one();
-            let resp = (notification.exec)(handles, scheduler_channel).await;
-            response_channel.send(resp).await.unwrap();
+            if let Some(resp) = (notification.exec)(handles, scheduler_channel).await {
+                response_channel.send(resp).await.unwrap();
+            }
         };
-        task::spawn(async move {
+        let fut = traced(trace, fut);
+        let name = move || format!("server notification {}", trace);
+        let task_handle = spawn_named(name, async move {
             fut.await;
             if let Some(x) = cleanup_fut {
                 x.await;
             }
         });
         notify.notified().await;
+        Some(task_handle)
+    }
+
+    async fn handle_server_request(
+        &mut self,
+        request: ServerRequest,
+        trace: TraceId,
+        scheduler_channel: Sender<ScheduledTask>,
+        response_channel: Sender<RpcMessage>,
+    ) -> Option<task::JoinHandle<()>> {
+        let state = self.state.lock().await.clone()?;
+        let id = next_server_request_id(&self.server_request_count);
+        self.pending_responses
+            .lock()
+            .await
+            .insert(id.clone(), request.on_response);
+        let locks = (request.create_locks)(state.clone()).await;
+        let notify = Arc::new(Notify::new());
+        let notify_clone = notify.clone();
+        let expiry = match self.server_request_timeout {
+            Some(timeout) if !self.deterministic => Some(RequestExpiry {
+                timeout,
+                retries: self.server_request_retries,
+                pending_responses: self.pending_responses.clone(),
+                request_ids: self.server_request_count.clone(),
+                scheduler_channel: scheduler_channel.clone(),
+                response_channel: response_channel.clone(),
+            }),
+            _ => None,
+        };
+        let fut = async move {
+            let handles = handling("server request", server_state_handles_from_locks(&locks)).await;
+            notify_clone.notify_one();
+            let req = (request.exec)(handles, scheduler_channel, id).await;
+            if let Some(expiry) = expiry {
+                // expiry outlives the request's locks
+                spawn_named(
+                    || "server request expiry".to_string(),
+                    expiry.run(req.clone()),
+                );
+            }
+            response_channel.send(req.into()).await.unwrap();
+        };
+        let name = move || format!("server request {}", trace);
+        let task_handle = spawn_named(name, traced(trace, fut));
+        notify.notified().await;
+        Some(task_handle)
+    }
+
+    async fn handle_server_work(
+        &mut self,
+        work: ServerWork,
+        trace: TraceId,
+        scheduler_channel: Sender<ScheduledTask>,
+    ) -> Option<task::JoinHandle<()>> {
+        let state = self.state.lock().await.clone()?;
+        let locks = (work.create_locks)(state.clone()).await;
+        let notify = Arc::new(Notify::new());
+        let notify_clone = notify.clone();
+        let fut = async move {
+            let handles = handling("server work", server_state_handles_from_locks(&locks)).await;
+            notify_clone.notify_one();
+            (work.exec)(handles, scheduler_channel).await;
+        };
+        let name = move || format!("server work {}", trace);
+        let task_handle = spawn_named(name, traced(trace, fut));
+        notify.notified().await;
+        Some(task_handle)
+    }
+
+    /// Takes the next task to handle, being either a client message or work
+    /// scheduled by the server
+    async fn next_task(
+        &self,
+        client_channel: &mut Receiver<ScheduledTask>,
+        msg_channel: &mut Receiver<ScheduledTask>,
+    ) -> Option<ScheduledTask> {
+        if self.deterministic {
+            // tasks spawned by the previous task get to schedule their work,
+            // which is handled ahead of further client messages
+            task::yield_now().await;
+            tokio::select! {
+                biased;
+                Some(x) = msg_channel.recv() => Some(x),
+                x = client_channel.recv() => x,
+            }
+        } else {
+            tokio::select! {
+                Some(x) = msg_channel.recv() => Some(x),
+                x = client_channel.recv() => x,
+            }
+        }
     }
 
     async fn handle_loop(
         &mut self,
+        mut client_channel: Receiver<ScheduledTask>,
         mut msg_channel: Receiver<ScheduledTask>,
         scheduler_channel: Sender<ScheduledTask>,
         response_channel: Sender<RpcMessage>,
-    ) {
+        published: SharedPublishedVersions,
+    ) -> SessionOutcome {
         loop {
-            match msg_channel.recv().await.unwrap() {
-                ScheduledTask::Client(rpc_message) => {
-                    if !self
+            let task = match self.next_task(&mut client_channel, &mut msg_channel).await {
+                Some(x) => x,
+                // the listener stops once the client closes the connection
+                None => return SessionOutcome::Disconnected,
+            };
+            let task_handle = match task {
+                ScheduledTask::Client(rpc_message, span, trace) => {
+                    match self
                         .handle_client_msg(
                             rpc_message,
+                            span,
+                            trace,
                             scheduler_channel.clone(),
                             response_channel.clone(),
+                            &published,
                         )
                         .await
                     {
-                        break;
+                        ControlFlow::Continue(x) => x,
+                        ControlFlow::Break(()) if self.shutdown => return SessionOutcome::Exit,
+                        ControlFlow::Break(()) => return SessionOutcome::ExitWithoutShutdown,
                     }
                 }
-                ScheduledTask::Server(server_task) => match server_task {
+                ScheduledTask::Server(server_task, trace) => match server_task {
                     ServerInitiated::Notification(notif) => {
                         self.handle_server_notification(
                             notif,
+                            trace,
                             scheduler_channel.clone(),
                             response_channel.clone(),
                             None,
                         )
                         .await
                     }
-                    _ => unimplemented!(),
+                    ServerInitiated::Request(req) => {
+                        self.handle_server_request(
+                            req,
+                            trace,
+                            scheduler_channel.clone(),
+                            response_channel.clone(),
+                        )
+                        .await
+                    }
+                    ServerInitiated::Work(work) => {
+                        self.handle_server_work(work, trace, scheduler_channel.clone())
+                            .await
+                    }
                 },
+            };
+            if self.deterministic {
+                if let Some(x) = task_handle {
+                    x.await.ok();
+                }
             }
         }
     }
 
-    /// Consumes assigned reader and writer to run service
+    /// Consumes assigned reader and writer to run service, until the client
+    /// asks the server to exit or closes the connection
     ///
     /// # Panics
     /// If called multiple times this function will panic
-    pub async fn run(&mut self) {
+    pub async fn run(&mut self) -> SessionOutcome {
         let mut reader = self.reader.take().unwrap();
         let mut writer = self.writer.take().unwrap();
-        eprintln!("starting server");
-        let (init_req_id, init_params) = get_init_msg(&mut reader, &mut writer).await;
-        // TODO add better error handling on failing to initialize
-        let capabilities = self.init(&init_params).await.unwrap();
+        log_info!("starting server");
+        let max_message_size = self.max_message_size;
+        let (init_req_id, init_params, offered_encoding) =
+            match get_init_msg(&mut reader, &mut writer, max_message_size).await {
+                Some(x) => x,
+                None => {
+                    log_info!("client disconnected before initializing");
+                    return SessionOutcome::Disconnected;
+                }
+            };
+        let capabilities = match self
+            .init(&init_params, offered_encoding.unwrap_or_default())
+            .await
+        {
+            Ok(x) => x,
+            Err(err) => {
+                let message = format!("failed initializing: {}", err);
+                // the client is told why before the session ends
+                let resp = RpcResponseMessage::from_error(Some(init_req_id), err.into());
+                let resp_msg = serde_json::to_string(&resp).unwrap();
+                write_msg(&mut writer, resp_msg.as_bytes()).await.ok();
+                return SessionOutcome::Fatal(message);
+            }
+        };
         let initialize_result = lsp_types::InitializeResult {
             capabilities,
             server_info: Some(SERVER_INFO.clone()),
         };
-        let result_resp = RpcResponseMessage::from_result(init_req_id, initialize_result);
+        let mut result_value = serde_json::to_value(initialize_result).unwrap();
+        // lsp_types predates notebook sync, so its capability is added here
+        #[cfg(feature = "notebook")]
+        {
+            result_value["capabilities"]["notebookDocumentSync"] =
+                serde_json::to_value(notebook_document_sync()).unwrap();
+        }
+        // as is the negotiated position encoding
+        if let Some(encoding) = offered_encoding {
+            result_value["capabilities"]["positionEncoding"] = encoding.kind().into();
+        }
+        if let Some(hook) = &self.initialize_hook {
+            hook(&init_params, &mut result_value);
+        }
+        let result_resp = RpcResponseMessage::from_result(init_req_id, result_value);
         let result_msg = serde_json::to_string(&result_resp).unwrap();
-        write_msg(&mut writer, result_msg.as_bytes()).await.unwrap();
+        if write_msg(&mut writer, result_msg.as_bytes()).await.is_err() {
+            log_info!("client disconnected while initializing");
+            return SessionOutcome::Disconnected;
+        }
+        let middlewares = self.middlewares.clone();
+        let (client_s, client_r) = channel(1000);
         let (msg_s, msg_r) = channel(1000);
         let (resp_s, resp_r) = channel(1000);
-        let (msg_listen, resp_listen) = (msg_s.clone(), resp_s.clone());
-        let listen_task = task::spawn(async move {
-            eprintln!("started listener");
-            listen_loop(&mut reader, msg_listen, resp_listen).await;
+        let resp_listen = resp_s.clone();
+        let published = SharedPublishedVersions::default();
+        let published_handle = published.clone();
+        let listen_task = spawn_named(|| "listener".to_string(), async move {
+            log_info!("started listener");
+            listen_loop(&mut reader, client_s, resp_listen, max_message_size).await;
         });
-        let sender_task = task::spawn(async move {
-            eprintln!("started sender");
-            sender_loop(&mut writer, resp_r).await;
+        let sender_task = spawn_named(|| "sender".to_string(), async move {
+            log_info!("started sender");
+            sender_loop(&mut writer, resp_r, published, middlewares).await;
         });
-        self.handle_loop(msg_r, msg_s.clone(), resp_s).await;
+        // the state was created with the defaults in place of malformed
+        // options, which the client is told of as it can't see stderr
+        if let Some(Err(err)) = init_params
+            .initialization_options
+            .clone()
+            .map(ServerConfig::from_value)
+        {
+            resp_s
+                .send(log_message::malformed_options(&err).into())
+                .await
+                .ok();
+        }
+        let spill_channel = msg_s.clone();
+        let spill_task = (!self.deterministic).then(|| {
+            spawn_named(|| "spill".to_string(), async move {
+                spill_loop(spill_channel).await;
+            })
+        });
+        let end = self
+            .handle_loop(client_r, msg_r, msg_s.clone(), resp_s, published_handle)
+            .await;
+        if let Some(x) = spill_task {
+            x.abort();
+        }
+        self.save_warm_cache().await;
+        // buffers only need recovering after an unclean shutdown, which
+        // losing the client or exiting without shutting down is
+        if matches!(end, SessionOutcome::Exit) {
+            if let Some(dir) = self.spill_dir().await {
+                if let Err(err) = spill::clear(&dir) {
+                    log_error!("failed clearing spills: {}", err);
+                }
+            }
+        }
+        if !self.ignored_methods.is_empty() {
+            let mut ignored = self.ignored_methods.iter().collect::<Vec<_>>();
+            ignored.sort();
+            log_info!("ignored methods:");
+            for (method, count) in ignored {
+                log_info!("  {}: {}", method, count);
+            }
+        }
         listen_task.abort();
-        eprintln!("stopped listener");
+        log_info!("stopped listener");
         sender_task.abort();
-        eprintln!("stopped sender");
+        log_info!("stopped sender");
+        end
+    }
+}
+
+/// Passes a client message through the middlewares, returning `None` if it
+/// was rejected
+///
+/// Rejected requests are answered from a separate task, as the rejection
+/// still passes through the middlewares on its way out
+fn apply_inbound(
+    middlewares: &MiddlewareChain,
+    message: RpcMessage,
+    response_channel: &Sender<RpcMessage>,
+) -> Option<RpcMessage> {
+    let id = match &message {
+        RpcMessage::Request(x) => Some(x.id.clone()),
+        _ => None,
+    };
+    match middlewares
+        .iter()
+        .try_fold(message, |msg, x| x.inbound(msg))
+    {
+        Ok(x) => Some(x),
+        Err(err) => {
+            if let Some(id) = id {
+                let resp = RpcResponseMessage::from_error(Some(id), err);
+                let response_channel = response_channel.clone();
+                spawn_named(|| "respond".to_string(), async move {
+                    response_channel.send(resp.into()).await.unwrap();
+                });
+            }
+            None
+        }
+    }
+}
+
+/// Activates `profile` in a newly created state unless its settings name a
+/// profile
+fn select_initial_profile(state: &ServerState, profile: &str) {
+    let current = state.config_snapshot.load();
+    if current.config.profile.is_some() {
+        return;
+    }
+    if !current.config.profiles.contains_key(profile) {
+        log_warn!("profile {} isn't defined in the settings", profile);
     }
+    state
+        .config_snapshot
+        .update(|x| x.config.profile = Some(profile.to_string()));
 }
 
+/// Methods prefixed with `$/` are protocol implementation dependent and may
+/// be ignored
+fn is_optional_method(method: &str) -> bool {
+    method.starts_with("$/")
+}
+
+/// Reports the panic of `handler` to the client if it has opted into
+/// telemetry
+#[cfg(feature = "telemetry")]
+async fn report_panic(
+    state: &Arc<Mutex<ServerState>>,
+    response_channel: &Sender<RpcMessage>,
+    handler: &str,
+    payload: PanicPayload,
+) {
+    let event = telemetry::panic_event(handler, &payload);
+    telemetry::report(state, response_channel, event).await;
+}
+
+#[cfg(not(feature = "telemetry"))]
+async fn report_panic(
+    _state: &Arc<Mutex<ServerState>>,
+    _response_channel: &Sender<RpcMessage>,
+    _handler: &str,
+    _payload: PanicPayload,
+) {
+}
+
+/// Reports the error response of `handler` to the client if it has opted
+/// into telemetry
+#[cfg(feature = "telemetry")]
+async fn report_error(
+    state: &Arc<Mutex<ServerState>>,
+    response_channel: &Sender<RpcMessage>,
+    handler: &str,
+    error: &RpcResponseError,
+) {
+    if let Some(event) = telemetry::error_event(handler, error) {
+        telemetry::report(state, response_channel, event).await;
+    }
+}
+
+#[cfg(not(feature = "telemetry"))]
+async fn report_error(
+    _state: &Arc<Mutex<ServerState>>,
+    _response_channel: &Sender<RpcMessage>,
+    _handler: &str,
+    _error: &RpcResponseError,
+) {
+}
+
+/// Names the task handling a client message after its method and the
+/// document it's about, which is taken ahead of the params being consumed
+#[cfg(feature = "console")]
+fn task_name(kind: &str, method: &str, params: Option<&Params>) -> impl FnOnce() -> String {
+    let params = params.map(Params::to_value);
+    let uri = params.as_deref().and_then(|x| {
+        x.pointer("/textDocument/uri")
+            .or_else(|| x.pointer("/notebookDocument/uri"))
+            .or_else(|| x.get("uri"))
+            .and_then(|x| x.as_str())
+    });
+    let name = match uri {
+        Some(uri) => format!("{} {} {}", kind, method, uri),
+        None => format!("{} {}", kind, method),
+    };
+    move || name
+}
+
+#[cfg(not(feature = "console"))]
+fn task_name(_kind: &str, _method: &str, _params: Option<&Params>) -> impl FnOnce() -> String {
+    String::new
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn schedule_request(
     state: Arc<Mutex<ServerState>>,
+    request: Option<Request>,
     req: RpcRequest,
+    span: Span,
+    trace: TraceId,
     scheduler_channel: Sender<ScheduledTask>,
     response_channel: Sender<RpcMessage>,
     cleanup_fut: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
 ) -> task::JoinHandle<()> {
-    match REQUEST_REGISTRY.get(req.method.as_str()) {
+    let name = task_name("request", &req.method, req.params.as_ref());
+    match request {
         Some(request) => {
             let locks = (request.create_locks)(state.clone()).await;
             let notify = Arc::new(Notify::new());
             let notify_clone = notify.clone();
             let fut = async move {
-                let handles = server_state_handles_from_locks(&locks).await;
+                let handles = handling(&req.method, server_state_handles_from_locks(&locks))
+                    .instrument(tracing::debug_span!("lock"))
+                    .await;
                 notify_clone.notify_one();
-                let resp = (request.exec)(handles, scheduler_channel, req.id, req.params).await;
-                response_channel.send(resp.into()).await.unwrap();
+                log_debug!("handling {} {:?}", req.method, req.id);
+                let exec_fut =
+                    (request.exec)(handles, scheduler_channel, req.id.clone(), req.params);
+                let resp = match unwind::catch_unwind(exec_fut)
+                    .instrument(tracing::debug_span!("execute"))
+                    .await
+                {
+                    Ok(resp) => resp,
+                    Err(payload) => {
+                        report_panic(&state, &response_channel, &req.method, payload).await;
+                        RpcResponseMessage::from_error(Some(req.id), RpcErrors::INTERNAL_ERROR)
+                    }
+                };
+                if let RpcResponseMessage::Error(x) = &resp {
+                    report_error(&state, &response_channel, &req.method, &x.error).await;
+                }
+                response_channel
+                    .send(resp.into())
+                    .instrument(tracing::debug_span!("respond"))
+                    .await
+                    .unwrap();
             };
-            let task_handle = task::spawn(async move {
+            let fut = traced(trace, fut).instrument(span);
+            let task_handle = spawn_named(name, async move {
                 fut.await;
                 if let Some(x) = cleanup_fut {
                     x.await;
@@ -268,43 +1062,151 @@ async fn schedule_request(
             notify.notified().await;
             task_handle
         }
-        None => task::spawn(async move {
-            let resp = RpcResponseMessage::from_error(Some(req.id), RpcErrors::METHOD_NOT_FOUND);
-            response_channel.send(resp.into()).await.unwrap();
-        }),
+        None => spawn_named(
+            name,
+            async move {
+                let resp =
+                    RpcResponseMessage::from_error(Some(req.id), RpcErrors::METHOD_NOT_FOUND);
+                response_channel.send(resp.into()).await.unwrap();
+            }
+            .instrument(span),
+        ),
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn schedule_notification(
     state: Arc<Mutex<ServerState>>,
+    notification: Notification,
     notif: RpcNotification,
+    span: Span,
+    trace: TraceId,
     scheduler_channel: Sender<ScheduledTask>,
     response_channel: Sender<RpcMessage>,
     cleanup_fut: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
-) -> Option<task::JoinHandle<()>> {
-    match NOTIFICATION_REGISTRY.get(notif.method.as_str()) {
-        Some(notification) => {
-            let locks = (notification.create_locks)(state.clone()).await;
-            let notify = Arc::new(Notify::new());
-            let notify_clone = notify.clone();
-            let fut = async move {
-                let handles = server_state_handles_from_locks(&locks).await;
-                notify_clone.notify_one();
-                let resp = (notification.exec)(handles, scheduler_channel, notif.params).await;
-                if let Some(x) = resp {
-                    response_channel.send(x.into()).await.unwrap();
-                }
+) -> task::JoinHandle<()> {
+    let name = task_name("notification", &notif.method, notif.params.as_ref());
+    let locks = (notification.create_locks)(state.clone()).await;
+    let notify = Arc::new(Notify::new());
+    let notify_clone = notify.clone();
+    let fut = async move {
+        let handles = handling(&notif.method, server_state_handles_from_locks(&locks))
+            .instrument(tracing::debug_span!("lock"))
+            .await;
+        notify_clone.notify_one();
+        log_debug!("handling {}", notif.method);
+        let exec_fut = (notification.exec)(handles, scheduler_channel, notif.params);
+        let resp = match unwind::catch_unwind(exec_fut)
+            .instrument(tracing::debug_span!("execute"))
+            .await
+        {
+            Ok(resp) => resp,
+            Err(payload) => {
+                report_panic(&state, &response_channel, &notif.method, payload).await;
+                None
+            }
+        };
+        match resp {
+            // errors are logged rather than responded to, as there's no
+            // request for the response to answer
+            Some(RpcResponseMessage::Error(x)) => {
+                report_error(&state, &response_channel, &notif.method, &x.error).await;
+                log_message::notification_error(&state, &response_channel, &notif.method, &x.error)
+                    .instrument(tracing::debug_span!("respond"))
+                    .await;
+            }
+            Some(x) => {
+                response_channel
+                    .send(x.into())
+                    .instrument(tracing::debug_span!("respond"))
+                    .await
+                    .unwrap();
+            }
+            None => {}
+        }
+    };
+    let fut = traced(trace, fut).instrument(span);
+    let task_handle = spawn_named(name, async move {
+        fut.await;
+        if let Some(x) = cleanup_fut {
+            x.await;
+        }
+    });
+    notify.notified().await;
+    task_handle
+}
+
+fn next_server_request_id(count: &AtomicI32) -> lsp_types::NumberOrString {
+    lsp_types::NumberOrString::Number(count.fetch_add(1, Ordering::Relaxed) + 1)
+}
+
+/// Timeout and retry policy of a server request awaiting its response
+struct RequestExpiry {
+    timeout: Duration,
+    retries: usize,
+    pending_responses: PendingResponses,
+    request_ids: Arc<AtomicI32>,
+    scheduler_channel: Sender<ScheduledTask>,
+    response_channel: Sender<RpcMessage>,
+}
+
+impl RequestExpiry {
+    /// Waits on the client's response to `req`, cancelling it should the
+    /// client not respond in time and resending it while retries remain
+    ///
+    /// Once out of retries the response handler is removed from the pending
+    /// responses and called with a `RESPONSE_TIMED_OUT` error instead
+    async fn run(mut self, mut req: RpcRequest) {
+        loop {
+            time::sleep(self.timeout).await;
+            let handler = match self.pending_responses.lock().await.remove(&req.id) {
+                Some(x) => x,
+                // answered in time
+                None => return,
             };
-            let task_handle = task::spawn(async move {
-                fut.await;
-                if let Some(x) = cleanup_fut {
-                    x.await;
+            let cancel = RpcNotification::new(
+                "$/cancelRequest".to_string(),
+                Some(serde_json::json!({ "id": req.id })),
+            );
+            self.response_channel.send(cancel.into()).await.ok();
+            if self.retries == 0 {
+                log_warn!("{} timed out awaiting the client", req.method);
+                let err = RpcErrors::RESPONSE_TIMED_OUT.with_message(format!(
+                    "no response to {} within {:?}",
+                    req.method, self.timeout
+                ));
+                if let Some(follow_up) = handler(RpcResponseMessage::from_error(Some(req.id), err))
+                {
+                    self.scheduler_channel
+                        .send(ScheduledTask::server(follow_up))
+                        .await
+                        .ok();
                 }
-            });
-            notify.notified().await;
-            Some(task_handle)
+                return;
+            }
+            self.retries -= 1;
+            log_debug!("retrying {} after timing out", req.method);
+            req.id = next_server_request_id(&self.request_ids);
+            self.pending_responses
+                .lock()
+                .await
+                .insert(req.id.clone(), handler);
+            self.response_channel.send(req.clone().into()).await.ok();
         }
-        None => None,
+    }
+}
+
+/// Periodically schedules spilling of modified buffers
+async fn spill_loop(scheduler_channel: Sender<ScheduledTask>) {
+    let mut interval = time::interval(spill::SPILL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let spill_op = run_spill_op();
+        scheduler_channel
+            .send(ScheduledTask::server(spill_op))
+            .await
+            .ok()
+            .unwrap();
     }
 }
 
@@ -312,33 +1214,50 @@ async fn listen_loop<R>(
     reader: &mut R,
     msg_channel: Sender<ScheduledTask>,
     response_channel: Sender<RpcMessage>,
+    max_message_size: usize,
 ) where
     R: AsyncBufReadExt + AsyncReadExt + Unpin,
 {
     loop {
-        let next_msg_result = match read_next_msg(reader).await {
-            Ok(message) => match serde_json::from_str::<RpcMessage>(&message) {
-                Ok(rpc_message) => {
-                    if !rpc_message.validate() {
-                        Err(RpcErrors::INVALID_REQUEST)
-                    } else {
-                        Ok(rpc_message)
-                    }
-                }
-                Err(x) => Err(x.into()),
-            },
+        // the span starts once a message arrives, such that time spent
+        // awaiting the client isn't attributed to the message
+        let next_msg_result = match read_header(reader).await {
+            Ok(Some(content_length)) => {
+                let trace = TraceId::next();
+                let span = tracing::info_span!(
+                    "rpc",
+                    method = field::Empty,
+                    id = field::Empty,
+                    trace = %trace
+                );
+                read_payload(reader, content_length, max_message_size)
+                    .instrument(tracing::debug_span!(parent: &span, "read", bytes = content_length))
+                    .await
+                    .and_then(|message| {
+                        tracing::debug_span!(parent: &span, "parse")
+                            .in_scope(|| parse_message(&message))
+                    })
+                    .map(|message| {
+                        record_message(&span, &message);
+                        (message, span, trace)
+                    })
+            }
+            Ok(None) => {
+                log_info!("client closed the connection");
+                return;
+            }
             Err(err) => Err(err),
         };
         match next_msg_result {
-            Ok(message) => msg_channel
-                .send(ScheduledTask::Client(message))
+            Ok((message, span, trace)) => msg_channel
+                .send(ScheduledTask::Client(message, span, trace))
                 .await
                 .ok()
                 .unwrap(),
             Err(err) => {
                 let resp = RpcResponseMessage::from_error(None, err);
                 let response_channel = response_channel.clone();
-                task::spawn(async move {
+                spawn_named(|| "respond".to_string(), async move {
                     response_channel.send(resp.into()).await.unwrap();
                 });
             }
@@ -346,20 +1265,85 @@ async fn listen_loop<R>(
     }
 }
 
-async fn sender_loop<W>(writer: &mut W, mut response_channel: Receiver<RpcMessage>)
-where
+fn parse_message(message: &str) -> RpcResult<RpcMessage> {
+    let rpc_message = serde_json::from_str::<RpcMessage>(message)?;
+    if rpc_message.validate() {
+        Ok(rpc_message)
+    } else {
+        Err(RpcErrors::INVALID_REQUEST)
+    }
+}
+
+/// Records the method and id of a message on the span of its handling
+fn record_message(span: &Span, message: &RpcMessage) {
+    match message {
+        RpcMessage::Request(x) => {
+            span.record("method", x.method.as_str());
+            span.record("id", field::debug(&x.id));
+        }
+        RpcMessage::Notification(x) => {
+            span.record("method", x.method.as_str());
+        }
+        RpcMessage::Response(x) => {
+            span.record("id", field::debug(x.id()));
+        }
+    }
+}
+
+/// Writes every message sent to the client, in the order queued
+///
+/// Being the only writer, frames are never interleaved. Diagnostics older
+/// than those already published for a document are dropped
+async fn sender_loop<W>(
+    writer: &mut W,
+    mut response_channel: Receiver<RpcMessage>,
+    published: SharedPublishedVersions,
+    middlewares: MiddlewareChain,
+) where
     W: AsyncWriteExt + Unpin,
 {
+    let mut frames = FrameWriter::new();
     loop {
-        let resp = response_channel.recv().await.unwrap();
-        let msg_str = serde_json::to_string(&resp).unwrap();
-        write_msg(writer, msg_str.as_bytes()).await.unwrap();
+        let msg = response_channel.recv().await.unwrap();
+        if !published.lock().unwrap().admit(&msg) {
+            log_debug!("dropped diagnostics older than those published");
+            continue;
+        }
+        let resp = middlewares.iter().rev().fold(msg, |msg, x| x.outbound(msg));
+        frames.write(writer, &resp).await.unwrap();
+    }
+}
+
+/// Initialize request, along with the position encoding negotiated from it
+type InitRequest = (
+    lsp_types::NumberOrString,
+    lsp_types::InitializeParams,
+    Option<PositionEncoding>,
+);
+
+/// Picks the position encoding from the `positionEncodings` offered by the
+/// client, which lsp_types predates. `None` if the client offers none, in
+/// which case the protocol's default is left unannounced
+fn negotiate_position_encoding(params: &serde_json::Value) -> Option<PositionEncoding> {
+    let offered = params
+        .pointer("/capabilities/general/positionEncodings")?
+        .as_array()?;
+    Some(PositionEncoding::negotiate(
+        offered.iter().filter_map(|x| x.as_str()),
+    ))
+}
+
+/// Nulls an empty `rootUri`, sent by some clients without a workspace,
+/// which would otherwise fail to parse as a uri
+fn strip_empty_root_uri(params: &mut serde_json::Value) {
+    if let Some(root_uri) = params.get_mut("rootUri") {
+        if root_uri.as_str() == Some("") {
+            *root_uri = serde_json::Value::Null;
+        }
     }
 }
 
-fn parse_init_request(
-    req_msg: &str,
-) -> RpcResult<(lsp_types::NumberOrString, lsp_types::InitializeParams)> {
+fn parse_init_request(req_msg: &str) -> RpcResult<InitRequest> {
     // NOTE any message not matching the format required for initialization
     // is treated as a PARSE_ERROR
     let request: RpcMessage = serde_json::from_str(req_msg)?;
@@ -368,30 +1352,36 @@ fn parse_init_request(
             if !req.method.eq("initialize") {
                 return Err(RpcErrors::SERVER_NOT_INITIALIZED);
             }
-            let param_string = req.params.ok_or(RpcErrors::PARSE_ERROR)?;
+            let mut param_string = req.params.ok_or(RpcErrors::PARSE_ERROR)?.into_value();
+            strip_empty_root_uri(&mut param_string);
+            let encoding = negotiate_position_encoding(&param_string);
             let params: lsp_types::InitializeParams = serde_json::from_value(param_string)?;
-            Ok((req.id, params))
+            Ok((req.id, params, encoding))
         }
         _ => Err(RpcErrors::SERVER_NOT_INITIALIZED),
     }
 }
 
+/// Reads messages until the initialize request, `None` if the client closes
+/// the connection first
 async fn get_init_msg<R, W>(
     reader: &mut R,
     writer: &mut W,
-) -> (lsp_types::NumberOrString, lsp_types::InitializeParams)
+    max_message_size: usize,
+) -> Option<InitRequest>
 where
     R: AsyncBufReadExt + AsyncReadExt + Unpin,
     W: AsyncWriteExt + Unpin,
 {
     loop {
-        let message_result = match read_next_msg(reader).await {
-            Ok(msg) => parse_init_request(msg.as_str()),
+        let message_result = match read_next_msg(reader, max_message_size).await {
+            Ok(Some(msg)) => parse_init_request(msg.as_str()),
+            Ok(None) => return None,
             Err(err) => Err(err),
         };
         match message_result {
             Ok(rv) => {
-                break rv;
+                break Some(rv);
             }
             Err(err) => {
                 let resp = RpcResponseMessage::from_error(None, err);
@@ -402,7 +1392,22 @@ where
     }
 }
 
-async fn read_next_msg<R>(reader: &mut R) -> RpcResult<String>
+/// Reads the next message, `None` if the client closed the connection
+async fn read_next_msg<R>(reader: &mut R, max_message_size: usize) -> RpcResult<Option<String>>
+where
+    R: AsyncBufReadExt + AsyncReadExt + Unpin,
+{
+    match read_header(reader).await? {
+        Some(content_length) => read_payload(reader, content_length, max_message_size)
+            .await
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads the header of the next message, returning its `Content-Length`, or
+/// `None` if the client closed the connection
+async fn read_header<R>(reader: &mut R) -> RpcResult<Option<usize>>
 where
     R: AsyncBufReadExt + AsyncReadExt + Unpin,
 {
@@ -410,21 +1415,51 @@ where
     let mut buff = String::new();
     let num_str = loop {
         buff.clear();
-        reader.read_line(&mut buff).await?;
+        if reader.read_line(&mut buff).await? == 0 {
+            return Ok(None);
+        }
         if let Some(match_str) = PAYLOAD_START_PATTERN.captures(&buff) {
             break match_str["size"].to_string();
         }
     };
-    let content_length = num_str.parse::<u32>().unwrap();
+    let content_length = num_str
+        .parse::<usize>()
+        .map_err(|_| RpcErrors::PARSE_ERROR.with_message("invalid Content-Length"))?;
     // content-type
     buff.clear();
     reader.read_line(&mut buff).await?;
     (buff.trim().eq("utf8") || buff.trim().eq("utf-8") || buff.trim().eq(""))
         .then_some(..)
         .ok_or(RuntimeError::UnknownEncoding(buff))?;
-    let mut bytes_rv = vec![0u8; content_length as usize];
+    Ok(Some(content_length))
+}
+
+/// Reads a message payload of `content_length` bytes following its header
+async fn read_payload<R>(
+    reader: &mut R,
+    content_length: usize,
+    max_message_size: usize,
+) -> RpcResult<String>
+where
+    R: AsyncBufReadExt + AsyncReadExt + Unpin,
+{
+    if content_length > max_message_size {
+        // the payload is discarded unread so as not to allocate for it
+        io::copy(
+            &mut (&mut *reader).take(content_length as u64),
+            &mut io::sink(),
+        )
+        .await?;
+        return Err(RpcErrors::REQUEST_FAILED.with_message(format!(
+            "message of {} bytes exceeds the limit of {} bytes",
+            content_length, max_message_size
+        )));
+    }
+    let mut bytes_rv = vec![0u8; content_length];
     reader.read_exact(&mut bytes_rv).await?;
-    Ok(String::from_utf8(bytes_rv).unwrap())
+    String::from_utf8(bytes_rv).map_err(|err| {
+        RpcErrors::PARSE_ERROR.with_message(format!("payload isn't valid UTF-8: {}", err))
+    })
 }
 
 async fn write_msg<W>(writer: &mut W, msg: &[u8]) -> io::Result<()>
@@ -433,9 +1468,51 @@ where
 {
     // NOTE this function cannot return an RpcResult as it is the reporter of
     // RpcErrors
-    let header_str = format!("Content-Length: {}\r\n\r\n", msg.len());
-    let bytes = [header_str.as_bytes(), msg].concat();
-    writer.write_all(&bytes[..]).await?;
-    writer.flush().await?;
-    Ok(())
+    write_frame(writer, msg).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_init_request_empty_root() {
+        let msg = serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {"processId": null, "rootUri": "", "rootPath": "/tmp", "capabilities": {}}
+        });
+        let params = parse_init_request(&msg.to_string()).unwrap().1;
+        assert_eq!(params.root_uri, None);
+    }
+
+    #[test]
+    fn test_negotiate_position_encoding() {
+        let init = |capabilities: serde_json::Value| {
+            let msg = serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": {"processId": null, "rootUri": null, "capabilities": capabilities}
+            });
+            parse_init_request(&msg.to_string()).unwrap().2
+        };
+        assert_eq!(init(serde_json::json!({})), None);
+        let offered =
+            |x: serde_json::Value| serde_json::json!({"general": {"positionEncodings": x}});
+        assert_eq!(
+            init(offered(serde_json::json!(["utf-16"]))),
+            Some(PositionEncoding::Utf16)
+        );
+        assert_eq!(
+            init(offered(serde_json::json!(["utf-8", "utf-32", "utf-16"]))),
+            Some(PositionEncoding::Utf32)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_payload_invalid_utf8() {
+        let mut reader = io::BufReader::new(&[0xff, 0xfe, b'{', b'}'][..]);
+        let err = read_payload(&mut reader, 4, 1 << 10).await.unwrap_err();
+        assert_eq!(err.code, RpcErrors::PARSE_ERROR.code);
+        let mut reader = io::BufReader::new(&b"{}"[..]);
+        assert_eq!(read_payload(&mut reader, 2, 1 << 10).await.unwrap(), "{}");
+    }
 }
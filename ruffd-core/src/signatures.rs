@@ -0,0 +1,424 @@
+//! Signatures of the function being called at the cursor, served by
+//! `textDocument/signatureHelp`
+//!
+//! Signatures are read from the `def` statements of the document, falling
+//! back to those of common builtins. Both calls and definitions are found
+//! from tokens, such that calls still being typed, which don't parse, are
+//! understood
+use ruffd_types::lsp_types;
+use ruffd_types::rustpython_ast::Location;
+use ruffd_types::rustpython_parser::lexer::{make_tokenizer, Spanned};
+use ruffd_types::rustpython_parser::token::Tok;
+
+/// Signatures of the builtins given help for, as they'd be defined in python
+const BUILTINS: &str = "\
+def abs(x, /): ...
+def all(iterable, /): ...
+def any(iterable, /): ...
+def divmod(x, y, /): ...
+def enumerate(iterable, start=0): ...
+def filter(function, iterable, /): ...
+def getattr(object, name, default=None, /): ...
+def hasattr(obj, name, /): ...
+def isinstance(obj, class_or_tuple, /): ...
+def issubclass(cls, class_or_tuple, /): ...
+def iter(object, sentinel=None, /): ...
+def len(obj, /): ...
+def map(function, iterable, /, *iterables): ...
+def max(iterable, /, *, key=None, default=None): ...
+def min(iterable, /, *, key=None, default=None): ...
+def next(iterator, default=None, /): ...
+def open(file, mode='r', buffering=-1, encoding=None, errors=None, newline=None, closefd=True, opener=None): ...
+def print(*values, sep=' ', end='\\n', file=None, flush=False): ...
+def range(start, stop=None, step=1, /): ...
+def repr(obj, /): ...
+def round(number, ndigits=None): ...
+def setattr(obj, name, value, /): ...
+def sorted(iterable, /, *, key=None, reverse=False): ...
+def sum(iterable, /, start=0): ...
+def zip(*iterables, strict=False): ...
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParameterKind {
+    Positional,
+    /// `/` or a bare `*`, separating kinds of parameters
+    Separator,
+    VarArgs,
+    KeywordOnly,
+    VarKeywords,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Parameter {
+    /// Source of the parameter, whitespace collapsed
+    label: String,
+    name: String,
+    kind: ParameterKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    /// Location of the `def` statement
+    location: Location,
+    parameters: Vec<Parameter>,
+}
+
+/// Argument of a call the cursor is in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallSite {
+    pub callee: String,
+    /// Whether the callee is an attribute, as when calling a method
+    pub attribute: bool,
+    /// Number of arguments preceding the cursor's
+    pub argument: usize,
+    /// Keyword the cursor's argument is passed as
+    pub keyword: Option<String>,
+}
+
+/// Text of `source` from `start` to `end`, with runs of whitespace collapsed
+/// to a single space
+fn text_between(lines: &[Vec<char>], start: Location, end: Location) -> String {
+    let mut rv = String::new();
+    for row in start.row()..=end.row() {
+        let line = match lines.get(row - 1) {
+            Some(x) => x,
+            None => break,
+        };
+        let from = if row == start.row() {
+            start.column()
+        } else {
+            0
+        };
+        let to = if row == end.row() {
+            end.column()
+        } else {
+            line.len()
+        };
+        if !rv.is_empty() {
+            rv.push(' ');
+        }
+        rv.extend(line[from.min(line.len())..to.min(line.len())].iter());
+    }
+    rv.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Parameter spanning `tokens`, being those between two commas at the depth
+/// of the parameter list
+fn parameter(lines: &[Vec<char>], tokens: &[Spanned], keyword_only: bool) -> Option<Parameter> {
+    let (first, last) = (tokens.first()?, tokens.last()?);
+    let label = text_between(lines, first.0, last.2);
+    let name = tokens.iter().find_map(|x| match &x.1 {
+        Tok::Name { name } => Some(name.clone()),
+        _ => None,
+    });
+    let kind = match (&first.1, &name) {
+        (Tok::Slash | Tok::Star, None) => ParameterKind::Separator,
+        (Tok::Star, Some(_)) => ParameterKind::VarArgs,
+        (Tok::DoubleStar, Some(_)) => ParameterKind::VarKeywords,
+        (_, Some(_)) if keyword_only => ParameterKind::KeywordOnly,
+        (_, Some(_)) => ParameterKind::Positional,
+        _ => return None,
+    };
+    Some(Parameter {
+        label,
+        name: name.unwrap_or_default(),
+        kind,
+    })
+}
+
+/// Signatures of the functions defined in `source`, in the order they're
+/// defined. Definitions past a lexical error are skipped
+pub fn signatures(source: &str) -> Vec<Signature> {
+    let lines = source
+        .lines()
+        .map(|x| x.chars().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let tokens = make_tokenizer(source)
+        .map_while(Result::ok)
+        .collect::<Vec<_>>();
+    let mut rv = vec![];
+    for (idx, window) in tokens.windows(3).enumerate() {
+        let name = match window {
+            [(location, Tok::Def, _), (_, Tok::Name { name }, _), (_, Tok::Lpar, _)] => {
+                (location, name)
+            }
+            _ => continue,
+        };
+        let mut parameters = vec![];
+        let mut depth = 0;
+        let mut start = idx + 3;
+        let mut keyword_only = false;
+        for (offset, token) in tokens[idx + 3..].iter().enumerate() {
+            let end = idx + 3 + offset;
+            match token.1 {
+                Tok::Lpar | Tok::Lsqb | Tok::Lbrace => depth += 1,
+                Tok::Rpar | Tok::Rsqb | Tok::Rbrace if depth > 0 => depth -= 1,
+                Tok::Comma | Tok::Rpar if depth == 0 => {
+                    if let Some(x) = parameter(&lines, &tokens[start..end], keyword_only) {
+                        // parameters following `*` can only be passed by keyword
+                        keyword_only |= x.kind == ParameterKind::VarArgs || x.label == "*";
+                        parameters.push(x);
+                    }
+                    start = end + 1;
+                    if token.1 == Tok::Rpar {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        rv.push(Signature {
+            name: name.1.clone(),
+            location: *name.0,
+            parameters,
+        });
+    }
+    rv
+}
+
+/// Call whose parentheses the cursor at `at` is within, the innermost if
+/// calls are nested
+pub fn call_at(source: &str, at: Location) -> Option<CallSite> {
+    // only the source preceding the cursor is lexed, the rest of the call
+    // possibly not being typed yet
+    let prefix = source
+        .split_inclusive('\n')
+        .take(at.row())
+        .enumerate()
+        .map(|(idx, line)| match idx + 1 == at.row() {
+            true => line.chars().take(at.column()).collect::<String>(),
+            false => line.to_string(),
+        })
+        .collect::<String>();
+    let tokens = make_tokenizer(&prefix)
+        .map_while(Result::ok)
+        .collect::<Vec<_>>();
+    // brackets opened before the cursor, with the call of each paren
+    let mut brackets: Vec<Option<CallSite>> = vec![];
+    for (idx, (_, tok, _)) in tokens.iter().enumerate() {
+        let prev = idx.checked_sub(1).map(|x| &tokens[x].1);
+        let next = tokens.get(idx + 1).map(|x| &x.1);
+        match tok {
+            Tok::Lpar => {
+                let call = match prev {
+                    Some(Tok::Name { name }) if idx < 2 || tokens[idx - 2].1 != Tok::Def => {
+                        Some(CallSite {
+                            callee: name.clone(),
+                            attribute: idx >= 2 && tokens[idx - 2].1 == Tok::Dot,
+                            argument: 0,
+                            keyword: None,
+                        })
+                    }
+                    _ => None,
+                };
+                brackets.push(call);
+            }
+            Tok::Lsqb | Tok::Lbrace => brackets.push(None),
+            Tok::Rpar | Tok::Rsqb | Tok::Rbrace => {
+                brackets.pop();
+            }
+            Tok::Comma => {
+                if let Some(Some(call)) = brackets.last_mut() {
+                    call.argument += 1;
+                    call.keyword = None;
+                }
+            }
+            Tok::Name { name } if matches!(prev, Some(Tok::Lpar | Tok::Comma)) => {
+                if let (Some(Some(call)), Some(Tok::Equal)) = (brackets.last_mut(), next) {
+                    call.keyword = Some(name.clone());
+                }
+            }
+            Tok::Newline => brackets.clear(),
+            _ => {}
+        }
+    }
+    brackets.into_iter().rev().flatten().next()
+}
+
+impl Signature {
+    pub fn label(&self) -> String {
+        let parameters = self
+            .parameters
+            .iter()
+            .map(|x| x.label.as_str())
+            .collect::<Vec<_>>();
+        format!("{}({})", self.name, parameters.join(", "))
+    }
+
+    /// Index of the parameter `call` is passing its argument to
+    pub fn active_parameter(&self, call: &CallSite) -> Option<usize> {
+        let find = |kind: ParameterKind| self.parameters.iter().position(|x| x.kind == kind);
+        if let Some(keyword) = &call.keyword {
+            return self
+                .parameters
+                .iter()
+                .position(|x| {
+                    x.name == *keyword
+                        && matches!(
+                            x.kind,
+                            ParameterKind::Positional | ParameterKind::KeywordOnly
+                        )
+                })
+                .or_else(|| find(ParameterKind::VarKeywords));
+        }
+        let positional = self
+            .parameters
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.kind == ParameterKind::Positional)
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        // the instance is bound to the first parameter of a method
+        let bound = call.attribute
+            && matches!(
+                self.parameters.first(),
+                Some(x) if x.name == "self" || x.name == "cls"
+            );
+        positional
+            .get(call.argument + usize::from(bound))
+            .copied()
+            .or_else(|| find(ParameterKind::VarArgs))
+    }
+
+    pub fn information(&self, call: &CallSite) -> lsp_types::SignatureInformation {
+        let active_parameter = self.active_parameter(call);
+        lsp_types::SignatureInformation {
+            label: self.label(),
+            documentation: None,
+            parameters: Some(
+                self.parameters
+                    .iter()
+                    .map(|x| lsp_types::ParameterInformation {
+                        label: lsp_types::ParameterLabel::Simple(x.label.clone()),
+                        documentation: None,
+                    })
+                    .collect(),
+            ),
+            active_parameter: active_parameter.map(|x| x as u32),
+        }
+    }
+}
+
+/// Signature of the function `call` is calling, preferring the last one
+/// defined in `source` before `at`. Builtins are only matched to calls of
+/// names, as methods of the same name are unrelated
+pub fn signature_for(source: &str, call: &CallSite, at: Location) -> Option<Signature> {
+    let mut local = signatures(source)
+        .into_iter()
+        .filter(|x| x.name == call.callee)
+        .collect::<Vec<_>>();
+    let idx = local.iter().rposition(|x| x.location < at).unwrap_or(0);
+    if idx < local.len() {
+        return Some(local.swap_remove(idx));
+    }
+    if call.attribute {
+        return None;
+    }
+    signatures(BUILTINS)
+        .into_iter()
+        .find(|x| x.name == call.callee)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(callee: &str, attribute: bool, argument: usize, keyword: Option<&str>) -> CallSite {
+        CallSite {
+            callee: callee.to_string(),
+            attribute,
+            argument,
+            keyword: keyword.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_signatures() {
+        let source = "\
+def f(a, b: int = (1, 2), /, *args, c, **kwargs):
+    pass
+class A:
+    def g(self,
+          x):
+        pass
+";
+        let found = signatures(source);
+        assert_eq!(
+            found.iter().map(Signature::label).collect::<Vec<_>>(),
+            vec!["f(a, b: int = (1, 2), /, *args, c, **kwargs)", "g(self, x)"]
+        );
+        let kinds = found[0]
+            .parameters
+            .iter()
+            .map(|x| x.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ParameterKind::Positional,
+                ParameterKind::Positional,
+                ParameterKind::Separator,
+                ParameterKind::VarArgs,
+                ParameterKind::KeywordOnly,
+                ParameterKind::VarKeywords,
+            ]
+        );
+        let f = &found[0];
+        assert_eq!(f.active_parameter(&call("f", false, 1, None)), Some(1));
+        assert_eq!(f.active_parameter(&call("f", false, 4, None)), Some(3));
+        assert_eq!(f.active_parameter(&call("f", false, 2, Some("c"))), Some(4));
+        assert_eq!(f.active_parameter(&call("f", false, 2, Some("d"))), Some(5));
+        let g = &found[1];
+        assert_eq!(g.active_parameter(&call("g", true, 0, None)), Some(1));
+        assert_eq!(g.active_parameter(&call("g", true, 1, None)), None);
+    }
+
+    #[test]
+    fn test_call_at() {
+        let source = "x = f(a, g([1, 2]), key=h(\n";
+        assert_eq!(
+            call_at(source, Location::new(1, 6)),
+            Some(call("f", false, 0, None))
+        );
+        assert_eq!(
+            call_at(source, Location::new(1, 16)),
+            Some(call("g", false, 0, None))
+        );
+        assert_eq!(
+            call_at(source, Location::new(1, 24)),
+            Some(call("f", false, 2, Some("key")))
+        );
+        assert_eq!(
+            call_at(source, Location::new(1, 26)),
+            Some(call("h", false, 0, None))
+        );
+        assert_eq!(call_at(source, Location::new(1, 4)), None);
+        assert_eq!(
+            call_at("self.run(1, ", Location::new(1, 12)),
+            Some(call("run", true, 1, None))
+        );
+        // the parameters of a definition aren't a call
+        assert_eq!(call_at("def f(a, ", Location::new(1, 9)), None);
+    }
+
+    #[test]
+    fn test_signature_for() {
+        let source = "def print(x):\n    pass\nprint(\n";
+        let at = Location::new(3, 6);
+        let call = call_at(source, at).unwrap();
+        assert_eq!(
+            signature_for(source, &call, at).unwrap().label(),
+            "print(x)"
+        );
+        let at = Location::new(1, 6);
+        let builtin = call_at("len(", Location::new(1, 4)).unwrap();
+        assert_eq!(
+            signature_for("", &builtin, at).unwrap().label(),
+            "len(obj, /)"
+        );
+        let method = call_at("x.len(", Location::new(1, 6)).unwrap();
+        assert_eq!(signature_for("", &method, at), None);
+    }
+}
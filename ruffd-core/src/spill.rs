@@ -0,0 +1,340 @@
+use ruffd_types::lsp_types;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Interval between writes of modified buffers to the spill directory
+pub const SPILL_INTERVAL: Duration = Duration::from_secs(30);
+
+const SPILL_EXTENSION: &str = "spill";
+const SPILL_ROOT_NAME: &str = "ruffd-spill";
+
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(0);
+
+/// A buffer recovered from a spill directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpilledBuffer {
+    pub uri: String,
+    pub text: String,
+    /// Spill file the buffer was read from
+    pub path: PathBuf,
+}
+
+/// Directory holding the spill directories of every server process of the
+/// user
+///
+/// Spills hold unsaved source, so are kept apart from those of other users
+/// sharing the temporary directory
+#[cfg(unix)]
+pub fn spill_root() -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}", SPILL_ROOT_NAME, current_uid()))
+}
+
+/// Directory holding the spill directories of every server process of the
+/// user, whose temporary directory isn't shared with other users
+#[cfg(not(unix))]
+pub fn spill_root() -> PathBuf {
+    std::env::temp_dir().join(SPILL_ROOT_NAME)
+}
+
+#[cfg(unix)]
+fn current_uid() -> libc::uid_t {
+    unsafe { libc::getuid() }
+}
+
+/// Errors unless `dir` is a directory owned by the user that no other user
+/// can access, such that its spills were written by the user's servers and
+/// are read by no one else
+#[cfg(unix)]
+fn check_private(dir: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    if !metadata.is_dir() || metadata.uid() != current_uid() || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} isn't a directory private to the user", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Creates `dir` accessible only to the user unless it exists, refusing an
+/// existing directory that isn't private to the user, as another user may
+/// have created it to read or plant spills
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => return Err(err),
+        _ => {}
+    }
+    // links aren't followed, as they may lead anywhere
+    check_private(dir, &fs::symlink_metadata(dir)?)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+/// Writes a file readable only by the user
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// Whether the spill root exists, erroring if it isn't private to the
+/// user such that spills planted by another user aren't recovered
+#[cfg(unix)]
+fn root_exists(root: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(root) {
+        Ok(x) => check_private(root, &x).map(|_| true),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn root_exists(root: &Path) -> io::Result<bool> {
+    Ok(root.exists())
+}
+
+/// Creates the name of the spill directory of a new session of the running
+/// server, keyed by process id such that concurrent servers don't clobber
+/// each other, then by session such that a session reconnecting to a client
+/// doesn't clobber or clear the spills of the session it lost
+pub fn session_spill_dir() -> PathBuf {
+    let session = NEXT_SESSION.fetch_add(1, Ordering::Relaxed);
+    spill_root().join(format!("{}-{}", process::id(), session))
+}
+
+/// Process id of the server owning a spill directory
+fn spill_dir_pid(dir: &Path) -> Option<u32> {
+    let name = dir.file_name()?.to_str()?;
+    name.split('-').next()?.parse::<u32>().ok()
+}
+
+fn spill_file_name(uri: &lsp_types::Url) -> String {
+    let mut hasher = DefaultHasher::new();
+    uri.as_str().hash(&mut hasher);
+    format!("{:016x}.{}", hasher.finish(), SPILL_EXTENSION)
+}
+
+/// Writes the buffer text to `dir`, replacing any previous spill of `uri`
+///
+/// The file holds the uri on its first line followed by the text, and is
+/// written to a temporary file first such that a crash mid write never
+/// leaves a truncated spill. `dir` and the spill root above it are created
+/// private to the user, and neither is written to unless it is
+pub fn write_buffer(dir: &Path, uri: &lsp_types::Url, text: &str) -> io::Result<()> {
+    if let Some(root) = dir.parent() {
+        create_private_dir(root)?;
+    }
+    create_private_dir(dir)?;
+    let path = dir.join(spill_file_name(uri));
+    let tmp_path = path.with_extension("tmp");
+    write_private(&tmp_path, &format!("{}\n{}", uri, text))?;
+    fs::rename(tmp_path, path)
+}
+
+/// Removes the spill of `uri` from `dir` if present
+pub fn remove_buffer(dir: &Path, uri: &lsp_types::Url) -> io::Result<()> {
+    match fs::remove_file(dir.join(spill_file_name(uri))) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Removes a spill directory and everything in it, a no-op if it doesn't
+/// exist
+pub fn clear(dir: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the process of a spill directory is still running, in which
+/// case its spills are still being written
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // pids outside the positive range would signal process groups
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(x) if x > 0 => x,
+        _ => return false,
+    };
+    // signal 0 only checks the process exists, being denied permission to
+    // signal it meaning it exists under another user
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Liveness of other processes isn't checked on other platforms, so every
+/// spill directory is taken to be left by a crashed server
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+/// Removes the spill directories below `root` of servers no longer running,
+/// leaving those of running servers to them
+pub fn clear_stale(root: &Path) -> io::Result<()> {
+    if !root_exists(root)? {
+        return Ok(());
+    }
+    let process_dirs = fs::read_dir(root)?;
+    for process_dir in process_dirs {
+        let process_dir = process_dir?.path();
+        match spill_dir_pid(&process_dir) {
+            Some(pid) if process_dir.is_dir() && !process_alive(pid) => clear(&process_dir)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_spill_file(path: PathBuf) -> io::Result<SpilledBuffer> {
+    let contents = fs::read_to_string(&path)?;
+    let (uri, text) = contents
+        .split_once('\n')
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing uri header"))?;
+    Ok(SpilledBuffer {
+        uri: uri.to_string(),
+        text: text.to_string(),
+        path,
+    })
+}
+
+/// Reads every spilled buffer below `root`, ordered by spill file path
+///
+/// Spills of servers that shut down cleanly have been removed, so any
+/// found belong to servers that crashed or are still running. A root that
+/// isn't private to the user isn't read
+pub fn read_spilled_buffers(root: &Path) -> io::Result<Vec<SpilledBuffer>> {
+    let mut paths = vec![];
+    if !root_exists(root)? {
+        return Ok(vec![]);
+    }
+    let process_dirs = fs::read_dir(root)?;
+    for process_dir in process_dirs {
+        let process_dir = process_dir?.path();
+        if !process_dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(process_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|x| x.to_str()) == Some(SPILL_EXTENSION) {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    paths.into_iter().map(read_spill_file).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("{}-test-{}", SPILL_ROOT_NAME, name));
+        clear(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_spill_round_trip() {
+        let root = test_root("round-trip");
+        let dir = root.join("1");
+        let uri_a = lsp_types::Url::parse("file:///project/a.py").unwrap();
+        let uri_b = lsp_types::Url::parse("file:///project/b.py").unwrap();
+        write_buffer(&dir, &uri_a, "import os\n").unwrap();
+        write_buffer(&dir, &uri_b, "first").unwrap();
+        write_buffer(&dir, &uri_b, "second\nline").unwrap();
+        let mut spilled = read_spilled_buffers(&root)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.uri, x.text))
+            .collect::<Vec<_>>();
+        spilled.sort();
+        assert_eq!(
+            spilled,
+            vec![
+                (uri_a.to_string(), "import os\n".to_string()),
+                (uri_b.to_string(), "second\nline".to_string()),
+            ]
+        );
+        remove_buffer(&dir, &uri_a).unwrap();
+        remove_buffer(&dir, &uri_a).unwrap();
+        assert_eq!(read_spilled_buffers(&root).unwrap().len(), 1);
+        clear(&root).unwrap();
+        assert!(read_spilled_buffers(&root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_session_spill_dir() {
+        let (first, second) = (session_spill_dir(), session_spill_dir());
+        // sessions of a process spill apart, under the process's id
+        assert_ne!(first, second);
+        assert_eq!(spill_dir_pid(&first), Some(process::id()));
+        assert_eq!(spill_dir_pid(&second), Some(process::id()));
+        assert_eq!(spill_dir_pid(Path::new("/tmp/ruffd-spill/12")), Some(12));
+        assert_eq!(spill_dir_pid(Path::new("/tmp/ruffd-spill/x-1")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_spills_private() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let root = test_root("private");
+        let dir = root.join("1");
+        let uri = lsp_types::Url::parse("file:///project/a.py").unwrap();
+        write_buffer(&dir, &uri, "unsaved").unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().mode() & 0o777;
+        assert_eq!(mode(&root), 0o700);
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&dir.join(spill_file_name(&uri))), 0o600);
+        // a root others can write to may hold planted spills, and one they
+        // can read would expose spills written to it
+        fs::set_permissions(&root, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(read_spilled_buffers(&root).is_err());
+        assert!(clear_stale(&root).is_err());
+        assert!(write_buffer(&dir, &uri, "unsaved").is_err());
+        clear(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_clear_stale() {
+        let root = test_root("clear-stale");
+        let uri = lsp_types::Url::parse("file:///project/a.py").unwrap();
+        let running = root.join(process::id().to_string());
+        let lost = root.join(format!("{}-1", process::id()));
+        let crashed = root.join(format!("{}-0", i32::MAX));
+        write_buffer(&running, &uri, "running").unwrap();
+        write_buffer(&lost, &uri, "lost").unwrap();
+        write_buffer(&crashed, &uri, "crashed").unwrap();
+        clear_stale(&root).unwrap();
+        let spilled = read_spilled_buffers(&root).unwrap();
+        let texts = spilled.iter().map(|x| x.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["running", "lost"]);
+        clear(&root).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+//! Top-level definitions of the workspace's files, searched by
+//! `workspace/symbol`
+//!
+//! Files are parsed lazily as they're searched, with the symbols of the
+//! most recently searched files kept in the state's `SymbolCache` by
+//! content hash, such that successive queries while typing don't parse the
+//! workspace again
+use crate::positions::range_from_locations;
+use ruffd_types::rustpython_ast::{Stmt, StmtKind, Suite};
+use ruffd_types::rustpython_parser::parser;
+use ruffd_types::tasks::spawn_blocking_named;
+use ruffd_types::{content_hash, lsp_types, Symbol, SymbolCache};
+use std::sync::Arc;
+
+fn symbol_from_stmt(stmt: &Stmt) -> Option<Symbol> {
+    let (name, kind) = match &stmt.node {
+        StmtKind::FunctionDef { name, .. } | StmtKind::AsyncFunctionDef { name, .. } => {
+            (name, lsp_types::SymbolKind::FUNCTION)
+        }
+        StmtKind::ClassDef { name, .. } => (name, lsp_types::SymbolKind::CLASS),
+        _ => return None,
+    };
+    let end_location = stmt.end_location.unwrap_or(stmt.location);
+    Some(Symbol {
+        name: name.clone(),
+        kind,
+        range: range_from_locations(stmt.location, end_location),
+    })
+}
+
+/// Functions and classes defined at the top level of a parsed module
+pub fn suite_symbols(suite: &Suite) -> Vec<Symbol> {
+    suite.iter().filter_map(symbol_from_stmt).collect()
+}
+
+/// Functions and classes defined at the top level of `source`, a source
+/// failing to parse having none
+pub fn top_level_symbols(source: &str) -> Vec<Symbol> {
+    match parser::parse_program(source, "<filename>") {
+        Ok(suite) => suite_symbols(&suite),
+        Err(_) => vec![],
+    }
+}
+
+/// Symbols of the file at `uri` with contents `source`, parsed on the
+/// blocking thread pool unless cached
+pub async fn file_symbols(
+    cache: &SymbolCache,
+    uri: &lsp_types::Url,
+    source: String,
+) -> Arc<Vec<Symbol>> {
+    let hash = content_hash(&source);
+    if let Some(x) = cache.get(uri, hash) {
+        return x;
+    }
+    let name = || format!("symbols {}", uri);
+    let symbols = spawn_blocking_named(name, move || top_level_symbols(&source))
+        .await
+        .unwrap_or_default();
+    let symbols = Arc::new(symbols);
+    cache.insert(uri.clone(), hash, symbols.clone());
+    symbols
+}
+
+/// Determines whether `name` matches a query, as its characters appear in
+/// order within the name regardless of case
+pub fn matches_query(name: &str, query: &str) -> bool {
+    let mut name_chars = name.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|c| name_chars.any(|x| x == c))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_top_level_symbols() {
+        let source = "import os\nclass Foo:\n    def method(self):\n        pass\ndef bar():\n    pass\nasync def baz():\n    pass\n";
+        let symbols = top_level_symbols(source);
+        let names = symbols
+            .iter()
+            .map(|x| (x.name.as_str(), x.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("Foo", lsp_types::SymbolKind::CLASS),
+                ("bar", lsp_types::SymbolKind::FUNCTION),
+                ("baz", lsp_types::SymbolKind::FUNCTION),
+            ]
+        );
+        assert_eq!(symbols[1].range.start, lsp_types::Position::new(4, 0));
+        assert!(top_level_symbols("def (").is_empty());
+    }
+
+    #[test]
+    fn test_matches_query() {
+        assert!(matches_query("DocumentBuffer", ""));
+        assert!(matches_query("DocumentBuffer", "docbuf"));
+        assert!(matches_query("DocumentBuffer", "DB"));
+        assert!(!matches_query("DocumentBuffer", "bufdoc"));
+        assert!(!matches_query("Doc", "Docs"));
+    }
+}
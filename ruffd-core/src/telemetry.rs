@@ -0,0 +1,105 @@
+use crate::unwind::PanicPayload;
+use crate::PKG_VERSION;
+use ruffd_types::extensions::{ErrorTelemetry, ErrorTelemetryEvent};
+use ruffd_types::tokio::sync::mpsc::Sender;
+use ruffd_types::tokio::sync::Mutex;
+use ruffd_types::{
+    serde_json, RpcErrors, RpcMessage, RpcNotification, RpcResponseError, ServerState,
+};
+use std::sync::Arc;
+
+/// Extracts a panic message only if it is a string literal, as formatted
+/// messages may contain user data
+fn static_panic_message(payload: &PanicPayload) -> Option<String> {
+    payload
+        .downcast_ref::<&'static str>()
+        .map(|x| x.to_string())
+}
+
+fn telemetry_notification(telemetry: ErrorTelemetry) -> RpcNotification {
+    RpcNotification::new(
+        "telemetry/event".to_string(),
+        Some(serde_json::to_value(telemetry).unwrap()),
+    )
+}
+
+/// Creates the telemetry event for an error response of `handler`
+///
+/// Only internal errors are reported, as other errors are caused by the
+/// client
+pub fn error_event(handler: &str, error: &RpcResponseError) -> Option<RpcNotification> {
+    if error.code != RpcErrors::INTERNAL_ERROR.code {
+        return None;
+    }
+    let kind = error
+        .data
+        .as_ref()
+        .and_then(|x| x.get("kind"))
+        .and_then(|x| x.as_str())
+        .unwrap_or("Unknown");
+    Some(telemetry_notification(ErrorTelemetry {
+        event: ErrorTelemetryEvent::Error,
+        kind: kind.to_string(),
+        handler: handler.to_string(),
+        server_version: PKG_VERSION.to_string(),
+        message: None,
+    }))
+}
+
+pub fn panic_event(handler: &str, payload: &PanicPayload) -> RpcNotification {
+    telemetry_notification(ErrorTelemetry {
+        event: ErrorTelemetryEvent::Panic,
+        kind: "Panic".to_string(),
+        handler: handler.to_string(),
+        server_version: PKG_VERSION.to_string(),
+        message: static_panic_message(payload),
+    })
+}
+
+/// Sends the telemetry event if the client has opted in
+pub async fn report(
+    state: &Arc<Mutex<ServerState>>,
+    response_channel: &Sender<RpcMessage>,
+    event: RpcNotification,
+) {
+    let enabled = state.lock().await.config_snapshot.load().config.telemetry;
+    if enabled {
+        response_channel.send(event.into()).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_static_panic_message() {
+        let literal: PanicPayload = Box::new("literal message");
+        let formatted: PanicPayload = Box::new(format!("/home/user/{}", "file.py"));
+        assert_eq!(
+            static_panic_message(&literal).as_deref(),
+            Some("literal message")
+        );
+        assert_eq!(static_panic_message(&formatted), None);
+    }
+
+    #[test]
+    fn test_error_event() {
+        let internal = RpcResponseError {
+            code: RpcErrors::INTERNAL_ERROR.code,
+            message: "Uri: 'file:///secret.py' not open".to_string(),
+            data: Some(serde_json::json!({"kind": "EditUnopenedDocument"})),
+        };
+        let event = error_event("textDocument/didChange", &internal).unwrap();
+        let params = event.params.unwrap().into_value();
+        assert_eq!(params["kind"], "EditUnopenedDocument");
+        assert_eq!(params["handler"], "textDocument/didChange");
+        assert!(!params.to_string().contains("secret"));
+        let invalid_params = RpcResponseError {
+            code: RpcErrors::INVALID_PARAMS.code,
+            message: "Invalid params".to_string(),
+            data: None,
+        };
+        assert!(error_event("textDocument/didChange", &invalid_params).is_none());
+    }
+}
@@ -0,0 +1,35 @@
+//! Isolation of handlers, such that a panicking handler is answered with an
+//! error rather than taking down the server
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+pub type PanicPayload = Box<dyn Any + Send>;
+
+/// Future resolving to the panic payload if the wrapped future panics
+pub struct CatchUnwind<F: Future> {
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, PanicPayload>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // NOTE state guards held by the future are released while unwinding,
+        // and tokio locks do not poison
+        let inner = &mut self.inner;
+        match panic::catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(cx))) {
+            Ok(Poll::Ready(x)) => Poll::Ready(Ok(x)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+pub fn catch_unwind<F: Future>(fut: F) -> CatchUnwind<F> {
+    CatchUnwind {
+        inner: Box::pin(fut),
+    }
+}
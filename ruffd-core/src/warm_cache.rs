@@ -0,0 +1,265 @@
+//! Snapshots of workspace state reused across server restarts
+//!
+//! Walking a very large workspace for its python files dominates startup,
+//! so the workspace index is written to a cache directory on shutdown along
+//! with the diagnostics of linted documents, and read back on the next
+//! start in place of walking the workspace. Ruff's settings can't be
+//! serialized and are cheap to resolve, so only a fingerprint of the
+//! pyproject and the editor's settings is kept, discarding snapshots taken
+//! under different settings
+//!
+//! Files removed while the server wasn't running are pruned as the
+//! snapshot is restored, whereas those added are only indexed once watched
+//! file changes report them
+use crate::ruff_utils::registry_diagnostics;
+use crate::PKG_VERSION;
+use ruffd_types::serde::{Deserialize, Serialize};
+use ruffd_types::tasks::spawn_blocking_named;
+use ruffd_types::uri::uri_to_path;
+use ruffd_types::{content_hash, log_warn, lsp_types, serde_json};
+use ruffd_types::{CachedDiagnostics, ServerConfig, ServerState};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Version of the snapshot layout, snapshots of other versions are ignored
+const SNAPSHOT_FORMAT: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "ruffd_types::serde", rename_all = "camelCase")]
+pub struct Snapshot {
+    format: u32,
+    server_version: String,
+    pub project_root: lsp_types::Url,
+    pub settings_fingerprint: u64,
+    pub index: Vec<lsp_types::Url>,
+    pub diagnostics: HashMap<lsp_types::Url, CachedDiagnostics>,
+}
+
+impl Snapshot {
+    pub fn new(project_root: lsp_types::Url, config: &ServerConfig) -> Self {
+        let settings_fingerprint = settings_fingerprint(&project_root, config);
+        Self {
+            format: SNAPSHOT_FORMAT,
+            server_version: PKG_VERSION.to_string(),
+            project_root,
+            settings_fingerprint,
+            index: vec![],
+            diagnostics: HashMap::new(),
+        }
+    }
+
+    /// Determines whether the snapshot was taken of `project_root` by this
+    /// server version, under the settings `config` is fingerprinted as
+    fn is_current(&self, project_root: &lsp_types::Url, config: &ServerConfig) -> bool {
+        self.format == SNAPSHOT_FORMAT
+            && self.server_version == PKG_VERSION
+            && &self.project_root == project_root
+            && self.settings_fingerprint == settings_fingerprint(project_root, config)
+    }
+
+    /// Drops the files of the index and the diagnostics of those no longer
+    /// on disk
+    fn prune_missing(&mut self) {
+        let exists = |uri: &lsp_types::Url| matches!(uri_to_path(uri), Some(x) if x.is_file());
+        self.index.retain(exists);
+        self.diagnostics.retain(|uri, _| exists(uri));
+    }
+}
+
+/// Hash of the settings checks are computed under, being the pyprojects
+/// read at `project_root` and the editor's settings of rule selection,
+/// under the active profile, and of reporting
+pub fn settings_fingerprint(project_root: &lsp_types::Url, config: &ServerConfig) -> u64 {
+    let read = |path: &Path| fs::read_to_string(path).unwrap_or_default();
+    let (pyproject, ruffd_pyproject) = match uri_to_path(project_root) {
+        Some(root) => (
+            read(&root.join("pyproject.toml")),
+            config.layer.config.as_ref().map(|x| read(&root.join(x))),
+        ),
+        None => (String::new(), None),
+    };
+    let fingerprinted = serde_json::json!({
+        "pyproject": pyproject,
+        "ruffdPyproject": ruffd_pyproject,
+        "lint": config.lint_config(),
+        "src": config.src,
+        "suppressDiagnostics": config.suppress_diagnostics,
+        "generatedFiles": config.generated_files,
+    });
+    content_hash(&fingerprinted.to_string())
+}
+
+/// Snapshot file of a project root, such that workspaces sharing a cache
+/// directory don't clobber each other
+fn snapshot_path(dir: &Path, project_root: &lsp_types::Url) -> PathBuf {
+    dir.join(format!("{:016x}.json", content_hash(project_root.as_str())))
+}
+
+/// Writes the snapshot to `dir`, via a temporary file such that an
+/// interrupted write never leaves a truncated snapshot
+pub fn write_snapshot(dir: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = snapshot_path(dir, &snapshot.project_root);
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(snapshot)?)?;
+    fs::rename(tmp_path, path)
+}
+
+/// Reads the snapshot of `project_root` from `dir`, returning `None` if
+/// there is none or it's stale under `config`
+pub fn read_snapshot(
+    dir: &Path,
+    project_root: &lsp_types::Url,
+    config: &ServerConfig,
+) -> Option<Snapshot> {
+    let path = snapshot_path(dir, project_root);
+    let contents = fs::read(&path).ok()?;
+    let snapshot = match serde_json::from_slice::<Snapshot>(&contents) {
+        Ok(x) => x,
+        Err(err) => {
+            log_warn!("ignoring unreadable snapshot {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    snapshot
+        .is_current(project_root, config)
+        .then_some(snapshot)
+}
+
+/// Restores the workspace index and diagnostics of the snapshot of the
+/// state's project root, less those of files since removed, returning
+/// whether there was a current snapshot
+pub async fn restore(dir: &Path, state: &ServerState) -> bool {
+    let config = state.config_snapshot.load();
+    let project_root = match config.project_root.clone() {
+        Some(x) => x,
+        None => return false,
+    };
+    let dir = dir.to_path_buf();
+    let snapshot = spawn_blocking_named(
+        || "restore warm cache".to_string(),
+        move || {
+            let mut snapshot = read_snapshot(&dir, &project_root, &config.config)?;
+            snapshot.prune_missing();
+            Some(snapshot)
+        },
+    )
+    .await;
+    let snapshot = match snapshot {
+        Ok(Some(x)) => x,
+        _ => return false,
+    };
+    state.workspace_index.write().await.extend(snapshot.index);
+    state
+        .cached_diagnostics
+        .write()
+        .await
+        .extend(snapshot.diagnostics);
+    true
+}
+
+/// Takes a snapshot of the state and writes it to `dir`
+///
+/// Diagnostics are kept for documents linted from known content, along
+/// with restored diagnostics of documents not linted since
+pub async fn save(dir: &Path, state: &ServerState) -> io::Result<()> {
+    let config = state.config_snapshot.load();
+    let project_root = match config.project_root.clone() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let overrides = &config.project_config.severity_overrides;
+    let mut snapshot = Snapshot::new(project_root, &config.config);
+    snapshot.index = state.workspace_index.read().await.iter().cloned().collect();
+    snapshot.diagnostics = state.cached_diagnostics.read().await.clone();
+    for (uri, registry) in state.checks.read().await.iter() {
+        if let Some(content_hash) = registry.content_hash() {
+            let diagnostics = registry_diagnostics(registry, overrides);
+            snapshot.diagnostics.insert(
+                uri.clone(),
+                CachedDiagnostics {
+                    content_hash,
+                    diagnostics,
+                },
+            );
+        }
+    }
+    write_snapshot(dir, &snapshot)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ruffd_types::LintConfig;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ruffd-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let dir = temp_dir("warm-cache");
+        let root_dir = temp_dir("warm-cache-root");
+        fs::create_dir_all(&root_dir).unwrap();
+        let root = lsp_types::Url::from_directory_path(&root_dir).unwrap();
+        let config = ServerConfig::default();
+        let mut snapshot = Snapshot::new(root.clone(), &config);
+        snapshot.index = vec![root.join("a.py").unwrap()];
+        snapshot.diagnostics.insert(
+            root.join("a.py").unwrap(),
+            CachedDiagnostics {
+                content_hash: 1,
+                diagnostics: vec![],
+            },
+        );
+        write_snapshot(&dir, &snapshot).unwrap();
+        assert_eq!(read_snapshot(&dir, &root, &config), Some(snapshot));
+        let other = lsp_types::Url::parse("file:///elsewhere/").unwrap();
+        assert!(read_snapshot(&dir, &other, &config).is_none());
+        // editor settings or the active profile changed since the snapshot
+        let mut profiled = config.clone();
+        profiled.profiles.insert(
+            "strict".to_string(),
+            LintConfig {
+                select: vec!["E".to_string()],
+                ..Default::default()
+            },
+        );
+        assert!(read_snapshot(&dir, &root, &profiled).is_some());
+        profiled.profile = Some("strict".to_string());
+        assert!(read_snapshot(&dir, &root, &profiled).is_none());
+        // pyproject changed since the snapshot
+        fs::write(root_dir.join("pyproject.toml"), "[tool.ruff]\n").unwrap();
+        assert!(read_snapshot(&dir, &root, &config).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_missing() {
+        let root_dir = temp_dir("warm-cache-prune");
+        fs::create_dir_all(&root_dir).unwrap();
+        fs::write(root_dir.join("kept.py"), "").unwrap();
+        let root = lsp_types::Url::from_directory_path(&root_dir).unwrap();
+        let kept = root.join("kept.py").unwrap();
+        let removed = root.join("removed.py").unwrap();
+        let mut snapshot = Snapshot::new(root, &ServerConfig::default());
+        snapshot.index = vec![kept.clone(), removed.clone()];
+        for uri in [&kept, &removed] {
+            snapshot.diagnostics.insert(
+                uri.clone(),
+                CachedDiagnostics {
+                    content_hash: 1,
+                    diagnostics: vec![],
+                },
+            );
+        }
+        snapshot.prune_missing();
+        assert_eq!(snapshot.index, vec![kept.clone()]);
+        assert!(snapshot.diagnostics.contains_key(&kept));
+        assert!(!snapshot.diagnostics.contains_key(&removed));
+        fs::remove_dir_all(&root_dir).unwrap();
+    }
+}
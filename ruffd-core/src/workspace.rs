@@ -0,0 +1,133 @@
+use ruffd_types::lsp_types;
+use std::path::{Path, PathBuf};
+
+/// Directory names never descended into when collecting workspace files
+const EXCLUDED_DIRS: &[&str] = &["__pycache__", "node_modules", "site-packages", "venv"];
+
+pub fn is_python_path(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|x| x.to_str()),
+        Some("py") | Some("pyi")
+    )
+}
+
+#[cfg(feature = "watch")]
+pub fn is_python_uri(uri: &lsp_types::Url) -> bool {
+    is_python_path(Path::new(uri.path()))
+}
+
+#[cfg(feature = "watch")]
+pub fn is_pyproject_uri(uri: &lsp_types::Url) -> bool {
+    uri.path().ends_with("/pyproject.toml")
+}
+
+/// Recursively collects python files below `root`, skipping hidden and
+/// excluded directories
+///
+/// NOTE this performs blocking io
+pub fn collect_python_files(root: &Path) -> Vec<PathBuf> {
+    let mut rv = vec![];
+    let mut dir_stack = vec![root.to_path_buf()];
+    while let Some(dir) = dir_stack.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let file_type = match entry.file_type() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if !name.starts_with('.') && !EXCLUDED_DIRS.contains(&name.as_ref()) {
+                    dir_stack.push(path);
+                }
+            } else if file_type.is_file() && is_python_path(&path) {
+                rv.push(path);
+            }
+        }
+    }
+    rv.sort();
+    rv
+}
+
+/// Returns true if `uri` is `dir` or is contained by `dir`
+pub fn is_under(uri: &lsp_types::Url, dir: &lsp_types::Url) -> bool {
+    let dir_str = dir.as_str().trim_end_matches('/');
+    match uri.as_str().strip_prefix(dir_str) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Maps `uri` to its location after `old` has been renamed to `new`
+///
+/// Handles both file and folder renames, returning `None` if `uri` is
+/// unaffected by the rename
+pub fn renamed_uri(
+    uri: &lsp_types::Url,
+    old: &lsp_types::Url,
+    new: &lsp_types::Url,
+) -> Option<lsp_types::Url> {
+    if !is_under(uri, old) {
+        return None;
+    }
+    let old_str = old.as_str().trim_end_matches('/');
+    let rest = &uri.as_str()[old_str.len()..];
+    let new_str = new.as_str().trim_end_matches('/');
+    lsp_types::Url::parse(format!("{}{}", new_str, rest).as_str()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(val: &str) -> lsp_types::Url {
+        lsp_types::Url::parse(val).unwrap()
+    }
+
+    #[test]
+    fn test_renamed_file() {
+        let old = url("file:///proj/pkg/a.py");
+        let new = url("file:///proj/pkg/b.py");
+        assert_eq!(renamed_uri(&old, &old, &new), Some(new.clone()));
+        let other = url("file:///proj/pkg/a.pyi");
+        assert_eq!(renamed_uri(&other, &old, &new), None);
+    }
+
+    #[test]
+    fn test_renamed_folder() {
+        let old = url("file:///proj/pkg");
+        let new = url("file:///proj/renamed/");
+        let child = url("file:///proj/pkg/sub/a.py");
+        assert_eq!(
+            renamed_uri(&child, &old, &new),
+            Some(url("file:///proj/renamed/sub/a.py"))
+        );
+        let sibling = url("file:///proj/pkg2/a.py");
+        assert_eq!(renamed_uri(&sibling, &old, &new), None);
+    }
+
+    #[test]
+    fn test_collect_python_files() {
+        let root = std::env::temp_dir().join(format!("ruffd-collect-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("pkg")).unwrap();
+        std::fs::create_dir_all(root.join(".hidden")).unwrap();
+        std::fs::create_dir_all(root.join("__pycache__")).unwrap();
+        for name in [
+            "main.py",
+            "pkg/mod.py",
+            "pkg/notes.txt",
+            ".hidden/skip.py",
+            "__pycache__/skip.py",
+        ] {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+        let files = collect_python_files(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(files, vec![root.join("main.py"), root.join("pkg/mod.py")]);
+    }
+}
@@ -0,0 +1,171 @@
+//! Replays client traces through the stdio transport, asserting the
+//! diagnostics published once every message has been handled
+//!
+//! Traces are captured from editors and trimmed to the messages that affect
+//! diagnostics. Edits within them are positioned as the client sent them,
+//! such that a regression in how the server maps a client's coordinates
+//! shows up as stale or misplaced diagnostics
+mod common;
+
+use common::{recv, send};
+use ruffd_core::Service;
+use ruffd_types::serde_json::{self, json, Value};
+use ruffd_types::tokio;
+use ruffd_types::tokio::io::{self, BufReader};
+use ruffd_types::tokio::task;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Id of the request sent after a trace, whose response marks every
+/// message of the trace as handled
+const BARRIER_ID: i64 = 1 << 20;
+
+/// Responds to a request of the server as a client without any settings
+/// would
+fn client_response(request: &Value) -> Value {
+    let result = match request["method"].as_str() {
+        Some("workspace/configuration") => {
+            let items = request["params"]["items"].as_array().map_or(0, Vec::len);
+            Value::Array(vec![json!({}); items])
+        }
+        _ => Value::Null,
+    };
+    json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+}
+
+/// Substitutes the project root of the trace, which is only known once
+/// the temporary directory standing in for it is created
+fn substitute_root(value: &mut Value, root: &str, root_path: &str) {
+    match value {
+        Value::String(x) => {
+            *x = x
+                .replace("${ROOT_PATH}", root_path)
+                .replace("${ROOT}", root)
+        }
+        Value::Array(xs) => xs
+            .iter_mut()
+            .for_each(|x| substitute_root(x, root, root_path)),
+        Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(k, mut v)| {
+                    substitute_root(&mut v, root, root_path);
+                    (k.replace("${ROOT}", root), v)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+/// Code and start position of each diagnostic, sorted such that the order
+/// diagnostics are published in doesn't matter
+fn summarize(diagnostics: &Value) -> Vec<(String, u64, u64)> {
+    let mut rv = diagnostics
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| {
+            (
+                x["code"].as_str().unwrap().to_string(),
+                x["range"]["start"]["line"].as_u64().unwrap(),
+                x["range"]["start"]["character"].as_u64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    rv.sort();
+    rv
+}
+
+fn expected_summary(expected: &Value) -> Vec<(String, u64, u64)> {
+    let mut rv = expected
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| {
+            (
+                x["code"].as_str().unwrap().to_string(),
+                x["start"][0].as_u64().unwrap(),
+                x["start"][1].as_u64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    rv.sort();
+    rv
+}
+
+async fn replay(name: &str, trace: &str) {
+    let root_dir: PathBuf =
+        std::env::temp_dir().join(format!("ruffd-trace-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&root_dir).unwrap();
+    let root_path = root_dir.to_str().unwrap().trim_end_matches('/').to_string();
+    let root = format!("file://{}", root_path);
+    let mut trace: Value = serde_json::from_str(trace).unwrap();
+    substitute_root(&mut trace, &root, &root_path);
+
+    let (client, server) = io::duplex(1 << 16);
+    let (server_read, server_write) = io::split(server);
+    let (client_read, mut client_write) = io::split(client);
+    let mut client_read = BufReader::new(client_read);
+    let mut service = Service::new(BufReader::new(server_read), server_write);
+    service.set_deterministic(true);
+    let service_task = task::spawn(async move { service.run().await });
+
+    for message in trace["messages"].as_array().unwrap() {
+        send(&mut client_write, message).await;
+    }
+    send(
+        &mut client_write,
+        &json!({"jsonrpc": "2.0", "id": BARRIER_ID, "method": "ruffd/documentStatus"}),
+    )
+    .await;
+    let mut published = HashMap::new();
+    loop {
+        let msg = recv(&mut client_read).await;
+        match (msg.get("method"), msg.get("id")) {
+            (Some(_), Some(_)) => {
+                // requests of the server are answered, as the client would
+                send(&mut client_write, &client_response(&msg)).await;
+            }
+            (Some(method), None) if method == "textDocument/publishDiagnostics" => {
+                let uri = msg["params"]["uri"].as_str().unwrap().to_string();
+                published.insert(uri, summarize(&msg["params"]["diagnostics"]));
+            }
+            (None, Some(id)) if *id == BARRIER_ID => break,
+            _ => {}
+        }
+    }
+    send(
+        &mut client_write,
+        &json!({"jsonrpc": "2.0", "id": BARRIER_ID + 1, "method": "shutdown"}),
+    )
+    .await;
+    send(
+        &mut client_write,
+        &json!({"jsonrpc": "2.0", "method": "exit"}),
+    )
+    .await;
+    service_task.await.unwrap();
+    fs::remove_dir_all(&root_dir).unwrap();
+
+    for (uri, expected) in trace["expected"].as_object().unwrap() {
+        assert_eq!(
+            published.get(uri),
+            Some(&expected_summary(expected)),
+            "diagnostics of {} replaying {}",
+            uri,
+            trace["client"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_neovim_trace() {
+    replay("neovim", include_str!("traces/neovim.json")).await;
+}
+
+#[tokio::test]
+async fn test_vscode_trace() {
+    replay("vscode", include_str!("traces/vscode.json")).await;
+}
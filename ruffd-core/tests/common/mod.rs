@@ -0,0 +1,109 @@
+//! Framing of the messages the integration tests exchange with the server,
+//! written and read as a client would over stdio, and sessions with a
+//! server over an in-memory transport
+// each test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use ruffd_core::{Service, SessionOutcome};
+use ruffd_types::serde_json::{self, json, Value};
+use ruffd_types::tokio::io::{
+    self, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream,
+    ReadHalf, WriteHalf,
+};
+use ruffd_types::tokio::task::{self, JoinHandle};
+
+pub type SessionService = Service<BufReader<ReadHalf<DuplexStream>>, WriteHalf<DuplexStream>>;
+
+pub async fn send<W: AsyncWrite + Unpin>(writer: &mut W, value: &Value) {
+    let body = value.to_string();
+    let header = format!("Content-Length: {}\r\n\r\n", body.len());
+    writer.write_all(header.as_bytes()).await.unwrap();
+    writer.write_all(body.as_bytes()).await.unwrap();
+    writer.flush().await.unwrap();
+}
+
+pub async fn recv<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Value {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(len) = line.strip_prefix("Content-Length: ") {
+            content_length = Some(len.parse::<usize>().unwrap());
+        }
+    }
+    let mut body = vec![0; content_length.unwrap()];
+    reader.read_exact(&mut body).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Client end of a session started by [`start_session`]
+pub struct Client {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+    /// Response of the server to the client's `initialize` request
+    pub init_response: Value,
+}
+
+impl Client {
+    pub async fn send(&mut self, value: Value) {
+        send(&mut self.writer, &value).await;
+    }
+
+    pub async fn recv(&mut self) -> Value {
+        recv(&mut self.reader).await
+    }
+
+    /// Receives messages until one matches `predicate`, returning it
+    pub async fn recv_until<P>(&mut self, predicate: P) -> Value
+    where
+        P: Fn(&Value) -> bool,
+    {
+        loop {
+            let msg = self.recv().await;
+            if predicate(&msg) {
+                return msg;
+            }
+        }
+    }
+
+    /// Shuts the server down and asks it to exit, as clients end sessions
+    pub async fn shutdown(&mut self, id: i64) {
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": "shutdown"}))
+            .await;
+        self.send(json!({"jsonrpc": "2.0", "method": "exit"})).await;
+    }
+}
+
+/// Runs a service configured by `configure` and initializes it with
+/// `init_params`, returning the client once the server has responded along
+/// with the task running the service
+pub async fn start_session<F>(
+    init_params: Value,
+    configure: F,
+) -> (Client, JoinHandle<SessionOutcome>)
+where
+    F: FnOnce(&mut SessionService),
+{
+    let (client, server) = io::duplex(1 << 16);
+    let (server_read, server_write) = io::split(server);
+    let (client_read, client_write) = io::split(client);
+    let mut service = Service::new(BufReader::new(server_read), server_write);
+    configure(&mut service);
+    let service_task = task::spawn(async move { service.run().await });
+    let mut client = Client {
+        reader: BufReader::new(client_read),
+        writer: client_write,
+        init_response: Value::Null,
+    };
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": init_params
+        }))
+        .await;
+    client.init_response = client.recv().await;
+    (client, service_task)
+}
@@ -0,0 +1,771 @@
+//! Sessions with the service through an in-memory transport, exercising
+//! its lifecycle and the hooks it offers embedders
+mod common;
+
+use common::start_session;
+use ruffd_core::{Middleware, SessionOutcome, TimingMiddleware};
+use ruffd_macros::request;
+use ruffd_types::serde_json::{self, json, Value};
+use ruffd_types::tokio;
+use ruffd_types::tokio::sync::RwLock;
+use ruffd_types::tokio::task;
+use ruffd_types::{create_locks_fut, lsp_types};
+use ruffd_types::{
+    CachedDiagnostics, Clock, RateLimiter, RpcErrors, RpcMessage, RpcNotification, RpcResult,
+    RuntimeError, Scheduler, ServerInitiated, ServerNotification, ServerNotificationExec,
+    ServerState,
+};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[request(open_buffers)]
+fn open_count() -> Result<usize, RuntimeError> {
+    Ok(open_buffers.len())
+}
+
+#[tokio::test]
+async fn test_embedder_hooks() {
+    let message_count = Arc::new(AtomicUsize::new(0));
+    let counter = message_count.clone();
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.add_requests([("embedder/openCount", open_count)]);
+        service.set_initialize_hook(Box::new(|_, result| {
+            result["capabilities"]["experimental"] = json!({"openCount": true});
+        }));
+        service.set_message_hook(Box::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+    })
+    .await;
+    assert_eq!(
+        client.init_response["result"]["capabilities"]["experimental"]["openCount"],
+        true
+    );
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "embedder/openCount"}))
+        .await;
+    let resp = client.recv().await;
+    assert_eq!(resp["id"], 2);
+    assert_eq!(resp["result"], 0);
+    client.shutdown(3).await;
+    assert_eq!(service_task.await.unwrap(), SessionOutcome::Exit);
+    assert_eq!(message_count.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |_| {}).await;
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}))
+        .await;
+    let resp = client.recv().await;
+    assert_eq!(resp["id"], 2);
+    assert!(resp["result"].is_null());
+    // requests after shutdown are invalid
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 3, "method": "shutdown"}))
+        .await;
+    let resp = client.recv().await;
+    assert_eq!(resp["id"], 3);
+    assert_eq!(resp["error"]["code"], -32600);
+    client
+        .send(json!({"jsonrpc": "2.0", "method": "exit"}))
+        .await;
+    assert_eq!(service_task.await.unwrap(), SessionOutcome::Exit);
+}
+
+#[tokio::test]
+async fn test_exit_without_shutdown() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |_| {}).await;
+    client
+        .send(json!({"jsonrpc": "2.0", "method": "exit"}))
+        .await;
+    assert_eq!(
+        service_task.await.unwrap(),
+        SessionOutcome::ExitWithoutShutdown
+    );
+}
+
+#[tokio::test]
+async fn test_deterministic_order() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.add_requests([("embedder/openCount", open_count)]);
+        service.set_deterministic(true);
+    })
+    .await;
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {
+                "uri": "file:///tmp/a.py", "languageId": "python",
+                "version": 1, "text": "import os\n"
+            }}
+        }))
+        .await;
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "embedder/openCount"}))
+        .await;
+    // the lint scheduled on opening is handled before the request
+    let published = client.recv().await;
+    assert_eq!(published["method"], "textDocument/publishDiagnostics");
+    assert_eq!(
+        published["params"]["diagnostics"].as_array().unwrap().len(),
+        1
+    );
+    let resp = client.recv().await;
+    assert_eq!(resp["id"], 2);
+    assert_eq!(resp["result"], 1);
+    client.shutdown(3).await;
+    service_task.await.unwrap();
+}
+
+/// Edits and saves a document of a workspace over and over, then lints the
+/// workspace, returning every message the server sent until answering the
+/// request after
+async fn save_heavy_session(root: &Path) -> Vec<Value> {
+    let root_uri = lsp_types::Url::from_directory_path(root).unwrap();
+    let uri = root_uri.join("a.py").unwrap();
+    fs::write(root.join("a.py"), "import os\n").unwrap();
+    fs::write(root.join("b.py"), "import sys\n").unwrap();
+    let init_params = json!({"rootUri": root_uri, "capabilities": {}});
+    let (mut client, service_task) = start_session(init_params, |service| {
+        service.add_requests([("embedder/openCount", open_count)]);
+        service.set_deterministic(true);
+    })
+    .await;
+    client
+        .send(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}))
+        .await;
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {
+                "uri": uri, "languageId": "python", "version": 1, "text": "import os\n"
+            }}
+        }))
+        .await;
+    for version in 2..12 {
+        let text = format!("import os\nimport sys\nx = {}\n", version);
+        client
+            .send(json!({
+                "jsonrpc": "2.0", "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": {"uri": uri, "version": version},
+                    "contentChanges": [{"text": text}]
+                }
+            }))
+            .await;
+        fs::write(root.join("a.py"), &text).unwrap();
+        // every other save leaves the text to be taken from the buffer
+        let text = (version % 2 == 0).then_some(text);
+        client
+            .send(json!({
+                "jsonrpc": "2.0", "method": "textDocument/didSave",
+                "params": {"textDocument": {"uri": uri}, "text": text}
+            }))
+            .await;
+    }
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "ruffd/lintWorkspace", "params": {}}))
+        .await;
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 3, "method": "embedder/openCount"}))
+        .await;
+    let mut messages = vec![];
+    loop {
+        let msg = client.recv().await;
+        let done = msg["id"] == 3;
+        messages.push(msg);
+        if done {
+            break;
+        }
+    }
+    client.shutdown(4).await;
+    assert_eq!(service_task.await.unwrap(), SessionOutcome::Exit);
+    messages
+}
+
+#[tokio::test]
+async fn test_deterministic_replay() {
+    let root = std::env::temp_dir().join(format!("ruffd-replay-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    let first = save_heavy_session(&root).await;
+    let second = save_heavy_session(&root).await;
+    fs::remove_dir_all(&root).unwrap();
+    // the work of saving and linting the workspace is handled ahead of the
+    // messages after, rather than racing them
+    assert_eq!(first, second);
+    let published = first
+        .iter()
+        .filter(|x| x["method"] == "textDocument/publishDiagnostics")
+        .count();
+    assert!(published > 1, "{:?}", first);
+    assert_eq!(first[first.len() - 1]["result"], 1);
+}
+
+#[tokio::test]
+async fn test_duplicate_open() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.add_requests([("embedder/openCount", open_count)]);
+        service.set_deterministic(true);
+    })
+    .await;
+    for text in ["import os\n", "x = 1\n"] {
+        client
+            .send(json!({
+                "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": {"textDocument": {
+                    "uri": "file:///tmp/a.py", "languageId": "python",
+                    "version": 1, "text": text
+                }}
+            }))
+            .await;
+    }
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "embedder/openCount"}))
+        .await;
+    let published = client.recv().await;
+    assert_eq!(
+        published["params"]["diagnostics"].as_array().unwrap().len(),
+        1
+    );
+    // the reopened content replaces the buffer rather than opening it a
+    // second time
+    let published = client.recv().await;
+    assert_eq!(published["params"]["version"], 1);
+    assert!(published["params"]["diagnostics"]
+        .as_array()
+        .unwrap()
+        .is_empty());
+    let resp = client.recv().await;
+    assert_eq!(resp["result"], 1);
+    client.shutdown(3).await;
+    service_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_fix_all_relints() {
+    let init_params = json!({
+        "capabilities": {"workspace": {"workspaceEdit": {"documentChanges": true}}},
+        "initializationOptions": {"runMode": "onSave"}
+    });
+    let (mut client, service_task) =
+        start_session(init_params, |service| service.set_deterministic(true)).await;
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {
+                "uri": "file:///tmp/a.py", "languageId": "python",
+                "version": 1, "text": "import os\n"
+            }}
+        }))
+        .await;
+    // the change isn't linted until saved, so the checks are stale once
+    // fixing all
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "method": "textDocument/didChange",
+            "params": {
+                "textDocument": {"uri": "file:///tmp/a.py", "version": 2},
+                "contentChanges": [{"text": "import os\nimport sys\n"}]
+            }
+        }))
+        .await;
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "id": 2, "method": "workspace/executeCommand",
+            "params": {"command": "ruffd.fixAll", "arguments": ["file:///tmp/a.py"]}
+        }))
+        .await;
+    let apply_edit = client
+        .recv_until(|x| x["method"] == "workspace/applyEdit")
+        .await;
+    let document_changes = &apply_edit["params"]["edit"]["documentChanges"];
+    assert_eq!(document_changes[0]["textDocument"]["version"], 2);
+    assert_eq!(document_changes[0]["edits"].as_array().unwrap().len(), 2);
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "id": apply_edit["id"], "result": {"applied": true}
+        }))
+        .await;
+    client.shutdown(3).await;
+    service_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_preview_fix_relints() {
+    let init_params = json!({
+        "capabilities": {},
+        "initializationOptions": {"runMode": "onSave"}
+    });
+    let (mut client, service_task) =
+        start_session(init_params, |service| service.set_deterministic(true)).await;
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {
+                "uri": "file:///tmp/a.py", "languageId": "python",
+                "version": 1, "text": "import os\n"
+            }}
+        }))
+        .await;
+    let published = client.recv().await;
+    let diagnostic = published["params"]["diagnostics"][0].clone();
+    // the import is removed without the document being linted again
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "method": "textDocument/didChange",
+            "params": {
+                "textDocument": {"uri": "file:///tmp/a.py", "version": 2},
+                "contentChanges": [{"text": "x = 1\n"}]
+            }
+        }))
+        .await;
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "id": 2, "method": "workspace/executeCommand",
+            "params": {"command": "ruffd.previewFix", "arguments": [{
+                "uri": "file:///tmp/a.py",
+                "code": diagnostic["code"],
+                "range": diagnostic["range"]
+            }]}
+        }))
+        .await;
+    let resp = client.recv_until(|x| x["id"] == 2).await;
+    // the check of the stale lint isn't previewed
+    assert!(resp["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("no check"));
+    client.shutdown(3).await;
+    service_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_fix_on_save_versioned() {
+    let init_params = json!({
+        "capabilities": {"workspace": {
+            "applyEdit": true,
+            "workspaceEdit": {"documentChanges": true}
+        }},
+        "initializationOptions": {"runMode": "onSave", "fixOnSave": "applyEdit"}
+    });
+    let (mut client, service_task) =
+        start_session(init_params, |service| service.set_deterministic(true)).await;
+    for (method, params) in [
+        (
+            "textDocument/didOpen",
+            json!({"textDocument": {
+                "uri": "file:///tmp/a.py", "languageId": "python",
+                "version": 1, "text": "import os\n"
+            }}),
+        ),
+        (
+            "textDocument/didChange",
+            json!({
+                "textDocument": {"uri": "file:///tmp/a.py", "version": 2},
+                "contentChanges": [{"text": "import os\nimport sys\n"}]
+            }),
+        ),
+        (
+            "textDocument/didSave",
+            json!({"textDocument": {"uri": "file:///tmp/a.py"}}),
+        ),
+    ] {
+        client
+            .send(json!({"jsonrpc": "2.0", "method": method, "params": params}))
+            .await;
+    }
+    let apply_edit = client
+        .recv_until(|x| x["method"] == "workspace/applyEdit")
+        .await;
+    // the fixes are of the saved version, which the client checks before
+    // applying them
+    let document_changes = &apply_edit["params"]["edit"]["documentChanges"];
+    assert_eq!(document_changes[0]["textDocument"]["version"], 2);
+    assert_eq!(document_changes[0]["edits"].as_array().unwrap().len(), 2);
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "id": apply_edit["id"], "result": {"applied": true}
+        }))
+        .await;
+    client.shutdown(3).await;
+    service_task.await.unwrap();
+}
+
+/// Records a spill and warm cache diagnostics for the document, as if it
+/// had been spilled and restored
+#[request(mut spilled, mut cached_diagnostics)]
+fn seed_document_state(params: lsp_types::Url) -> Result<(), RuntimeError> {
+    spilled.insert(params.clone(), 1);
+    let cached = CachedDiagnostics {
+        content_hash: 0,
+        diagnostics: vec![],
+    };
+    cached_diagnostics.insert(params, cached);
+    Ok(())
+}
+
+/// Names of the state fields holding anything for the document
+#[request(
+    open_buffers,
+    document_status,
+    shadow_buffers,
+    checks,
+    spilled,
+    cached_diagnostics
+)]
+fn document_state(params: lsp_types::Url) -> Result<Vec<&'static str>, RuntimeError> {
+    let held = [
+        ("open_buffers", open_buffers.contains_key(&params)),
+        ("document_status", document_status.contains_key(&params)),
+        ("shadow_buffers", shadow_buffers.contains_key(&params)),
+        ("checks", checks.contains_key(&params)),
+        ("spilled", spilled.contains_key(&params)),
+        (
+            "cached_diagnostics",
+            cached_diagnostics.contains_key(&params),
+        ),
+    ];
+    Ok(held
+        .into_iter()
+        .filter(|(_, held)| *held)
+        .map(|(name, _)| name)
+        .collect())
+}
+
+#[tokio::test]
+async fn test_close_clears_state() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.add_requests([
+            ("embedder/seedDocumentState", seed_document_state),
+            ("embedder/documentState", document_state),
+        ]);
+        service.set_deterministic(true);
+    })
+    .await;
+    let uri = "file:///tmp/a.py";
+    let messages = [
+        json!({
+            "jsonrpc": "2.0", "method": "textDocument/didOpen",
+            "params": {"textDocument": {
+                "uri": uri, "languageId": "python", "version": 1, "text": "import os\n"
+            }}
+        }),
+        json!({
+            "jsonrpc": "2.0", "id": 2, "method": "embedder/seedDocumentState", "params": uri
+        }),
+        json!({
+            "jsonrpc": "2.0", "method": "textDocument/didChange",
+            "params": {
+                "textDocument": {"uri": uri, "version": 2},
+                "contentChanges": [{"text": "import os\nimport sys\n"}]
+            }
+        }),
+        json!({
+            "jsonrpc": "2.0", "method": "textDocument/didClose",
+            "params": {"textDocument": {"uri": uri}}
+        }),
+        json!({
+            "jsonrpc": "2.0", "id": 3, "method": "embedder/documentState", "params": uri
+        }),
+    ];
+    for message in messages {
+        client.send(message).await;
+    }
+    let mut published = vec![];
+    let resp = loop {
+        let msg = client.recv().await;
+        if msg["method"] == "textDocument/publishDiagnostics" {
+            published.push(msg["params"]["diagnostics"].as_array().unwrap().len());
+        }
+        if msg["id"] == 3 {
+            break msg;
+        }
+    };
+    // the unsaved changes are discarded, so are their diagnostics
+    assert_eq!(published.last(), Some(&0));
+    assert_eq!(resp["result"], json!([]));
+    client.shutdown(4).await;
+    service_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_notification_errors_rate_limited() {
+    let start = Instant::now();
+    let now = Arc::new(std::sync::Mutex::new(start));
+    let clock_now = now.clone();
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.set_deterministic(true);
+        service.add_requests([("embedder/openCount", open_count)]);
+        service.set_state_factory(Box::new(move |init_params| {
+            let mut state = ServerState::from_init(init_params)?;
+            let clock_now = clock_now.clone();
+            let clock: Clock = Arc::new(move || *clock_now.lock().unwrap());
+            state.log_limiter = Arc::new(RwLock::new(RateLimiter::new(
+                Duration::from_secs(10),
+                clock,
+            )));
+            Ok(state)
+        }));
+    })
+    .await;
+    let edit_unopened = json!({
+        "jsonrpc": "2.0", "method": "textDocument/didChange",
+        "params": {
+            "textDocument": {"uri": "file:///tmp/unopened.py", "version": 2},
+            "contentChanges": [{"text": "import os\n"}]
+        }
+    });
+    let mut logged = vec![];
+    for (id, edits, elapsed) in [(2, 3, 0), (3, 1, 10)] {
+        *now.lock().unwrap() = start + Duration::from_secs(elapsed);
+        for _ in 0..edits {
+            client.send(edit_unopened.clone()).await;
+        }
+        client
+            .send(json!({"jsonrpc": "2.0", "id": id, "method": "embedder/openCount"}))
+            .await;
+        loop {
+            let msg = client.recv().await;
+            if msg["method"] == "window/logMessage" {
+                logged.push(msg["params"]["message"].as_str().unwrap().to_string());
+            }
+            if msg["id"] == id {
+                break;
+            }
+        }
+    }
+    // errors of a kind are logged once per interval, counting those
+    // suppressed since
+    assert_eq!(logged.len(), 2);
+    assert!(!logged[0].contains("suppressed"));
+    assert!(logged[1].ends_with("(2 similar errors suppressed)"));
+    client.shutdown(4).await;
+    service_task.await.unwrap();
+}
+
+#[cfg(feature = "watch")]
+#[tokio::test]
+async fn test_watcher_status_of_registration() {
+    let init_params = json!({"capabilities": {
+        "workspace": {"didChangeWatchedFiles": {"dynamicRegistration": true}}
+    }});
+    let (mut client, service_task) =
+        start_session(init_params, |service| service.set_deterministic(true)).await;
+    client
+        .send(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}))
+        .await;
+    let registration = client
+        .recv_until(|x| x["method"] == "client/registerCapability")
+        .await;
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "ruffd/status"}))
+        .await;
+    let resp = client.recv().await;
+    assert_eq!(resp["result"]["watcher"], "pending");
+    // the client rejecting the registration doesn't watch files
+    client
+        .send(json!({
+            "jsonrpc": "2.0", "id": registration["id"],
+            "error": {"code": -32601, "message": "unsupported"}
+        }))
+        .await;
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 3, "method": "ruffd/status"}))
+        .await;
+    let resp = client.recv().await;
+    assert_eq!(resp["result"]["watcher"], "failed");
+    client.shutdown(4).await;
+    service_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_disconnect() {
+    let (client, service_task) = start_session(json!({"capabilities": {}}), |_| {}).await;
+    drop(client);
+    assert_eq!(service_task.await.unwrap(), SessionOutcome::Disconnected);
+}
+
+#[tokio::test]
+async fn test_fatal_initialization() {
+    let (client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.set_state_factory(Box::new(|_| Err(RuntimeError::UnexpectedNone)));
+    })
+    .await;
+    // the client is answered with the error rather than left waiting
+    assert_eq!(client.init_response["id"], 1);
+    assert_eq!(client.init_response["error"]["message"], "Unexpected None");
+    assert_eq!(
+        service_task.await.unwrap(),
+        SessionOutcome::Fatal("failed initializing: Unexpected None".to_string())
+    );
+}
+
+#[tokio::test]
+async fn test_malformed_options() {
+    let init_params = json!({"capabilities": {}, "initializationOptions": {"runMode": 1}});
+    let (mut client, service_task) = start_session(init_params, |_| {}).await;
+    // the session runs with the defaults, the client being told why
+    assert!(client.init_response["result"]["capabilities"].is_object());
+    let msg = client.recv().await;
+    assert_eq!(msg["method"], "window/logMessage");
+    assert_eq!(msg["params"]["type"], 2);
+    let message = msg["params"]["message"].as_str().unwrap();
+    assert!(message.starts_with("malformed initializationOptions"));
+    assert!(message.contains("invalid type"), "{}", message);
+    client.shutdown(2).await;
+    assert_eq!(service_task.await.unwrap(), SessionOutcome::Exit);
+}
+
+/// Publishes a diagnostic distinct to each follow-up, as unchanged
+/// diagnostics aren't published again
+fn publish_op(uri: lsp_types::Url, message: &str) -> ServerNotification {
+    let diagnostic = lsp_types::Diagnostic {
+        message: message.to_string(),
+        ..Default::default()
+    };
+    let params = lsp_types::PublishDiagnosticsParams::new(uri, vec![diagnostic], None);
+    let notification = RpcNotification::new(
+        "textDocument/publishDiagnostics".to_string(),
+        Some(serde_json::to_value(params).unwrap()),
+    );
+    let exec: ServerNotificationExec =
+        Box::new(move |_, _| Box::pin(async move { Some(notification.into()) }));
+    ServerNotification {
+        exec,
+        create_locks: create_locks_fut!(),
+    }
+}
+
+#[request]
+fn publish_returned(params: lsp_types::Url) -> Result<(bool, Vec<ServerInitiated>), RuntimeError> {
+    let publish_op = publish_op(params, "returned");
+    Ok((true, vec![ServerInitiated::Notification(publish_op)]))
+}
+
+#[request]
+fn publish_scheduled(scheduler: Scheduler, params: lsp_types::Url) -> Result<bool, RuntimeError> {
+    scheduler.schedule(publish_op(params, "scheduled"));
+    Ok(true)
+}
+
+#[tokio::test]
+async fn test_request_follow_ups() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.add_requests([
+            ("embedder/publishReturned", publish_returned),
+            ("embedder/publishScheduled", publish_scheduled),
+        ]);
+    })
+    .await;
+    for (id, method) in [
+        (2, "embedder/publishReturned"),
+        (3, "embedder/publishScheduled"),
+    ] {
+        client
+            .send(json!({
+                "jsonrpc": "2.0", "id": id, "method": method,
+                "params": "file:///tmp/a.py"
+            }))
+            .await;
+        // the follow-up is handled concurrently with the response
+        let mut received = [client.recv().await, client.recv().await];
+        received.sort_by_key(|x| x["id"].is_null());
+        assert_eq!(received[0]["id"], id);
+        assert_eq!(received[0]["result"], true);
+        assert_eq!(received[1]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(received[1]["params"]["uri"], "file:///tmp/a.py");
+    }
+    client.shutdown(4).await;
+    service_task.await.unwrap();
+}
+
+#[request]
+fn ping_client(scheduler: Scheduler) -> Result<bool, RuntimeError> {
+    let response = scheduler.request_client("client/ping", None);
+    task::spawn(async move {
+        let code = match response.await.unwrap().into_result() {
+            Ok(_) => 0,
+            Err(err) => err.code,
+        };
+        scheduler.notify_client(RpcNotification::new(
+            "embedder/pinged".to_string(),
+            Some(json!(code)),
+        ));
+    });
+    Ok(true)
+}
+
+#[tokio::test]
+async fn test_server_request_timeout() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.add_requests([("embedder/pingClient", ping_client)]);
+        service.set_server_request_timeout(Some(Duration::from_millis(20)));
+        service.set_server_request_retries(1);
+    })
+    .await;
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "embedder/pingClient"}))
+        .await;
+    // the request is never answered, so is cancelled and resent once
+    let mut received = vec![];
+    for _ in 0..6 {
+        received.push(client.recv().await);
+    }
+    let with_method = |method: &str| {
+        received
+            .iter()
+            .filter(|x| x["method"] == method)
+            .collect::<Vec<_>>()
+    };
+    let pings = with_method("client/ping");
+    let cancels = with_method("$/cancelRequest");
+    assert_eq!(pings.len(), 2);
+    assert_ne!(pings[0]["id"], pings[1]["id"]);
+    assert_eq!(cancels.len(), 2);
+    assert_eq!(cancels[0]["params"]["id"], pings[0]["id"]);
+    assert_eq!(cancels[1]["params"]["id"], pings[1]["id"]);
+    assert_eq!(
+        with_method("embedder/pinged")[0]["params"],
+        RpcErrors::RESPONSE_TIMED_OUT.code
+    );
+    assert!(received.iter().any(|x| x["id"] == 2 && x["result"] == true));
+    client.shutdown(3).await;
+    service_task.await.unwrap();
+}
+
+/// Rejects methods outside of the protocol
+struct Gate;
+
+impl Middleware for Gate {
+    fn inbound(&self, message: RpcMessage) -> RpcResult<RpcMessage> {
+        match &message {
+            RpcMessage::Request(x) if x.method.starts_with("embedder/") => {
+                Err(RpcErrors::REQUEST_FAILED.with_message("forbidden"))
+            }
+            _ => Ok(message),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_middleware_rejects() {
+    let (mut client, service_task) = start_session(json!({"capabilities": {}}), |service| {
+        service.add_requests([("embedder/openCount", open_count)]);
+        service.add_middleware(Box::new(TimingMiddleware::default()));
+        service.add_middleware(Box::new(Gate));
+    })
+    .await;
+    client
+        .send(json!({"jsonrpc": "2.0", "id": 2, "method": "embedder/openCount"}))
+        .await;
+    let resp = client.recv().await;
+    assert_eq!(resp["id"], 2);
+    assert_eq!(resp["error"]["message"], "forbidden");
+    client.shutdown(3).await;
+    service_task.await.unwrap();
+}
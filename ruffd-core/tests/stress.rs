@@ -0,0 +1,257 @@
+//! Drives hundreds of interleaved opens, edits, closes and code actions
+//! across documents through an in-memory transport, asserting the server
+//! neither deadlocks nor loses a response, and that its buffers end up as
+//! the client's copies of the documents
+//!
+//! Messages are interleaved by a seeded generator, such that a failure is
+//! reproduced by running the same seed again
+mod common;
+
+use common::{recv, send};
+use ruffd_core::Service;
+use ruffd_macros::request;
+use ruffd_types::serde_json::{json, Value};
+use ruffd_types::tokio;
+use ruffd_types::tokio::io::{self, BufReader};
+use ruffd_types::tokio::sync::{oneshot, Mutex};
+use ruffd_types::tokio::task;
+use ruffd_types::tokio::time::timeout;
+use ruffd_types::{lsp_types, RuntimeError};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+const DOCUMENTS: usize = 16;
+const MESSAGES: usize = 800;
+/// Time given to the whole run before the server is taken as deadlocked
+const DEADLINE: Duration = Duration::from_secs(60);
+/// Id of the request sent after every message, reading back the buffers
+const BARRIER_ID: u64 = 1 << 20;
+
+/// Text and version of each open buffer
+#[request(open_buffers, document_status)]
+fn buffer_contents() -> Result<Vec<(lsp_types::Url, i32, String)>, RuntimeError> {
+    let mut rv = open_buffers
+        .iter()
+        .map(|(uri, buffer)| {
+            let version = document_status.get(uri).map(|x| x.version).unwrap_or(-1);
+            (uri.clone(), version, buffer.iter().collect())
+        })
+        .collect::<Vec<_>>();
+    rv.sort();
+    Ok(rv)
+}
+
+/// Linear congruential generator, enough to interleave messages
+/// reproducibly without a dependency
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % bound
+    }
+}
+
+/// Client's copy of a document
+struct Document {
+    uri: String,
+    version: i32,
+    text: Option<String>,
+}
+
+/// Next message of the client for `doc`, updating its copy as the server's
+/// buffer should be once the message is handled
+fn next_message(doc: &mut Document, rng: &mut Lcg, step: usize, next_id: &mut u64) -> Value {
+    doc.version += 1;
+    let text = match doc.text.as_mut() {
+        Some(x) => x,
+        None => {
+            let text = format!("import os\nx = {}\n", step);
+            doc.text = Some(text.clone());
+            return json!({
+                "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": {"textDocument": {
+                    "uri": doc.uri, "languageId": "python",
+                    "version": doc.version, "text": text
+                }}
+            });
+        }
+    };
+    match rng.next(10) {
+        0 => {
+            doc.text = None;
+            json!({
+                "jsonrpc": "2.0", "method": "textDocument/didClose",
+                "params": {"textDocument": {"uri": doc.uri}}
+            })
+        }
+        1..=4 => {
+            let line = format!("y{} = {}\n", step, step);
+            text.insert_str(0, &line);
+            json!({
+                "jsonrpc": "2.0", "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": {"uri": doc.uri, "version": doc.version},
+                    "contentChanges": [{
+                        "range": {
+                            "start": {"line": 0, "character": 0},
+                            "end": {"line": 0, "character": 0}
+                        },
+                        "text": line
+                    }]
+                }
+            })
+        }
+        5 | 6 => {
+            *text = format!("import sys\nz = {}\n", step);
+            json!({
+                "jsonrpc": "2.0", "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": {"uri": doc.uri, "version": doc.version},
+                    "contentChanges": [{"text": text}]
+                }
+            })
+        }
+        _ => {
+            // requests don't change the document
+            doc.version -= 1;
+            *next_id += 1;
+            json!({
+                "jsonrpc": "2.0", "id": *next_id, "method": "textDocument/codeAction",
+                "params": {
+                    "textDocument": {"uri": doc.uri},
+                    "range": {
+                        "start": {"line": 0, "character": 0},
+                        "end": {"line": 1, "character": 0}
+                    },
+                    "context": {"diagnostics": []}
+                }
+            })
+        }
+    }
+}
+
+async fn stress(seed: u64) {
+    let (client, server) = io::duplex(1 << 16);
+    let (server_read, server_write) = io::split(server);
+    let (client_read, client_write) = io::split(client);
+    let mut client_read = BufReader::new(client_read);
+    let client_write = Arc::new(Mutex::new(client_write));
+    let mut service = Service::new(BufReader::new(server_read), server_write);
+    service.add_requests([("embedder/bufferContents", buffer_contents)]);
+    let service_task = task::spawn(async move { service.run().await });
+
+    send(
+        &mut *client_write.lock().await,
+        &json!({
+            "jsonrpc": "2.0", "id": 0, "method": "initialize",
+            "params": {"capabilities": {
+                "textDocument": {"codeAction": {"codeActionLiteralSupport": {
+                    "codeActionKind": {"valueSet": ["quickfix"]}
+                }}}
+            }}
+        }),
+    )
+    .await;
+    recv(&mut client_read).await;
+
+    // messages of the server are read as they're written, such that the
+    // transport never fills while the client is still sending
+    let reader_write = client_write.clone();
+    let (requests_sent, mut sent) = oneshot::channel::<u64>();
+    let reader = task::spawn(async move {
+        let mut responses = BTreeSet::new();
+        let mut barrier = None;
+        let mut expected = None;
+        loop {
+            // requests are handled concurrently, such that their responses
+            // may follow that of the barrier
+            if let (Some(_), Some(x)) = (&barrier, expected) {
+                if responses.len() as u64 == x {
+                    return (responses, barrier.unwrap());
+                }
+            }
+            let msg = recv(&mut client_read).await;
+            match (msg.get("method"), msg.get("id")) {
+                (Some(_), Some(id)) => {
+                    let resp = json!({"jsonrpc": "2.0", "id": id, "result": null});
+                    send(&mut *reader_write.lock().await, &resp).await;
+                }
+                (None, Some(id)) if *id == BARRIER_ID => {
+                    expected = Some((&mut sent).await.unwrap());
+                    barrier = Some(msg);
+                }
+                (None, Some(id)) => {
+                    assert!(msg.get("error").is_none(), "{}", msg);
+                    assert!(responses.insert(id.as_u64().unwrap()), "{}", msg);
+                }
+                _ => {}
+            }
+        }
+    });
+
+    let mut rng = Lcg(seed);
+    let mut docs = (0..DOCUMENTS)
+        .map(|idx| Document {
+            uri: format!("file:///tmp/ruffd-stress/module_{}.py", idx),
+            version: 0,
+            text: None,
+        })
+        .collect::<Vec<_>>();
+    let mut next_id = 0;
+    for step in 0..MESSAGES {
+        let doc = rng.next(DOCUMENTS);
+        let msg = next_message(&mut docs[doc], &mut rng, step, &mut next_id);
+        send(&mut *client_write.lock().await, &msg).await;
+    }
+    requests_sent.send(next_id).unwrap();
+    send(
+        &mut *client_write.lock().await,
+        &json!({"jsonrpc": "2.0", "id": BARRIER_ID, "method": "embedder/bufferContents"}),
+    )
+    .await;
+
+    let (responses, contents) = timeout(DEADLINE, reader)
+        .await
+        .unwrap_or_else(|_| panic!("server deadlocked running seed {}", seed))
+        .unwrap();
+    assert_eq!(responses, (1..=next_id).collect(), "seed {}", seed);
+    let buffers = contents["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| {
+            let entry = (x[1].as_i64().unwrap() as i32, x[2].as_str().unwrap());
+            (x[0].as_str().unwrap(), entry)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let expected = docs
+        .iter()
+        .filter_map(|x| Some((x.uri.as_str(), (x.version, x.text.as_deref()?))))
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(buffers, expected, "seed {}", seed);
+
+    let mut writer = client_write.lock().await;
+    send(
+        &mut *writer,
+        &json!({"jsonrpc": "2.0", "id": BARRIER_ID + 1, "method": "shutdown"}),
+    )
+    .await;
+    send(&mut *writer, &json!({"jsonrpc": "2.0", "method": "exit"})).await;
+    drop(writer);
+    timeout(DEADLINE, service_task)
+        .await
+        .unwrap_or_else(|_| panic!("server didn't exit running seed {}", seed))
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_interleaved_documents() {
+    for seed in [1, 2, 3] {
+        stress(seed).await;
+    }
+}
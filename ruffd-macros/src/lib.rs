@@ -4,33 +4,95 @@ use proc_macro2::Span;
 use proc_macro_error::{abort, proc_macro_error, Diagnostic, Level};
 use quote::{quote, ToTokens};
 use syn::{
-    parse_macro_input, parse_quote, AttributeArgs, Fields, FnArg, GenericParam, Ident, Index,
-    ItemFn, ItemStruct, Lit, Meta, NestedMeta, Pat, PatIdent, PatType, Stmt, Token, Type,
+    parse_macro_input, parse_quote, AttributeArgs, Field, Fields, FnArg, GenericArgument,
+    GenericParam, Ident, Index, ItemFn, ItemStruct, Lit, Meta, NestedMeta, Pat, PatIdent, PatType,
+    PathArguments, ReturnType, Stmt, Token, Type,
 };
 
+/// Name of the parameter through which handlers may take the channel for
+/// scheduling server work
+const SCHEDULER_PARAM: &str = "scheduler";
+
 struct FnDetails {
     asyncness: bool,
     fn_identifier: Ident,
     parameter: Option<PatType>,
+    scheduler: Option<PatType>,
+    follow_ups: bool,
+}
+
+fn is_scheduler_param(param: &PatType) -> bool {
+    matches!(param.pat.as_ref(), Pat::Ident(x) if x.ident == SCHEDULER_PARAM)
+}
+
+/// Splits a type into the last identifier of its path and its generic type
+/// arguments
+fn path_generics(ty: &Type) -> Option<(&Ident, Vec<&Type>)> {
+    let segment = match ty {
+        Type::Path(x) => x.path.segments.last()?,
+        _ => return None,
+    };
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(x) => x
+            .args
+            .iter()
+            .filter_map(|x| match x {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    Some((&segment.ident, args))
+}
+
+/// Determines whether the return type is spelt
+/// `Result<(T, Vec<ServerInitiated>), E>`
+fn returns_follow_ups(output: &ReturnType) -> bool {
+    let ty = match output {
+        ReturnType::Type(_, x) => x.as_ref(),
+        ReturnType::Default => return false,
+    };
+    let ok_ty = match path_generics(ty) {
+        Some((ident, args)) if ident == "Result" => args.first().copied(),
+        _ => None,
+    };
+    let follow_ups_ty = match ok_ty {
+        Some(Type::Tuple(x)) if x.elems.len() == 2 => &x.elems[1],
+        _ => return false,
+    };
+    match path_generics(follow_ups_ty) {
+        Some((ident, args)) if ident == "Vec" => matches!(
+            args.first().and_then(|x| path_generics(x)),
+            Some((ident, _)) if ident == "ServerInitiated"
+        ),
+        _ => false,
+    }
 }
 
 impl FnDetails {
     fn from_item_fn(input: &ItemFn) -> Self {
-        let params = &input.sig.inputs;
-        let mut params_iter = params.iter().cloned();
-        let parameter = params_iter.next().map(|param| match param {
-            FnArg::Receiver(_) => {
-                abort!(Diagnostic::new(
-                    Level::Error,
-                    "self parameter disallowed".to_string()
-                ));
-            }
-            FnArg::Typed(x) => x,
-        });
-        if params_iter.next().is_some() {
+        let params = input
+            .sig
+            .inputs
+            .iter()
+            .cloned()
+            .map(|param| match param {
+                FnArg::Receiver(_) => {
+                    abort!(Diagnostic::new(
+                        Level::Error,
+                        "self parameter disallowed".to_string()
+                    ));
+                }
+                FnArg::Typed(x) => x,
+            })
+            .collect::<Vec<_>>();
+        let (mut schedulers, others): (Vec<_>, Vec<_>) =
+            params.into_iter().partition(is_scheduler_param);
+        if others.len() > 1 || schedulers.len() > 1 {
             abort!(Diagnostic::new(
                 Level::Error,
-                "Exactly one or zero parameters allowed".to_string()
+                "Exactly one or zero parameters allowed besides `scheduler`".to_string()
             ));
         }
         let fn_identifier = input.sig.ident.clone();
@@ -38,7 +100,9 @@ impl FnDetails {
         Self {
             asyncness,
             fn_identifier,
-            parameter,
+            parameter: others.into_iter().next(),
+            scheduler: schedulers.pop(),
+            follow_ups: returns_follow_ups(&input.sig.output),
         }
     }
 }
@@ -80,9 +144,9 @@ fn make_create_locks_fn(members: &[PatIdent]) -> impl ToTokens {
         let statement_iter = members.iter().map(|member| -> Stmt {
             let ident = &member.ident;
             let rhs = if member.mutability.is_some() {
-                quote!(::ruffd_types::RwReq::Write(state.#ident.clone()))
+                quote!(::ruffd_types::StateField::write_req(&state.#ident))
             } else {
-                quote!(::ruffd_types::RwReq::Read(state.#ident.clone()))
+                quote!(::ruffd_types::StateField::read_req(&state.#ident))
             };
             parse_quote!(rv.#ident = Some(#rhs);)
         });
@@ -113,33 +177,41 @@ fn make_setup_state(members: &[PatIdent]) -> impl ToTokens {
     let statements = members.iter().map(|member| -> Stmt {
         let ident = &member.ident;
         let mutability = &member.mutability;
-        let guard_type = if mutability.is_some() {
-            quote!(Write)
+        let unwrap_fn = if mutability.is_some() {
+            quote!(into_write)
         } else {
-            quote!(Read)
+            quote!(into_read)
         };
+        // snapshot cells are replaced through a shared reference
         parse_quote! {
-            let #mutability #ident = match state.#ident.unwrap() {
-                ::ruffd_types::RwGuarded::#guard_type(x) => x,
-                _ => unreachable!(),
-            };
+            #[allow(unused_mut)]
+            let #mutability #ident = ::ruffd_types::StateHandle::#unwrap_fn(state.#ident.unwrap());
         }
     });
     quote!(#(#statements)*)
 }
 
 /// Creates augmented inner function to execute
-fn make_inner_fn(func: &ItemFn, members: &[PatIdent]) -> impl ToTokens {
+///
+/// The scheduler channel is passed as the handler's `scheduler` parameter
+/// if it has one, otherwise as `_scheduler_channel`
+fn make_inner_fn(func: &ItemFn, fn_details: &FnDetails, members: &[PatIdent]) -> impl ToTokens {
     let sig = {
         let mut rv = func.sig.clone();
         rv.ident = Ident::new("inner", Span::call_site());
-        let old_inputs = rv.inputs;
+        let scheduler = match &fn_details.scheduler {
+            Some(x) => quote!(#x),
+            None => quote! {
+                _scheduler_channel: ::ruffd_types::tokio::sync::mpsc::Sender<
+                    ::ruffd_types::ScheduledTask
+                >
+            },
+        };
+        let parameter = fn_details.parameter.iter();
         rv.inputs = parse_quote!(
             state: ::ruffd_types::ServerStateHandles<'_>,
-            _scheduler_channel: ::ruffd_types::tokio::sync::mpsc::Sender<
-                ::ruffd_types::ScheduledTask
-            >,
-            #old_inputs);
+            #scheduler,
+            #(#parameter)*);
         rv
     };
     let block = func.block.clone();
@@ -152,6 +224,30 @@ fn make_inner_fn(func: &ItemFn, members: &[PatIdent]) -> impl ToTokens {
     }
 }
 
+/// Creates `typed`, pairing the handler `handler` of type `handler_type`
+/// with the type of its params for registering it under a typed method
+fn make_typed_fn(
+    parameter: Option<&PatType>,
+    handler: &Ident,
+    handler_type: proc_macro2::TokenStream,
+) -> impl ToTokens {
+    match parameter {
+        Some(param) => {
+            let param_type = &param.ty;
+            quote! {
+                pub fn typed() -> ::ruffd_types::Typed<#handler_type, #param_type> {
+                    ::ruffd_types::Typed::new(#handler)
+                }
+            }
+        }
+        None => quote! {
+            pub fn typed<P>() -> ::ruffd_types::Typed<#handler_type, P> {
+                ::ruffd_types::Typed::new(#handler)
+            }
+        },
+    }
+}
+
 fn make_params_check(param: PatType, is_notification: bool) -> impl ToTokens {
     let error_return = if is_notification {
         quote!(Some(::ruffd_types::RpcResponseMessage::from_error(
@@ -164,9 +260,7 @@ fn make_params_check(param: PatType, is_notification: bool) -> impl ToTokens {
     quote! {
         let params_result: Result<#param_type, ::ruffd_types::RpcError> = match params {
             None => Err(::ruffd_types::RpcErrors::INVALID_PARAMS),
-            Some(x) => {
-                ::ruffd_types::serde_json::from_value(x).map_err(|e| e.into())
-            }
+            Some(x) => x.into_typed(),
         };
         let params = match params_result {
             Err(err) => return #error_return,
@@ -183,7 +277,7 @@ pub fn notification(args: TokenStream, stream: TokenStream) -> TokenStream {
     let create_locks_fn = make_create_locks_fn(&state_members);
     let input = parse_macro_input!(stream as ItemFn);
     let fn_details = FnDetails::from_item_fn(&input);
-    let inner_fn = make_inner_fn(&input, &state_members);
+    let inner_fn = make_inner_fn(&input, &fn_details, &state_members);
     let params_check = fn_details
         .parameter
         .clone()
@@ -196,18 +290,24 @@ pub fn notification(args: TokenStream, stream: TokenStream) -> TokenStream {
     let inner_call_params = fn_details.parameter.clone().map(|_| quote!(params));
     let inner_await = fn_details.asyncness.then(|| quote!(.await));
     let fn_identifier = fn_details.fn_identifier;
+    let typed_fn = make_typed_fn(
+        fn_details.parameter.as_ref(),
+        &fn_identifier,
+        quote!(::ruffd_types::Notification),
+    );
     quote! {
         #[allow(dead_code)]
         mod #fn_identifier {
             use super::*;
             #inner_fn
             #create_locks_fn
+            #typed_fn
             fn exec(
                 state: ::ruffd_types::ServerStateHandles<'_>,
                 scheduler_channel: ::ruffd_types::tokio::sync::mpsc::Sender<
                     ::ruffd_types::ScheduledTask
                 >,
-                #params_ident: Option<::ruffd_types::serde_json::Value>,
+                #params_ident: Option<::ruffd_types::Params>,
             ) -> ::std::pin::Pin<
                 Box<
                     dyn Send + ::std::future::Future<
@@ -218,7 +318,11 @@ pub fn notification(args: TokenStream, stream: TokenStream) -> TokenStream {
             {
                 Box::pin(async move {
                     #params_check
-                    let rv = inner(state, scheduler_channel, #inner_call_params)#inner_await;
+                    let rv = inner(
+                        state,
+                        ::std::convert::Into::into(scheduler_channel),
+                        #inner_call_params
+                    )#inner_await;
                     match rv {
                         Ok(_) => None,
                         Err(e) => Some(
@@ -255,6 +359,26 @@ pub fn notification(args: TokenStream, stream: TokenStream) -> TokenStream {
 /// prior to request execution. These arguments appear as tuple
 /// matching patterns e.g. `#[request(mut open_buffers)]` will acquire
 /// the field `open_buffers` with a write lock prior to execution
+///
+/// # Scheduling server work
+///
+/// A parameter named `scheduler` of type `ruffd_types::Scheduler`, or the
+/// `Sender<ruffd_types::ScheduledTask>` it wraps, is passed the channel
+/// through which server work is scheduled. This is accepted by
+/// `#[notification]` alike.
+///
+/// Alternatively a request returning `Result<(T, Vec<ServerInitiated>), E>`
+/// responds with `T`, having scheduled the returned work in order. The
+/// return type must be spelt as such, rather than through an alias, to be
+/// recognised
+///
+/// # Registering
+///
+/// The module also exports `typed()`, pairing the handler with the type of
+/// its params. Registering it through `ruffd_types::request_entry` keys it
+/// by the method of an `lsp_types` request, failing to compile if the
+/// handler's params differ from those of the request. `#[notification]`
+/// handlers are registered through `ruffd_types::notification_entry` alike
 #[proc_macro_error]
 #[proc_macro_attribute]
 pub fn request(args: TokenStream, stream: TokenStream) -> TokenStream {
@@ -263,7 +387,7 @@ pub fn request(args: TokenStream, stream: TokenStream) -> TokenStream {
     let create_locks_fn = make_create_locks_fn(&state_members);
     let input = parse_macro_input!(stream as ItemFn);
     let fn_details = FnDetails::from_item_fn(&input);
-    let inner_fn = make_inner_fn(&input, &state_members);
+    let inner_fn = make_inner_fn(&input, &fn_details, &state_members);
     let params_check = fn_details
         .parameter
         .clone()
@@ -275,20 +399,62 @@ pub fn request(args: TokenStream, stream: TokenStream) -> TokenStream {
     };
     let inner_call_params = fn_details.parameter.clone().map(|_| quote!(params));
     let inner_await = fn_details.asyncness.then(|| quote!(.await));
+    // follow-ups are scheduled before responding, such that they're handled
+    // ahead of any later message
+    let (follow_up_channel, ok_arm) = if fn_details.follow_ups {
+        (
+            quote!(let follow_up_channel = scheduler_channel.clone();),
+            quote! {
+                Ok((val, follow_ups)) => {
+                    for follow_up in follow_ups {
+                        // the session may have ended, the response then
+                        // being sent nowhere either
+                        if follow_up_channel
+                            .send(::ruffd_types::ScheduledTask::server(follow_up))
+                            .await
+                            .is_err()
+                        {
+                            ::ruffd_types::log_debug!(
+                                "session ended before the follow-ups of a request were sent"
+                            );
+                            break;
+                        }
+                    }
+                    ::ruffd_types::RpcResponseMessage::from_result(id, val)
+                }
+            },
+        )
+    } else {
+        (
+            quote!(),
+            quote! {
+                Ok(val) => ::ruffd_types::RpcResponseMessage::from_result(
+                    id,
+                    val,
+                ),
+            },
+        )
+    };
     let fn_identifier = fn_details.fn_identifier;
+    let typed_fn = make_typed_fn(
+        fn_details.parameter.as_ref(),
+        &fn_identifier,
+        quote!(::ruffd_types::Request),
+    );
     quote! {
         #[allow(dead_code)]
         mod #fn_identifier {
             use super::*;
             #inner_fn
             #create_locks_fn
+            #typed_fn
             fn exec(
                 state: ::ruffd_types::ServerStateHandles<'_>,
                 scheduler_channel: ::ruffd_types::tokio::sync::mpsc::Sender<
                     ::ruffd_types::ScheduledTask
                 >,
                 id: ::ruffd_types::lsp_types::NumberOrString,
-                #params_ident: Option<::ruffd_types::serde_json::Value>,
+                #params_ident: Option<::ruffd_types::Params>,
             ) -> ::std::pin::Pin<
                 Box<
                     dyn Send + ::std::future::Future<
@@ -299,12 +465,14 @@ pub fn request(args: TokenStream, stream: TokenStream) -> TokenStream {
             {
                 Box::pin(async move {
                     #params_check
-                    let rv = inner(state, scheduler_channel, #inner_call_params)#inner_await;
+                    #follow_up_channel
+                    let rv = inner(
+                        state,
+                        ::std::convert::Into::into(scheduler_channel),
+                        #inner_call_params
+                    )#inner_await;
                     match rv {
-                        Ok(val) => ::ruffd_types::RpcResponseMessage::from_result(
-                            id,
-                            val,
-                        ),
+                        #ok_arm
                         Err(e) => ::ruffd_types::RpcResponseMessage::from_error(
                             Some(id),
                             ::ruffd_types::RpcError::from(e)
@@ -326,6 +494,18 @@ pub fn request(args: TokenStream, stream: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Whether a field is marked `#[snapshot]`, being held in a
+/// `ruffd_types::Snapshot` rather than locked
+fn is_snapshot(field: &Field) -> bool {
+    field.attrs.iter().any(|x| x.path.is_ident("snapshot"))
+}
+
+fn strip_snapshot_attrs(item: &mut ItemStruct) {
+    for field in item.fields.iter_mut() {
+        field.attrs.retain(|x| !x.path.is_ident("snapshot"));
+    }
+}
+
 fn wrap_rw_fields(item: &mut ItemStruct, flags: &ServerStateFlags) {
     let fields = match &mut item.fields {
         Fields::Named(x) => Some(&mut x.named),
@@ -335,7 +515,13 @@ fn wrap_rw_fields(item: &mut ItemStruct, flags: &ServerStateFlags) {
     if let Some(fields) = fields {
         for field in fields.iter_mut() {
             let inner_ty = &field.ty;
-            let new_ty: Type = if flags.in_ruffd_types {
+            let new_ty: Type = if is_snapshot(field) {
+                if flags.in_ruffd_types {
+                    parse_quote!(::std::sync::Arc<crate::state::Snapshot<#inner_ty>>)
+                } else {
+                    parse_quote!(::std::sync::Arc<::ruffd_types::Snapshot<#inner_ty>>)
+                }
+            } else if flags.in_ruffd_types {
                 parse_quote!(::std::sync::Arc<::tokio::sync::RwLock<#inner_ty>>)
             } else {
                 parse_quote!(::std::sync::Arc<::ruffd_types::tokio::sync::RwLock<#inner_ty>>)
@@ -343,6 +529,7 @@ fn wrap_rw_fields(item: &mut ItemStruct, flags: &ServerStateFlags) {
             field.ty = new_ty;
         }
     }
+    strip_snapshot_attrs(item);
 }
 
 fn make_handle_struct(item: &mut ItemStruct, flags: &ServerStateFlags) {
@@ -360,7 +547,13 @@ fn make_handle_struct(item: &mut ItemStruct, flags: &ServerStateFlags) {
     if let Some(fields) = fields {
         for field in fields.iter_mut() {
             let inner_ty = &field.ty;
-            let new_ty: Type = if flags.in_ruffd_types {
+            let new_ty: Type = if is_snapshot(field) {
+                if flags.in_ruffd_types {
+                    parse_quote!(Option<::std::sync::Arc<crate::state::Snapshot<#inner_ty>>>)
+                } else {
+                    parse_quote!(Option<::std::sync::Arc<::ruffd_types::Snapshot<#inner_ty>>>)
+                }
+            } else if flags.in_ruffd_types {
                 parse_quote!(Option<crate::state::RwGuarded<'guard, #inner_ty>>)
             } else {
                 parse_quote!(Option<::ruffd_types::RwGuarded<'guard, #inner_ty>>)
@@ -368,6 +561,7 @@ fn make_handle_struct(item: &mut ItemStruct, flags: &ServerStateFlags) {
             field.ty = new_ty;
         }
     }
+    strip_snapshot_attrs(item);
     item.attrs = vec![];
 }
 
@@ -382,7 +576,13 @@ fn make_lock_req_struct(item: &mut ItemStruct, flags: &ServerStateFlags) {
     if let Some(fields) = fields {
         for field in fields.iter_mut() {
             let inner_ty = &field.ty;
-            let new_ty: Type = if flags.in_ruffd_types {
+            let new_ty: Type = if is_snapshot(field) {
+                if flags.in_ruffd_types {
+                    parse_quote!(Option<::std::sync::Arc<crate::state::Snapshot<#inner_ty>>>)
+                } else {
+                    parse_quote!(Option<::std::sync::Arc<::ruffd_types::Snapshot<#inner_ty>>>)
+                }
+            } else if flags.in_ruffd_types {
                 parse_quote!(Option<crate::state::RwReq<#inner_ty>>)
             } else {
                 parse_quote!(Option<::ruffd_types::RwReq<#inner_ty>>)
@@ -390,6 +590,7 @@ fn make_lock_req_struct(item: &mut ItemStruct, flags: &ServerStateFlags) {
             field.ty = new_ty;
         }
     }
+    strip_snapshot_attrs(item);
     item.attrs = vec![parse_quote!(#[derive(Default)])];
 }
 
@@ -411,14 +612,20 @@ fn make_lock_to_handle_func(item: &ItemStruct) -> impl ToTokens {
                 .iter()
                 .map(|field| field.ident.as_ref().unwrap().clone())
                 .collect::<Vec<_>>();
-            let statements = variable_idents
+            let statements = fields
+                .named
                 .iter()
-                .map(|field_ident| {
-                    quote! {
-                        let #field_ident = match &locks.#field_ident {
-                            Some(x) => Some(x.lock().await),
-                            None => None,
-                        };
+                .zip(variable_idents.iter())
+                .map(|(field, field_ident)| {
+                    if is_snapshot(field) {
+                        quote!(let #field_ident = locks.#field_ident.clone();)
+                    } else {
+                        quote! {
+                            let #field_ident = match &locks.#field_ident {
+                                Some(x) => Some(x.lock_timed(stringify!(#field_ident)).await),
+                                None => None,
+                            };
+                        }
                     }
                 })
                 .collect::<Vec<_>>();
@@ -437,16 +644,22 @@ fn make_lock_to_handle_func(item: &ItemStruct) -> impl ToTokens {
                 .enumerate()
                 .map(|(idx, _)| Ident::new(format!("var_{}", idx).as_str(), Span::call_site()))
                 .collect::<Vec<_>>();
-            let statements = variable_idents
+            let statements = fields
+                .unnamed
                 .iter()
+                .zip(variable_idents.iter())
                 .enumerate()
-                .map(|(idx, var_name)| {
+                .map(|(idx, (field, var_name))| {
                     let field_idx = Index::from(idx);
-                    quote! {
-                        let #var_name = match &locks.#field_idx {
-                            Some(x) => Some(x.lock().await),
-                            None => None,
-                        };
+                    if is_snapshot(field) {
+                        quote!(let #var_name = locks.#field_idx.clone();)
+                    } else {
+                        quote! {
+                            let #var_name = match &locks.#field_idx {
+                                Some(x) => Some(x.lock_timed(stringify!(#field_idx)).await),
+                                None => None,
+                            };
+                        }
                     }
                 })
                 .collect::<Vec<_>>();
@@ -503,6 +716,10 @@ impl ServerStateFlags {
 /// `<Ident:snake_case>_handles_from_locks` will construct an `<Ident>Handles`
 /// type from a reference to `<Ident>Locks`
 ///
+/// Fields marked `#[snapshot]` are instead wrapped by
+/// `Arc<ruffd_types::Snapshot<T>>` in each struct, being read without
+/// locking
+///
 /// # Arguments
 ///
 /// Use `#[server_state(in_ruffd_types = true)]` for use inside the ruffd_types crate
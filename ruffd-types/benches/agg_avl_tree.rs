@@ -73,6 +73,18 @@ fn sparse_insert_max(bench: &mut Bencher) {
     bench.iter(|| tree.insert(next_insert.next().unwrap(), 1));
 }
 
+// Closures are inlined rather than called through a pointer, a weighted
+// sum standing in for aggregators carrying state
+fn sparse_insert_closure(bench: &mut Bencher) {
+    let weight = 3;
+    let mut tree = AggAvlTree::from_vec_with(
+        [1u64].into_iter().cycle().take(SIZE).collect::<Vec<_>>(),
+        move |a: &u64, b: &u64| *a + *b * weight,
+    );
+    let mut next_insert = create_sparse_iterator(SIZE);
+    bench.iter(|| tree.insert(next_insert.next().unwrap(), 1));
+}
+
 benchmark_group!(
     benches,
     iter_rng,
@@ -80,5 +92,6 @@ benchmark_group!(
     sparse_insert_add,
     same_insert_max,
     sparse_insert_max,
+    sparse_insert_closure,
 );
 benchmark_main!(benches);
@@ -0,0 +1,122 @@
+#[macro_use]
+extern crate bencher;
+
+use bencher::Bencher;
+use hex_literal::hex;
+use rand::rngs::SmallRng;
+use rand::{RngCore, SeedableRng};
+use ruffd_types::lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+use ruffd_types::{DocumentBuffer, PositionBounds, PositionEncoding};
+
+const PROGRAM_CHUNK: &str = r#"
+def main():
+    print('I am a small program')
+
+if __name__ == '__main__':
+    main()
+"#;
+const DOCUMENT_SIZE: usize = 1_000_000;
+const TYPED_LINE: &str = "    result = compute(first, second)";
+const SESSION_EDITS: usize = 200;
+
+fn create_rng() -> SmallRng {
+    SmallRng::from_seed(hex!(
+        "
+        DADADADA DADADADA DADADADA DADADADA
+        DADADADA DADADADA DADADADA DADADADA
+        "
+    ))
+}
+
+fn create_document() -> (DocumentBuffer, usize) {
+    let repeats = DOCUMENT_SIZE / PROGRAM_CHUNK.len() + 1;
+    let text = PROGRAM_CHUNK.repeat(repeats);
+    let rows = text.matches('\n').count();
+    (DocumentBuffer::from_string(text), rows)
+}
+
+fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+    TextDocumentContentChangeEvent {
+        range: Some(Range {
+            start: Position {
+                line: start.0,
+                character: start.1,
+            },
+            end: Position {
+                line: end.0,
+                character: end.1,
+            },
+        }),
+        range_length: None,
+        text: text.to_string(),
+    }
+}
+
+/// Records an editing session as a client sends it, with every keypress
+/// being a separate change
+///
+/// Each edit types a new line at a random row character by character, then
+/// removes it again with backspaces, such that the session leaves the
+/// document unchanged and can be replayed repeatedly
+fn record_typing_session(rows: usize) -> Vec<TextDocumentContentChangeEvent> {
+    let mut rng = create_rng();
+    let mut rv = vec![];
+    for _ in 0..SESSION_EDITS {
+        let row = (rng.next_u64() as usize % rows) as u32;
+        rv.push(change((row, 0), (row, 0), "\n"));
+        for (col, c) in TYPED_LINE.chars().enumerate() {
+            let col = col as u32;
+            rv.push(change((row, col), (row, col), c.to_string().as_str()));
+        }
+        for col in (0..TYPED_LINE.len() as u32).rev() {
+            rv.push(change((row, col), (row, col + 1), ""));
+        }
+        rv.push(change((row, 0), (row + 1, 0), ""));
+    }
+    rv
+}
+
+/// Records whole line edits such as cutting and pasting lines
+fn record_line_session(rows: usize) -> Vec<TextDocumentContentChangeEvent> {
+    let mut rng = create_rng();
+    let mut rv = vec![];
+    let lines = PROGRAM_CHUNK.lines().skip(1).collect::<Vec<_>>();
+    for _ in 0..SESSION_EDITS * 25 {
+        let row = (rng.next_u64() as usize % rows) as u32;
+        let line = lines[rng.next_u64() as usize % lines.len()];
+        let pasted = format!("{}\n", line);
+        rv.push(change((row, 0), (row, 0), pasted.as_str()));
+        rv.push(change((row, 0), (row + 1, 0), ""));
+    }
+    rv
+}
+
+fn replay(doc: &mut DocumentBuffer, session: &[TextDocumentContentChangeEvent]) {
+    for change in session {
+        doc.apply_content_change(change, PositionEncoding::Utf16, PositionBounds::Clamp)
+            .unwrap();
+    }
+}
+
+// To determine the time spent on required setup, document_create is provided
+fn document_create(bench: &mut Bencher) {
+    let text = PROGRAM_CHUNK.repeat(DOCUMENT_SIZE / PROGRAM_CHUNK.len() + 1);
+    bench.iter(|| DocumentBuffer::from_string(text.clone()));
+}
+
+fn typing_session(bench: &mut Bencher) {
+    let (mut doc, rows) = create_document();
+    let session = record_typing_session(rows);
+    assert!(session.len() >= 10_000);
+    bench.iter(|| replay(&mut doc, &session));
+}
+
+fn line_session(bench: &mut Bencher) {
+    let (mut doc, rows) = create_document();
+    let session = record_line_session(rows);
+    assert!(session.len() >= 10_000);
+    bench.iter(|| replay(&mut doc, &session));
+}
+
+benchmark_group!(benches, document_create, typing_session, line_session);
+benchmark_main!(benches);
@@ -9,6 +9,8 @@ use ruffd_types::collections::Rope;
 
 const TEST_STR: &str = "insert me";
 const ROPE_SIZE: usize = 1_000_000;
+/// Size of a typical source file, held without a tree
+const SMALL_SIZE: usize = 2_000;
 
 fn create_sparse_iterator(size: usize) -> impl Iterator<Item = usize> {
     let mut rng = SmallRng::from_seed(hex!(
@@ -68,6 +70,84 @@ fn sparse_delete(bench: &mut Bencher) {
     });
 }
 
+// Deleting a region then typing at the deletion point, as when replacing
+// a selection
+fn delete_insert_hotspot(bench: &mut Bencher) {
+    let chars = "a".chars().cycle().take(ROPE_SIZE).collect::<Vec<_>>();
+    let mut doc = Rope::from_document(chars);
+    let insert_str = TEST_STR.chars().collect::<Vec<_>>();
+    bench.iter(|| {
+        doc.delete(500_000..500_000 + TEST_STR.len());
+        for idx in 0..insert_str.len() {
+            doc.insert(insert_str[idx..=idx].to_vec(), 500_000 + idx)
+                .unwrap();
+        }
+    });
+}
+
+fn sparse_delete_insert(bench: &mut Bencher) {
+    let chars = "a".chars().cycle().take(ROPE_SIZE).collect::<Vec<_>>();
+    let mut doc = Rope::from_document(chars);
+    let insert_str = TEST_STR.chars().collect::<Vec<_>>();
+    let mut next_idx = create_sparse_iterator(ROPE_SIZE - TEST_STR.len());
+    bench.iter(|| {
+        let start = next_idx.next().unwrap();
+        doc.delete(start..start + TEST_STR.len());
+        doc.insert(insert_str.clone(), start).unwrap();
+    });
+}
+
+// Pathological case for splaying, the accessed index alternates between
+// the extremes of the rope
+fn alternating_front_back_insert(bench: &mut Bencher) {
+    let chars = "a".chars().cycle().take(ROPE_SIZE).collect::<Vec<_>>();
+    let mut doc = Rope::from_document(chars);
+    let insert_str = TEST_STR.chars().collect::<Vec<_>>();
+    let mut at_front = false;
+    let mut max_depth = 0;
+    bench.iter(|| {
+        at_front = !at_front;
+        let idx = if at_front { 0 } else { doc.len() };
+        doc.insert(insert_str.clone(), idx).unwrap();
+        max_depth = max_depth.max(doc.depth());
+    });
+    assert!(max_depth <= Rope::<char>::max_depth(doc.len()));
+}
+
+// Typing into a file small enough to be edited as a single vector
+fn small_delete_insert(bench: &mut Bencher) {
+    let chars = "a".chars().cycle().take(SMALL_SIZE).collect::<Vec<_>>();
+    let mut doc = Rope::from_document(chars);
+    let insert_str = TEST_STR.chars().collect::<Vec<_>>();
+    let mut next_idx = create_sparse_iterator(SMALL_SIZE - TEST_STR.len());
+    bench.iter(|| {
+        let start = next_idx.next().unwrap();
+        doc.delete(start..start + TEST_STR.len());
+        doc.insert(insert_str.clone(), start).unwrap();
+    });
+}
+
+fn iter_fresh(bench: &mut Bencher) {
+    let chars = "a".chars().cycle().take(ROPE_SIZE).collect::<Vec<_>>();
+    let doc = Rope::from_document(chars);
+    bench.iter(|| doc.iter().count());
+}
+
+// Iterating a document after edits across it, as after a refactor, which
+// split and shrink leaves until the rope is compacted
+fn iter_after_edits(bench: &mut Bencher) {
+    let chars = "a".chars().cycle().take(ROPE_SIZE).collect::<Vec<_>>();
+    let mut doc = Rope::from_document(chars);
+    let insert_str = TEST_STR.chars().collect::<Vec<_>>();
+    let mut next_idx = create_sparse_iterator(ROPE_SIZE / 2);
+    while doc.len() > ROPE_SIZE / 2 {
+        let start = next_idx.next().unwrap();
+        doc.delete(start..start + 4 * TEST_STR.len());
+        doc.insert(insert_str.clone(), start).unwrap();
+    }
+    bench.iter(|| doc.iter().count());
+}
+
 benchmark_group!(
     benches,
     string_clone,
@@ -75,6 +155,12 @@ benchmark_group!(
     same_insert,
     sparse_insert,
     same_delete,
-    sparse_delete
+    sparse_delete,
+    delete_insert_hotspot,
+    sparse_delete_insert,
+    alternating_front_back_insert,
+    small_delete_insert,
+    iter_fresh,
+    iter_after_edits
 );
 benchmark_main!(benches);
@@ -0,0 +1,22 @@
+//! Exposes the version of ruff pinned in the manifest as `RUFF_VERSION`,
+//! such that it's reported as built rather than restated by hand, and
+//! declares the `tokio_unstable` cfg set when building for tokio-console
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let manifest_path = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.toml");
+    println!("cargo:rerun-if-changed={}", manifest_path.display());
+    let manifest: toml::Value = fs::read_to_string(&manifest_path).unwrap().parse().unwrap();
+    // versions 0.0.x are only compatible with themselves, so the requirement
+    // is the version resolved
+    let version = manifest["dependencies"]["ruff"]["version"]
+        .as_str()
+        .expect("ruff is a dependency with a version")
+        .trim_start_matches(['=', '^']);
+    println!("cargo:rustc-env=RUFF_VERSION={}", version);
+    // a `[lints]` table would require a newer cargo than the MSRV, whereas
+    // cargo versions predating checked cfgs ignore this
+    println!("cargo:rustc-check-cfg=cfg(tokio_unstable)");
+}
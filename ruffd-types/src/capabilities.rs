@@ -0,0 +1,465 @@
+//! Construction of the server capabilities advertised on initialization
+//!
+//! Capabilities are derived from the client's capabilities and the server
+//! settings in effect at initialization, such that nothing is advertised
+//! which the client can't use or the user has disabled
+use crate::config::{FixOnSave, ServerConfig};
+use crate::extensions::{FIX_ALL_COMMAND, PREVIEW_FIX_COMMAND, SELECT_PROFILE_COMMAND};
+use crate::notebook::{
+    NotebookCellSelector, NotebookDocumentSyncOptions, NotebookFilter, NotebookSelector,
+    JUPYTER_NOTEBOOK_TYPE,
+};
+
+/// Determines whether the client accepts progress created by the server
+/// through `window/workDoneProgress/create`
+pub fn supports_work_done_progress(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .window
+        .as_ref()
+        .and_then(|x| x.work_done_progress)
+        .unwrap_or(false)
+}
+
+/// Determines whether the client can resolve the edits of code actions
+/// lazily through `codeAction/resolve`
+pub fn supports_edit_resolve(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .text_document
+        .as_ref()
+        .and_then(|x| x.code_action.as_ref())
+        .and_then(|x| x.resolve_support.as_ref())
+        .map(|x| x.properties.iter().any(|property| property == "edit"))
+        .unwrap_or(false)
+}
+
+/// Determines whether the client accepts code action literals, as opposed
+/// to only commands
+fn supports_code_action_literals(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .text_document
+        .as_ref()
+        .and_then(|x| x.code_action.as_ref())
+        .and_then(|x| x.code_action_literal_support.as_ref())
+        .is_some()
+}
+
+/// Determines whether the client applies edits sent through
+/// `workspace/applyEdit`
+pub fn supports_apply_edit(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|x| x.apply_edit)
+        .unwrap_or(false)
+}
+
+/// Determines whether the client accepts versioned `documentChanges` in
+/// workspace edits, rejecting edits of documents changed since
+pub fn supports_document_changes(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|x| x.workspace_edit.as_ref())
+        .and_then(|x| x.document_changes)
+        .unwrap_or(false)
+}
+
+/// Determines whether the client opens documents requested through
+/// `window/showDocument`
+pub fn supports_show_document(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .window
+        .as_ref()
+        .and_then(|x| x.show_document.as_ref())
+        .map(|x| x.support)
+        .unwrap_or(false)
+}
+
+/// Determines whether the client registers file watchers on behalf of the
+/// server, notifying it through `workspace/didChangeWatchedFiles`
+pub fn supports_watched_files_registration(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|x| x.did_change_watched_files.as_ref())
+        .and_then(|x| x.dynamic_registration)
+        .unwrap_or(false)
+}
+
+/// Determines whether the client pulls diagnostics of every document again
+/// once sent `workspace/diagnostic/refresh`
+pub fn supports_diagnostic_refresh(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|x| x.diagnostic.as_ref())
+        .and_then(|x| x.refresh_support)
+        .unwrap_or(false)
+}
+
+fn supports_will_save(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .text_document
+        .as_ref()
+        .and_then(|x| x.synchronization.as_ref())
+        .and_then(|x| x.will_save)
+        .unwrap_or(false)
+}
+
+fn supports_will_save_wait_until(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .text_document
+        .as_ref()
+        .and_then(|x| x.synchronization.as_ref())
+        .and_then(|x| x.will_save_wait_until)
+        .unwrap_or(false)
+}
+
+fn supports_pull_diagnostics(capabilities: &lsp_types::ClientCapabilities) -> bool {
+    capabilities
+        .text_document
+        .as_ref()
+        .and_then(|x| x.diagnostic.as_ref())
+        .is_some()
+}
+
+fn file_operations(
+    capabilities: &lsp_types::ClientCapabilities,
+) -> Option<&lsp_types::WorkspaceFileOperationsClientCapabilities> {
+    capabilities
+        .workspace
+        .as_ref()
+        .and_then(|x| x.file_operations.as_ref())
+}
+
+fn rename_registration() -> lsp_types::FileOperationRegistrationOptions {
+    lsp_types::FileOperationRegistrationOptions {
+        filters: vec![
+            lsp_types::FileOperationFilter {
+                scheme: Some("file".to_string()),
+                pattern: lsp_types::FileOperationPattern {
+                    glob: "**/*.{py,pyi}".to_string(),
+                    matches: Some(lsp_types::FileOperationPatternKind::File),
+                    options: None,
+                },
+            },
+            lsp_types::FileOperationFilter {
+                scheme: Some("file".to_string()),
+                pattern: lsp_types::FileOperationPattern {
+                    glob: "**".to_string(),
+                    matches: Some(lsp_types::FileOperationPatternKind::Folder),
+                    options: None,
+                },
+            },
+        ],
+    }
+}
+
+fn code_action_provider(
+    config: &ServerConfig,
+    client_capabilities: &lsp_types::ClientCapabilities,
+) -> Option<lsp_types::CodeActionProviderCapability> {
+    // quick fixes are only offered as code action literals
+    if !config.code_actions || !supports_code_action_literals(client_capabilities) {
+        return None;
+    }
+    let resolve_provider = supports_edit_resolve(client_capabilities).then_some(true);
+    Some(lsp_types::CodeActionProviderCapability::Options(
+        lsp_types::CodeActionOptions {
+            code_action_kinds: Some(vec![lsp_types::CodeActionKind::QUICKFIX]),
+            work_done_progress_options: lsp_types::WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+            resolve_provider,
+        },
+    ))
+}
+
+fn diagnostic_provider(
+    config: &ServerConfig,
+    client_capabilities: &lsp_types::ClientCapabilities,
+) -> Option<lsp_types::DiagnosticServerCapabilities> {
+    if !config.pull_diagnostics || !supports_pull_diagnostics(client_capabilities) {
+        return None;
+    }
+    Some(lsp_types::DiagnosticServerCapabilities::Options(
+        lsp_types::DiagnosticOptions {
+            identifier: Some("ruff".to_string()),
+            // checks of a document depend only on its content and settings
+            inter_file_dependencies: false,
+            workspace_diagnostics: false,
+            work_done_progress_options: lsp_types::WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+        },
+    ))
+}
+
+fn workspace_capabilities(
+    client_capabilities: &lsp_types::ClientCapabilities,
+) -> Option<lsp_types::WorkspaceServerCapabilities> {
+    let file_operations = file_operations(client_capabilities)?;
+    let did_rename = file_operations
+        .did_rename
+        .unwrap_or(false)
+        .then(rename_registration);
+    let will_rename = file_operations
+        .will_rename
+        .unwrap_or(false)
+        .then(rename_registration);
+    if did_rename.is_none() && will_rename.is_none() {
+        return None;
+    }
+    Some(lsp_types::WorkspaceServerCapabilities {
+        workspace_folders: None,
+        file_operations: Some(lsp_types::WorkspaceFileOperationsServerCapabilities {
+            did_rename,
+            will_rename,
+            ..Default::default()
+        }),
+    })
+}
+
+/// Builds the capabilities to advertise given the client's capabilities and
+/// the server settings at initialization
+///
+/// Settings changed after initialization don't alter the capabilities
+pub fn server_capabilities(
+    config: &ServerConfig,
+    client_capabilities: &lsp_types::ClientCapabilities,
+) -> lsp_types::ServerCapabilities {
+    lsp_types::ServerCapabilities {
+        text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Options(
+            lsp_types::TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(lsp_types::TextDocumentSyncKind::INCREMENTAL),
+                will_save: supports_will_save(client_capabilities).then_some(true),
+                will_save_wait_until: (config.fix_on_save == FixOnSave::WillSaveWaitUntil
+                    && supports_will_save_wait_until(client_capabilities))
+                .then_some(true),
+                save: Some(lsp_types::TextDocumentSyncSaveOptions::Supported(true)),
+            },
+        )),
+        code_action_provider: code_action_provider(config, client_capabilities),
+        diagnostic_provider: diagnostic_provider(config, client_capabilities),
+        workspace: workspace_capabilities(client_capabilities),
+        workspace_symbol_provider: Some(lsp_types::OneOf::Left(true)),
+        document_highlight_provider: Some(lsp_types::OneOf::Left(true)),
+        references_provider: Some(lsp_types::OneOf::Left(true)),
+        call_hierarchy_provider: config
+            .call_hierarchy
+            .then_some(lsp_types::CallHierarchyServerCapability::Simple(true)),
+        signature_help_provider: Some(lsp_types::SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+            retrigger_characters: None,
+            work_done_progress_options: Default::default(),
+        }),
+        execute_command_provider: Some(lsp_types::ExecuteCommandOptions {
+            commands: commands(client_capabilities),
+            work_done_progress_options: lsp_types::WorkDoneProgressOptions {
+                work_done_progress: None,
+            },
+        }),
+        ..Default::default()
+    }
+}
+
+/// Commands run through `workspace/executeCommand`, fixing all only being
+/// possible where the client applies edits sent by the server
+fn commands(client_capabilities: &lsp_types::ClientCapabilities) -> Vec<String> {
+    let mut rv = vec![
+        PREVIEW_FIX_COMMAND.to_string(),
+        SELECT_PROFILE_COMMAND.to_string(),
+    ];
+    if supports_apply_edit(client_capabilities) {
+        rv.push(FIX_ALL_COMMAND.to_string());
+    }
+    rv
+}
+
+/// Builds the `notebookDocumentSync` capability, syncing the python cells
+/// of jupyter notebooks
+///
+/// Kept apart from `server_capabilities` as `lsp_types` has no field for it
+pub fn notebook_document_sync() -> NotebookDocumentSyncOptions {
+    NotebookDocumentSyncOptions {
+        notebook_selector: vec![NotebookSelector {
+            notebook: NotebookFilter {
+                notebook_type: JUPYTER_NOTEBOOK_TYPE.to_string(),
+            },
+            cells: vec![NotebookCellSelector {
+                language: "python".to_string(),
+            }],
+        }],
+        save: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn full_client_capabilities() -> lsp_types::ClientCapabilities {
+        serde_json::from_value(serde_json::json!({
+            "workspace": {
+                "applyEdit": true,
+                "fileOperations": {"didRename": true, "willRename": true}
+            },
+            "textDocument": {
+                "synchronization": {
+                    "willSave": true,
+                    "willSaveWaitUntil": true,
+                    "didSave": true
+                },
+                "diagnostic": {"dynamicRegistration": false},
+                "codeAction": {
+                    "codeActionLiteralSupport": {
+                        "codeActionKind": {"valueSet": ["quickfix"]}
+                    },
+                    "resolveSupport": {"properties": ["edit"]}
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn code_action_options(
+        capabilities: &lsp_types::ServerCapabilities,
+    ) -> Option<&lsp_types::CodeActionOptions> {
+        match capabilities.code_action_provider.as_ref()? {
+            lsp_types::CodeActionProviderCapability::Options(x) => Some(x),
+            lsp_types::CodeActionProviderCapability::Simple(_) => None,
+        }
+    }
+
+    fn sync_options(
+        capabilities: &lsp_types::ServerCapabilities,
+    ) -> &lsp_types::TextDocumentSyncOptions {
+        match capabilities.text_document_sync.as_ref().unwrap() {
+            lsp_types::TextDocumentSyncCapability::Options(x) => x,
+            lsp_types::TextDocumentSyncCapability::Kind(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_full_client() {
+        let capabilities =
+            server_capabilities(&ServerConfig::default(), &full_client_capabilities());
+        let code_actions = code_action_options(&capabilities).unwrap();
+        assert_eq!(code_actions.resolve_provider, Some(true));
+        assert_eq!(sync_options(&capabilities).will_save, Some(true));
+        // edits are only returned at save when configured to
+        assert_eq!(sync_options(&capabilities).will_save_wait_until, None);
+        let config = ServerConfig {
+            fix_on_save: FixOnSave::WillSaveWaitUntil,
+            ..Default::default()
+        };
+        let fixing = server_capabilities(&config, &full_client_capabilities());
+        assert_eq!(sync_options(&fixing).will_save_wait_until, Some(true));
+        assert_eq!(
+            capabilities.execute_command_provider.unwrap().commands,
+            vec![
+                PREVIEW_FIX_COMMAND.to_string(),
+                SELECT_PROFILE_COMMAND.to_string(),
+                FIX_ALL_COMMAND.to_string()
+            ]
+        );
+        let file_operations = capabilities
+            .workspace
+            .and_then(|x| x.file_operations)
+            .unwrap();
+        assert!(file_operations.did_rename.is_some());
+        assert!(file_operations.will_rename.is_some());
+    }
+
+    #[test]
+    fn test_minimal_client() {
+        let capabilities = server_capabilities(
+            &ServerConfig::default(),
+            &lsp_types::ClientCapabilities::default(),
+        );
+        assert!(capabilities.code_action_provider.is_none());
+        assert_eq!(
+            capabilities
+                .execute_command_provider
+                .as_ref()
+                .unwrap()
+                .commands,
+            vec![
+                PREVIEW_FIX_COMMAND.to_string(),
+                SELECT_PROFILE_COMMAND.to_string()
+            ]
+        );
+        assert!(capabilities.workspace.is_none());
+        let sync = sync_options(&capabilities);
+        assert_eq!(sync.will_save, None);
+        assert_eq!(
+            sync.change,
+            Some(lsp_types::TextDocumentSyncKind::INCREMENTAL)
+        );
+    }
+
+    #[test]
+    fn test_partial_client() {
+        let client_capabilities = serde_json::from_value(serde_json::json!({
+            "workspace": {"fileOperations": {"didRename": true}},
+            "textDocument": {
+                "codeAction": {
+                    "codeActionLiteralSupport": {
+                        "codeActionKind": {"valueSet": ["quickfix"]}
+                    },
+                    "resolveSupport": {"properties": ["command"]}
+                }
+            }
+        }))
+        .unwrap();
+        let capabilities = server_capabilities(&ServerConfig::default(), &client_capabilities);
+        // edits are computed eagerly when they can't be resolved
+        let code_actions = code_action_options(&capabilities).unwrap();
+        assert_eq!(code_actions.resolve_provider, None);
+        let file_operations = capabilities
+            .workspace
+            .and_then(|x| x.file_operations)
+            .unwrap();
+        assert!(file_operations.did_rename.is_some());
+        assert!(file_operations.will_rename.is_none());
+    }
+
+    #[test]
+    fn test_code_actions_disabled() {
+        let config = ServerConfig {
+            code_actions: false,
+            ..Default::default()
+        };
+        let capabilities = server_capabilities(&config, &full_client_capabilities());
+        assert!(capabilities.code_action_provider.is_none());
+    }
+
+    #[test]
+    fn test_call_hierarchy() {
+        let capabilities =
+            server_capabilities(&ServerConfig::default(), &full_client_capabilities());
+        assert!(capabilities.call_hierarchy_provider.is_none());
+        let config = ServerConfig {
+            call_hierarchy: true,
+            ..Default::default()
+        };
+        let capabilities = server_capabilities(&config, &full_client_capabilities());
+        assert!(capabilities.call_hierarchy_provider.is_some());
+    }
+
+    #[test]
+    fn test_pull_diagnostics() {
+        let capabilities =
+            server_capabilities(&ServerConfig::default(), &full_client_capabilities());
+        assert!(capabilities.diagnostic_provider.is_none());
+        let config = ServerConfig {
+            pull_diagnostics: true,
+            ..Default::default()
+        };
+        let capabilities = server_capabilities(&config, &full_client_capabilities());
+        assert!(capabilities.diagnostic_provider.is_some());
+        let capabilities = server_capabilities(&config, &lsp_types::ClientCapabilities::default());
+        assert!(capabilities.diagnostic_provider.is_none());
+    }
+}
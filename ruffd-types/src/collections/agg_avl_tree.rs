@@ -1,6 +1,9 @@
 use crate::error::AggAvlTreeError;
+use std::fmt::{self, Debug, Write};
+use std::ops::{Bound, RangeBounds};
 
-type AggFn<T> = fn(&T, &T) -> T;
+/// Aggregator of a tree unless given otherwise, see [`AggAvlTree`]
+pub type AggFn<T> = fn(&T, &T) -> T;
 
 struct ChildNode<T> {
     /// Option for ease of swapping values without a default
@@ -25,7 +28,11 @@ where
     /// Requires both left and right nodes to be defined
     ///
     /// Use case for child node is to group 2 leaf nodes, or recursive children
-    pub fn new(left: Box<TreeNode<T>>, right: Box<TreeNode<T>>, agg_fn: AggFn<T>) -> Self {
+    pub fn new<F: Fn(&T, &T) -> T>(
+        left: Box<TreeNode<T>>,
+        right: Box<TreeNode<T>>,
+        agg_fn: &F,
+    ) -> Self {
         let left = Some(left);
         let right = Some(right);
         let agg = Self::calc_agg(&left, &right, agg_fn);
@@ -40,10 +47,10 @@ where
         rv
     }
 
-    fn calc_agg(
+    fn calc_agg<F: Fn(&T, &T) -> T>(
         left: &Option<Box<TreeNode<T>>>,
         right: &Option<Box<TreeNode<T>>>,
-        agg_fn: AggFn<T>,
+        agg_fn: &F,
     ) -> T {
         match left {
             Some(x) => {
@@ -60,7 +67,7 @@ where
         }
     }
 
-    fn update_agg(&mut self, agg_fn: AggFn<T>) {
+    fn update_agg<F: Fn(&T, &T) -> T>(&mut self, agg_fn: &F) {
         self.agg = Self::calc_agg(&self.left, &self.right, agg_fn);
     }
 
@@ -99,7 +106,7 @@ where
     ///
     /// **Must** call this method on mutation of left or right
     /// values
-    pub fn update_node(&mut self, agg_fn: AggFn<T>) {
+    pub fn update_node<F: Fn(&T, &T) -> T>(&mut self, agg_fn: &F) {
         self.update_agg(agg_fn);
         self.update_height();
         self.update_elem_count();
@@ -146,7 +153,7 @@ impl<T> TreeNode<T>
 where
     T: Clone,
 {
-    pub fn get_range<R>(&self, range: R, agg_fn: AggFn<T>) -> Option<T>
+    pub fn get_range<R, F: Fn(&T, &T) -> T>(&self, range: R, agg_fn: &F) -> Option<T>
     where
         R: std::ops::RangeBounds<usize>,
     {
@@ -228,7 +235,7 @@ where
     /// ```
     ///
     /// WARNING should only be reached via `self.balance`
-    fn balance_ll(mut old_root: ChildNode<T>, agg_fn: AggFn<T>) -> Self {
+    fn balance_ll<F: Fn(&T, &T) -> T>(mut old_root: ChildNode<T>, agg_fn: &F) -> Self {
         let mut rv = match *old_root.left.take().unwrap() {
             Self::Child(x) => x,
             _ => unreachable!(),
@@ -247,7 +254,7 @@ where
     /// ```
     ///
     /// WARNING should only be reached via `self.balance`
-    fn balance_lr(mut old_root: ChildNode<T>, agg_fn: AggFn<T>) -> Self {
+    fn balance_lr<F: Fn(&T, &T) -> T>(mut old_root: ChildNode<T>, agg_fn: &F) -> Self {
         let mut old_left = match *old_root.left.take().unwrap() {
             Self::Child(x) => x,
             _ => unreachable!(),
@@ -273,7 +280,7 @@ where
     /// ```
     ///
     /// WARNING should only be reached via `self.balance`
-    fn balance_rl(mut old_root: ChildNode<T>, agg_fn: AggFn<T>) -> Self {
+    fn balance_rl<F: Fn(&T, &T) -> T>(mut old_root: ChildNode<T>, agg_fn: &F) -> Self {
         let mut old_right = match *old_root.right.take().unwrap() {
             Self::Child(x) => x,
             _ => unreachable!(),
@@ -299,7 +306,7 @@ where
     /// ```
     ///
     /// WARNING should only be reached via `self.balance`
-    fn balance_rr(mut old_root: ChildNode<T>, agg_fn: AggFn<T>) -> Self {
+    fn balance_rr<F: Fn(&T, &T) -> T>(mut old_root: ChildNode<T>, agg_fn: &F) -> Self {
         let mut rv = match *old_root.right.take().unwrap() {
             Self::Child(x) => x,
             _ => unreachable!(),
@@ -311,7 +318,7 @@ where
         Self::Child(rv)
     }
 
-    fn balance(self, agg_fn: AggFn<T>) -> Self {
+    fn balance<F: Fn(&T, &T) -> T>(self, agg_fn: &F) -> Self {
         let node = match self {
             Self::Child(node) => node,
             Self::Leaf(node) => return Self::Leaf(node),
@@ -349,6 +356,76 @@ where
         }
     }
 
+    /// Appends the node and its descendants to a graphviz digraph body,
+    /// returning the id assigned to this node
+    fn write_dot(&self, out: &mut String, next_id: &mut usize) -> usize
+    where
+        T: Debug,
+    {
+        let id = *next_id;
+        *next_id += 1;
+        match self {
+            Self::Leaf(x) => {
+                writeln!(out, "    n{} [shape=box, label=\"{:?}\"];", id, x.val).unwrap();
+            }
+            Self::Child(x) => {
+                let balance =
+                    x.get_left_height().unwrap_or(-1) - x.get_right_height().unwrap_or(-1);
+                writeln!(
+                    out,
+                    "    n{} [label=\"agg={:?}\\nheight={} count={} balance={}\"];",
+                    id, x.agg, x.child_height, x.elem_count, balance
+                )
+                .unwrap();
+                for child in [&x.left, &x.right].into_iter().flatten() {
+                    let child_id = child.write_dot(out, next_id);
+                    writeln!(out, "    n{} -> n{};", id, child_id).unwrap();
+                }
+            }
+        }
+        id
+    }
+
+    /// Recursively checks node invariants, returning the height, element
+    /// count and aggregate of the node
+    #[cfg(any(test, debug_assertions))]
+    fn validate<F: Fn(&T, &T) -> T>(
+        &self,
+        agg_fn: &F,
+    ) -> Result<(Option<i64>, usize, T), AggAvlTreeError>
+    where
+        T: PartialEq,
+    {
+        let node = match self {
+            Self::Leaf(x) => return Ok((None, 1, x.val.clone())),
+            Self::Child(x) => x,
+        };
+        let (left, right) = match (&node.left, &node.right) {
+            (Some(left), Some(right)) => (left, right),
+            _ => {
+                return Err(AggAvlTreeError::InvariantViolation(
+                    "child node missing a child",
+                ))
+            }
+        };
+        let (left_height, left_count, left_agg) = left.validate(agg_fn)?;
+        let (right_height, right_count, right_agg) = right.validate(agg_fn)?;
+        let expected_height = left_height.max(right_height).map_or(0, |x| x + 1);
+        if node.child_height != expected_height {
+            return Err(AggAvlTreeError::InvariantViolation("stale height"));
+        }
+        if (left_height.unwrap_or(-1) - right_height.unwrap_or(-1)).abs() >= 2 {
+            return Err(AggAvlTreeError::InvariantViolation("unbalanced node"));
+        }
+        if node.elem_count != left_count + right_count {
+            return Err(AggAvlTreeError::InvariantViolation("stale element count"));
+        }
+        if node.agg != agg_fn(&left_agg, &right_agg) {
+            return Err(AggAvlTreeError::InvariantViolation("stale aggregate"));
+        }
+        Ok((Some(node.child_height), node.elem_count, node.agg.clone()))
+    }
+
     fn get_agg(&self) -> &T {
         match self {
             Self::Child(x) => &x.agg,
@@ -356,7 +433,7 @@ where
         }
     }
 
-    pub fn insert(self, idx: usize, val: T, agg_fn: AggFn<T>) -> Self {
+    pub fn insert<F: Fn(&T, &T) -> T>(self, idx: usize, val: T, agg_fn: &F) -> Self {
         let rv = match self {
             Self::Leaf(x) => {
                 let tp_node = Box::new(Self::Leaf(LeafNode::new(val)));
@@ -389,7 +466,12 @@ where
         rv.balance(agg_fn)
     }
 
-    pub fn update(&mut self, idx: usize, val: T, agg_fn: AggFn<T>) -> Result<(), AggAvlTreeError> {
+    pub fn update<F: Fn(&T, &T) -> T>(
+        &mut self,
+        idx: usize,
+        val: T,
+        agg_fn: &F,
+    ) -> Result<(), AggAvlTreeError> {
         match self {
             Self::Child(x) => {
                 let mid_idx = x.get_left_elem_count();
@@ -418,7 +500,7 @@ where
     ///
     /// Panics if index out of bounds as short circuiting this can break
     /// the structure
-    pub fn delete(self, idx: usize, agg_fn: AggFn<T>) -> Option<Self> {
+    pub fn delete<F: Fn(&T, &T) -> T>(self, idx: usize, agg_fn: &F) -> Option<Self> {
         match self {
             Self::Child(x) => {
                 let mid_idx = x.get_left_elem_count();
@@ -459,9 +541,16 @@ where
 ///
 /// use `from_vec` for linear time construction, otherwise
 /// inserting each node leads to O(n*log_2(n)) insertion
-pub struct AggAvlTree<T> {
+///
+/// Elements are aggregated by `accumulate`, being any associative function
+/// of a pair of elements, such that aggregators may carry state. It
+/// defaults to a fn pointer, such that the type of a tree can be named
+/// without naming that of a closure. `new` and `from_vec` build trees
+/// aggregated by fn pointers, `with_accumulate` and `from_vec_with` those
+/// aggregated by closures
+pub struct AggAvlTree<T, F = AggFn<T>> {
     root: Option<TreeNode<T>>,
-    accumulate: AggFn<T>,
+    accumulate: F,
 }
 
 impl<T> AggAvlTree<T>
@@ -469,15 +558,29 @@ where
     T: Clone,
 {
     pub fn new(accumulate: AggFn<T>) -> Self {
+        Self::with_accumulate(accumulate)
+    }
+
+    pub fn from_vec(elems: Vec<T>, accumulate: AggFn<T>) -> Self {
+        Self::from_vec_with(elems, accumulate)
+    }
+}
+
+impl<T, F> AggAvlTree<T, F>
+where
+    T: Clone,
+    F: Fn(&T, &T) -> T,
+{
+    pub fn with_accumulate(accumulate: F) -> Self {
         Self {
             root: None,
             accumulate,
         }
     }
 
-    pub fn from_vec(elems: Vec<T>, accumulate: fn(&T, &T) -> T) -> Self {
+    pub fn from_vec_with(elems: Vec<T>, accumulate: F) -> Self {
         // TODO build bottom up balanced bst inplace
-        let mut rv = Self::new(accumulate);
+        let mut rv = Self::with_accumulate(accumulate);
         elems.into_iter().for_each(|x| rv.insert_back(x));
         rv
     }
@@ -495,7 +598,7 @@ where
         R: std::ops::RangeBounds<usize>,
     {
         match &self.root {
-            Some(root) => root.get_range(range, self.accumulate),
+            Some(root) => root.get_range(range, &self.accumulate),
             None => None,
         }
     }
@@ -505,7 +608,7 @@ where
     /// if the index is larger than the element count, insert at the back
     pub fn insert(&mut self, idx: usize, val: T) {
         if let Some(root) = self.root.take() {
-            self.root = Some(root.insert(idx, val, self.accumulate));
+            self.root = Some(root.insert(idx, val, &self.accumulate));
         } else {
             self.root = Some(TreeNode::Leaf(LeafNode::new(val)));
         }
@@ -525,7 +628,7 @@ where
 
     pub fn update(&mut self, idx: usize, val: T) -> Result<(), AggAvlTreeError> {
         match &mut self.root {
-            Some(x) => x.update(idx, val, self.accumulate),
+            Some(x) => x.update(idx, val, &self.accumulate),
             None => Err(AggAvlTreeError::IndexOutOfBounds),
         }
     }
@@ -543,7 +646,7 @@ where
         };
         if result.is_ok() {
             self.root = match self.root.take() {
-                Some(x) => x.delete(idx, self.accumulate),
+                Some(x) => x.delete(idx, &self.accumulate),
                 None => None,
             };
         }
@@ -554,6 +657,81 @@ where
         self.root.is_none()
     }
 
+    /// Verifies AVL balance along with the height, element count and
+    /// aggregate held at every node
+    ///
+    /// This is a linear time check intended for catching corruption in tests
+    /// and debug builds
+    #[cfg(any(test, debug_assertions))]
+    pub fn validate(&self) -> Result<(), AggAvlTreeError>
+    where
+        T: PartialEq,
+    {
+        match &self.root {
+            Some(root) => root.validate(&self.accumulate).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Iterates elements in index order
+    pub fn iter(&self) -> AggAvlTreeIter<'_, T> {
+        self.iter_range(..)
+    }
+
+    /// Iterates elements within the index range in index order
+    pub fn iter_range<R>(&self, range: R) -> AggAvlTreeIter<'_, T>
+    where
+        R: RangeBounds<usize>,
+    {
+        let len = self.len();
+        let start = match range.start_bound() {
+            Bound::Included(x) => *x,
+            Bound::Excluded(x) => x.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(x) => x.saturating_add(1),
+            Bound::Excluded(x) => *x,
+            Bound::Unbounded => len,
+        }
+        .min(len);
+        let mut stack = vec![];
+        let mut curr = self.root.as_ref().filter(|_| start < end);
+        let mut idx = start;
+        // descend to the first element, deferring right subtrees
+        while let Some(TreeNode::Child(x)) = curr {
+            let mid_idx = x.get_left_elem_count();
+            if idx < mid_idx {
+                stack.push(x.right.as_deref().unwrap());
+                curr = x.left.as_deref();
+            } else {
+                idx -= mid_idx;
+                curr = x.right.as_deref();
+            }
+        }
+        if let Some(leaf) = curr {
+            stack.push(leaf);
+        }
+        AggAvlTreeIter {
+            stack,
+            remaining: end.saturating_sub(start),
+        }
+    }
+
+    /// Renders the tree structure in graphviz dot format, labelling internal
+    /// nodes with their aggregate, height, element count and balance factor
+    pub fn to_dot(&self) -> String
+    where
+        T: Debug,
+    {
+        let mut rv = String::from("digraph AggAvlTree {\n");
+        if let Some(root) = &self.root {
+            root.write_dot(&mut rv, &mut 0);
+        }
+        rv.push_str("}\n");
+        rv
+    }
+
     pub fn len(&self) -> usize {
         match &self.root {
             None => 0,
@@ -562,6 +740,55 @@ where
     }
 }
 
+impl<T, F> Debug for AggAvlTree<T, F>
+where
+    T: Clone + Debug,
+    F: Fn(&T, &T) -> T,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AggAvlTree")
+            .field("height", &self.root.as_ref().and_then(|x| x.get_height()))
+            .field("agg", &self.get_range(..))
+            .field("elems", &self.iter().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Iterator over the elements of an `AggAvlTree` in index order, as
+/// returned by `iter` and `iter_range`
+pub struct AggAvlTreeIter<'a, T> {
+    /// Subtrees yet to be visited, the next subtree being last
+    stack: Vec<&'a TreeNode<T>>,
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for AggAvlTreeIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut curr = self.stack.pop()?;
+        loop {
+            match curr {
+                TreeNode::Leaf(x) => {
+                    self.remaining -= 1;
+                    return Some(&x.val);
+                }
+                TreeNode::Child(x) => {
+                    self.stack.push(x.right.as_deref().unwrap());
+                    curr = x.left.as_deref().unwrap();
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -604,6 +831,117 @@ mod test {
         assert_eq!(result, 9 + 2);
     }
 
+    #[test]
+    fn test_iter() {
+        let nums = (0..100).into_iter().collect::<Vec<_>>();
+        let mut tree = AggAvlTree::from_vec(nums.clone(), agg_add);
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), nums);
+        tree.delete(3).unwrap();
+        tree.insert(50, 1000);
+        let mut expected = nums;
+        expected.remove(3);
+        expected.insert(50, 1000);
+        assert_eq!(tree.iter().cloned().collect::<Vec<_>>(), expected);
+        assert_eq!(AggAvlTree::<i32>::new(agg_add).iter().count(), 0);
+    }
+
+    #[test]
+    fn test_iter_range() {
+        let nums = (0..100).into_iter().collect::<Vec<_>>();
+        let tree = AggAvlTree::from_vec(nums.clone(), agg_add);
+        let ranges = vec![(2, 5), (40, 50), (0, 100), (99, 100), (0, 1), (50, 40)];
+        ranges.into_iter().for_each(|(start, end)| {
+            let expected = nums
+                .iter()
+                .skip(start)
+                .take(end.max(start) - start)
+                .cloned()
+                .collect::<Vec<_>>();
+            let result = tree.iter_range(start..end).cloned().collect::<Vec<_>>();
+            assert_eq!(result, expected, "failed on range: {}..{}", start, end);
+        });
+        assert_eq!(tree.iter_range(98..).cloned().collect::<Vec<_>>(), [98, 99]);
+        assert_eq!(tree.iter_range(..=1).cloned().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(tree.iter_range(95..200).count(), 5);
+        assert_eq!(tree.iter_range(98..=usize::MAX).count(), 2);
+        let from_max = (Bound::Excluded(usize::MAX), Bound::Unbounded);
+        assert_eq!(tree.iter_range(from_max).count(), 0);
+    }
+
+    #[test]
+    fn test_debug_visualization() {
+        let tree = AggAvlTree::from_vec(vec![1, 2, 3], agg_add);
+        assert_eq!(
+            format!("{:?}", tree),
+            "AggAvlTree { height: Some(1), agg: Some(6), elems: [1, 2, 3] }"
+        );
+        let dot = tree.to_dot();
+        assert!(dot.starts_with("digraph AggAvlTree {"));
+        assert!(dot.contains("agg=6\\nheight=1 count=3"));
+        // 3 leaves and 2 internal nodes joined by 4 edges
+        assert_eq!(dot.matches("shape=box").count(), 3);
+        assert_eq!(dot.matches(" -> ").count(), 4);
+    }
+
+    #[test]
+    fn test_validate() {
+        let nums = (0..100).into_iter().collect::<Vec<_>>();
+        let mut tree = AggAvlTree::from_vec(nums, agg_add);
+        tree.validate().unwrap();
+        for idx in (0..50).rev() {
+            tree.delete(idx * 2).unwrap();
+            tree.validate().unwrap();
+        }
+        for idx in 0..50 {
+            tree.insert(idx, idx);
+            tree.update(idx * 2, 0).unwrap();
+            tree.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_validate_detects_corruption() {
+        let nums = (0..10).into_iter().collect::<Vec<_>>();
+        let mut tree = AggAvlTree::from_vec(nums.clone(), agg_add);
+        if let Some(TreeNode::Child(x)) = &mut tree.root {
+            x.agg = 0;
+        }
+        assert!(matches!(
+            tree.validate(),
+            Err(AggAvlTreeError::InvariantViolation("stale aggregate"))
+        ));
+        let mut tree = AggAvlTree::from_vec(nums.clone(), agg_add);
+        if let Some(TreeNode::Child(x)) = &mut tree.root {
+            x.elem_count += 1;
+        }
+        assert!(matches!(
+            tree.validate(),
+            Err(AggAvlTreeError::InvariantViolation("stale element count"))
+        ));
+        let mut tree = AggAvlTree::from_vec(nums, agg_add);
+        if let Some(TreeNode::Child(x)) = &mut tree.root {
+            x.child_height += 1;
+        }
+        assert!(matches!(
+            tree.validate(),
+            Err(AggAvlTreeError::InvariantViolation("stale height"))
+        ));
+        // a chain of nodes without rebalancing
+        let mut root = TreeNode::Leaf(LeafNode::new(0));
+        for idx in 1..4 {
+            let leaf = Box::new(TreeNode::Leaf(LeafNode::new(idx)));
+            root = TreeNode::Child(ChildNode::new(Box::new(root), leaf, &agg_add));
+        }
+        let tree = AggAvlTree {
+            root: Some(root),
+            accumulate: agg_add,
+        };
+        assert!(matches!(
+            tree.validate(),
+            Err(AggAvlTreeError::InvariantViolation("unbalanced node"))
+        ));
+    }
+
     #[test]
     fn test_delete() {
         let nums = (0..100).into_iter().collect::<Vec<_>>();
@@ -612,4 +950,21 @@ mod test {
         let result = tree.get_range(2..4).unwrap();
         assert_eq!(result, 9 - 3);
     }
+
+    #[test]
+    fn test_closure_aggregate() {
+        // aggregators may capture state
+        let modulus = 7;
+        let nums = (0..100).collect::<Vec<_>>();
+        let mut tree = AggAvlTree::from_vec_with(nums, move |a: &i32, b: &i32| (a + b) % modulus);
+        assert_eq!(tree.get_range(10..20), Some((10..20).sum::<i32>() % 7));
+        tree.insert(50, 3);
+        tree.delete(0).unwrap();
+        tree.validate().unwrap();
+        assert_eq!(tree.get_range(..), Some(((1..100).sum::<i32>() + 3) % 7));
+        // as may pairs of aggregates
+        let lens = "aé€😀".chars().map(|x| (x.len_utf8(), x.len_utf16()));
+        let tree = AggAvlTree::from_vec_with(lens.collect(), |a, b| (a.0 + b.0, a.1 + b.1));
+        assert_eq!(tree.get_range(..), Some((10, 5)));
+    }
 }
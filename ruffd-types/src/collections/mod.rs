@@ -1,5 +1,5 @@
 mod agg_avl_tree;
 mod rope;
 
-pub use agg_avl_tree::AggAvlTree;
+pub use agg_avl_tree::{AggAvlTree, AggAvlTreeIter, AggFn};
 pub use rope::Rope;
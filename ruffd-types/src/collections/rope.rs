@@ -1,17 +1,39 @@
 use crate::error::RopeError;
 use std::collections::VecDeque;
 use std::fmt;
+use std::mem;
 use std::ops::{Bound, RangeBounds};
 
 // NOTE There's a lot of room for better memory management in this collection
 // implementation, however, everything exists without unsafe blocks for now,
 // which is nice
 
-// TODO make LEAF_SIZE size compilation configurable, and type dependant, such that
-// a leaf will always fit in a cache line, (will come when val is a sized slice rather)
-// than a vector
+/// Elements held by a leaf of a rope unless given otherwise, see [`Rope`]
 const LEAF_SIZE: usize = 64;
 
+/// Length up to which a document is held as a single vector rather than a
+/// tree, being larger than most source files
+const FLAT_SIZE: usize = 4096;
+
+/// Depth allowed beyond twice the depth of a balanced rope before the rope
+/// is rebalanced, see `Rope::max_depth`
+const DEPTH_SLACK: usize = 8;
+
+/// Depth allowed beyond that of a balanced rope for a subtree to be kept
+/// intact when rebalancing
+const PIECE_SLACK: usize = 2;
+
+/// Leaves allowed per two leaves of a rope with full leaves before the
+/// leaves of the rope are compacted, see `RopeNode::compact`
+const FRAGMENTATION: usize = 3;
+
+/// Depth of a rope of `len` elements split evenly into leaves of
+/// `leaf_size` elements
+fn balanced_depth(len: usize, leaf_size: usize) -> usize {
+    let leaf_count = len / leaf_size + 1;
+    (usize::BITS - leaf_count.leading_zeros()) as usize
+}
+
 #[derive(Debug)]
 enum Lr<T> {
     Left(T),
@@ -28,25 +50,25 @@ impl<T> Lr<T> {
     }
 }
 
-struct L2Val<T> {
-    parent: Box<RopeParent<T>>,
-    target: Lr<Box<RopeParent<T>>>,
+struct L2Val<T, const LEAF: usize> {
+    parent: Box<RopeParent<T, LEAF>>,
+    target: Lr<Box<RopeParent<T, LEAF>>>,
 }
 
-impl<T> L2Val<T> {
-    fn new(parent: Box<RopeParent<T>>, target: Lr<Box<RopeParent<T>>>) -> Self {
+impl<T, const LEAF: usize> L2Val<T, LEAF> {
+    fn new(parent: Box<RopeParent<T, LEAF>>, target: Lr<Box<RopeParent<T, LEAF>>>) -> Self {
         Self { parent, target }
     }
 }
 
-enum SplayRet<T> {
-    L1(Box<RopeParent<T>>),
-    L2(L2Val<T>),
+enum SplayRet<T, const LEAF: usize> {
+    L1(Box<RopeParent<T, LEAF>>),
+    L2(L2Val<T, LEAF>),
     Leaf(Vec<T>),
 }
 
-impl<T> From<RopeNode<T>> for SplayRet<T> {
-    fn from(node: RopeNode<T>) -> Self {
+impl<T, const LEAF: usize> From<RopeNode<T, LEAF>> for SplayRet<T, LEAF> {
+    fn from(node: RopeNode<T, LEAF>) -> Self {
         match node {
             RopeNode::Parent(x) => Self::L1(x),
             RopeNode::Leaf(x) => Self::Leaf(x),
@@ -54,8 +76,8 @@ impl<T> From<RopeNode<T>> for SplayRet<T> {
     }
 }
 
-impl<T> From<SplayRet<T>> for RopeNode<T> {
-    fn from(splay_ret: SplayRet<T>) -> Self {
+impl<T, const LEAF: usize> From<SplayRet<T, LEAF>> for RopeNode<T, LEAF> {
+    fn from(splay_ret: SplayRet<T, LEAF>) -> Self {
         match splay_ret {
             SplayRet::L1(x) => Self::Parent(x),
             SplayRet::L2(L2Val { parent, target }) => Self::zig_splay(*parent, target),
@@ -64,12 +86,12 @@ impl<T> From<SplayRet<T>> for RopeNode<T> {
     }
 }
 
-enum RopeNode<T> {
+enum RopeNode<T, const LEAF: usize> {
     Leaf(Vec<T>),
-    Parent(Box<RopeParent<T>>),
+    Parent(Box<RopeParent<T, LEAF>>),
 }
 
-impl<T> fmt::Debug for RopeNode<T> {
+impl<T, const LEAF: usize> fmt::Debug for RopeNode<T, LEAF> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Leaf(x) => f.debug_tuple("Leaf").field(&x.len()).finish(),
@@ -78,32 +100,39 @@ impl<T> fmt::Debug for RopeNode<T> {
     }
 }
 
-struct RopeParent<T> {
+struct RopeParent<T, const LEAF: usize> {
     // internal values are only option to enable swap with
     // no default
-    left: Option<RopeNode<T>>,
-    right: Option<RopeNode<T>>,
+    left: Option<RopeNode<T, LEAF>>,
+    right: Option<RopeNode<T, LEAF>>,
     elem_count: usize,
+    /// Length of the longest path to a leaf
+    depth: usize,
+    leaf_count: usize,
 }
 
-impl<T> fmt::Debug for RopeParent<T> {
+impl<T, const LEAF: usize> fmt::Debug for RopeParent<T, LEAF> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RopeParent")
             .field("left", &self.left)
             .field("right", &self.right)
             .field("elem_count", &self.elem_count)
+            .field("depth", &self.depth)
+            .field("leaf_count", &self.leaf_count)
             .finish()
     }
 }
 
-impl<T> RopeParent<T> {
-    fn new(lhs: RopeNode<T>, rhs: RopeNode<T>) -> Self {
+impl<T, const LEAF: usize> RopeParent<T, LEAF> {
+    fn new(lhs: RopeNode<T, LEAF>, rhs: RopeNode<T, LEAF>) -> Self {
         let left = Some(lhs);
         let right = Some(rhs);
         let mut rv = Self {
             left,
             right,
             elem_count: 0,
+            depth: 0,
+            leaf_count: 0,
         };
         rv.update_node();
         rv
@@ -130,18 +159,32 @@ impl<T> RopeParent<T> {
         self.elem_count = left_count + right_count;
     }
 
-    /// Method for recomputing elem_count
+    fn update_depth(&mut self) {
+        let left_depth = self.left.as_ref().map_or(0, |x| x.depth());
+        let right_depth = self.right.as_ref().map_or(0, |x| x.depth());
+        self.depth = left_depth.max(right_depth) + 1;
+    }
+
+    fn update_leaf_count(&mut self) {
+        let left_count = self.left.as_ref().map_or(0, |x| x.leaf_count());
+        let right_count = self.right.as_ref().map_or(0, |x| x.leaf_count());
+        self.leaf_count = left_count + right_count;
+    }
+
+    /// Method for recomputing elem_count, depth and leaf_count
     ///
     /// **Must** call this method on mutation of left or right
     /// values
     pub fn update_node(&mut self) {
         self.update_elem_count();
+        self.update_depth();
+        self.update_leaf_count();
     }
 }
 
-impl<T> RopeNode<T> {
+impl<T, const LEAF: usize> RopeNode<T, LEAF> {
     pub fn new(mut val: Vec<T>) -> Self {
-        if val.len() > LEAF_SIZE {
+        if val.len() > LEAF {
             let mid_idx = val.len() >> 1;
             let rhs = val.drain(mid_idx..).collect::<Vec<_>>();
             let rhs_node = Self::new(rhs);
@@ -153,7 +196,7 @@ impl<T> RopeNode<T> {
     }
 
     pub fn from_nodes(lhs: Self, rhs: Self) -> Self {
-        if lhs.elem_count() + rhs.elem_count() < LEAF_SIZE {
+        if lhs.elem_count() + rhs.elem_count() < LEAF {
             let mut val = lhs.drain();
             let mut tp = rhs.drain();
             val.append(&mut tp);
@@ -170,6 +213,155 @@ impl<T> RopeNode<T> {
         }
     }
 
+    pub fn depth(&self) -> usize {
+        match self {
+            Self::Parent(x) => x.depth,
+            Self::Leaf(_) => 0,
+        }
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        match self {
+            Self::Parent(x) => x.leaf_count,
+            Self::Leaf(_) => 1,
+        }
+    }
+
+    /// Whether the node has over `FRAGMENTATION / 2` times the leaves it
+    /// would have were its leaves full
+    ///
+    /// Leaves split in half on overflowing and shrink on deletions, being
+    /// merged only when joined on the splayed path, such that edits across
+    /// a document leave it with many part filled leaves, slowing iteration
+    fn is_fragmented(&self) -> bool {
+        2 * self.leaf_count() > FRAGMENTATION * (self.elem_count() / LEAF + 1)
+    }
+
+    /// Packs the elements of the node into full leaves, and rebuilds the
+    /// tree balanced over them
+    ///
+    /// The cost is linear in the size of the rope, but amortized over the
+    /// edits splitting or shrinking leaves by half as many as the rope has
+    /// before it's compacted again
+    fn compact(self) -> Self {
+        let mut leaves = vec![];
+        self.collect_leaves(&mut leaves);
+        let mut packed: Vec<Vec<T>> = vec![];
+        for mut leaf in leaves {
+            while !leaf.is_empty() {
+                if packed.last().map_or(LEAF, Vec::len) == LEAF {
+                    packed.push(Vec::with_capacity(LEAF));
+                }
+                let last = packed.last_mut().unwrap();
+                let count = (LEAF - last.len()).min(leaf.len());
+                last.extend(leaf.drain(..count));
+            }
+        }
+        Self::from_pieces(packed.into_iter().map(Self::Leaf).collect())
+    }
+
+    fn collect_leaves(self, leaves: &mut Vec<Vec<T>>) {
+        match self {
+            Self::Leaf(x) => leaves.push(x),
+            Self::Parent(mut x) => {
+                x.left.take().unwrap().collect_leaves(leaves);
+                x.right.take().unwrap().collect_leaves(leaves);
+            }
+        }
+    }
+
+    /// Restores the depth bound of `Rope::max_depth` if exceeded
+    ///
+    /// Splaying keeps repeated access to nearby indices fast, but displaces
+    /// nodes off the accessed path downwards, such that patterns like
+    /// repeated edits at one index or alternating edits at the front and
+    /// back grow chains of nodes linearly. The tree is split into its
+    /// maximal well balanced subtrees, being the subtrees off these chains,
+    /// which are then joined back up weighted by size. The cost is linear in
+    /// the number of subtrees rather than elements, and as each chain node
+    /// stems from a mutation, this is amortized over the mutations
+    fn rebalance(self) -> Self {
+        match &self {
+            Self::Parent(x) if x.depth > Rope::<T, LEAF>::max_depth(x.elem_count) => {
+                let mut pieces = vec![];
+                self.collect_balanced(&mut pieces);
+                Self::from_pieces(pieces)
+            }
+            _ => self,
+        }
+    }
+
+    /// Splits the node into maximal subtrees no more than `PIECE_SLACK`
+    /// deeper than a balanced tree of the same element count
+    fn collect_balanced(self, pieces: &mut Vec<Self>) {
+        match self {
+            Self::Parent(mut x) if x.depth > balanced_depth(x.elem_count, LEAF) + PIECE_SLACK => {
+                x.left.take().unwrap().collect_balanced(pieces);
+                x.right.take().unwrap().collect_balanced(pieces);
+            }
+            x => pieces.push(x),
+        }
+    }
+
+    /// Joins consecutive nodes into a tree, splitting at the midpoint by
+    /// element count such that large nodes sit near the root
+    fn from_pieces(mut pieces: Vec<Self>) -> Self {
+        if pieces.len() == 1 {
+            return pieces.pop().unwrap();
+        }
+        let total = pieces.iter().map(|x| x.elem_count()).sum::<usize>();
+        let mut prefix = 0;
+        let mut split_idx = 1;
+        for (idx, piece) in pieces.iter().enumerate() {
+            prefix += piece.elem_count();
+            if prefix * 2 >= total {
+                split_idx = idx + 1;
+                break;
+            }
+        }
+        // both sides require at least one node
+        let split_idx = split_idx.clamp(1, pieces.len() - 1);
+        let rhs = pieces.split_off(split_idx);
+        Self::from_nodes(Self::from_pieces(pieces), Self::from_pieces(rhs))
+    }
+
+    /// Joins nodes either of which may be absent
+    fn join(lhs: Option<Self>, rhs: Option<Self>) -> Option<Self> {
+        match (lhs, rhs) {
+            (Some(lhs), Some(rhs)) => Some(Self::from_nodes(lhs, rhs)),
+            (lhs, rhs) => lhs.or(rhs),
+        }
+    }
+
+    /// Splits the node into the elements before and from `idx`, either
+    /// being `None` if empty
+    ///
+    /// Only nodes on the path to `idx` are rebuilt, the subtrees off the
+    /// path being moved into either side
+    fn split_at(self, idx: usize) -> (Option<Self>, Option<Self>) {
+        match self {
+            Self::Leaf(mut x) => {
+                let rhs = x.split_off(idx);
+                let non_empty = |x: Vec<T>| (!x.is_empty()).then(|| Self::Leaf(x));
+                (non_empty(x), non_empty(rhs))
+            }
+            Self::Parent(mut node) => {
+                let mid_idx = node.get_left_elem_count();
+                let left = node.left.take().unwrap();
+                let right = node.right.take().unwrap();
+                if idx < mid_idx {
+                    let (lhs, rhs) = left.split_at(idx);
+                    (lhs, Self::join(rhs, Some(right)))
+                } else if idx > mid_idx {
+                    let (lhs, rhs) = right.split_at(idx - mid_idx);
+                    (Self::join(Some(left), lhs), rhs)
+                } else {
+                    (Some(left), Some(right))
+                }
+            }
+        }
+    }
+
     fn drain(self) -> Vec<T> {
         match self {
             Self::Leaf(x) => x,
@@ -183,9 +375,9 @@ impl<T> RopeNode<T> {
     }
 
     fn splay(
-        grandparent: RopeParent<T>,
-        parent: Lr<Box<RopeParent<T>>>,
-        target: Lr<Box<RopeParent<T>>>,
+        grandparent: RopeParent<T, LEAF>,
+        parent: Lr<Box<RopeParent<T, LEAF>>>,
+        target: Lr<Box<RopeParent<T, LEAF>>>,
     ) -> Self {
         // NOTE this method assumes that self and parent have removed parent
         // and target from the corresponding left and right fields
@@ -235,7 +427,7 @@ impl<T> RopeNode<T> {
         }
     }
 
-    fn zig_splay(parent: RopeParent<T>, target: Lr<Box<RopeParent<T>>>) -> Self {
+    fn zig_splay(parent: RopeParent<T, LEAF>, target: Lr<Box<RopeParent<T, LEAF>>>) -> Self {
         match target {
             Lr::Left(mut target_node) => {
                 let new_parent =
@@ -254,7 +446,7 @@ impl<T> RopeNode<T> {
     ///
     /// If the provided index is greater than the maximum,
     /// the value will be inserted at the back
-    pub fn insert(self, mut val: Vec<T>, idx: usize) -> SplayRet<T> {
+    pub fn insert(self, mut val: Vec<T>, idx: usize) -> SplayRet<T, LEAF> {
         match self {
             Self::Leaf(mut x) => {
                 let mut rhs = x.drain(idx..).collect::<Vec<_>>();
@@ -272,32 +464,48 @@ impl<T> RopeNode<T> {
                     let rv = parent_node.right.take().unwrap().insert(val, idx - mid_idx);
                     (false, rv)
                 };
-                match ret_val {
-                    SplayRet::L1(x) => SplayRet::L2(L2Val::new(parent_node, Lr::new(x, is_left))),
-                    SplayRet::L2(L2Val { parent, target }) => {
-                        Self::splay(*parent_node, Lr::new(parent, is_left), target).into()
-                    }
-                    SplayRet::Leaf(x) => {
-                        let ret_node = if is_left {
-                            Self::from_nodes(Self::new(x), parent_node.right.unwrap())
-                        } else {
-                            Self::from_nodes(parent_node.left.unwrap(), Self::new(x))
-                        };
-                        ret_node.into()
-                    }
-                }
+                Self::splay_child(parent_node, ret_val, is_left)
             }
         }
     }
 
-    pub fn delete<R: RangeBounds<usize>>(self, range: R) -> Option<Self> {
+    /// Combines the result of a mutation within one child of `parent_node`
+    /// continuing the splay of the mutated node towards the root
+    ///
+    /// Expects the mutated child to have been taken from `parent_node`
+    fn splay_child(
+        mut parent_node: Box<RopeParent<T, LEAF>>,
+        ret_val: SplayRet<T, LEAF>,
+        is_left: bool,
+    ) -> SplayRet<T, LEAF> {
+        match ret_val {
+            SplayRet::L1(x) => SplayRet::L2(L2Val::new(parent_node, Lr::new(x, is_left))),
+            SplayRet::L2(L2Val { parent, target }) => {
+                Self::splay(*parent_node, Lr::new(parent, is_left), target).into()
+            }
+            SplayRet::Leaf(x) => {
+                let ret_node = if is_left {
+                    Self::from_nodes(Self::new(x), parent_node.right.take().unwrap())
+                } else {
+                    Self::from_nodes(parent_node.left.take().unwrap(), Self::new(x))
+                };
+                ret_node.into()
+            }
+        }
+    }
+
+    /// Deletes the range from the rope, splaying the node at the deletion
+    /// point such that subsequent edits nearby remain fast
+    ///
+    /// Returns `None` if all elements are deleted
+    pub fn delete<R: RangeBounds<usize>>(self, range: R) -> Option<SplayRet<T, LEAF>> {
         match self {
             Self::Leaf(mut val) => {
                 val.drain(range).for_each(drop);
                 if val.is_empty() {
                     None
                 } else {
-                    Some(Self::new(val))
+                    Some(Self::new(val).into())
                 }
             }
             Self::Parent(mut node) => {
@@ -312,33 +520,40 @@ impl<T> RopeNode<T> {
                     Bound::Unbounded => node.elem_count,
                 };
                 let mid_idx = node.get_left_elem_count();
-                let left = node.left.take().unwrap();
-                let lhs = if start_idx < mid_idx {
-                    let end_bound = mid_idx.min(end_idx);
-                    left.delete(start_idx..end_bound)
-                } else {
-                    Some(left)
-                };
-                let right = node.right.take().unwrap();
-                let rhs = if end_idx > mid_idx {
-                    let start_bound = start_idx.max(mid_idx) - mid_idx;
-                    right.delete(start_bound..(end_idx - mid_idx))
+                if end_idx <= mid_idx {
+                    // deletion confined to the left child
+                    let left = node.left.take().unwrap();
+                    match left.delete(start_idx..end_idx) {
+                        Some(ret_val) => Some(Self::splay_child(node, ret_val, true)),
+                        None => Some(node.right.take().unwrap().into()),
+                    }
+                } else if start_idx >= mid_idx {
+                    // deletion confined to the right child
+                    let right = node.right.take().unwrap();
+                    match right.delete((start_idx - mid_idx)..(end_idx - mid_idx)) {
+                        Some(ret_val) => Some(Self::splay_child(node, ret_val, false)),
+                        None => Some(node.left.take().unwrap().into()),
+                    }
                 } else {
-                    Some(right)
-                };
-                match (lhs, rhs) {
-                    (None, rhs) => rhs,
-                    (lhs, None) => lhs,
-                    (Some(lhs), Some(rhs)) => Some(Self::from_nodes(lhs, rhs)),
+                    // the deletion point is the join of both children
+                    let lhs = node.left.take().unwrap().delete(start_idx..mid_idx);
+                    let rhs = node.right.take().unwrap().delete(0..(end_idx - mid_idx));
+                    match (lhs, rhs) {
+                        (None, rhs) => rhs,
+                        (lhs, None) => lhs,
+                        (Some(lhs), Some(rhs)) => {
+                            Some(Self::from_nodes(lhs.into(), rhs.into()).into())
+                        }
+                    }
                 }
             }
         }
     }
 }
 
-pub struct RopeIterator<'a, T> {
+pub struct RopeIterator<'a, T, const LEAF: usize = LEAF_SIZE> {
     /// Call stack for dfs
-    node_stack: VecDeque<&'a RopeParent<T>>,
+    node_stack: VecDeque<&'a RopeParent<T, LEAF>>,
 
     /// Number of iteration calls expected if Some else infinite
     iter_len: Option<usize>,
@@ -350,20 +565,27 @@ pub struct RopeIterator<'a, T> {
     item_iter: Box<dyn Iterator<Item = &'a T> + 'a>,
 }
 
-impl<'a, T> RopeIterator<'a, T> {
-    fn new<R: RangeBounds<usize>>(root: &'a RopeNode<T>, range: R) -> Self {
+/// Start index and length of iteration over `range`, the length being
+/// `None` if unbounded
+fn iter_bounds<R: RangeBounds<usize>>(range: R) -> (usize, Option<usize>) {
+    let start_idx = match range.start_bound() {
+        Bound::Included(x) => *x,
+        Bound::Excluded(x) => x + 1usize,
+        Bound::Unbounded => 0,
+    };
+    let iter_len = match range.end_bound() {
+        Bound::Included(x) => Some(x - start_idx + 1),
+        Bound::Excluded(x) => Some(x - start_idx),
+        Bound::Unbounded => None,
+    };
+    (start_idx, iter_len)
+}
+
+impl<'a, T, const LEAF: usize> RopeIterator<'a, T, LEAF> {
+    fn new<R: RangeBounds<usize>>(root: &'a RopeNode<T, LEAF>, range: R) -> Self {
         let mut curr_node = root;
-        let mut node_stack = VecDeque::<&'a RopeParent<T>>::new();
-        let start_idx = match range.start_bound() {
-            Bound::Included(x) => *x,
-            Bound::Excluded(x) => x + 1usize,
-            Bound::Unbounded => 0,
-        };
-        let iter_len = match range.end_bound() {
-            Bound::Included(x) => Some(x - start_idx + 1),
-            Bound::Excluded(x) => Some(x - start_idx),
-            Bound::Unbounded => None,
-        };
+        let mut node_stack = VecDeque::<&'a RopeParent<T, LEAF>>::new();
+        let (start_idx, iter_len) = iter_bounds(range);
         let mut tp_agg = 0usize;
         while let RopeNode::Parent(node) = curr_node {
             let left_elem_count = node.left.as_ref().unwrap().elem_count();
@@ -387,17 +609,18 @@ impl<'a, T> RopeIterator<'a, T> {
         }
     }
 
-    fn empty() -> Self {
+    fn from_slice<R: RangeBounds<usize>>(val: &'a [T], range: R) -> Self {
+        let (start_idx, iter_len) = iter_bounds(range);
         Self {
-            node_stack: VecDeque::<&'a RopeParent<T>>::new(),
-            iter_len: None,
+            node_stack: VecDeque::<&'a RopeParent<T, LEAF>>::new(),
+            iter_len,
             curr_idx: 0,
-            item_iter: Box::new(std::iter::empty()),
+            item_iter: Box::new(val.get(start_idx..).unwrap_or_default().iter()),
         }
     }
 }
 
-impl<'a, T> Iterator for RopeIterator<'a, T> {
+impl<'a, T, const LEAF: usize> Iterator for RopeIterator<'a, T, LEAF> {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(x) = self.iter_len {
@@ -410,7 +633,7 @@ impl<'a, T> Iterator for RopeIterator<'a, T> {
             return Some(x);
         }
         if let Some(x) = self.node_stack.pop_back() {
-            let mut curr_node: &RopeNode<T> = x.right.as_ref().unwrap();
+            let mut curr_node: &RopeNode<T, LEAF> = x.right.as_ref().unwrap();
             while let RopeNode::Parent(x) = curr_node {
                 self.node_stack.push_back(x);
                 curr_node = x.left.as_ref().unwrap();
@@ -426,41 +649,125 @@ impl<'a, T> Iterator for RopeIterator<'a, T> {
     }
 }
 
+/// Elements of a rope, held as a tree only once the document grows past
+/// `FLAT_SIZE`, such that small documents are edited in place without the
+/// overhead of nodes
+#[derive(Debug)]
+enum RopeRoot<T, const LEAF: usize> {
+    Flat(Vec<T>),
+    Tree(RopeNode<T, LEAF>),
+}
+
+impl<T, const LEAF: usize> Default for RopeRoot<T, LEAF> {
+    fn default() -> Self {
+        Self::Flat(vec![])
+    }
+}
+
+impl<T, const LEAF: usize> RopeRoot<T, LEAF> {
+    fn new(val: Vec<T>) -> Self {
+        if val.len() > FLAT_SIZE {
+            Self::Tree(RopeNode::new(val))
+        } else {
+            Self::Flat(val)
+        }
+    }
+
+    fn from_node(node: Option<RopeNode<T, LEAF>>) -> Self {
+        match node {
+            Some(x) => Self::Tree(x),
+            None => Self::default(),
+        }
+    }
+
+    fn into_node(self) -> Option<RopeNode<T, LEAF>> {
+        match self {
+            Self::Flat(x) if x.is_empty() => None,
+            Self::Flat(x) => Some(RopeNode::new(x)),
+            Self::Tree(x) => Some(x),
+        }
+    }
+}
+
 /// Rope datastructure for fast insert / delete ops
 ///
 /// Novelty of this implementation is it performs a splay op after
 /// each mutation op, such that traversal to similar indices
 /// is dynamically optimal (unproven but Levy is nearly there!)
+///
+/// Leaves hold up to `LEAF` elements, which should be non-zero. Documents
+/// of up to `FLAT_SIZE` elements are held as a single vector, and become a
+/// tree only once they grow past it, returning to a vector once shrunk to
+/// half of it
 #[derive(Debug)]
-pub struct Rope<T> {
-    root: Option<RopeNode<T>>,
+pub struct Rope<T, const LEAF: usize = LEAF_SIZE> {
+    root: RopeRoot<T, LEAF>,
 }
 
-impl<T> Default for Rope<T> {
+impl<T, const LEAF: usize> Default for Rope<T, LEAF> {
     fn default() -> Self {
-        Self { root: None }
+        Self {
+            root: RopeRoot::default(),
+        }
+    }
+}
+
+impl<T, const LEAF: usize> From<Vec<T>> for Rope<T, LEAF> {
+    fn from(document: Vec<T>) -> Self {
+        let root = RopeRoot::new(document);
+        Self { root }
     }
 }
 
+// constructors are of the default leaf size, as defaults of const
+// parameters aren't inferred, ropes of other leaf sizes being built through
+// `Default` and `From`
 impl<T> Rope<T> {
     pub fn new() -> Self {
         Self::default()
     }
 
     pub fn from_document(document: Vec<T>) -> Self {
-        let root = Some(RopeNode::new(document));
-        Self { root }
+        Self::from(document)
     }
+}
 
+impl<T, const LEAF: usize> Rope<T, LEAF> {
     pub fn len(&self) -> usize {
         match &self.root {
-            Some(x) => x.elem_count(),
-            None => 0,
+            RopeRoot::Flat(x) => x.len(),
+            RopeRoot::Tree(x) => x.elem_count(),
         }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.root.is_none()
+        self.len() == 0
+    }
+
+    /// Length of the longest path from the root to a leaf
+    pub fn depth(&self) -> usize {
+        match &self.root {
+            RopeRoot::Flat(_) => 0,
+            RopeRoot::Tree(x) => x.depth(),
+        }
+    }
+
+    /// Depth beyond which a rope of `len` elements is considered
+    /// unbalanced, being a constant slack over twice the depth of a balanced
+    /// rope
+    pub fn max_depth(len: usize) -> usize {
+        2 * balanced_depth(len, LEAF) + DEPTH_SLACK
+    }
+
+    fn rebalance(&mut self) {
+        self.root = match mem::take(&mut self.root) {
+            // halving the threshold stops documents about its size from
+            // being rebuilt on alternating edits
+            RopeRoot::Tree(x) if x.elem_count() <= FLAT_SIZE / 2 => RopeRoot::Flat(x.drain()),
+            RopeRoot::Tree(x) if x.is_fragmented() => RopeRoot::Tree(x.compact()),
+            RopeRoot::Tree(x) => RopeRoot::Tree(x.rebalance()),
+            x => x,
+        };
     }
 
     /// Inserts collection into the datastructure at the given index
@@ -469,28 +776,79 @@ impl<T> Rope<T> {
         if idx > self.len() {
             return Err(RopeError::IndexOutOfBounds);
         }
-        self.root = match self.root.take() {
-            None => Some(RopeNode::new(string)),
-            Some(x) => Some(x.insert(string, idx).into()),
+        self.root = match mem::take(&mut self.root) {
+            RopeRoot::Flat(mut x) => {
+                x.splice(idx..idx, string);
+                RopeRoot::new(x)
+            }
+            RopeRoot::Tree(x) => RopeRoot::Tree(x.insert(string, idx).into()),
         };
+        self.rebalance();
         Ok(())
     }
 
     pub fn delete<R: RangeBounds<usize>>(&mut self, range: R) {
-        self.root = match self.root.take() {
-            None => None,
-            Some(x) => x.delete(range),
+        self.root = match mem::take(&mut self.root) {
+            RopeRoot::Flat(mut x) => {
+                x.drain(range).for_each(drop);
+                RopeRoot::Flat(x)
+            }
+            RopeRoot::Tree(x) => match x.delete(range) {
+                Some(x) => RopeRoot::Tree(x.into()),
+                None => RopeRoot::default(),
+            },
+        };
+        self.rebalance();
+    }
+
+    /// Splits the rope into the elements before and from `idx`
+    ///
+    /// The subtrees of the rope are moved into either side rather than
+    /// copied, such that only the nodes on the path to `idx` are rebuilt
+    pub fn split_at(self, idx: usize) -> Result<(Self, Self), RopeError> {
+        if idx > self.len() {
+            return Err(RopeError::IndexOutOfBounds);
+        }
+        let (lhs, rhs) = match self.root {
+            RopeRoot::Flat(mut x) => {
+                let rhs = x.split_off(idx);
+                (RopeRoot::Flat(x), RopeRoot::Flat(rhs))
+            }
+            RopeRoot::Tree(x) => {
+                let (lhs, rhs) = x.split_at(idx);
+                (RopeRoot::from_node(lhs), RopeRoot::from_node(rhs))
+            }
         };
+        let mut lhs = Self { root: lhs };
+        let mut rhs = Self { root: rhs };
+        lhs.rebalance();
+        rhs.rebalance();
+        Ok((lhs, rhs))
     }
 
-    pub fn iter(&self) -> RopeIterator<'_, T> {
+    /// Joins the elements of `lhs` followed by those of `rhs`, moving the
+    /// subtrees of both into the joined rope
+    pub fn concat(lhs: Self, rhs: Self) -> Self {
+        let root = match (lhs.root, rhs.root) {
+            (RopeRoot::Flat(mut lhs), RopeRoot::Flat(mut rhs)) => {
+                lhs.append(&mut rhs);
+                RopeRoot::new(lhs)
+            }
+            (lhs, rhs) => RopeRoot::from_node(RopeNode::join(lhs.into_node(), rhs.into_node())),
+        };
+        let mut rv = Self { root };
+        rv.rebalance();
+        rv
+    }
+
+    pub fn iter(&self) -> RopeIterator<'_, T, LEAF> {
         self.iter_range(..)
     }
 
-    pub fn iter_range<R: RangeBounds<usize>>(&self, bounds: R) -> RopeIterator<'_, T> {
-        match self.root {
-            Some(ref x) => RopeIterator::new(x, bounds),
-            None => RopeIterator::empty(),
+    pub fn iter_range<R: RangeBounds<usize>>(&self, bounds: R) -> RopeIterator<'_, T, LEAF> {
+        match &self.root {
+            RopeRoot::Flat(x) => RopeIterator::from_slice(x, bounds),
+            RopeRoot::Tree(x) => RopeIterator::new(x, bounds),
         }
     }
 }
@@ -575,6 +933,64 @@ if __name__ == '__main__':
         assert_eq!(full_str, expected);
     }
 
+    #[test]
+    fn bounded_depth_alternating_edits() {
+        let characters = SMALL_STR.chars().cycle().take(10000).collect::<Vec<_>>();
+        let mut expected = characters.iter().collect::<String>();
+        let mut rope = Rope::from_document(characters);
+        for idx in 0..2000 {
+            if idx % 2 == 0 {
+                rope.insert("front".chars().collect::<Vec<_>>(), 0).unwrap();
+                expected.insert_str(0, "front");
+            } else {
+                rope.insert("back".chars().collect::<Vec<_>>(), rope.len())
+                    .unwrap();
+                expected.push_str("back");
+            }
+            assert!(rope.depth() <= Rope::<char>::max_depth(rope.len()));
+        }
+        assert_eq!(rope.iter().collect::<String>(), expected);
+    }
+
+    #[test]
+    fn delete_spanning_children() {
+        let characters = SMALL_STR.chars().cycle().take(10000).collect::<Vec<_>>();
+        let mut expected = characters.iter().collect::<String>();
+        let mut rope = Rope::from_document(characters);
+        // deletes within a leaf, across leaves, and up to either end
+        let ranges = vec![
+            (5000, 5001),
+            (4000, 6000),
+            (0, 100),
+            (7500, 7800),
+            (6000, 6500),
+        ];
+        for (start, end) in ranges {
+            rope.delete(start..end);
+            expected.replace_range(start..end, "");
+            assert_eq!(rope.iter().collect::<String>(), expected);
+        }
+        rope.delete(..);
+        assert!(rope.is_empty());
+    }
+
+    #[test]
+    fn delete_then_insert_nearby() {
+        let characters = SMALL_STR.chars().cycle().take(10000).collect::<Vec<_>>();
+        let mut expected = characters.iter().collect::<String>();
+        let mut rope = Rope::from_document(characters);
+        for idx in 0..500 {
+            let start = 3000 + (idx % 7) * 3;
+            rope.delete(start..start + 10);
+            expected.replace_range(start..start + 10, "");
+            rope.insert("inserted".chars().collect::<Vec<_>>(), start + 1)
+                .unwrap();
+            expected.insert_str(start + 1, "inserted");
+            assert!(rope.depth() <= Rope::<char>::max_depth(rope.len()));
+        }
+        assert_eq!(rope.iter().collect::<String>(), expected);
+    }
+
     #[test]
     fn consecutive_updates() {
         let characters = SMALL_PROGRAM.chars().collect::<Vec<_>>();
@@ -587,4 +1003,123 @@ if __name__ == '__main__':
         let full_str = rope.iter().collect::<String>();
         assert_eq!(full_str, SMALL_PROGRAM);
     }
+
+    #[test]
+    fn flat_until_grown() {
+        let characters = SMALL_PROGRAM.chars().collect::<Vec<_>>();
+        let mut expected = SMALL_PROGRAM.to_string();
+        let mut rope = Rope::from_document(characters);
+        assert!(matches!(rope.root, RopeRoot::Flat(_)));
+        while rope.len() <= FLAT_SIZE {
+            rope.insert(SMALL_STR.chars().collect::<Vec<_>>(), 10)
+                .unwrap();
+            expected.insert_str(10, SMALL_STR);
+        }
+        assert!(matches!(rope.root, RopeRoot::Tree(_)));
+        assert_eq!(rope.iter().collect::<String>(), expected);
+        // shrinking below the threshold keeps the tree until half of it
+        rope.delete(0..10);
+        expected.replace_range(0..10, "");
+        assert!(matches!(rope.root, RopeRoot::Tree(_)));
+        rope.delete(100..FLAT_SIZE / 2 + 100);
+        expected.replace_range(100..FLAT_SIZE / 2 + 100, "");
+        assert!(matches!(rope.root, RopeRoot::Flat(_)));
+        assert_eq!(rope.iter_range(5..50).collect::<String>(), &expected[5..50]);
+        assert_eq!(rope.iter().collect::<String>(), expected);
+    }
+
+    #[test]
+    fn small_leaf_size() {
+        let characters = SMALL_STR.chars().cycle().take(10000).collect::<Vec<_>>();
+        let mut expected = characters.iter().collect::<String>();
+        let mut rope = Rope::<char, 4>::from(characters);
+        for idx in 0..500 {
+            let start = (idx * 37) % 9000;
+            rope.delete(start..start + 3);
+            expected.replace_range(start..start + 3, "");
+            rope.insert("abcd".chars().collect::<Vec<_>>(), start)
+                .unwrap();
+            expected.insert_str(start, "abcd");
+            assert!(rope.depth() <= Rope::<char, 4>::max_depth(rope.len()));
+        }
+        assert_eq!(rope.iter().collect::<String>(), expected);
+        assert_eq!(
+            rope.iter_range(4000..4100).collect::<String>(),
+            &expected[4000..4100]
+        );
+    }
+
+    #[test]
+    fn split_concat() {
+        let characters = SMALL_STR.chars().cycle().take(10000).collect::<Vec<_>>();
+        let expected = characters.iter().collect::<String>();
+        let mut rope = Rope::from_document(characters);
+        // splay the tree such that the split crosses rebuilt nodes
+        rope.insert(vec![], 7000).unwrap();
+        for idx in [0, 1, 64, 3001, 5000, 9999, 10000] {
+            let (lhs, rhs) = rope.split_at(idx).unwrap();
+            assert_eq!(lhs.iter().collect::<String>(), &expected[..idx]);
+            assert_eq!(rhs.iter().collect::<String>(), &expected[idx..]);
+            assert!(lhs.depth() <= Rope::<char>::max_depth(lhs.len()));
+            assert!(rhs.depth() <= Rope::<char>::max_depth(rhs.len()));
+            rope = Rope::concat(lhs, rhs);
+            assert_eq!(rope.len(), expected.len());
+        }
+        assert_eq!(rope.iter().collect::<String>(), expected);
+        assert!(rope.split_at(10001).is_err());
+    }
+
+    #[test]
+    fn split_concat_small() {
+        let characters = SMALL_PROGRAM.chars().collect::<Vec<_>>();
+        let rope = Rope::from_document(characters);
+        let (lhs, rhs) = rope.split_at(20).unwrap();
+        assert_eq!(lhs.iter().collect::<String>(), &SMALL_PROGRAM[..20]);
+        // extracting a region, then joining ropes held flat and as trees
+        let (region, rhs) = rhs.split_at(10).unwrap();
+        assert_eq!(region.iter().collect::<String>(), &SMALL_PROGRAM[20..30]);
+        let large = Rope::from_document(vec!['a'; 2 * FLAT_SIZE]);
+        let rope = Rope::concat(Rope::concat(lhs, large), rhs);
+        assert!(matches!(rope.root, RopeRoot::Tree(_)));
+        let expected = format!(
+            "{}{}{}",
+            &SMALL_PROGRAM[..20],
+            "a".repeat(2 * FLAT_SIZE),
+            &SMALL_PROGRAM[30..]
+        );
+        assert_eq!(rope.iter().collect::<String>(), expected);
+        let rope = Rope::concat(rope, Rope::new());
+        assert_eq!(rope.len(), expected.len());
+    }
+
+    #[test]
+    fn compact_after_edits() {
+        let characters = SMALL_STR.chars().cycle().take(100_000).collect::<Vec<_>>();
+        let mut expected = characters.iter().collect::<String>();
+        let mut rope = Rope::from_document(characters);
+        let leaf_count = |rope: &Rope<char>| match &rope.root {
+            RopeRoot::Tree(x) => x.leaf_count(),
+            RopeRoot::Flat(_) => unreachable!(),
+        };
+        let mut compactions = 0;
+        let mut last_leaf_count = leaf_count(&rope);
+        // edits spread across the rope split and shrink leaves
+        let mut start = 0;
+        while rope.len() > 20_000 {
+            start = (start + 997) % (rope.len() - 20);
+            rope.delete(start..start + 20);
+            expected.replace_range(start..start + 20, "");
+            rope.insert(vec!['x'], start).unwrap();
+            expected.insert(start, 'x');
+            let count = leaf_count(&rope);
+            let full_count = rope.len() / LEAF_SIZE + 1;
+            assert!(2 * count <= FRAGMENTATION * full_count);
+            if count < last_leaf_count * 3 / 4 {
+                compactions += 1;
+            }
+            last_leaf_count = count;
+        }
+        assert!(compactions > 0);
+        assert_eq!(rope.iter().collect::<String>(), expected);
+    }
 }
@@ -1,22 +1,96 @@
-use serde::{Deserialize, Serialize};
+use serde::de::{DeserializeOwned, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::borrow::Cow;
 
-use crate::error::RpcError;
+use crate::error::{params_from_raw, params_from_value, RpcError, RpcResult};
 
 const JSON_RPC_VERSION: &str = "2.0";
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Params of a message
+///
+/// Params received from the client are kept as the JSON they were sent as,
+/// such that they're deserialized once into the type of their handler
+/// rather than first into a `serde_json::Value`
+#[derive(Debug, Clone)]
+pub enum Params {
+    Raw(Box<RawValue>),
+    Value(serde_json::Value),
+}
+
+impl Params {
+    /// Deserializes the params into the type of their handler
+    pub fn into_typed<T: DeserializeOwned>(self) -> RpcResult<T> {
+        match self {
+            Self::Raw(x) => params_from_raw(&x),
+            Self::Value(x) => params_from_value(x),
+        }
+    }
+
+    pub fn into_value(self) -> serde_json::Value {
+        match self {
+            Self::Raw(x) => serde_json::from_str(x.get()).unwrap(),
+            Self::Value(x) => x,
+        }
+    }
+
+    pub fn to_value(&self) -> Cow<'_, serde_json::Value> {
+        match self {
+            Self::Raw(x) => Cow::Owned(serde_json::from_str(x.get()).unwrap()),
+            Self::Value(x) => Cow::Borrowed(x),
+        }
+    }
+}
+
+impl From<serde_json::Value> for Params {
+    fn from(value: serde_json::Value) -> Self {
+        Self::Value(value)
+    }
+}
+
+impl Serialize for Params {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Raw(x) => x.serialize(serializer),
+            Self::Value(x) => x.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Params {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Box::<RawValue>::deserialize(deserializer).map(Self::Raw)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RpcRequest {
     pub jsonrpc: String,
     pub id: lsp_types::NumberOrString,
     pub method: String,
-    pub params: Option<serde_json::Value>,
+    pub params: Option<Params>,
+}
+
+impl RpcRequest {
+    pub fn new(
+        id: lsp_types::NumberOrString,
+        method: String,
+        params: Option<serde_json::Value>,
+    ) -> Self {
+        Self {
+            jsonrpc: JSON_RPC_VERSION.to_string(),
+            id,
+            method,
+            params: params.map(Params::Value),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RpcNotification {
     pub jsonrpc: String,
     pub method: String,
-    pub params: Option<serde_json::Value>,
+    pub params: Option<Params>,
 }
 
 impl RpcNotification {
@@ -24,12 +98,12 @@ impl RpcNotification {
         Self {
             jsonrpc: JSON_RPC_VERSION.to_string(),
             method,
-            params,
+            params: params.map(Params::Value),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum RpcMessage {
     Request(RpcRequest),
@@ -37,6 +111,55 @@ pub enum RpcMessage {
     Response(RpcResponseMessage),
 }
 
+/// Fields of any message, read in a single pass before telling which kind
+/// of message it is
+///
+/// The version and method borrow from the source where they're unescaped,
+/// and the params are kept raw until handled
+#[derive(Deserialize)]
+struct MessageFields<'a> {
+    #[serde(borrow)]
+    jsonrpc: Cow<'a, str>,
+    id: Option<lsp_types::NumberOrString>,
+    #[serde(borrow)]
+    method: Option<Cow<'a, str>>,
+    params: Option<Params>,
+    result: Option<serde_json::Value>,
+    error: Option<RpcResponseError>,
+}
+
+impl<'de> Deserialize<'de> for RpcMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = MessageFields::deserialize(deserializer)?;
+        let jsonrpc = fields.jsonrpc.into_owned();
+        Ok(match (fields.method, fields.id) {
+            (Some(method), Some(id)) => Self::Request(RpcRequest {
+                jsonrpc,
+                id,
+                method: method.into_owned(),
+                params: fields.params,
+            }),
+            (Some(method), None) => Self::Notification(RpcNotification {
+                jsonrpc,
+                method: method.into_owned(),
+                params: fields.params,
+            }),
+            (None, id) => match fields.error {
+                Some(error) => Self::Response(RpcResponseMessage::Error(RpcResponseMessageError {
+                    jsonrpc,
+                    id,
+                    error,
+                })),
+                None => Self::Response(RpcResponseMessage::Result(RpcResponseMessageResult {
+                    jsonrpc,
+                    id,
+                    result: fields.result,
+                })),
+            },
+        })
+    }
+}
+
 impl RpcMessage {
     pub fn validate(&self) -> bool {
         let jsonrpc = match self {
@@ -73,24 +196,40 @@ pub struct RpcResponseError {
     pub data: Option<serde_json::Value>,
 }
 
+// NOTE Error must precede Result, as all fields of RpcResponseMessageResult
+// are optional, an error response would otherwise deserialize as a result
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum RpcResponseMessage {
-    Result(RpcResponseMessageResult),
     Error(RpcResponseMessageError),
+    Result(RpcResponseMessageResult),
 }
 
 impl From<RpcError> for RpcResponseError {
     fn from(err: RpcError) -> Self {
         Self {
             code: err.code,
-            message: err.message.to_string(),
-            data: None,
+            message: err.message.into_owned(),
+            data: err.data,
         }
     }
 }
 
 impl RpcResponseMessage {
+    pub fn id(&self) -> Option<&lsp_types::NumberOrString> {
+        match self {
+            Self::Result(x) => x.id.as_ref(),
+            Self::Error(x) => x.id.as_ref(),
+        }
+    }
+    /// Splits the response into the result value or the error reported by
+    /// the remote
+    pub fn into_result(self) -> Result<Option<serde_json::Value>, RpcResponseError> {
+        match self {
+            Self::Result(x) => Ok(x.result),
+            Self::Error(x) => Err(x.error),
+        }
+    }
     pub fn from_error(id: Option<lsp_types::NumberOrString>, err: RpcError) -> Self {
         Self::Error(RpcResponseMessageError {
             jsonrpc: JSON_RPC_VERSION.to_string(),
@@ -124,3 +263,45 @@ impl From<RpcResponseMessage> for RpcMessage {
         Self::Response(val)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(message: &str) -> RpcMessage {
+        serde_json::from_str(message).unwrap()
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let message = parse(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {"position": {"line": 2, "character": 4}}}"#,
+        );
+        let params = match message {
+            RpcMessage::Request(x) => {
+                assert_eq!(x.id, lsp_types::NumberOrString::Number(1));
+                assert_eq!(x.method, "textDocument/hover");
+                x.params.unwrap()
+            }
+            _ => panic!("expected a request"),
+        };
+        assert!(matches!(params, Params::Raw(_)));
+        assert_eq!(params.to_value()["position"]["line"], 2);
+        let message = parse(r#"{"jsonrpc": "2.0", "method": "exit"}"#);
+        assert!(matches!(message, RpcMessage::Notification(x) if x.params.is_none()));
+        let message =
+            parse(r#"{"jsonrpc": "2.0", "id": "a", "error": {"code": -32601, "message": "x"}}"#);
+        assert!(matches!(
+            message,
+            RpcMessage::Response(RpcResponseMessage::Error(_))
+        ));
+        let message = parse(r#"{"jsonrpc": "2.0", "id": "a", "result": null}"#);
+        match message {
+            RpcMessage::Response(x) => assert_eq!(x.into_result().unwrap(), None),
+            _ => panic!("expected a response"),
+        }
+        // raw params are written back as received
+        let source = r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#;
+        assert_eq!(serde_json::to_string(&parse(source)).unwrap(), source);
+    }
+}
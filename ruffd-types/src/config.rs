@@ -0,0 +1,314 @@
+use crate::layered::ConfigLayer;
+use crate::log_warn;
+use ruff::checks_gen::CheckCodePrefix;
+use ruff::settings::configuration::Configuration;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Section name used when pulling settings from the client
+pub const CONFIG_SECTION: &str = "ruffd";
+
+/// Default limit on the size of buffered documents, in characters
+pub const DEFAULT_MAX_DOCUMENT_SIZE: usize = 8 * 1024 * 1024;
+
+/// Default limit on the memory held by checks of documents that aren't
+/// open, in bytes
+pub const DEFAULT_CHECKS_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Default number of closed documents whose checks are kept
+pub const DEFAULT_RECENTLY_CLOSED: usize = 16;
+
+/// Settings for the server itself as supplied by the client, either through
+/// `initializationOptions` or the `ruffd` section of `workspace/configuration`
+///
+/// Unknown keys are ignored and missing keys take their default, such that
+/// partial settings objects from the client are always accepted
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ServerConfig {
+    /// Opts in to reporting internal errors and panics to the client
+    /// through `telemetry/event`
+    pub telemetry: bool,
+    /// Opts in to periodically writing modified open buffers to a temporary
+    /// spill directory, such that `ruffd recover` can retrieve them should
+    /// the server or editor crash
+    pub spill: bool,
+    /// Offers quick fixes for fixable checks, taking effect on
+    /// initialization only
+    pub code_actions: bool,
+    /// Largest document in characters that is buffered and linted as it's
+    /// edited, larger documents are only linted on save
+    pub max_document_size: usize,
+    /// Serves diagnostics as clients pull them rather than publishing them,
+    /// taking effect on initialization only and if the client supports it
+    pub pull_diagnostics: bool,
+    /// Serves the callers and callees of functions through the call
+    /// hierarchy, taking effect on initialization only. Calls are only
+    /// followed within a file, such that callers in other files are missed
+    pub call_hierarchy: bool,
+    /// Roots of first-party imports relative to the project root, in
+    /// addition to those of ruff's settings. Inferred from the layout of
+    /// the project when empty
+    pub src: Vec<PathBuf>,
+    /// Lints a document on `willSave` before handling further messages,
+    /// such that code actions run as part of saving see its current checks
+    pub lint_on_will_save: bool,
+    /// Rule selection layered over that of the pyproject
+    pub lint: LintConfig,
+    /// Named rule selections such as `strict` or `ci`, the active one
+    /// being layered over `lint`
+    pub profiles: HashMap<String, LintConfig>,
+    /// Active profile, otherwise that selected by the `--profile` flag or
+    /// the `ruffd.selectProfile` command
+    pub profile: Option<String>,
+    /// Focuses the first location changed by `ruffd.fixAll` once applied,
+    /// through `window/showDocument` if the client supports it
+    pub show_document_after_fix: bool,
+    /// Memory in bytes held by checks of documents that aren't open, such
+    /// as those linted across the workspace, beyond which the least
+    /// recently used are dropped until the document is linted again
+    pub checks_memory_budget: usize,
+    /// Closed documents whose checks are kept, such that reopening them is
+    /// instant and their diagnostics stay published, those closed less
+    /// recently being cleared. Kept checks count towards
+    /// `checks_memory_budget`
+    pub recently_closed: usize,
+    /// Glob patterns of paths whose diagnostics aren't reported, such as
+    /// `**/migrations/*.py`, matched relative to the project root.
+    /// Independent of ruff's `exclude`, such that the command line still
+    /// lints them
+    pub suppress_diagnostics: Vec<String>,
+    /// Detection of generated files and the handling of their checks
+    pub generated_files: GeneratedFilesConfig,
+    /// Fixes all fixable checks of a document as it's saved. Returning the
+    /// fixes from `willSaveWaitUntil` takes effect on initialization only
+    pub fix_on_save: FixOnSave,
+    /// `logLevel`, `config` and `runMode`, taking precedence over the
+    /// pyproject but not the environment or command line
+    #[serde(flatten)]
+    pub layer: ConfigLayer,
+}
+
+/// How fixes are applied to a document being saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FixOnSave {
+    /// Returns the fixes as the edits of `willSaveWaitUntil`, such that
+    /// they're part of the saved text
+    WillSaveWaitUntil,
+    /// Applies the fixes through `workspace/applyEdit` once the document is
+    /// saved, for clients timing out `willSaveWaitUntil`. The fixed
+    /// document is left modified
+    ApplyEdit,
+    Off,
+}
+
+/// Files are taken as generated if one of the markers appears, regardless
+/// of case, within their first lines
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GeneratedFilesConfig {
+    pub markers: Vec<String>,
+    /// Number of lines searched for a marker
+    pub lines: usize,
+    pub action: GeneratedFileAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GeneratedFileAction {
+    /// Reports the checks of generated files as those of any other file
+    Report,
+    /// Reports the checks of generated files without their fixes, such
+    /// that generated code isn't edited by quick fixes or fixing all
+    Unfixable,
+    /// Reports the checks of generated files as hints without their fixes,
+    /// such that they're shown without drawing attention
+    Hint,
+    /// Reports no checks for generated files
+    Suppress,
+}
+
+impl Default for GeneratedFilesConfig {
+    fn default() -> Self {
+        Self {
+            markers: vec!["# generated".to_string(), "@generated".to_string()],
+            lines: 5,
+            action: GeneratedFileAction::Unfixable,
+        }
+    }
+}
+
+/// Rule selection overrides from the editor, taking precedence over the
+/// pyproject as ruff's `--select`, `--extend-select` and `--ignore` flags do
+///
+/// Each replaces the pyproject's value of the same name when not empty
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LintConfig {
+    pub select: Vec<String>,
+    pub extend_select: Vec<String>,
+    pub ignore: Vec<String>,
+}
+
+/// Parses rule codes and prefixes, skipping those ruff doesn't recognise
+fn code_prefixes(codes: &[String]) -> Vec<CheckCodePrefix> {
+    codes
+        .iter()
+        .filter_map(|x| match CheckCodePrefix::from_str(x) {
+            Ok(prefix) => Some(prefix),
+            Err(_) => {
+                log_warn!("ignoring unknown rule selector {}", x);
+                None
+            }
+        })
+        .collect()
+}
+
+impl LintConfig {
+    /// Layers the selections of `self` over those of `base`, each replacing
+    /// the value of the same name when not empty
+    pub fn layered_over(&self, base: &LintConfig) -> LintConfig {
+        let layer = |x: &Vec<String>, base: &Vec<String>| match x.is_empty() {
+            true => base.clone(),
+            false => x.clone(),
+        };
+        LintConfig {
+            select: layer(&self.select, &base.select),
+            extend_select: layer(&self.extend_select, &base.extend_select),
+            ignore: layer(&self.ignore, &base.ignore),
+        }
+    }
+
+    /// Applies the overrides to a configuration read from a pyproject
+    pub fn apply(&self, configuration: &mut Configuration) {
+        if !self.select.is_empty() {
+            configuration.select = code_prefixes(&self.select);
+        }
+        if !self.extend_select.is_empty() {
+            configuration.extend_select = code_prefixes(&self.extend_select);
+        }
+        if !self.ignore.is_empty() {
+            configuration.ignore = code_prefixes(&self.ignore);
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            telemetry: false,
+            spill: false,
+            code_actions: true,
+            max_document_size: DEFAULT_MAX_DOCUMENT_SIZE,
+            pull_diagnostics: false,
+            call_hierarchy: false,
+            src: vec![],
+            lint_on_will_save: false,
+            lint: LintConfig::default(),
+            profiles: HashMap::new(),
+            profile: None,
+            show_document_after_fix: false,
+            checks_memory_budget: DEFAULT_CHECKS_MEMORY_BUDGET,
+            recently_closed: DEFAULT_RECENTLY_CLOSED,
+            suppress_diagnostics: vec![],
+            generated_files: GeneratedFilesConfig::default(),
+            fix_on_save: FixOnSave::Off,
+            layer: ConfigLayer::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads settings from a client supplied value
+    ///
+    /// Accepts either the settings object directly or an object wrapping
+    /// the settings under the `ruffd` section, as sent by clients using
+    /// `workspace/didChangeConfiguration`
+    pub fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        let value = match value {
+            serde_json::Value::Object(mut map) if map.contains_key(CONFIG_SECTION) => {
+                map.remove(CONFIG_SECTION).unwrap()
+            }
+            x => x,
+        };
+        serde_json::from_value(value)
+    }
+
+    /// Rule selection of the active profile layered over `lint`, or `lint`
+    /// alone if no profile is active or the active one isn't defined
+    pub fn lint_config(&self) -> LintConfig {
+        match self.profile.as_ref().and_then(|x| self.profiles.get(x)) {
+            Some(profile) => profile.layered_over(&self.lint),
+            None => self.lint.clone(),
+        }
+    }
+
+    /// Keeps the active profile of `previous` if these settings don't name
+    /// one, such that a profile selected at runtime outlives settings
+    /// changes
+    pub fn inherit_profile(&mut self, previous: &ServerConfig) {
+        if self.profile.is_none() {
+            self.profile = previous.profile.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::project::{LogLevel, RunMode};
+
+    #[test]
+    fn test_lint_config() {
+        let codes = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let mut config = ServerConfig::from_value(serde_json::json!({
+            "lint": {"select": ["E", "F"], "ignore": ["E501"]},
+            "profiles": {"strict": {"select": ["ALL"]}, "editor": {"ignore": ["F401"]}},
+        }))
+        .unwrap();
+        assert_eq!(config.lint_config(), config.lint);
+        config.profile = Some("strict".to_string());
+        assert_eq!(config.lint_config().select, codes(&["ALL"]));
+        assert_eq!(config.lint_config().ignore, codes(&["E501"]));
+        config.profile = Some("editor".to_string());
+        assert_eq!(config.lint_config().select, codes(&["E", "F"]));
+        assert_eq!(config.lint_config().ignore, codes(&["F401"]));
+        config.profile = Some("unknown".to_string());
+        assert_eq!(config.lint_config(), config.lint);
+        let mut new_config = ServerConfig::default();
+        new_config.inherit_profile(&config);
+        assert_eq!(new_config.profile.as_deref(), Some("unknown"));
+    }
+
+    #[test]
+    fn test_fix_on_save() {
+        let config =
+            ServerConfig::from_value(serde_json::json!({"fixOnSave": "willSaveWaitUntil"}))
+                .unwrap();
+        assert_eq!(config.fix_on_save, FixOnSave::WillSaveWaitUntil);
+        assert_eq!(ServerConfig::default().fix_on_save, FixOnSave::Off);
+        assert!(ServerConfig::from_value(serde_json::json!({"fixOnSave": "always"})).is_err());
+    }
+
+    #[test]
+    fn test_config_layer() {
+        let config = ServerConfig::from_value(serde_json::json!({
+            "logLevel": "debug",
+            "runMode": "onSave",
+            "telemetry": true,
+        }))
+        .unwrap();
+        assert!(config.telemetry);
+        assert_eq!(
+            config.layer,
+            ConfigLayer {
+                log_level: Some(LogLevel::Debug),
+                config: None,
+                run_mode: Some(RunMode::OnSave),
+            }
+        );
+    }
+}
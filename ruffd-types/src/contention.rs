@@ -0,0 +1,109 @@
+//! Time handlers wait to lock fields of the server state
+//!
+//! Waits are aggregated by handler and field for the lifetime of the
+//! process, such that contention can be read off of `ruffd/status` rather
+//! than guessed at. Waits longer than [`WARN_THRESHOLD`] are also logged as
+//! they happen, under the trace of the handler
+use crate::extensions::LockWait;
+use crate::log_warn;
+use std::cmp::Reverse;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Waits longer than this are logged
+pub const WARN_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// Handler of waits recorded outside of [`handling`]
+const UNKNOWN_HANDLER: &str = "unknown";
+
+static LOCK_WAITS: Mutex<Vec<LockWait>> = Mutex::new(Vec::new());
+
+tokio::task_local! {
+    static HANDLER: String;
+}
+
+/// Runs `fut`, recording the locks it waits for against `handler`
+pub async fn handling<F: Future>(handler: &str, fut: F) -> F::Output {
+    HANDLER.scope(handler.to_string(), fut).await
+}
+
+/// Records a wait of `waited` to lock `field`, against the handler of the
+/// running task
+pub fn record(field: &'static str, waited: Duration) {
+    let handler = HANDLER
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| UNKNOWN_HANDLER.to_string());
+    if waited > WARN_THRESHOLD {
+        log_warn!(
+            "{} waited {}ms to lock {}",
+            handler,
+            waited.as_millis(),
+            field
+        );
+    }
+    let micros = waited.as_micros() as u64;
+    let mut waits = LOCK_WAITS.lock().unwrap();
+    match waits
+        .iter_mut()
+        .find(|x| x.handler == handler && x.field == field)
+    {
+        Some(wait) => {
+            wait.count += 1;
+            wait.total_micros += micros;
+            wait.max_micros = wait.max_micros.max(micros);
+        }
+        None => waits.push(LockWait {
+            handler,
+            field: field.to_string(),
+            count: 1,
+            total_micros: micros,
+            max_micros: micros,
+        }),
+    }
+}
+
+/// Forgets the recorded waits, as once the session they were of is lost
+pub fn reset() {
+    LOCK_WAITS.lock().unwrap().clear();
+}
+
+/// Waits of each handler for each field, the longest in total first
+pub fn lock_waits() -> Vec<LockWait> {
+    let mut waits = LOCK_WAITS.lock().unwrap().clone();
+    waits.sort_by_key(|x| Reverse(x.total_micros));
+    waits
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wait_of(handler: &str, field: &str) -> Option<LockWait> {
+        lock_waits()
+            .into_iter()
+            .find(|x| x.handler == handler && x.field == field)
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        handling("test/record", async {
+            record("open_buffers", Duration::from_micros(30));
+            record("open_buffers", Duration::from_micros(10));
+            record("config", Duration::from_millis(150));
+        })
+        .await;
+        let wait = wait_of("test/record", "open_buffers").unwrap();
+        assert_eq!(
+            (wait.count, wait.total_micros, wait.max_micros),
+            (2, 40, 30)
+        );
+        assert_eq!(
+            wait_of("test/record", "config").unwrap().max_micros,
+            150_000
+        );
+        // waits outside of a handler are still counted
+        record("ast_cache", Duration::ZERO);
+        assert!(wait_of(UNKNOWN_HANDLER, "ast_cache").is_some());
+    }
+}
@@ -0,0 +1,185 @@
+//! Log of the edits applied to a `DocumentBuffer`, mapping positions of an
+//! earlier revision of the buffer to where their text is now
+//!
+//! Positions are pairs of row and column, 0-indexed. Columns are counted in
+//! whatever unit the edits are, being chars for the edits of a buffer
+use std::collections::VecDeque;
+
+/// Replacement of the text from `start` to `end` by text ending at `new_end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditDelta {
+    pub start: (usize, usize),
+    /// End of the replaced text, before the edit
+    pub end: (usize, usize),
+    /// End of the inserted text, after the edit
+    pub new_end: (usize, usize),
+}
+
+impl EditDelta {
+    pub fn new(start: (usize, usize), end: (usize, usize), new_end: (usize, usize)) -> Self {
+        Self {
+            start,
+            end,
+            new_end,
+        }
+    }
+
+    /// Delta of inserting text of rows with the given lengths at `start`,
+    /// the rows being split at line breaks
+    pub fn insertion(start: (usize, usize), row_lens: &[usize]) -> Self {
+        let new_end = match row_lens {
+            [] => start,
+            [len] => (start.0, start.1 + len),
+            [.., last] => (start.0 + row_lens.len() - 1, *last),
+        };
+        Self::new(start, start, new_end)
+    }
+
+    pub fn deletion(start: (usize, usize), end: (usize, usize)) -> Self {
+        Self::new(start, end, start)
+    }
+
+    /// Net change to the number of rows
+    pub fn line_delta(&self) -> isize {
+        self.new_end.0 as isize - self.end.0 as isize
+    }
+
+    /// Net change to the columns following the edit on its last row
+    pub fn char_delta(&self) -> isize {
+        self.new_end.1 as isize - self.end.1 as isize
+    }
+
+    /// Position of the text at `position` once edited, positions within
+    /// the replaced text being moved to its start
+    pub fn map(&self, position: (usize, usize)) -> (usize, usize) {
+        if position < self.start {
+            position
+        } else if position < self.end {
+            self.start
+        } else if position.0 == self.end.0 {
+            (self.new_end.0, self.new_end.1 + position.1 - self.end.1)
+        } else {
+            (position.0 - self.end.0 + self.new_end.0, position.1)
+        }
+    }
+
+    /// Whether the edit removes the text from `start` to `end` whole
+    pub fn removes(&self, start: (usize, usize), end: (usize, usize)) -> bool {
+        self.start < self.end && self.start <= start && end <= self.end
+    }
+
+    /// Whether the edit replaces text within `start` to `end`, an insertion
+    /// only overlapping a span it's strictly within
+    pub fn overlaps(&self, start: (usize, usize), end: (usize, usize)) -> bool {
+        start < self.end && self.start < end
+    }
+}
+
+/// Edits of the most recent revisions, each edit being a revision
+#[derive(Debug, Clone)]
+pub struct EditLog {
+    deltas: VecDeque<EditDelta>,
+    /// Revision the oldest delta applies to
+    base: usize,
+    capacity: usize,
+}
+
+impl EditLog {
+    /// Log of the edits made from `revision`, keeping at most `capacity`
+    pub fn new(revision: usize, capacity: usize) -> Self {
+        Self {
+            deltas: VecDeque::with_capacity(capacity),
+            base: revision,
+            capacity,
+        }
+    }
+
+    /// Revision following the last logged edit
+    pub fn revision(&self) -> usize {
+        self.base + self.deltas.len()
+    }
+
+    pub fn push(&mut self, delta: EditDelta) {
+        if self.capacity == 0 {
+            self.base += 1;
+            return;
+        }
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+            self.base += 1;
+        }
+        self.deltas.push_back(delta);
+    }
+
+    /// Forgets the logged edits, as once the text is replaced whole, with
+    /// the replacement taken as the edit making `revision`
+    pub fn reset(&mut self, revision: usize) {
+        self.deltas.clear();
+        self.base = revision;
+    }
+
+    /// Edits made since `revision`, `None` if some are no longer logged
+    pub fn since(&self, revision: usize) -> Option<impl Iterator<Item = &EditDelta>> {
+        if revision < self.base || revision > self.revision() {
+            return None;
+        }
+        Some(self.deltas.iter().skip(revision - self.base))
+    }
+
+    /// Maps `position` of the text at `revision` through the edits since
+    pub fn map_position(
+        &self,
+        position: (usize, usize),
+        revision: usize,
+    ) -> Option<(usize, usize)> {
+        Some(self.since(revision)?.fold(position, |acc, x| x.map(acc)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_edit_delta() {
+        let typed = EditDelta::insertion((1, 4), &[3]);
+        assert_eq!((typed.line_delta(), typed.char_delta()), (0, 3));
+        assert_eq!(typed.map((1, 2)), (1, 2));
+        assert_eq!(typed.map((1, 4)), (1, 7));
+        assert_eq!(typed.map((2, 4)), (2, 4));
+        assert!(!typed.overlaps((1, 4), (1, 6)));
+        assert!(typed.overlaps((1, 2), (1, 6)));
+        // joining lines moves the rest of the joined line up
+        let joined = EditDelta::deletion((0, 9), (1, 0));
+        assert_eq!((joined.line_delta(), joined.char_delta()), (-1, 9));
+        assert_eq!(joined.map((1, 3)), (0, 12));
+        assert_eq!(joined.map((3, 3)), (2, 3));
+        assert!(joined.removes((0, 9), (1, 0)));
+        assert!(!joined.removes((0, 9), (1, 3)));
+        let broken = EditDelta::new((2, 1), (2, 3), (3, 1));
+        assert_eq!(broken.map((2, 2)), (2, 1));
+        assert_eq!(broken.map((2, 5)), (3, 3));
+        assert_eq!(broken.map((4, 0)), (5, 0));
+        assert!(broken.overlaps((2, 0), (2, 2)));
+        assert!(!broken.overlaps((2, 3), (2, 6)));
+    }
+
+    #[test]
+    fn test_edit_log() {
+        let mut log = EditLog::new(4, 2);
+        log.push(EditDelta::insertion((0, 0), &[2]));
+        log.push(EditDelta::insertion((0, 0), &[0, 0]));
+        assert_eq!(log.revision(), 6);
+        assert_eq!(log.map_position((0, 1), 4), Some((1, 3)));
+        assert_eq!(log.map_position((0, 1), 5), Some((1, 1)));
+        assert_eq!(log.map_position((0, 1), 6), Some((0, 1)));
+        assert_eq!(log.map_position((0, 1), 7), None);
+        // the oldest edit is dropped once the log is full
+        log.push(EditDelta::deletion((0, 0), (1, 0)));
+        assert_eq!(log.map_position((0, 1), 4), None);
+        assert_eq!(log.map_position((1, 1), 5), Some((1, 1)));
+        log.reset(8);
+        assert_eq!(log.map_position((1, 1), 5), None);
+        assert_eq!(log.map_position((1, 1), 8), Some((1, 1)));
+    }
+}
@@ -1,65 +1,78 @@
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::fmt;
 use std::io;
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Debug, Clone)]
 pub struct RpcError {
     pub code: i64,
-    pub message: &'static str,
+    pub message: Cow<'static, str>,
+    pub data: Option<serde_json::Value>,
+}
+
+impl RpcError {
+    pub const fn new_static(code: i64, message: &'static str) -> Self {
+        Self {
+            code,
+            message: Cow::Borrowed(message),
+            data: None,
+        }
+    }
+
+    pub fn new<S: Into<Cow<'static, str>>>(code: i64, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Replaces the message, keeping the error code
+    pub fn with_message<S: Into<Cow<'static, str>>>(mut self, message: S) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Attaches additional information about the error for the client
+    pub fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
+    }
 }
 
 pub struct RpcErrors {}
 
 impl RpcErrors {
-    pub const PARSE_ERROR: RpcError = RpcError {
-        code: -32700,
-        message: "Parse error",
-    };
-    pub const INVALID_REQUEST: RpcError = RpcError {
-        code: -32600,
-        message: "Invalid request",
-    };
-    pub const METHOD_NOT_FOUND: RpcError = RpcError {
-        code: -32601,
-        message: "Method not found",
-    };
-    pub const INVALID_PARAMS: RpcError = RpcError {
-        code: -32602,
-        message: "Invalid params",
-    };
-    pub const INTERNAL_ERROR: RpcError = RpcError {
-        code: -32603,
-        message: "Internal error",
-    };
-    pub const SERVER_NOT_INITIALIZED: RpcError = RpcError {
-        code: -32002,
-        message: "Server not initialized",
-    };
-    pub const UNKNOWN_ERROR_CODE: RpcError = RpcError {
-        code: -32001,
-        message: "Unknown error code",
-    };
-    pub const REQUEST_FAILED: RpcError = RpcError {
-        code: -32803,
-        message: "Request failed",
-    };
-    pub const SERVER_CANCELLED: RpcError = RpcError {
-        code: -32802,
-        message: "Server cancelled",
-    };
-    pub const CONTENT_MODIFIED: RpcError = RpcError {
-        code: lsp_types::error_codes::CONTENT_MODIFIED,
-        message: "Content modified",
-    };
-    pub const REQUEST_CANCELLED: RpcError = RpcError {
-        code: lsp_types::error_codes::REQUEST_CANCELLED,
-        message: "Request cancelled",
-    };
+    pub const PARSE_ERROR: RpcError = RpcError::new_static(-32700, "Parse error");
+    pub const INVALID_REQUEST: RpcError = RpcError::new_static(-32600, "Invalid request");
+    pub const METHOD_NOT_FOUND: RpcError = RpcError::new_static(-32601, "Method not found");
+    pub const INVALID_PARAMS: RpcError = RpcError::new_static(-32602, "Invalid params");
+    pub const INTERNAL_ERROR: RpcError = RpcError::new_static(-32603, "Internal error");
+    pub const SERVER_NOT_INITIALIZED: RpcError =
+        RpcError::new_static(-32002, "Server not initialized");
+    pub const UNKNOWN_ERROR_CODE: RpcError = RpcError::new_static(-32001, "Unknown error code");
+    pub const REQUEST_FAILED: RpcError = RpcError::new_static(-32803, "Request failed");
+    pub const SERVER_CANCELLED: RpcError = RpcError::new_static(-32802, "Server cancelled");
+    pub const CONTENT_MODIFIED: RpcError =
+        RpcError::new_static(lsp_types::error_codes::CONTENT_MODIFIED, "Content modified");
+    pub const REQUEST_CANCELLED: RpcError = RpcError::new_static(
+        lsp_types::error_codes::REQUEST_CANCELLED,
+        "Request cancelled",
+    );
+    /// Passed to the response handler of a server request the client
+    /// didn't answer in time, never sent to the client
+    pub const RESPONSE_TIMED_OUT: RpcError = RpcError::new_static(-32000, "Response timed out");
 }
 
 #[derive(Error, Debug)]
 pub enum AggAvlTreeError {
     #[error("Index out of bounds")]
     IndexOutOfBounds,
+    #[error("Invariant violated: {0}")]
+    InvariantViolation(&'static str),
 }
 
 #[derive(Error, Debug)]
@@ -76,6 +89,8 @@ pub enum DocumentError {
     RowOutOfBounds,
     #[error("Column out of bounds")]
     ColOutOfBounds,
+    #[error("Column splits a character")]
+    SplitCharacter,
     #[error(transparent)]
     AggAvlTreeError(#[from] AggAvlTreeError),
     #[error(transparent)]
@@ -96,6 +111,35 @@ pub enum RuntimeError {
     InternalError(#[from] anyhow::Error),
     #[error("Cannot convert uri to path: {0}")]
     UriToPathError(lsp_types::Url),
+    #[error("Invalid settings: {0}")]
+    InvalidSettings(serde_json::Error),
+    #[error("Cannot read '{}': {1}", .0.display())]
+    FileReadError(PathBuf, io::Error),
+    #[error("Unknown command {0}")]
+    UnknownCommand(String),
+    #[error("Invalid arguments to command {0}")]
+    InvalidCommandArguments(String),
+    #[error("Checks of '{0}' are of an earlier version of it")]
+    StaleChecks(lsp_types::Url),
+}
+
+impl RuntimeError {
+    /// Name of the error variant, free of any user data
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UnknownEncoding(_) => "UnknownEncoding",
+            Self::DocumentError(_) => "DocumentError",
+            Self::EditUnopenedDocument(_) => "EditUnopenedDocument",
+            Self::UnexpectedNone => "UnexpectedNone",
+            Self::InternalError(_) => "InternalError",
+            Self::UriToPathError(_) => "UriToPathError",
+            Self::InvalidSettings(_) => "InvalidSettings",
+            Self::FileReadError(..) => "FileReadError",
+            Self::UnknownCommand(_) => "UnknownCommand",
+            Self::InvalidCommandArguments(_) => "InvalidCommandArguments",
+            Self::StaleChecks(_) => "StaleChecks",
+        }
+    }
 }
 
 impl From<io::Error> for RpcError {
@@ -105,17 +149,110 @@ impl From<io::Error> for RpcError {
 }
 
 impl From<serde_json::Error> for RpcError {
-    fn from(_: serde_json::Error) -> Self {
-        RpcErrors::PARSE_ERROR
+    fn from(err: serde_json::Error) -> Self {
+        RpcErrors::PARSE_ERROR.with_message(err.to_string())
     }
 }
 
 impl From<RuntimeError> for RpcError {
     fn from(err: RuntimeError) -> Self {
-        // tmp logging for runtime errors
-        dbg!(err);
+        crate::log_error!("{:?}", err);
+        let mut data = serde_json::json!({ "kind": err.kind() });
+        if let RuntimeError::EditUnopenedDocument(uri)
+        | RuntimeError::UriToPathError(uri)
+        | RuntimeError::StaleChecks(uri) = &err
+        {
+            data["uri"] = serde_json::json!(uri);
+        }
         RpcErrors::INTERNAL_ERROR
+            .with_message(err.to_string())
+            .with_data(data)
     }
 }
 
 pub type RpcResult<T> = Result<T, RpcError>;
+
+/// Deserializes the params of a message, failing with `INVALID_PARAMS`
+///
+/// The error's data holds the path to the offending value, e.g.
+/// `position.line`, along with the type expected there, and the params
+/// are logged at debug level, such that client authors can tell what was
+/// rejected
+pub fn params_from_value<T: DeserializeOwned>(value: serde_json::Value) -> RpcResult<T> {
+    serde_path_to_error::deserialize(&value).map_err(|err| invalid_params(err, &value))
+}
+
+/// Deserializes params as received, without first reading them into a
+/// `serde_json::Value`, failing as `params_from_value` does
+pub fn params_from_raw<T: DeserializeOwned>(raw: &RawValue) -> RpcResult<T> {
+    let mut deserializer = serde_json::Deserializer::from_str(raw.get());
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| invalid_params(err, &raw))
+}
+
+fn invalid_params(
+    err: serde_path_to_error::Error<serde_json::Error>,
+    params: &dyn fmt::Display,
+) -> RpcError {
+    let path = err.path().to_string();
+    let err = err.into_inner();
+    let mut message = err.to_string();
+    // errors of raw params end with their location, which the path gives
+    let location = format!(" at line {} column {}", err.line(), err.column());
+    if err.line() != 0 && message.ends_with(&location) {
+        message.truncate(message.len() - location.len());
+    }
+    crate::log_debug!("invalid params at {}: {}", path, params);
+    let mut data = serde_json::json!({ "path": path, "message": message });
+    // serde's messages end with the type expected, if there is one
+    if let Some((_, expected)) = message.rsplit_once(", expected ") {
+        data["expected"] = serde_json::json!(expected);
+    }
+    RpcErrors::INVALID_PARAMS
+        .with_message(format!("{} at {}", message, path))
+        .with_data(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_params_from_value() {
+        let params = serde_json::json!({
+            "textDocument": {"uri": "file:///tmp/a.py"},
+            "position": {"line": "0", "character": 0},
+        });
+        let err = params_from_value::<lsp_types::TextDocumentPositionParams>(params).unwrap_err();
+        assert_eq!(err.code, RpcErrors::INVALID_PARAMS.code);
+        let data = err.data.unwrap();
+        assert_eq!(data["path"], "position.line");
+        assert_eq!(data["expected"], "u32");
+        assert!(err.message.ends_with(" at position.line"));
+        let params = serde_json::json!({"textDocument": {"uri": "file:///tmp/a.py"}});
+        let err = params_from_value::<lsp_types::TextDocumentPositionParams>(params).unwrap_err();
+        assert_eq!(err.data.unwrap()["message"], "missing field `position`");
+        let params = serde_json::json!({
+            "textDocument": {"uri": "file:///tmp/a.py"},
+            "position": {"line": 2, "character": 4},
+        });
+        let params = params_from_value::<lsp_types::TextDocumentPositionParams>(params).unwrap();
+        assert_eq!(params.position, lsp_types::Position::new(2, 4));
+    }
+
+    #[test]
+    fn test_params_from_raw() {
+        let raw = RawValue::from_string(
+            r#"{"textDocument": {"uri": "file:///tmp/a.py"}, "position": {"line": "0", "character": 0}}"#
+                .to_string(),
+        )
+        .unwrap();
+        let err = params_from_raw::<lsp_types::TextDocumentPositionParams>(&raw).unwrap_err();
+        let value = serde_json::from_str(raw.get()).unwrap();
+        let value_err =
+            params_from_value::<lsp_types::TextDocumentPositionParams>(value).unwrap_err();
+        // reported the same regardless of how params are held
+        assert_eq!(err.message, value_err.message);
+        assert_eq!(err.data, value_err.data);
+        assert_eq!(err.data.unwrap()["expected"], "u32");
+    }
+}
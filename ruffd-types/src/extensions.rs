@@ -0,0 +1,350 @@
+//! Types for the custom protocol extensions served by ruffd
+//!
+//! Each extension is described with the `lsp_types` request / notification
+//! traits such that clients written in rust can reuse them directly
+use crate::layered::ResolvedConfig;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Command of `workspace/executeCommand` applying every fix of a document
+/// through `workspace/applyEdit`, taking either the document's uri or
+/// `FixAllParams` as its argument
+pub const FIX_ALL_COMMAND: &str = "ruffd.fixAll";
+
+/// Command of `workspace/executeCommand` previewing a single fix without
+/// applying it, taking `PreviewFixParams` as its argument and returning a
+/// `FixPreview`
+pub const PREVIEW_FIX_COMMAND: &str = "ruffd.previewFix";
+
+/// Command of `workspace/executeCommand` activating the configuration
+/// profile named by its argument, or deactivating profiles given `null`,
+/// then re-linting under the resulting rule selection
+pub const SELECT_PROFILE_COMMAND: &str = "ruffd.selectProfile";
+
+/// Fix to preview, identified by its diagnostic as in the data of lazily
+/// resolved quick fixes
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewFixParams {
+    pub uri: lsp_types::Url,
+    /// Rule code of the diagnostic e.g. `F401`
+    pub code: String,
+    /// Range of the diagnostic
+    pub range: lsp_types::Range,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixAllParams {
+    pub uri: lsp_types::Url,
+    #[serde(default)]
+    pub format: FixAllFormat,
+}
+
+/// How the fixes of `ruffd.fixAll` are given
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FixAllFormat {
+    /// Applied through `workspace/applyEdit`, the command returning `null`
+    #[default]
+    Edit,
+    /// Returned as a unified diff of the document before and after the
+    /// fixes, nothing being applied
+    Diff,
+}
+
+/// Outcome of applying a fix to a copy of its document
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixPreview {
+    /// Unified diff of the document before and after the fix
+    pub diff: String,
+    /// Diagnostics cleared by the fix, as published before it
+    pub removed: Vec<lsp_types::Diagnostic>,
+    /// Diagnostics introduced by the fix, positioned in the fixed document
+    pub added: Vec<lsp_types::Diagnostic>,
+}
+
+pub enum RuleInfoRequest {}
+
+impl lsp_types::request::Request for RuleInfoRequest {
+    type Params = RuleInfoParams;
+    type Result = Option<RuleInfo>;
+    const METHOD: &'static str = "ruffd/ruleInfo";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleInfoParams {
+    /// Rule code as reported in diagnostics e.g. `F401`
+    pub code: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleInfo {
+    pub code: String,
+    pub name: String,
+    pub category: String,
+    pub explanation: String,
+    pub fixable: bool,
+    pub default_severity: lsp_types::DiagnosticSeverity,
+}
+
+pub enum RuleExplainRequest {}
+
+impl lsp_types::request::Request for RuleExplainRequest {
+    type Params = RuleExplainParams;
+    /// Markdown explanation, `None` if the rule isn't recognised
+    type Result = Option<lsp_types::MarkupContent>;
+    const METHOD: &'static str = "ruffd/executeRuleExplain";
+}
+
+/// Rule to explain, given either as a published diagnostic or as a rule
+/// code with the range it was reported at
+///
+/// The code of `diagnostic` takes precedence over `code`. With a document
+/// and a range, the explanation quotes the lines reported
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleExplainParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_document: Option<lsp_types::TextDocumentIdentifier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostic: Option<lsp_types::Diagnostic>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<lsp_types::Range>,
+}
+
+impl RuleExplainParams {
+    /// Rule code to explain
+    pub fn rule_code(&self) -> Option<String> {
+        let from_diagnostic =
+            self.diagnostic
+                .as_ref()
+                .and_then(|x| x.code.as_ref())
+                .map(|x| match x {
+                    lsp_types::NumberOrString::String(x) => x.clone(),
+                    lsp_types::NumberOrString::Number(x) => x.to_string(),
+                });
+        from_diagnostic.or_else(|| self.code.clone())
+    }
+
+    /// Range the rule was reported at
+    pub fn reported_range(&self) -> Option<lsp_types::Range> {
+        self.diagnostic.as_ref().map(|x| x.range).or(self.range)
+    }
+}
+
+pub enum ProfileLintRequest {}
+
+impl lsp_types::request::Request for ProfileLintRequest {
+    type Params = ProfileLintParams;
+    type Result = ProfileLintReport;
+    const METHOD: &'static str = "ruffd/profileLint";
+}
+
+/// Runs the lint pipeline over an open document repeatedly, timing each of
+/// its stages, for attaching to reports of slow linting
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileLintParams {
+    pub text_document: lsp_types::TextDocumentIdentifier,
+    /// Number of runs, defaulting to 10 and capped at 1000
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iterations: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileLintReport {
+    pub iterations: u32,
+    /// Number of checks of each run
+    pub checks: usize,
+    /// Timings of each stage in the order they run
+    pub stages: Vec<StageTiming>,
+}
+
+/// Timings of a stage over every run, in microseconds
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StageTiming {
+    /// One of `snapshot`, `parse`, `check`, `diagnostics` and `serialize`
+    pub stage: String,
+    pub total_micros: u64,
+    pub mean_micros: u64,
+    pub min_micros: u64,
+    pub max_micros: u64,
+}
+
+pub enum DocumentStatusRequest {}
+
+impl lsp_types::request::Request for DocumentStatusRequest {
+    type Params = ();
+    type Result = Vec<DocumentStatusReport>;
+    const METHOD: &'static str = "ruffd/documentStatus";
+}
+
+/// Synchronisation state of an open document as seen by the server, for
+/// client status bars and debugging sync mismatches
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentStatusReport {
+    pub uri: lsp_types::Url,
+    pub version: i32,
+    pub saved_version: Option<i32>,
+    pub dirty: bool,
+    /// Version announced by `willSave` that is yet to be saved
+    pub pending_save: Option<i32>,
+    /// Whether the document is too large to be linted as it's edited
+    pub oversized: bool,
+    /// Whether ruff panicked linting the document's current content
+    pub lint_failed: bool,
+}
+
+pub enum ServerStatusRequest {}
+
+impl lsp_types::request::Request for ServerStatusRequest {
+    type Params = ();
+    type Result = ServerStatus;
+    const METHOD: &'static str = "ruffd/status";
+}
+
+/// State of the server as a whole, for client status panes and attaching
+/// to bug reports
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    pub server_version: String,
+    pub ruff_version: String,
+    /// Pyprojects configuring ruff for the open documents
+    pub config_paths: Vec<PathBuf>,
+    pub open_documents: usize,
+    /// Work scheduled by the server that is yet to be handled
+    pub pending_tasks: usize,
+    /// Most recent lints, the latest first
+    pub recent_lints: Vec<LintTiming>,
+    /// Time handlers waited to lock each field of the state, the longest in
+    /// total first
+    pub lock_waits: Vec<LockWait>,
+    pub watcher: WatcherStatus,
+    /// Settings resolved over the environment, command line, client and
+    /// pyproject, along with the layer each was taken from
+    pub resolved_config: ResolvedConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LintTiming {
+    pub path: PathBuf,
+    pub micros: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockWait {
+    /// Method of the message handled, or the kind of work done by the server
+    pub handler: String,
+    pub field: String,
+    pub count: u64,
+    pub total_micros: u64,
+    pub max_micros: u64,
+}
+
+/// Whether the server is notified of changes to files on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WatcherStatus {
+    /// Registered for `workspace/didChangeWatchedFiles` on initialization
+    Registered,
+    /// Registration was requested, the client is yet to answer
+    Pending,
+    /// The client answered the registration with an error, or not at all
+    Failed,
+    /// The client can't register watchers, such that files changed outside
+    /// of the editor keep their checks until opened
+    #[default]
+    Unsupported,
+}
+
+pub enum LintWorkspaceRequest {}
+
+impl lsp_types::request::Request for LintWorkspaceRequest {
+    type Params = LintWorkspaceParams;
+    /// Number of files to be linted
+    type Result = usize;
+    const METHOD: &'static str = "ruffd/lintWorkspace";
+}
+
+/// Lints every indexed file that isn't open in the background, publishing
+/// diagnostics as each is linted
+///
+/// Progress is reported under the given token, or one created by the
+/// server if the client supports it, and may be cancelled through
+/// `window/workDoneProgress/cancel`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LintWorkspaceParams {
+    #[serde(flatten)]
+    pub work_done_progress_params: lsp_types::WorkDoneProgressParams,
+}
+
+pub enum IndexingStatusNotification {}
+
+impl lsp_types::notification::Notification for IndexingStatusNotification {
+    type Params = IndexingStatus;
+    const METHOD: &'static str = "ruffd/indexingStatus";
+}
+
+/// Progress of indexing or linting the workspace, published as it runs
+/// such that client status bars can show e.g. `ruffd: indexing 1450/3200
+/// files`, regardless of the client's support of work done progress
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexingStatus {
+    pub stage: IndexingStage,
+    /// Files handled so far
+    pub done: usize,
+    /// Files to handle, unknown while they're still being found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+    /// Whether the stage has ended, including by being cancelled
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IndexingStage {
+    /// Finding the Python files of the workspace
+    Indexing,
+    /// Linting the indexed files, as requested by `ruffd/lintWorkspace`
+    Linting,
+}
+
+/// Payload of the `telemetry/event` notifications reporting internal errors
+/// and panics, only sent when the client opts in through the `telemetry`
+/// setting
+///
+/// Contains no document contents, paths or formatted messages
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorTelemetry {
+    pub event: ErrorTelemetryEvent,
+    /// Category of the failure e.g. `EditUnopenedDocument`
+    pub kind: String,
+    /// Method of the handler that failed
+    pub handler: String,
+    pub server_version: String,
+    /// Panic message, only present for messages known at compile time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorTelemetryEvent {
+    Error,
+    Panic,
+}
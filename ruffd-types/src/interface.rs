@@ -1,7 +1,9 @@
-use crate::common::RpcResponseMessage;
+use crate::common::{Params, RpcRequest, RpcResponseMessage};
+use crate::logging::{current_trace, TraceId};
 use crate::state::{ServerState, ServerStateHandles, ServerStateLocks};
 use crate::RpcMessage;
 use std::future::Future;
+use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -11,35 +13,44 @@ type RequestExec = fn(
     state: ServerStateHandles<'_>,
     scheduler_channel: Sender<ScheduledTask>,
     id: lsp_types::NumberOrString,
-    params: Option<serde_json::Value>,
+    params: Option<Params>,
 ) -> Pin<Box<dyn Send + Future<Output = RpcResponseMessage> + '_>>;
 
 type NotificationExec = fn(
     state: ServerStateHandles<'_>,
     scheduler_channel: Sender<ScheduledTask>,
-    params: Option<serde_json::Value>,
+    params: Option<Params>,
 )
     -> Pin<Box<dyn Send + Future<Output = Option<RpcResponseMessage>> + '_>>;
 
 type CreateLocks =
     fn(state: Arc<Mutex<ServerState>>) -> Pin<Box<dyn Send + Future<Output = ServerStateLocks>>>;
 
+/// Resolving to `None` indicates there is nothing to send to the client
 pub type ServerNotificationExec = Box<
     dyn FnOnce(
             ServerStateHandles<'_>,
             Sender<ScheduledTask>,
-        ) -> Pin<Box<dyn Send + Future<Output = RpcMessage> + '_>>
+        ) -> Pin<Box<dyn Send + Future<Output = Option<RpcMessage>> + '_>>
         + Send,
 >;
 
+/// Produces the request to send to the client, the id is assigned by the
+/// service such that the response can be routed to the `ResponseHandler`
 pub type ServerRequestExec = Box<
     dyn FnOnce(
             ServerStateHandles<'_>,
             Sender<ScheduledTask>,
-        ) -> Pin<Box<dyn Send + Future<Output = RpcMessage> + '_>>
+            lsp_types::NumberOrString,
+        ) -> Pin<Box<dyn Send + Future<Output = RpcRequest> + '_>>
         + Send,
 >;
 
+/// Consumes the client's response to a server initiated request, optionally
+/// producing follow-up work to be scheduled, e.g. to update state with the
+/// response contents
+pub type ResponseHandler = Box<dyn FnOnce(RpcResponseMessage) -> Option<ServerInitiated> + Send>;
+
 pub type ServerWorkExec = Box<
     dyn FnOnce(
             ServerStateHandles<'_>,
@@ -53,16 +64,54 @@ pub type CreateLocksFn = Box<
         + Send,
 >;
 
+#[derive(Clone, Copy)]
 pub struct Request {
     pub exec: RequestExec,
     pub create_locks: CreateLocks,
 }
 
+#[derive(Clone, Copy)]
 pub struct Notification {
     pub exec: NotificationExec,
     pub create_locks: CreateLocks,
 }
 
+/// Handler along with the type of the params it deserializes, as produced
+/// by `#[request]` and `#[notification]` through `<handler>::typed()`
+///
+/// Handlers without params accept those of any method
+pub struct Typed<H, P> {
+    handler: H,
+    params: PhantomData<fn(P)>,
+}
+
+impl<H, P> Typed<H, P> {
+    pub const fn new(handler: H) -> Self {
+        Self {
+            handler,
+            params: PhantomData,
+        }
+    }
+}
+
+/// Registry entry of a handler of the request `R`, which only compiles if
+/// the handler takes the params of `R`
+pub fn request_entry<R>(typed: Typed<Request, R::Params>) -> (&'static str, Request)
+where
+    R: lsp_types::request::Request,
+{
+    (R::METHOD, typed.handler)
+}
+
+/// Registry entry of a handler of the notification `N`, which only
+/// compiles if the handler takes the params of `N`
+pub fn notification_entry<N>(typed: Typed<Notification, N::Params>) -> (&'static str, Notification)
+where
+    N: lsp_types::notification::Notification,
+{
+    (N::METHOD, typed.handler)
+}
+
 pub struct ServerNotification {
     pub exec: ServerNotificationExec,
     pub create_locks: CreateLocksFn,
@@ -71,6 +120,7 @@ pub struct ServerNotification {
 pub struct ServerRequest {
     pub exec: ServerRequestExec,
     pub create_locks: CreateLocksFn,
+    pub on_response: ResponseHandler,
 }
 
 pub struct ServerWork {
@@ -84,7 +134,36 @@ pub enum ServerInitiated {
     Work(ServerWork),
 }
 
+impl From<ServerNotification> for ServerInitiated {
+    fn from(notification: ServerNotification) -> Self {
+        Self::Notification(notification)
+    }
+}
+
+impl From<ServerRequest> for ServerInitiated {
+    fn from(request: ServerRequest) -> Self {
+        Self::Request(request)
+    }
+}
+
+impl From<ServerWork> for ServerInitiated {
+    fn from(work: ServerWork) -> Self {
+        Self::Work(work)
+    }
+}
+
 pub enum ScheduledTask {
-    Client(RpcMessage),
-    Server(ServerInitiated),
+    /// A client message, with the span and trace its handling is under
+    Client(RpcMessage, tracing::Span, TraceId),
+    /// Work of the server, with the trace its handling is under
+    Server(ServerInitiated, TraceId),
+}
+
+impl ScheduledTask {
+    /// Server work under the trace of the current task, such that records
+    /// logged handling it are attributed to the message it follows from.
+    /// Work scheduled outside of a trace starts its own
+    pub fn server(task: impl Into<ServerInitiated>) -> Self {
+        Self::Server(task.into(), current_trace().unwrap_or_else(TraceId::next))
+    }
 }
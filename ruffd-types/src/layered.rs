@@ -0,0 +1,262 @@
+//! Settings given through more than one of the environment, the command
+//! line, the client and the project's pyproject, resolved in that order of
+//! precedence
+//!
+//! The environment and the command line are fixed for the life of the
+//! process, so their layers are kept here rather than in the server state.
+//! The client's layer is that of its settings, given as
+//! `initializationOptions` or pulled later
+use crate::log_warn;
+use crate::logging::{set_max_level, DEFAULT_LEVEL, LOG_ENV_VAR};
+use crate::project::{LogLevel, ProjectConfig, RunMode};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// Name of the environment variable giving the pyproject read for the
+/// `[tool.ruffd]` table
+pub const CONFIG_ENV_VAR: &str = "RUFFD_CONFIG";
+/// Name of the environment variable selecting when documents are linted
+pub const RUN_MODE_ENV_VAR: &str = "RUFFD_RUN_MODE";
+
+static ENV_LAYER: RwLock<Option<ConfigLayer>> = RwLock::new(None);
+static CLI_LAYER: RwLock<Option<ConfigLayer>> = RwLock::new(None);
+
+/// Settings of a single layer, those unset deferring to the layers below
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ConfigLayer {
+    pub log_level: Option<LogLevel>,
+    /// Pyproject read for the `[tool.ruffd]` table rather than that at the
+    /// project root, relative to the project root. Takes effect as the
+    /// pyproject is next read
+    pub config: Option<PathBuf>,
+    pub run_mode: Option<RunMode>,
+}
+
+fn parse_var<T, F>(var: &F, name: &str) -> Option<T>
+where
+    T: FromStr<Err = String>,
+    F: Fn(&str) -> Option<String>,
+{
+    let value = var(name).filter(|x| !x.is_empty())?;
+    match value.parse() {
+        Ok(x) => Some(x),
+        Err(err) => {
+            log_warn!("ignoring {}: {}", name, err);
+            None
+        }
+    }
+}
+
+impl ConfigLayer {
+    /// Layer of the environment variables given by `var`, invalid values
+    /// being ignored
+    pub fn from_vars<F>(var: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        Self {
+            log_level: parse_var(&var, LOG_ENV_VAR),
+            config: var(CONFIG_ENV_VAR)
+                .filter(|x| !x.is_empty())
+                .map(PathBuf::from),
+            run_mode: parse_var(&var, RUN_MODE_ENV_VAR),
+        }
+    }
+
+    /// Layer of the environment of the process, read once
+    pub fn env() -> Self {
+        if let Some(layer) = ENV_LAYER.read().unwrap().as_ref() {
+            return layer.clone();
+        }
+        let layer = Self::from_vars(|x| std::env::var(x).ok());
+        *ENV_LAYER.write().unwrap() = Some(layer.clone());
+        layer
+    }
+
+    /// Layer of the command line, empty until set
+    pub fn cli() -> Self {
+        CLI_LAYER.read().unwrap().clone().unwrap_or_default()
+    }
+
+    /// Sets the layer of the command line, which must happen before the
+    /// server is initialized to take effect
+    pub fn set_cli(layer: ConfigLayer) {
+        *CLI_LAYER.write().unwrap() = Some(layer);
+    }
+}
+
+/// Layer a resolved value was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigSource {
+    Env,
+    Cli,
+    Client,
+    Pyproject,
+    /// No layer sets the value
+    Default,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Resolved<T> {
+    pub value: T,
+    pub source: ConfigSource,
+}
+
+/// Value of the first layer setting it, the layers of the environment,
+/// command line and client being read by `get`, or `default` along with
+/// the source `Default`
+fn first_set<T, F>(layers: [&ConfigLayer; 3], get: F, project: Option<T>, default: T) -> Resolved<T>
+where
+    F: Fn(&ConfigLayer) -> Option<T>,
+{
+    let sources = [ConfigSource::Env, ConfigSource::Cli, ConfigSource::Client];
+    sources
+        .into_iter()
+        .zip(layers)
+        .find_map(|(source, layer)| get(layer).map(|value| Resolved { value, source }))
+        .or_else(|| {
+            project.map(|value| Resolved {
+                value,
+                source: ConfigSource::Pyproject,
+            })
+        })
+        .unwrap_or(Resolved {
+            value: default,
+            source: ConfigSource::Default,
+        })
+}
+
+/// Settings resolved over every layer, along with the layer each is from
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedConfig {
+    /// Maximum level logged, `None` keeping the level the server started
+    /// with
+    pub log_level: Resolved<Option<LogLevel>>,
+    pub config: Resolved<Option<PathBuf>>,
+    pub run_mode: Resolved<RunMode>,
+}
+
+impl ResolvedConfig {
+    pub fn from_layers(
+        env: &ConfigLayer,
+        cli: &ConfigLayer,
+        client: &ConfigLayer,
+        project: &ProjectConfig,
+    ) -> Self {
+        let layers = [env, cli, client];
+        Self {
+            log_level: first_set(
+                layers,
+                |x| x.log_level.map(Some),
+                project.logging.map(Some),
+                None,
+            ),
+            // the pyproject can't point to another pyproject
+            config: first_set(layers, |x| x.config.clone().map(Some), None, None),
+            run_mode: first_set(layers, |x| x.run_mode, project.run_mode, RunMode::default()),
+        }
+    }
+
+    /// Resolves the client's and project's settings over the layers of the
+    /// process
+    pub fn resolve(client: &ConfigLayer, project: &ProjectConfig) -> Self {
+        Self::from_layers(&ConfigLayer::env(), &ConfigLayer::cli(), client, project)
+    }
+
+    /// Path of the pyproject read for the `[tool.ruffd]` table, `None` if
+    /// there's no project root to find it relative to
+    pub fn pyproject(&self, project_root: Option<&Path>) -> Option<PathBuf> {
+        match (&self.config.value, project_root) {
+            (Some(config), Some(root)) => Some(root.join(config)),
+            (Some(config), None) if config.is_absolute() => Some(config.clone()),
+            (None, Some(root)) => Some(root.join("pyproject.toml")),
+            _ => None,
+        }
+    }
+
+    /// Sets the maximum level logged, the default if no layer sets one such
+    /// that the level of a previous session's settings isn't kept
+    pub fn apply_log_level(&self) {
+        set_max_level(
+            self.log_level
+                .value
+                .map_or(Some(DEFAULT_LEVEL), LogLevel::level),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_from_vars() {
+        let vars = HashMap::from([
+            (LOG_ENV_VAR, "debug"),
+            (CONFIG_ENV_VAR, "ci/pyproject.toml"),
+            (RUN_MODE_ENV_VAR, "sometimes"),
+        ]);
+        let layer = ConfigLayer::from_vars(|x| vars.get(x).map(|x| x.to_string()));
+        assert_eq!(
+            layer,
+            ConfigLayer {
+                log_level: Some(LogLevel::Debug),
+                config: Some(PathBuf::from("ci/pyproject.toml")),
+                run_mode: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_layers() {
+        let env = ConfigLayer {
+            log_level: Some(LogLevel::Off),
+            ..Default::default()
+        };
+        let cli = ConfigLayer {
+            log_level: Some(LogLevel::Debug),
+            config: Some(PathBuf::from("ci.toml")),
+            ..Default::default()
+        };
+        let client = ConfigLayer {
+            config: Some(PathBuf::from("client.toml")),
+            ..Default::default()
+        };
+        let project = ProjectConfig {
+            run_mode: Some(RunMode::OnSave),
+            ..Default::default()
+        };
+        let resolved = ResolvedConfig::from_layers(&env, &cli, &client, &project);
+        assert_eq!(
+            resolved.log_level,
+            Resolved {
+                value: Some(LogLevel::Off),
+                source: ConfigSource::Env
+            }
+        );
+        assert_eq!(resolved.config.source, ConfigSource::Cli);
+        assert_eq!(
+            resolved.run_mode,
+            Resolved {
+                value: RunMode::OnSave,
+                source: ConfigSource::Pyproject
+            }
+        );
+        assert_eq!(
+            resolved.pyproject(Some(Path::new("/root"))),
+            Some(PathBuf::from("/root/ci.toml"))
+        );
+        let empty = ConfigLayer::default();
+        let resolved =
+            ResolvedConfig::from_layers(&empty, &empty, &empty, &ProjectConfig::default());
+        assert_eq!(resolved.run_mode.source, ConfigSource::Default);
+        assert_eq!(resolved.log_level.value, None);
+        assert_eq!(resolved.pyproject(None), None);
+    }
+}
@@ -1,22 +1,51 @@
+pub mod capabilities;
 pub mod collections;
 mod common;
+mod config;
+pub mod contention;
+mod edits;
 mod error;
+pub mod extensions;
 mod interface;
+pub mod layered;
+pub mod logging;
+pub mod notebook;
+pub mod project;
+mod scheduler;
 mod state;
+pub mod tasks;
+pub mod uri;
 
 pub use anyhow;
-pub use common::{RpcMessage, RpcNotification, RpcRequest, RpcResponseMessage};
-pub use error::{RpcError, RpcErrors, RpcResult, RuntimeError};
+pub use common::{
+    Params, RpcMessage, RpcNotification, RpcRequest, RpcResponseError, RpcResponseMessage,
+};
+pub use config::{
+    FixOnSave, GeneratedFileAction, GeneratedFilesConfig, LintConfig, ServerConfig, CONFIG_SECTION,
+};
+pub use edits::{EditDelta, EditLog};
+pub use error::{params_from_raw, params_from_value, RpcError, RpcErrors, RpcResult, RuntimeError};
 pub use interface::{
-    CreateLocksFn, Notification, Request, ScheduledTask, ServerInitiated, ServerNotification,
-    ServerNotificationExec, ServerRequest, ServerRequestExec, ServerWork, ServerWorkExec,
+    notification_entry, request_entry, CreateLocksFn, Notification, Request, ResponseHandler,
+    ScheduledTask, ServerInitiated, ServerNotification, ServerNotificationExec, ServerRequest,
+    ServerRequestExec, ServerWork, ServerWorkExec, Typed,
 };
 pub use lsp_types;
 pub use ruff;
+/// Version of ruff linted with, as pinned in the manifest
+pub const RUFF_VERSION: &str = env!("RUFF_VERSION");
+pub use rustpython_ast;
+pub use rustpython_parser;
+pub use scheduler::Scheduler;
 pub use serde;
 pub use serde_json;
 pub use state::{
-    server_state_handles_from_locks, CheckRegistry, DocumentBuffer, RwGuarded, RwReq, ServerState,
-    ServerStateHandles, ServerStateLocks,
+    content_hash, evict_checks, prune_closed_checks, server_state_handles_from_locks, sort_checks,
+    system_clock, AstCache, CachedDiagnostics, CheckRegistries, CheckRegistry, Clock,
+    ConfigSnapshot, DocumentBuffer, DocumentStatus, LintTimings, Notebook, PositionBounds,
+    PositionEncoding, RateLimiter, RwGuarded, RwReq, ServerState, ServerStateHandles,
+    ServerStateLocks, SettingsCache, Snapshot, StateField, StateHandle, Symbol, SymbolCache,
+    WorkspaceIndex,
 };
 pub use tokio;
+pub use tracing;
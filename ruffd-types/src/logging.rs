@@ -0,0 +1,279 @@
+//! Logging for the server
+//!
+//! Messages are always written to stderr, as stdout may carry the protocol
+//! when serving over stdio. Use the `log_*` macros rather than `println!` or
+//! `eprintln!` such that the level set with `RUFFD_LOG` is respected
+//!
+//! Records logged while handling a message or scheduled task are tagged with
+//! the trace of that handling, such that records of concurrent operations
+//! can be told apart. Work scheduled by a handler inherits its trace, as does
+//! work moved off of the async runtime with [`in_trace`]. Records of ruff,
+//! logged through the `log` crate, are bridged by [`bridge_log_crate`]
+use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// Name of the environment variable selecting the maximum level logged
+pub const LOG_ENV_VAR: &str = "RUFFD_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            x => Err(format!("unknown log level {}", x)),
+        }
+    }
+}
+
+impl From<log::Level> for Level {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => Self::Error,
+            log::Level::Warn => Self::Warn,
+            log::Level::Info => Self::Info,
+            log::Level::Debug | log::Level::Trace => Self::Debug,
+        }
+    }
+}
+
+/// Level logged unless one is set
+pub const DEFAULT_LEVEL: Level = Level::Info;
+
+/// Maximum level logged, 0 disabling logging entirely
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+
+/// Sets the maximum level logged, `None` disabling logging
+pub fn set_max_level(level: Option<Level>) {
+    MAX_LEVEL.store(level.map_or(0, |x| x as u8), Ordering::Relaxed);
+    log::set_max_level(match level {
+        None => log::LevelFilter::Off,
+        Some(Level::Error) => log::LevelFilter::Error,
+        Some(Level::Warn) => log::LevelFilter::Warn,
+        Some(Level::Info) => log::LevelFilter::Info,
+        Some(Level::Debug) => log::LevelFilter::Trace,
+    });
+}
+
+pub fn max_level() -> Option<Level> {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn enabled(level: Level) -> bool {
+    matches!(max_level(), Some(x) if level <= x)
+}
+
+/// Sets the maximum level from `RUFFD_LOG` if set, where `off` disables
+/// logging
+pub fn init_from_env() {
+    match std::env::var(LOG_ENV_VAR).as_deref() {
+        Ok("off") => set_max_level(None),
+        Ok(x) => match x.parse() {
+            Ok(level) => set_max_level(Some(level)),
+            Err(err) => log(Level::Warn, format_args!("{}", err)),
+        },
+        Err(_) => {}
+    }
+}
+
+/// Identifies the handling of a client message or scheduled task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceId(u64);
+
+static NEXT_TRACE: AtomicU64 = AtomicU64::new(1);
+
+impl TraceId {
+    /// A trace distinct from all others of the process
+    pub fn next() -> Self {
+        Self(NEXT_TRACE.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "t{}", self.0)
+    }
+}
+
+tokio::task_local! {
+    static TASK_TRACE: TraceId;
+}
+
+thread_local! {
+    static THREAD_TRACE: Cell<Option<TraceId>> = const { Cell::new(None) };
+}
+
+/// Trace of the running task, or of the work entered with [`in_trace`] on
+/// the current thread
+pub fn current_trace() -> Option<TraceId> {
+    THREAD_TRACE
+        .with(Cell::get)
+        .or_else(|| TASK_TRACE.try_with(|x| *x).ok())
+}
+
+/// Runs `fut` under `trace`
+///
+/// Tasks spawned by `fut` aren't under `trace` unless also run with this
+pub async fn traced<F: Future>(trace: TraceId, fut: F) -> F::Output {
+    TASK_TRACE.scope(trace, fut).await
+}
+
+/// Restores the trace of a thread once work entered with [`in_trace`] ends,
+/// including by panicking
+struct ThreadTraceGuard(Option<TraceId>);
+
+impl Drop for ThreadTraceGuard {
+    fn drop(&mut self) {
+        THREAD_TRACE.with(|x| x.set(self.0));
+    }
+}
+
+/// Runs `f` under `trace` on the current thread, for work moved off of the
+/// async runtime where the trace of the task moving it is lost
+pub fn in_trace<T, F: FnOnce() -> T>(trace: Option<TraceId>, f: F) -> T {
+    let _guard = ThreadTraceGuard(THREAD_TRACE.with(|x| x.replace(trace)));
+    f()
+}
+
+#[doc(hidden)]
+pub fn log(level: Level, args: fmt::Arguments<'_>) {
+    if enabled(level) {
+        // failing to log has nowhere left to be reported
+        let mut stderr = io::stderr().lock();
+        match current_trace() {
+            Some(trace) => writeln!(stderr, "[{}] [{}] {}", level, trace, args).ok(),
+            None => writeln!(stderr, "[{}] {}", level, args).ok(),
+        };
+    }
+}
+
+/// Forwards records of the `log` crate, being those of ruff, to [`log`]
+struct LogBridge;
+
+impl log::Log for LogBridge {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        enabled(metadata.level().into())
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        log(
+            record.level().into(),
+            format_args!("{}: {}", record.target(), record.args()),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+/// Logs records of the `log` crate as those of the server, unless another
+/// logger was set first
+pub fn bridge_log_crate() {
+    if log::set_logger(&LogBridge).is_ok() {
+        set_max_level(max_level());
+    }
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Error, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Warn, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Info, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)+) => {
+        $crate::logging::log($crate::logging::Level::Debug, format_args!($($arg)+))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!("WARN".parse::<Level>(), Ok(Level::Warn));
+        assert!("verbose".parse::<Level>().is_err());
+        set_max_level(Some(Level::Warn));
+        assert!(enabled(Level::Error));
+        assert!(!enabled(Level::Info));
+        set_max_level(None);
+        assert!(!enabled(Level::Error));
+        set_max_level(Some(Level::Info));
+        assert_eq!(max_level(), Some(Level::Info));
+        assert_eq!(log::max_level(), log::LevelFilter::Info);
+    }
+
+    #[tokio::test]
+    async fn test_traces() {
+        let (first, second) = (TraceId::next(), TraceId::next());
+        assert_ne!(first, second);
+        assert_eq!(current_trace(), None);
+        traced(first, async {
+            assert_eq!(current_trace(), Some(first));
+            let blocking = tokio::task::spawn_blocking(current_trace);
+            assert_eq!(blocking.await.unwrap(), None);
+            let trace = current_trace();
+            let blocking = tokio::task::spawn_blocking(move || in_trace(trace, current_trace));
+            assert_eq!(blocking.await.unwrap(), Some(first));
+            in_trace(Some(second), || assert_eq!(current_trace(), Some(second)));
+            assert_eq!(current_trace(), Some(first));
+        })
+        .await;
+        assert_eq!(current_trace(), None);
+    }
+}
@@ -0,0 +1,224 @@
+//! Types for the notebook document synchronisation added in LSP 3.17
+//!
+//! These aren't yet provided by `lsp_types`, so are described here with the
+//! `lsp_types` notification traits in the same manner as the extensions
+use serde::{Deserialize, Serialize};
+
+/// Notebook type of jupyter notebooks as reported by clients
+pub const JUPYTER_NOTEBOOK_TYPE: &str = "jupyter-notebook";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(into = "u8", try_from = "u8")]
+pub enum NotebookCellKind {
+    Markup,
+    Code,
+}
+
+impl From<NotebookCellKind> for u8 {
+    fn from(kind: NotebookCellKind) -> Self {
+        match kind {
+            NotebookCellKind::Markup => 1,
+            NotebookCellKind::Code => 2,
+        }
+    }
+}
+
+impl TryFrom<u8> for NotebookCellKind {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::Markup),
+            2 => Ok(Self::Code),
+            x => Err(format!("unknown notebook cell kind {}", x)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCell {
+    pub kind: NotebookCellKind,
+    /// Uri of the text document holding the cell's content
+    pub document: lsp_types::Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execution_summary: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocument {
+    pub uri: lsp_types::Url,
+    pub notebook_type: String,
+    pub version: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    pub cells: Vec<NotebookCell>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentIdentifier {
+    pub uri: lsp_types::Url,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionedNotebookDocumentIdentifier {
+    pub version: i32,
+    pub uri: lsp_types::Url,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidOpenNotebookDocumentParams {
+    pub notebook_document: NotebookDocument,
+    /// Text documents of the notebook's cells
+    pub cell_text_documents: Vec<lsp_types::TextDocumentItem>,
+}
+
+/// Replacement of `delete_count` cells from `start` with `cells`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellArrayChange {
+    pub start: u32,
+    pub delete_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cells: Option<Vec<NotebookCell>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellStructureChange {
+    pub array: NotebookCellArrayChange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_open: Option<Vec<lsp_types::TextDocumentItem>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_close: Option<Vec<lsp_types::TextDocumentIdentifier>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellTextChange {
+    pub document: lsp_types::VersionedTextDocumentIdentifier,
+    pub changes: Vec<lsp_types::TextDocumentContentChangeEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellsChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub structure: Option<NotebookCellStructureChange>,
+    /// Cells whose kind or metadata changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<NotebookCell>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_content: Option<Vec<NotebookCellTextChange>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentChangeEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cells: Option<NotebookCellsChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeNotebookDocumentParams {
+    pub notebook_document: VersionedNotebookDocumentIdentifier,
+    pub change: NotebookDocumentChangeEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidCloseNotebookDocumentParams {
+    pub notebook_document: NotebookDocumentIdentifier,
+    pub cell_text_documents: Vec<lsp_types::TextDocumentIdentifier>,
+}
+
+pub enum DidOpenNotebookDocument {}
+
+impl lsp_types::notification::Notification for DidOpenNotebookDocument {
+    type Params = DidOpenNotebookDocumentParams;
+    const METHOD: &'static str = "notebookDocument/didOpen";
+}
+
+pub enum DidChangeNotebookDocument {}
+
+impl lsp_types::notification::Notification for DidChangeNotebookDocument {
+    type Params = DidChangeNotebookDocumentParams;
+    const METHOD: &'static str = "notebookDocument/didChange";
+}
+
+pub enum DidCloseNotebookDocument {}
+
+impl lsp_types::notification::Notification for DidCloseNotebookDocument {
+    type Params = DidCloseNotebookDocumentParams;
+    const METHOD: &'static str = "notebookDocument/didClose";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookCellSelector {
+    pub language: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookFilter {
+    pub notebook_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookSelector {
+    pub notebook: NotebookFilter,
+    pub cells: Vec<NotebookCellSelector>,
+}
+
+/// Value of the `notebookDocumentSync` server capability
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotebookDocumentSyncOptions {
+    pub notebook_selector: Vec<NotebookSelector>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save: Option<bool>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_did_change_params() {
+        let value = serde_json::json!({
+            "notebookDocument": {"version": 2, "uri": "file:///nb.ipynb"},
+            "change": {
+                "cells": {
+                    "structure": {
+                        "array": {"start": 1, "deleteCount": 0, "cells": [
+                            {"kind": 2, "document": "vscode-notebook-cell:/nb.ipynb#c1"}
+                        ]},
+                        "didOpen": [{
+                            "uri": "vscode-notebook-cell:/nb.ipynb#c1",
+                            "languageId": "python",
+                            "version": 1,
+                            "text": "import os"
+                        }]
+                    }
+                }
+            }
+        });
+        let params: DidChangeNotebookDocumentParams = serde_json::from_value(value).unwrap();
+        let structure = params.change.cells.unwrap().structure.unwrap();
+        let cells = structure.array.cells.unwrap();
+        assert_eq!(cells[0].kind, NotebookCellKind::Code);
+        assert_eq!(structure.did_open.unwrap()[0].text, "import os");
+        assert!(serde_json::from_value::<NotebookCellKind>(serde_json::json!(3)).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! Settings of the server committed to a project, read from the
+//! `[tool.ruffd]` table of the pyproject at the project root
+//!
+//! Unlike the settings of the client, the table is validated strictly:
+//! unknown keys and values are rejected with the line and column they're
+//! at, such that a typo in a committed file isn't silently ignored
+use crate::logging::Level;
+use ruff::checks_gen::CheckCodePrefix;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Milliseconds a document is left unedited before it's linted, 0
+    /// linting after every edit
+    pub debounce: u64,
+    /// When documents are linted, unless set through `RUFFD_RUN_MODE`, the
+    /// command line or the client
+    pub run_mode: Option<RunMode>,
+    /// Severities of the diagnostics of rules by code or code prefix, such
+    /// as `E501` or `F`, the longest prefix matching a code applying
+    #[serde(deserialize_with = "severity_overrides")]
+    pub severity_overrides: BTreeMap<String, Severity>,
+    pub fix_safety: FixSafety,
+    /// Maximum level logged, unless set through `RUFFD_LOG`, the command
+    /// line or the client
+    pub logging: Option<LogLevel>,
+}
+
+/// When open documents are linted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RunMode {
+    #[default]
+    OnType,
+    /// Lints documents as they're opened and saved, edits only moving the
+    /// diagnostics already published
+    OnSave,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl FromStr for RunMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "onType" => Ok(Self::OnType),
+            "onSave" => Ok(Self::OnSave),
+            x => Err(format!("unknown run mode {}", x)),
+        }
+    }
+}
+
+impl From<Severity> for lsp_types::DiagnosticSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Error => Self::ERROR,
+            Severity::Warning => Self::WARNING,
+            Severity::Information => Self::INFORMATION,
+            Severity::Hint => Self::HINT,
+        }
+    }
+}
+
+/// How the fixes of checks are offered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FixSafety {
+    /// Offered as quick fixes and applied in bulk by fixing all or on save
+    #[default]
+    All,
+    /// Only offered as quick fixes, such that each fix is reviewed
+    QuickFix,
+    /// Checks are reported without their fixes
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("off") {
+            return Ok(Self::Off);
+        }
+        Ok(match s.parse()? {
+            Level::Error => Self::Error,
+            Level::Warn => Self::Warn,
+            Level::Info => Self::Info,
+            Level::Debug => Self::Debug,
+        })
+    }
+}
+
+impl LogLevel {
+    /// Maximum level logged, `None` disabling logging
+    pub fn level(self) -> Option<Level> {
+        match self {
+            Self::Off => None,
+            Self::Error => Some(Level::Error),
+            Self::Warn => Some(Level::Warn),
+            Self::Info => Some(Level::Info),
+            Self::Debug => Some(Level::Debug),
+        }
+    }
+}
+
+/// Rejects overrides of codes ruff doesn't recognise
+fn severity_overrides<'de, D>(deserializer: D) -> Result<BTreeMap<String, Severity>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let overrides = BTreeMap::<String, Severity>::deserialize(deserializer)?;
+    match overrides
+        .keys()
+        .find(|x| CheckCodePrefix::from_str(x).is_err())
+    {
+        Some(x) => Err(D::Error::custom(format!("unknown rule code prefix {}", x))),
+        None => Ok(overrides),
+    }
+}
+
+#[derive(Default, Deserialize)]
+struct PyProject {
+    #[serde(default)]
+    tool: Tool,
+}
+
+#[derive(Default, Deserialize)]
+struct Tool {
+    #[serde(default)]
+    ruffd: ProjectConfig,
+}
+
+impl ProjectConfig {
+    /// Reads the `[tool.ruffd]` table of a pyproject's source, defaults
+    /// being taken if it has none
+    pub fn from_toml(source: &str) -> Result<Self, toml::de::Error> {
+        Ok(toml::from_str::<PyProject>(source)?.tool.ruffd)
+    }
+
+    /// Reads the `[tool.ruffd]` table of the pyproject at `path`, defaults
+    /// being taken if there's no pyproject
+    ///
+    /// Errors are described along with the path and, for invalid settings,
+    /// the location of the error within the pyproject
+    pub fn from_pyproject(path: &Path) -> Result<Self, String> {
+        let source = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("failed reading {}: {}", path.display(), err)),
+        };
+        Self::from_toml(&source).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn severity_of(&self, code: &str) -> Option<Severity> {
+        severity_override(&self.severity_overrides, code)
+    }
+}
+
+/// Severity overridden for the rule of `code`, by the override of the
+/// longest prefix of it
+pub fn severity_override(overrides: &BTreeMap<String, Severity>, code: &str) -> Option<Severity> {
+    overrides
+        .iter()
+        .filter(|(prefix, _)| code.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, severity)| *severity)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let source = r#"
+[project]
+name = "example"
+
+[tool.ruffd]
+debounce = 250
+runMode = "onSave"
+fixSafety = "quickFix"
+logging = "debug"
+
+[tool.ruffd.severityOverrides]
+F = "error"
+F401 = "hint"
+"#;
+        let config = ProjectConfig::from_toml(source).unwrap();
+        assert_eq!(config.debounce, 250);
+        assert_eq!(config.run_mode, Some(RunMode::OnSave));
+        assert_eq!(config.fix_safety, FixSafety::QuickFix);
+        assert_eq!(config.logging, Some(LogLevel::Debug));
+        assert_eq!(config.severity_of("F401"), Some(Severity::Hint));
+        assert_eq!(config.severity_of("F841"), Some(Severity::Error));
+        assert_eq!(config.severity_of("E501"), None);
+        assert_eq!(
+            ProjectConfig::from_toml("[project]\nname = \"example\"\n").unwrap(),
+            ProjectConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_from_toml_errors() {
+        let error = |source: &str| ProjectConfig::from_toml(source).unwrap_err().to_string();
+        // errors are located within the pyproject
+        assert!(error("[tool.ruffd]\ndebounse = 250\n").contains("line"));
+        assert!(error("[tool.ruffd]\nrunMode = \"sometimes\"\n").contains("sometimes"));
+        assert!(error("[tool.ruffd.severityOverrides]\nXYZ = \"error\"\n").contains("XYZ"));
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("onSave".parse(), Ok(RunMode::OnSave));
+        assert!("on_save".parse::<RunMode>().is_err());
+        assert_eq!("OFF".parse(), Ok(LogLevel::Off));
+        assert_eq!("warn".parse(), Ok(LogLevel::Warn));
+        assert!("trace".parse::<LogLevel>().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::common::{RpcNotification, RpcRequest, RpcResponseMessage};
+use crate::interface::{
+    CreateLocksFn, ResponseHandler, ScheduledTask, ServerInitiated, ServerNotification,
+    ServerNotificationExec, ServerRequest, ServerRequestExec,
+};
+use crate::log_debug;
+use crate::state::{ServerStateHandles, ServerStateLocks};
+use crate::tasks::spawn_named;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+/// Schedules server work to be handled by the service, acquiring its locks
+/// in order with those of client messages
+///
+/// Work is sent from a separate task, such that handlers needn't await
+/// scheduling. Work scheduled by a single call is handled in the order
+/// given, whereas separate calls are unordered
+#[derive(Clone)]
+pub struct Scheduler(Sender<ScheduledTask>);
+
+impl Scheduler {
+    pub fn new(channel: Sender<ScheduledTask>) -> Self {
+        Self(channel)
+    }
+
+    pub fn channel(&self) -> &Sender<ScheduledTask> {
+        &self.0
+    }
+
+    /// Number of tasks sent to the service that are yet to be received,
+    /// excluding those still being sent
+    pub fn pending(&self) -> usize {
+        self.0.max_capacity() - self.0.capacity()
+    }
+
+    pub fn schedule(&self, task: impl Into<ServerInitiated>) {
+        self.schedule_all([task]);
+    }
+
+    /// Schedules each of the tasks in order
+    pub fn schedule_all<I>(&self, tasks: I)
+    where
+        I: IntoIterator,
+        I::Item: Into<ServerInitiated>,
+    {
+        // the trace is taken before spawning, as the spawned task has none
+        let tasks = tasks
+            .into_iter()
+            .map(ScheduledTask::server)
+            .collect::<Vec<_>>();
+        if tasks.is_empty() {
+            return;
+        }
+        let channel = self.0.clone();
+        spawn_named(|| "schedule".to_string(), async move {
+            for task in tasks {
+                // the receiver is dropped once the session ends, as on a
+                // reconnect or shutdown, leaving the work nowhere to go
+                if channel.send(task).await.is_err() {
+                    log_debug!("session ended before its scheduled work was sent");
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Sends a notification to the client, once work scheduled ahead of it
+    /// has been handled
+    pub fn notify_client(&self, notification: RpcNotification) {
+        let exec: ServerNotificationExec =
+            Box::new(move |_, _| Box::pin(async move { Some(notification.into()) }));
+        self.schedule(ServerNotification {
+            exec,
+            create_locks: no_locks(),
+        });
+    }
+
+    /// Sends a request to the client, returning a receiver of its response
+    ///
+    /// The receiver errors if the service stops before the client responds
+    pub fn request_client(
+        &self,
+        method: impl Into<String>,
+        params: Option<serde_json::Value>,
+    ) -> oneshot::Receiver<RpcResponseMessage> {
+        let (sender, receiver) = oneshot::channel();
+        let method = method.into();
+        let exec: ServerRequestExec = Box::new(
+            move |_: ServerStateHandles<'_>, _, id: lsp_types::NumberOrString| {
+                Box::pin(async move { RpcRequest::new(id, method, params) })
+            },
+        );
+        let on_response: ResponseHandler = Box::new(move |resp| {
+            // the requester may have stopped waiting
+            sender.send(resp).ok();
+            None
+        });
+        self.schedule(ServerRequest {
+            exec,
+            create_locks: no_locks(),
+            on_response,
+        });
+        receiver
+    }
+}
+
+impl From<Sender<ScheduledTask>> for Scheduler {
+    fn from(channel: Sender<ScheduledTask>) -> Self {
+        Self::new(channel)
+    }
+}
+
+fn no_locks() -> CreateLocksFn {
+    Box::new(|_| Box::pin(async { ServerStateLocks::default() }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::mpsc::channel;
+
+    #[tokio::test]
+    async fn test_request_client() {
+        let (sender, mut receiver) = channel(8);
+        let scheduler = Scheduler::new(sender);
+        let mut response = scheduler.request_client("client/method", None);
+        let request = match receiver.recv().await.unwrap() {
+            ScheduledTask::Server(ServerInitiated::Request(x), _) => x,
+            _ => panic!("expected a server request"),
+        };
+        assert!(response.try_recv().is_err());
+        let id = lsp_types::NumberOrString::Number(1);
+        let resp = RpcResponseMessage::from_result(id.clone(), serde_json::Value::Null);
+        assert!((request.on_response)(resp).is_none());
+        assert_eq!(response.try_recv().unwrap().id(), Some(&id));
+    }
+}
@@ -1,21 +1,143 @@
+use crate::capabilities::server_capabilities;
 use crate::collections::{AggAvlTree, Rope};
+use crate::config::ServerConfig;
+use crate::contention;
+use crate::edits::{EditDelta, EditLog};
 use crate::error::{DocumentError, RuntimeError};
+use crate::extensions::{LintTiming, WatcherStatus};
+use crate::log_warn;
+use crate::notebook::{NotebookCell, NotebookCellKind};
+use crate::project::{ProjectConfig, Severity};
+use crate::uri::{normalize_uri, path_to_uri};
 use ruff::checks::Check;
-use ruff::settings::configuration::Configuration;
+use ruff::settings::Settings;
 use ruffd_macros::server_state;
+use rustpython_ast::{Location, Suite};
+use rustpython_parser::error::ParseError;
+use rustpython_parser::parser;
+use serde::{Deserialize, Serialize};
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
 use std::iter::FromIterator;
-use std::ops::{Bound, RangeBounds};
+use std::mem;
+use std::ops::{Add, Bound, Deref, DerefMut, RangeBounds, Sub};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
+
+/// Unit in which the character offset of a `lsp_types::Position` is counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    /// Default encoding mandated by the protocol
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Name of the encoding as negotiated through `positionEncodings`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16 => "utf-16",
+            Self::Utf32 => "utf-32",
+        }
+    }
+
+    /// Picks the encoding of positions from those offered by the client
+    ///
+    /// UTF-32 counts chars as the buffers and ruff do, so is preferred as
+    /// it needs no conversion. Otherwise the protocol's default is used,
+    /// which every client supports
+    pub fn negotiate<'a, I>(offered: I) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        match offered.into_iter().any(|x| x == "utf-32") {
+            true => Self::Utf32,
+            false => Self::Utf16,
+        }
+    }
+
+    /// Units of `c` in the encoding
+    pub fn char_len(&self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+}
+
+/// Treatment of positions outside of a buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionBounds {
+    /// Positions outside of the buffer are errors, as is required where
+    /// they'd indicate the server's own bookkeeping is wrong
+    #[default]
+    Strict,
+    /// Positions past the end of a line are taken as the end of the line,
+    /// and those past the last line as the end of the document, as the
+    /// protocol specifies for positions sent by clients
+    Clamp,
+}
+
+/// Length of a row or span of text, counted in chars as the buffer is
+/// indexed and in the UTF-16 code units clients count by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct RowLen {
+    chars: usize,
+    utf16: usize,
+}
+
+impl RowLen {
+    fn of<'a, I: IntoIterator<Item = &'a char>>(chars: I) -> Self {
+        chars.into_iter().fold(Self::default(), |acc, c| Self {
+            chars: acc.chars + 1,
+            utf16: acc.utf16 + c.len_utf16(),
+        })
+    }
+}
+
+impl Sub for RowLen {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            chars: self.chars - other.chars,
+            utf16: self.utf16 - other.utf16,
+        }
+    }
+}
+
+impl Add for RowLen {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            chars: self.chars + other.chars,
+            utf16: self.utf16 + other.utf16,
+        }
+    }
+}
 
 pub struct DocumentBuffer {
-    row_tree: AggAvlTree<usize>,
+    row_tree: AggAvlTree<RowLen>,
     text: Rope<char>,
+    revision: usize,
+    edits: EditLog,
 }
 
-fn row_tree_accumulate(a: &usize, b: &usize) -> usize {
+/// Edits logged by a buffer, bounding the revisions its positions can be
+/// mapped from
+const EDIT_LOG_CAPACITY: usize = 256;
+
+fn row_tree_accumulate(a: &RowLen, b: &RowLen) -> RowLen {
     *a + *b
 }
 
@@ -24,6 +146,8 @@ impl Default for DocumentBuffer {
         Self {
             row_tree: AggAvlTree::new(row_tree_accumulate),
             text: Rope::default(),
+            revision: 0,
+            edits: EditLog::new(0, EDIT_LOG_CAPACITY),
         }
     }
 }
@@ -54,6 +178,19 @@ fn get_line_lengths(chars: &[char]) -> Vec<usize> {
     rv
 }
 
+/// Lengths of the rows of `chars`, as split by `get_line_lengths`
+fn get_row_lens(chars: &[char]) -> Vec<RowLen> {
+    let mut start = 0;
+    get_line_lengths(chars)
+        .into_iter()
+        .map(|len| {
+            let rv = RowLen::of(&chars[start..start + len]);
+            start += len;
+            rv
+        })
+        .collect()
+}
+
 impl DocumentBuffer {
     pub fn new() -> Self {
         Self::default()
@@ -61,10 +198,116 @@ impl DocumentBuffer {
 
     pub fn from_string(text: String) -> Self {
         let char_vec = text.chars().collect::<Vec<_>>();
-        let row_counts = get_line_lengths(&char_vec);
+        let row_lens = get_row_lens(&char_vec);
         let text = Rope::from_document(char_vec);
-        let row_tree = AggAvlTree::from_vec(row_counts, row_tree_accumulate);
-        Self { text, row_tree }
+        let row_tree = AggAvlTree::from_vec(row_lens, row_tree_accumulate);
+        Self {
+            text,
+            row_tree,
+            revision: 0,
+            edits: EditLog::new(0, EDIT_LOG_CAPACITY),
+        }
+    }
+
+    /// Number of characters in the buffer
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Number of edits applied since the buffer was created
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    /// Maps the row and char column of text at `since_version`, a revision
+    /// of the buffer, to where the text is now. Positions within text since
+    /// replaced are moved to the start of the replacement
+    ///
+    /// Returns `None` if the edits since aren't all logged, as once the
+    /// log is full or the document was replaced whole
+    pub fn map_position(
+        &self,
+        position: (usize, usize),
+        since_version: usize,
+    ) -> Option<(usize, usize)> {
+        self.edits.map_position(position, since_version)
+    }
+
+    /// Edits made since `since_version`, a revision of the buffer, in the
+    /// order they were applied
+    pub fn edits_since(&self, since_version: usize) -> Option<impl Iterator<Item = &EditDelta>> {
+        self.edits.since(since_version)
+    }
+
+    /// Length of `row` in chars, including its line ending
+    pub fn char_len(&self, row: usize) -> Option<usize> {
+        self.row_tree.get(row).map(|x| x.chars)
+    }
+
+    /// Length of `row` in UTF-16 code units, including its line ending
+    pub fn utf16_len(&self, row: usize) -> Option<usize> {
+        self.row_tree.get(row).map(|x| x.utf16)
+    }
+
+    /// Index of the first char of `row`
+    fn row_start(&self, row: usize) -> usize {
+        self.row_tree.get_range(..row).map_or(0, |x| x.chars)
+    }
+
+    /// Lengths of `row` before and from its column `col`, given the index
+    /// of its first char
+    ///
+    /// Rows of chars within the BMP have a code unit per char, so are split
+    /// from the lengths aggregated by the row tree alone. Only rows holding
+    /// chars beyond it are counted, and then only on the shorter side
+    fn split_row(&self, row: usize, row_start: usize, col: usize) -> (RowLen, RowLen) {
+        let row_len = self.row_tree.get(row).unwrap_or_default();
+        if row_len.chars == row_len.utf16 {
+            let prefix = RowLen {
+                chars: col,
+                utf16: col,
+            };
+            return (prefix, row_len - prefix);
+        }
+        let idx = row_start + col;
+        if 2 * col <= row_len.chars {
+            let prefix = RowLen::of(self.iter_range(row_start..idx));
+            (prefix, row_len - prefix)
+        } else {
+            let suffix = RowLen::of(self.iter_range(idx..row_start + row_len.chars));
+            (row_len - suffix, suffix)
+        }
+    }
+
+    /// Panics if the row tree is corrupt or out of sync with the text, a
+    /// no-op without debug assertions
+    fn debug_validate(&self) {
+        #[cfg(debug_assertions)]
+        {
+            if let Err(err) = self.row_tree.validate() {
+                panic!("corrupt row tree: {}", err);
+            }
+            let row_total = self.row_tree.get_range(..).map_or(0, |x| x.chars);
+            assert_eq!(row_total, self.text.len(), "row tree out of sync with text");
+        }
+    }
+
+    /// Length of the row excluding its line ending, being the greatest valid
+    /// column of the row
+    fn row_content_len(&self, row: usize, row_size: usize) -> usize {
+        let row_end = self.row_start(row) + row_size;
+        let tail = self
+            .iter_range(row_end - cmp::min(row_size, 2)..row_end)
+            .collect::<Vec<_>>();
+        match tail.as_slice() {
+            ['\r', '\n'] => row_size - 2,
+            [.., '\n'] | [.., '\r'] => row_size - 1,
+            _ => row_size,
+        }
     }
 
     pub fn insert_text(
@@ -78,38 +321,41 @@ impl DocumentBuffer {
             if row != 0 || col != 0 {
                 return Err(DocumentError::IndexOutOfBounds);
             }
-            let row_counts = get_line_lengths(&char_vec);
-            row_counts
+            get_row_lens(&char_vec)
                 .into_iter()
                 .for_each(|val| self.row_tree.insert_back(val));
+            self.edits
+                .push(EditDelta::insertion((0, 0), &get_line_lengths(&char_vec)));
             self.text.insert(char_vec, 0).unwrap();
+            self.revision += 1;
+            self.debug_validate();
             return Ok(());
         }
-        let curr_row_size = self
-            .row_tree
-            .get(row)
-            .ok_or(DocumentError::RowOutOfBounds)?;
-        if curr_row_size < col {
+        let curr_row_size = self.char_len(row).ok_or(DocumentError::RowOutOfBounds)?;
+        if col > self.row_content_len(row, curr_row_size) {
             return Err(DocumentError::ColOutOfBounds);
         }
-        let suffix_size = curr_row_size - col;
-        let row_counts = get_line_lengths(&char_vec);
+        let row_start = self.row_start(row);
+        let idx = row_start + col;
+        let (prefix, suffix) = self.split_row(row, row_start, col);
         // 3 cases: no line breaks, 1 line break, 2 or more line breaks
-        let mut row_counts_iter = row_counts.into_iter();
-        let first_count = row_counts_iter.next().unwrap();
-        self.row_tree.update(row, col + first_count)?;
-        if let Some(last_count) = row_counts_iter.next_back() {
-            self.row_tree.insert(row + 1, suffix_size + last_count);
+        let mut row_lens_iter = get_row_lens(&char_vec).into_iter();
+        let first_len = row_lens_iter.next().unwrap();
+        if let Some(last_len) = row_lens_iter.next_back() {
+            self.row_tree.update(row, prefix + first_len)?;
+            self.row_tree.insert(row + 1, last_len + suffix);
         } else {
             // add suffix length to line if there exists no suffix
-            self.row_tree.update(row, col + first_count + suffix_size)?;
+            self.row_tree.update(row, prefix + first_len + suffix)?;
         }
-        while let Some(count) = row_counts_iter.next_back() {
-            self.row_tree.insert(row + 1, count);
+        while let Some(len) = row_lens_iter.next_back() {
+            self.row_tree.insert(row + 1, len);
         }
-        // empty row range gives 0
-        let idx = self.row_tree.get_range(..row).unwrap_or(0) + col;
-        self.text.insert(text.chars().collect::<Vec<_>>(), idx)?;
+        self.edits
+            .push(EditDelta::insertion(row_col, &get_line_lengths(&char_vec)));
+        self.text.insert(char_vec, idx)?;
+        self.revision += 1;
+        self.debug_validate();
         Ok(())
     }
 
@@ -127,29 +373,149 @@ impl DocumentBuffer {
             return Err(DocumentError::IndexOutOfBounds);
         }
         let start_row_size = self
-            .row_tree
-            .get(start_row)
+            .char_len(start_row)
             .ok_or(DocumentError::RowOutOfBounds)?;
-        // TODO generalise column bounds check to account for line endings
-        if start_col > start_row_size {
+        // columns past the line ending belong to the next row
+        if start_col > self.row_content_len(start_row, start_row_size) {
             return Err(DocumentError::ColOutOfBounds);
         }
-        let start_idx = self.row_tree.get_range(..start_row).unwrap_or(0) + start_col;
+        let start_row_start = self.row_start(start_row);
+        let start_idx = start_row_start + start_col;
         let end_row_size = self
-            .row_tree
-            .get(end_row)
+            .char_len(end_row)
             .ok_or(DocumentError::RowOutOfBounds)?;
-        // TODO generalise column bounds check to account for line endings
-        if end_col > end_row_size {
+        if end_col > self.row_content_len(end_row, end_row_size) {
             return Err(DocumentError::ColOutOfBounds);
         }
-        let end_idx = self.row_tree.get_range(..end_row).unwrap_or(0) + end_col;
+        let end_row_start = self.row_start(end_row);
+        let end_idx = end_row_start + end_col;
+        let (prefix, _) = self.split_row(start_row, start_row_start, start_col);
+        let (_, suffix) = self.split_row(end_row, end_row_start, end_col);
         self.text.delete(start_idx..end_idx);
-        let suffix_len = end_row_size - end_col;
         for _ in (start_row + 1)..=(end_row) {
             self.row_tree.delete(start_row + 1)?;
         }
-        self.row_tree.update(start_row, start_col + suffix_len)?;
+        self.row_tree.update(start_row, prefix + suffix)?;
+        self.edits
+            .push(EditDelta::deletion(start_row_col, end_row_col));
+        self.revision += 1;
+        self.debug_validate();
+        Ok(())
+    }
+
+    /// Converts a position in the given encoding to the row and char column
+    /// used to index the buffer
+    ///
+    /// Clients commonly append to a document lacking a final newline at the
+    /// start of the row past its last, which is only valid when clamping
+    pub fn row_col_from_position(
+        &self,
+        position: &lsp_types::Position,
+        encoding: PositionEncoding,
+        bounds: PositionBounds,
+    ) -> Result<(usize, usize), DocumentError> {
+        let row = position.line as usize;
+        let character = position.character as usize;
+        let row_len = match (self.row_tree.get(row), bounds) {
+            (Some(x), _) => x,
+            // the empty buffer is only indexed by its start, checked on edit
+            (None, _) if self.row_tree.is_empty() => return Ok((row, character)),
+            (None, PositionBounds::Clamp) => {
+                let last_row = self.row_tree.len() - 1;
+                let last_row_size = self.char_len(last_row).unwrap();
+                return Ok((last_row, self.row_content_len(last_row, last_row_size)));
+            }
+            (None, PositionBounds::Strict) => return Err(DocumentError::RowOutOfBounds),
+        };
+        if character == 0 {
+            return Ok((row, 0));
+        }
+        let content_len = self.row_content_len(row, row_len.chars);
+        // rows without surrogate pairs have a UTF-16 unit for every char,
+        // so are indexed as is rather than scanned
+        let units_are_chars = match encoding {
+            PositionEncoding::Utf8 => false,
+            PositionEncoding::Utf16 => row_len.utf16 == row_len.chars,
+            PositionEncoding::Utf32 => true,
+        };
+        if units_are_chars {
+            return match bounds {
+                PositionBounds::Clamp => Ok((row, cmp::min(character, content_len))),
+                PositionBounds::Strict if character > content_len => {
+                    Err(DocumentError::ColOutOfBounds)
+                }
+                PositionBounds::Strict => Ok((row, character)),
+            };
+        }
+        let row_start = self.row_start(row);
+        let mut units = 0;
+        for (col, c) in self
+            .iter_range(row_start..row_start + content_len)
+            .enumerate()
+        {
+            if units == character {
+                return Ok((row, col));
+            }
+            units += encoding.char_len(*c);
+            if units > character {
+                return Err(DocumentError::SplitCharacter);
+            }
+        }
+        match bounds {
+            PositionBounds::Strict if units != character => Err(DocumentError::ColOutOfBounds),
+            _ => Ok((row, content_len)),
+        }
+    }
+
+    /// Replaces the whole text of the buffer
+    ///
+    /// The replacement counts as an edit, though positions before it can't
+    /// be mapped
+    pub fn replace(&mut self, text: String) {
+        let revision = self.revision + 1;
+        *self = Self::from_string(text);
+        self.revision = revision;
+        self.edits.reset(revision);
+    }
+
+    /// Applies a change as sent in `textDocument/didChange`, a change
+    /// without a range replaces the whole document
+    pub fn apply_content_change(
+        &mut self,
+        change: &lsp_types::TextDocumentContentChangeEvent,
+        encoding: PositionEncoding,
+        bounds: PositionBounds,
+    ) -> Result<(), DocumentError> {
+        match change.range {
+            Some(range) => {
+                let start = self.row_col_from_position(&range.start, encoding, bounds)?;
+                let end = self.row_col_from_position(&range.end, encoding, bounds)?;
+                self.delete_range(start, end)?;
+                self.insert_text(change.text.as_str(), start)
+            }
+            None => {
+                self.replace(change.text.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies the changes of a `textDocument/didChange` in order, each
+    /// positioned against the document as left by the changes before it
+    ///
+    /// Changes preceding the last change without a range are superseded by
+    /// it and skipped. On error the changes before the failing one remain
+    /// applied
+    pub fn apply_content_changes(
+        &mut self,
+        changes: &[lsp_types::TextDocumentContentChangeEvent],
+        encoding: PositionEncoding,
+        bounds: PositionBounds,
+    ) -> Result<(), DocumentError> {
+        let start = changes.iter().rposition(|x| x.range.is_none()).unwrap_or(0);
+        for change in changes[start..].iter() {
+            self.apply_content_change(change, encoding, bounds)?;
+        }
         Ok(())
     }
 
@@ -160,35 +526,187 @@ impl DocumentBuffer {
     pub fn iter(&self) -> impl Iterator<Item = &char> {
         self.text.iter()
     }
+
+    /// Hash of the buffer's content, equal to `content_hash` of its text
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Xxh3::new();
+        let mut chunk = Vec::with_capacity(HASH_CHUNK_SIZE + 4);
+        let mut encoded = [0u8; 4];
+        for c in self.iter() {
+            chunk.extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+            if chunk.len() >= HASH_CHUNK_SIZE {
+                hasher.update(&chunk);
+                chunk.clear();
+            }
+        }
+        hasher.update(&chunk);
+        hasher.digest()
+    }
+}
+
+/// Bytes of text hashed at a time by `DocumentBuffer::content_hash`
+const HASH_CHUNK_SIZE: usize = 4096;
+
+/// Hashes text such that diagnostics computed from it can be identified
+/// without retaining the text
+pub fn content_hash(text: &str) -> u64 {
+    xxh3_64(text.as_bytes())
 }
 
 // FIXME below handles queries with an exhaustive search
 // an intersection query datastructure would be more appropriate
 pub struct CheckRegistry {
     checks: Vec<Check>,
+    content_hash: Option<u64>,
+    /// Generation of the settings the checks were computed under, see
+    /// [`ConfigSnapshot::generation`]
+    generation: u64,
+    /// Severity of the diagnostics of every check in place of that of its
+    /// rule, such as for generated files
+    severity: Option<Severity>,
+    /// Whether the checks were dropped to bound memory, in which case the
+    /// client may still hold diagnostics of them
+    evicted: bool,
+    last_used: AtomicU64,
+    /// When the document was closed, if it was since the checks were
+    /// computed
+    closed_at: Option<u64>,
+}
+
+/// Orders checks by position, then by code, such that diagnostics are
+/// published in the same order whatever order the checks were found in
+pub fn sort_checks(checks: &mut [Check]) {
+    checks.sort_by(|a, b| {
+        get_check_start_loc(a)
+            .cmp(&get_check_start_loc(b))
+            .then_with(|| get_check_end_loc(a).cmp(&get_check_end_loc(b)))
+            .then_with(|| a.kind.code().as_ref().cmp(b.kind.code().as_ref()))
+    });
 }
 
 impl FromIterator<Check> for CheckRegistry {
     fn from_iter<T: IntoIterator<Item = Check>>(iter: T) -> Self {
-        let checks = iter.into_iter().collect::<Vec<_>>();
-        Self { checks }
+        let mut checks = iter.into_iter().collect::<Vec<_>>();
+        sort_checks(&mut checks);
+        Self {
+            checks,
+            content_hash: None,
+            generation: 0,
+            severity: None,
+            evicted: false,
+            last_used: AtomicU64::new(0),
+            closed_at: None,
+        }
     }
 }
 
 impl CheckRegistry {
-    /// Constructs an iterator for checks that intersect the given range
-    pub fn iter_range<R: RangeBounds<(usize, usize)>>(
-        &self,
-        range: R,
-    ) -> CheckRegistryRangeIter<'_> {
+    /// Records the hash of the content the checks were computed from
+    pub fn with_content_hash(mut self, content_hash: Option<u64>) -> Self {
+        self.content_hash = content_hash;
+        self
+    }
+
+    pub fn content_hash(&self) -> Option<u64> {
+        self.content_hash
+    }
+
+    /// Records the generation of the settings the checks were computed
+    /// under
+    pub fn with_generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Records the severity of the diagnostics of every check, taking
+    /// precedence over the severities of their rules
+    pub fn with_severity(mut self, severity: Option<Severity>) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn severity(&self) -> Option<Severity> {
+        self.severity
+    }
+
+    /// Identifies the checks to clients pulling diagnostics, being absent
+    /// if the content they were computed from is unknown
+    ///
+    /// Checks of the same content differ under changed settings, so the
+    /// generation of the settings is part of the identifier
+    pub fn result_id(&self) -> Option<String> {
+        self.content_hash
+            .map(|x| format!("{:016x}-{}", x, self.generation))
+    }
+
+    /// Forgets the content the checks were computed from, such that they're
+    /// recomputed even if the content is unchanged
+    pub fn invalidate(&mut self) {
+        self.content_hash = None;
+    }
+
+    /// Drops the checks, which are recomputed the next time the document is
+    /// linted as its content is forgotten
+    pub fn evict(&mut self) {
+        self.checks = vec![];
+        self.content_hash = None;
+        self.evicted = true;
+    }
+
+    /// Whether the checks were evicted, such that diagnostics published of
+    /// them are unknown
+    pub fn is_evicted(&self) -> bool {
+        self.evicted
+    }
+
+    /// Estimate of the memory held by the registry in bytes
+    pub fn memory_size(&self) -> usize {
+        let fixes = self
+            .checks
+            .iter()
+            .filter_map(|x| x.fix.as_ref())
+            .map(|x| mem::size_of_val(x) + x.patch.content.capacity())
+            .sum::<usize>();
+        mem::size_of::<Self>() + self.checks.capacity() * mem::size_of::<Check>() + fixes
+    }
+
+    /// Retains the checks for which `f` returns true, allowing `f` to
+    /// modify them
+    pub fn retain_mut<F: FnMut(&mut Check) -> bool>(&mut self, f: F) {
+        self.checks.retain_mut(f);
+        // moved checks may now share a position with differently coded ones
+        sort_checks(&mut self.checks);
+    }
+
+    /// Iterates all checks ordered by position, then by code, as given by
+    /// [`sort_checks`]
+    pub fn iter(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.checks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Constructs an iterator for checks that intersect the given range of
+    /// ruff locations
+    pub fn iter_range<R: RangeBounds<Location>>(&self, range: R) -> CheckRegistryRangeIter<'_> {
         let start_bound = match range.start_bound() {
-            Bound::Included(x) => *x,
-            Bound::Excluded((row, col)) => (*row, col + 1),
+            Bound::Included(x) => (x.row(), x.column()),
+            Bound::Excluded(x) => (x.row(), x.column() + 1),
             Bound::Unbounded => (0, 0),
         };
         let end_bound = match range.end_bound() {
-            Bound::Included((row, col)) => Some((*row, col + 1)),
-            Bound::Excluded(x) => Some(*x),
+            Bound::Included(x) => Some((x.row(), x.column() + 1)),
+            Bound::Excluded(x) => Some((x.row(), x.column())),
             Bound::Unbounded => None,
         };
         CheckRegistryRangeIter {
@@ -200,6 +718,130 @@ impl CheckRegistry {
     }
 }
 
+/// Check registries of documents, ordering their uses for evicting the
+/// least recently used
+///
+/// Registries are only ordered once inserted through
+/// [`CheckRegistries::insert`], rather than the map it dereferences to
+#[derive(Default)]
+pub struct CheckRegistries {
+    registries: HashMap<lsp_types::Url, CheckRegistry>,
+    clock: AtomicU64,
+}
+
+impl Deref for CheckRegistries {
+    type Target = HashMap<lsp_types::Url, CheckRegistry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.registries
+    }
+}
+
+impl DerefMut for CheckRegistries {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.registries
+    }
+}
+
+impl CheckRegistries {
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Replaces the registry of the document, as its most recently used
+    pub fn insert(
+        &mut self,
+        uri: lsp_types::Url,
+        registry: CheckRegistry,
+    ) -> Option<CheckRegistry> {
+        registry.last_used.store(self.tick(), Ordering::Relaxed);
+        self.registries.insert(uri, registry)
+    }
+
+    /// Marks the checks of the document as used, deferring their eviction
+    pub fn touch(&self, uri: &lsp_types::Url) {
+        if let Some(registry) = self.registries.get(uri) {
+            registry.last_used.store(self.tick(), Ordering::Relaxed);
+        }
+    }
+
+    /// Marks the document as closed, its checks being kept until it's no
+    /// longer among the most recently closed, see [`prune_closed_checks`]
+    pub fn close(&mut self, uri: &lsp_types::Url) {
+        let now = self.tick();
+        if let Some(registry) = self.registries.get_mut(uri) {
+            registry.last_used.store(now, Ordering::Relaxed);
+            registry.closed_at = Some(now);
+        }
+    }
+}
+
+/// Evicts the least recently used registries of documents that aren't open
+/// until the memory held by those is within `budget` bytes, returning the
+/// number evicted
+///
+/// Registries of open documents are kept, as they back the code actions
+/// offered as the document is edited
+pub fn evict_checks(
+    checks: &mut CheckRegistries,
+    open_buffers: &HashMap<lsp_types::Url, DocumentBuffer>,
+    budget: usize,
+) -> usize {
+    let mut candidates = checks
+        .iter()
+        .filter(|(uri, x)| !x.evicted && !open_buffers.contains_key(uri))
+        .map(|(uri, x)| (x.last_used.load(Ordering::Relaxed), x.memory_size(), uri))
+        .collect::<Vec<_>>();
+    let mut size = candidates.iter().map(|x| x.1).sum::<usize>();
+    if size <= budget {
+        return 0;
+    }
+    candidates.sort_unstable();
+    let mut evicted = vec![];
+    for (_, cost, uri) in candidates {
+        if size <= budget {
+            break;
+        }
+        size -= cost;
+        evicted.push(uri.clone());
+    }
+    for uri in evicted.iter() {
+        checks.get_mut(uri).unwrap().evict();
+    }
+    evicted.len()
+}
+
+/// Removes the registries of closed documents other than the `keep` most
+/// recently closed, returning the documents whose diagnostics are to be
+/// cleared
+///
+/// Registries of documents reopened since are kept, as are those computed
+/// since the document was closed, such as by linting the workspace
+pub fn prune_closed_checks(
+    checks: &mut CheckRegistries,
+    open_buffers: &HashMap<lsp_types::Url, DocumentBuffer>,
+    keep: usize,
+) -> Vec<lsp_types::Url> {
+    let mut closed = checks
+        .iter()
+        .filter(|(uri, _)| !open_buffers.contains_key(uri))
+        .filter_map(|(uri, x)| Some((cmp::Reverse(x.closed_at?), uri.clone())))
+        .collect::<Vec<_>>();
+    if closed.len() <= keep {
+        return vec![];
+    }
+    closed.sort_unstable();
+    closed
+        .into_iter()
+        .skip(keep)
+        .filter_map(|(_, uri)| {
+            let registry = checks.remove(&uri)?;
+            // the client may hold diagnostics of evicted checks
+            (!registry.is_empty() || registry.is_evicted()).then_some(uri)
+        })
+        .collect()
+}
+
 pub struct CheckRegistryRangeIter<'a> {
     registry: &'a CheckRegistry,
     // inclusive
@@ -255,13 +897,595 @@ impl<'a> Iterator for CheckRegistryRangeIter<'a> {
     }
 }
 
+/// Set of python files known to be present in the workspace
+#[derive(Default)]
+pub struct WorkspaceIndex {
+    files: BTreeSet<lsp_types::Url>,
+}
+
+impl WorkspaceIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the uri was not already present
+    pub fn insert(&mut self, uri: lsp_types::Url) -> bool {
+        self.files.insert(uri)
+    }
+
+    /// Returns true if the uri was present
+    pub fn remove(&mut self, uri: &lsp_types::Url) -> bool {
+        self.files.remove(uri)
+    }
+
+    pub fn contains(&self, uri: &lsp_types::Url) -> bool {
+        self.files.contains(uri)
+    }
+
+    /// Iterates the indexed uris in order
+    pub fn iter(&self) -> impl Iterator<Item = &lsp_types::Url> {
+        self.files.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl Extend<lsp_types::Url> for WorkspaceIndex {
+    fn extend<T: IntoIterator<Item = lsp_types::Url>>(&mut self, iter: T) {
+        self.files.extend(iter)
+    }
+}
+
+/// Default limit on the total size of the sources of cached modules, in
+/// characters
+pub const DEFAULT_AST_CACHE_SIZE: usize = 16 * 1024 * 1024;
+
+struct AstCacheEntry {
+    version: i32,
+    suite: Arc<Suite>,
+    cost: usize,
+    last_used: AtomicU64,
+}
+
+/// Parsed modules of open documents, shared by the features parsing them
+///
+/// A module is kept for the latest version of its document parsed, with
+/// the least recently used modules evicted once the sources parsed exceed
+/// the capacity. A source's length stands in for the memory of its module
+pub struct AstCache {
+    capacity: usize,
+    size: usize,
+    clock: AtomicU64,
+    entries: HashMap<lsp_types::Url, AstCacheEntry>,
+}
+
+impl Default for AstCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_AST_CACHE_SIZE)
+    }
+}
+
+impl AstCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            clock: AtomicU64::new(0),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Total length of the sources of the cached modules
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Gets the module of the document at `version`, if it's cached
+    pub fn get(&self, uri: &lsp_types::Url, version: i32) -> Option<Arc<Suite>> {
+        let entry = self.entries.get(uri).filter(|x| x.version == version)?;
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        entry.last_used.store(now, Ordering::Relaxed);
+        Some(entry.suite.clone())
+    }
+
+    /// Caches the module parsed from `cost` characters of the document at
+    /// `version`, replacing the module of any other version
+    ///
+    /// Modules of sources larger than the capacity aren't cached
+    pub fn insert(&mut self, uri: lsp_types::Url, version: i32, suite: Arc<Suite>, cost: usize) {
+        self.invalidate(&uri);
+        if cost > self.capacity {
+            return;
+        }
+        while self.size + cost > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, x)| x.last_used.load(Ordering::Relaxed))
+                .map(|(uri, _)| uri.clone())
+                .unwrap();
+            self.invalidate(&oldest);
+        }
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.size += cost;
+        self.entries.insert(
+            uri,
+            AstCacheEntry {
+                version,
+                suite,
+                cost,
+                last_used: AtomicU64::new(now),
+            },
+        );
+    }
+
+    /// Drops the module of the document, as once it's edited
+    pub fn invalidate(&mut self, uri: &lsp_types::Url) {
+        if let Some(entry) = self.entries.remove(uri) {
+            self.size -= entry.cost;
+        }
+    }
+
+    /// Gets the module of the document at `version`, parsing `source` if
+    /// it isn't cached. Sources failing to parse aren't cached
+    pub fn get_or_parse(
+        &mut self,
+        uri: &lsp_types::Url,
+        version: i32,
+        source: &str,
+    ) -> Result<Arc<Suite>, ParseError> {
+        if let Some(x) = self.get(uri, version) {
+            return Ok(x);
+        }
+        let suite = Arc::new(parser::parse_program(source, "<filename>")?);
+        self.insert(uri.clone(), version, suite.clone(), source.chars().count());
+        Ok(suite)
+    }
+}
+
+/// Default number of files whose symbols are cached
+pub const DEFAULT_SYMBOL_CACHE_SIZE: usize = 4096;
+
+/// Top-level definition of a file, as searched by `workspace/symbol`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: lsp_types::SymbolKind,
+    pub range: lsp_types::Range,
+}
+
+impl Symbol {
+    #[allow(deprecated)]
+    pub fn information(&self, uri: lsp_types::Url) -> lsp_types::SymbolInformation {
+        lsp_types::SymbolInformation {
+            name: self.name.clone(),
+            kind: self.kind,
+            tags: None,
+            deprecated: None,
+            location: lsp_types::Location::new(uri, self.range),
+            container_name: None,
+        }
+    }
+}
+
+struct SymbolCacheEntry {
+    content_hash: u64,
+    symbols: Arc<Vec<Symbol>>,
+    last_used: u64,
+}
+
+struct SymbolCacheInner {
+    capacity: usize,
+    /// Incremented on each access, ordering the entries by their last use
+    clock: u64,
+    entries: HashMap<lsp_types::Url, SymbolCacheEntry>,
+}
+
+/// Least recently used cache of the symbols of files, by the hash of the
+/// content they were parsed from
+///
+/// Clones share their entries, such that the cache can be used once the
+/// state is released, as while files are read and parsed
+#[derive(Clone)]
+pub struct SymbolCache(Arc<std::sync::Mutex<SymbolCacheInner>>);
+
+impl Default for SymbolCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SYMBOL_CACHE_SIZE)
+    }
+}
+
+impl SymbolCache {
+    pub fn new(capacity: usize) -> Self {
+        Self(Arc::new(std::sync::Mutex::new(SymbolCacheInner {
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        })))
+    }
+
+    /// Gets the symbols of the file, if cached for content hashing to
+    /// `content_hash`
+    pub fn get(&self, uri: &lsp_types::Url, content_hash: u64) -> Option<Arc<Vec<Symbol>>> {
+        let mut inner = self.0.lock().unwrap();
+        inner.clock += 1;
+        let now = inner.clock;
+        let entry = inner
+            .entries
+            .get_mut(uri)
+            .filter(|x| x.content_hash == content_hash)?;
+        entry.last_used = now;
+        Some(entry.symbols.clone())
+    }
+
+    /// Caches the symbols of the file's content hashing to `content_hash`,
+    /// evicting the least recently used file once full
+    pub fn insert(&self, uri: lsp_types::Url, content_hash: u64, symbols: Arc<Vec<Symbol>>) {
+        let mut inner = self.0.lock().unwrap();
+        inner.clock += 1;
+        if inner.entries.len() >= inner.capacity && !inner.entries.contains_key(&uri) {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, x)| x.last_used)
+                .map(|(uri, _)| uri.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        let last_used = inner.clock;
+        inner.entries.insert(
+            uri,
+            SymbolCacheEntry {
+                content_hash,
+                symbols,
+                last_used,
+            },
+        );
+    }
+}
+
+impl fmt::Debug for SymbolCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.0.lock().unwrap().entries.len();
+        f.debug_tuple("SymbolCache").field(&len).finish()
+    }
+}
+
+/// Source of the current time, replaced to drive timed state in tests
+pub type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Clock reading the system's monotonic clock
+pub fn system_clock() -> Clock {
+    Arc::new(Instant::now)
+}
+
+/// Default least interval between logging errors of the same kind
+pub const DEFAULT_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Admits events of each kind at most once per interval
+pub struct RateLimiter {
+    interval: Duration,
+    clock: Clock,
+    /// Time each kind was last admitted, and the number of events of the
+    /// kind suppressed since
+    admitted: HashMap<String, (Instant, usize)>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration, clock: Clock) -> Self {
+        Self {
+            interval,
+            clock,
+            admitted: HashMap::new(),
+        }
+    }
+
+    /// Determines whether an event of `kind` occurring now is admitted,
+    /// returning the number of events of the kind suppressed since the
+    /// last one admitted
+    pub fn admit(&mut self, kind: &str) -> Option<usize> {
+        let now = (self.clock)();
+        match self.admitted.get_mut(kind) {
+            Some((last, suppressed)) if now.duration_since(*last) < self.interval => {
+                *suppressed += 1;
+                None
+            }
+            Some((last, suppressed)) => {
+                *last = now;
+                Some(mem::take(suppressed))
+            }
+            None => {
+                self.admitted.insert(kind.to_string(), (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOG_INTERVAL, system_clock())
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("interval", &self.interval)
+            .field("admitted", &self.admitted)
+            .finish()
+    }
+}
+
+/// Diagnostics of a document restored from a warm cache snapshot, valid
+/// while the document's content hashes to `content_hash`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedDiagnostics {
+    pub content_hash: u64,
+    pub diagnostics: Vec<lsp_types::Diagnostic>,
+}
+
+/// Synchronisation state of an open document relative to its saved copy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentStatus {
+    /// Version of the last change applied
+    pub version: i32,
+    /// Version at the last save, `None` if saved at an unknown version
+    pub saved_version: Option<i32>,
+    /// Whether changes have been made since the last save or opening
+    pub dirty: bool,
+    /// Version at which a save was announced with `willSave` and has yet
+    /// to be confirmed with `didSave`
+    pub pending_save: Option<i32>,
+    /// Whether the document exceeds `ServerConfig::max_document_size`, in
+    /// which case it isn't buffered and is only linted from disk on save
+    pub oversized: bool,
+    /// Whether ruff panicked linting the document as last linted, in which
+    /// case it has no diagnostics
+    pub lint_failed: bool,
+}
+
+impl DocumentStatus {
+    /// Status of a newly opened document, assumed to match its saved copy
+    pub fn opened(version: i32) -> Self {
+        Self {
+            version,
+            saved_version: Some(version),
+            dirty: false,
+            pending_save: None,
+            oversized: false,
+            lint_failed: false,
+        }
+    }
+
+    pub fn changed(&mut self, version: i32) {
+        self.version = version;
+        self.dirty = true;
+    }
+
+    pub fn will_save(&mut self) {
+        self.pending_save = Some(self.version);
+    }
+
+    /// Marks the document saved, returning false if the save didn't match
+    /// the version announced by `willSave`
+    ///
+    /// A save without a preceding `willSave` is consistent, as clients need
+    /// not send it
+    pub fn saved(&mut self) -> bool {
+        let consistent = !matches!(self.pending_save, Some(x) if x != self.version);
+        self.saved_version = Some(self.version);
+        self.dirty = false;
+        self.pending_save = None;
+        consistent
+    }
+}
+
+/// An open notebook, the text of whose code cells is held in
+/// `ServerState::open_buffers` under the uris of the cells
+///
+/// Markup cells aren't linted, so their text isn't kept
+#[derive(Debug, Clone)]
+pub struct Notebook {
+    pub version: i32,
+    pub cells: Vec<NotebookCell>,
+}
+
+impl Notebook {
+    pub fn new(version: i32, cells: Vec<NotebookCell>) -> Self {
+        Self { version, cells }
+    }
+
+    pub fn cell(&self, uri: &lsp_types::Url) -> Option<&NotebookCell> {
+        self.cells.iter().find(|x| &x.document == uri)
+    }
+
+    /// Iterates the uris of the notebook's code cells in order
+    pub fn code_cells(&self) -> impl Iterator<Item = &lsp_types::Url> {
+        self.cells
+            .iter()
+            .filter(|x| x.kind == NotebookCellKind::Code)
+            .map(|x| &x.document)
+    }
+}
+
 #[server_state(in_ruffd_types = true)]
 pub struct ServerState {
-    pub project_root: Option<lsp_types::Url>,
     pub open_buffers: HashMap<lsp_types::Url, DocumentBuffer>,
     pub capabilities: lsp_types::ServerCapabilities,
-    pub settings: Configuration,
-    pub checks: HashMap<lsp_types::Url, CheckRegistry>,
+    pub checks: CheckRegistries,
+    pub client_capabilities: lsp_types::ClientCapabilities,
+    pub workspace_index: WorkspaceIndex,
+    pub document_status: HashMap<lsp_types::Url, DocumentStatus>,
+    pub notebooks: HashMap<lsp_types::Url, Notebook>,
+    /// Text of documents as last saved to disk
+    pub shadow_buffers: HashMap<lsp_types::Url, DocumentBuffer>,
+    /// Diagnostics restored from a warm cache snapshot, published when a
+    /// document is opened unchanged rather than awaiting its lint
+    pub cached_diagnostics: HashMap<lsp_types::Url, CachedDiagnostics>,
+    /// Whether a re-lint of open documents is scheduled, such that a burst
+    /// of settings changes re-lints them once
+    pub relint_pending: bool,
+    pub ast_cache: AstCache,
+    /// Symbols of the workspace's files, shared with the searches reading
+    /// and parsing files once the state is released
+    pub symbol_cache: SymbolCache,
+    /// Revision of each buffer as of its last spill, such that unmodified
+    /// buffers aren't spilled again
+    pub spilled: HashMap<lsp_types::Url, usize>,
+    /// Limits the failures of notification handlers logged to the client
+    pub log_limiter: RateLimiter,
+    /// Settings of the server, replaced whole as they change such that
+    /// lints needn't wait on a lock to read them
+    #[snapshot]
+    pub config_snapshot: ConfigSnapshot,
+}
+
+/// Settings a document is linted with, as of their last change
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSnapshot {
+    pub project_root: Option<lsp_types::Url>,
+    pub config: ServerConfig,
+    /// Settings of the `[tool.ruffd]` table of the project's pyproject,
+    /// read once initialized such that errors can be shown to the user
+    pub project_config: ProjectConfig,
+    /// Encoding of positions negotiated with the client
+    pub position_encoding: PositionEncoding,
+    /// Whether messages are handled one at a time in a reproducible order,
+    /// in which case nothing is timed by the wall clock
+    pub deterministic: bool,
+    /// Counts changes of the settings, such that checks computed under
+    /// previous settings are told apart from those of the current
+    pub generation: u64,
+    /// Ruff's settings resolved under the current settings
+    pub settings_cache: SettingsCache,
+    /// Durations of recent lints, kept across changes of the settings
+    pub lint_timings: LintTimings,
+    /// Whether the client watches files on the server's behalf, as of its
+    /// answer to the registration
+    pub watcher: WatcherStatus,
+    /// Directory buffers with unsaved changes are spilled to, unique to the
+    /// session. `None` until the session is initialized, buffers not being
+    /// spilled until then
+    pub spill_dir: Option<PathBuf>,
+}
+
+impl ConfigSnapshot {
+    /// Marks the settings as changed, such that checks computed under them
+    /// are recomputed and ruff's settings are resolved again
+    pub fn settings_changed(&mut self) {
+        self.generation += 1;
+        self.settings_cache = SettingsCache::default();
+    }
+}
+
+/// Ruff's settings resolved for the files of each directory, such that
+/// pyprojects aren't read again whenever a file is linted
+///
+/// Clones share their entries. Files of the same directory share a
+/// settings root and inferred first-party roots, so resolve to the same
+/// settings
+#[derive(Clone, Default)]
+pub struct SettingsCache(Arc<std::sync::Mutex<HashMap<PathBuf, Arc<Settings>>>>);
+
+impl SettingsCache {
+    /// Settings of the files of `dir`, resolved with `resolve` unless
+    /// already cached
+    ///
+    /// The lock isn't held while resolving, so concurrent lints of a
+    /// directory may each resolve its settings
+    pub fn get_or_resolve<F, E>(&self, dir: &Path, resolve: F) -> Result<Arc<Settings>, E>
+    where
+        F: FnOnce() -> Result<Settings, E>,
+    {
+        if let Some(settings) = self.0.lock().unwrap().get(dir) {
+            return Ok(settings.clone());
+        }
+        let settings = Arc::new(resolve()?);
+        self.0
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), settings.clone());
+        Ok(settings)
+    }
+}
+
+impl fmt::Debug for SettingsCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.0.lock().unwrap().len();
+        f.debug_tuple("SettingsCache").field(&len).finish()
+    }
+}
+
+/// Number of lints whose durations are kept for status reports
+const RECENT_LINTS: usize = 16;
+
+/// Durations of the most recent lints of the session, the latest first
+///
+/// Clones share their entries, such that lints record their durations
+/// through the scope they were run under
+#[derive(Clone, Default)]
+pub struct LintTimings(Arc<std::sync::Mutex<VecDeque<LintTiming>>>);
+
+impl LintTimings {
+    pub fn record(&self, timing: LintTiming) {
+        let mut timings = self.0.lock().unwrap();
+        if timings.len() == RECENT_LINTS {
+            timings.pop_back();
+        }
+        timings.push_front(timing);
+    }
+
+    pub fn recent(&self) -> Vec<LintTiming> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl fmt::Debug for LintTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let len = self.0.lock().unwrap().len();
+        f.debug_tuple("LintTimings").field(&len).finish()
+    }
+}
+
+/// Value replaced whole rather than modified in place, in the manner of
+/// `arc_swap::ArcSwap`
+///
+/// Readers load the current value without waiting on writers, keeping the
+/// value loaded for as long as they need it. The inner lock is only held to
+/// clone or replace the `Arc`, never across an await
+#[derive(Debug, Default)]
+pub struct Snapshot<T>(std::sync::RwLock<Arc<T>>);
+
+impl<T> Snapshot<T> {
+    pub fn new(value: T) -> Self {
+        Self(std::sync::RwLock::new(Arc::new(value)))
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Replaces the value with a modified copy, updates being applied one
+    /// at a time such that none are lost
+    pub fn update<F>(&self, f: F)
+    where
+        T: Clone,
+        F: FnOnce(&mut T),
+    {
+        let mut current = self.0.write().unwrap();
+        let mut value = T::clone(&current);
+        f(&mut value);
+        *current = Arc::new(value);
+    }
 }
 
 macro_rules! make_rw_send {
@@ -271,52 +1495,90 @@ macro_rules! make_rw_send {
 }
 
 impl ServerState {
+    /// Root of the project given by the client, the deprecated `rootPath`
+    /// and then the first workspace folder standing in for a missing
+    /// `rootUri`
+    ///
+    /// Clients giving none have no project root, rather than one taken from
+    /// the working directory of the server, which for a server started by an
+    /// editor is unrelated to the files edited. Documents are then linted
+    /// with the settings of their nearest pyproject, and the workspace isn't
+    /// indexed
+    fn project_root_from_init(init_params: &lsp_types::InitializeParams) -> Option<lsp_types::Url> {
+        if let Some(x) = &init_params.root_uri {
+            return Some(normalize_uri(x));
+        }
+        #[allow(deprecated)]
+        let root_path = init_params.root_path.as_deref().filter(|x| !x.is_empty());
+        if let Some(root_path) = root_path {
+            log_warn!("client gave the deprecated rootPath rather than rootUri");
+            match path_to_uri(Path::new(root_path)) {
+                Some(x) => return Some(normalize_uri(&x)),
+                None => log_warn!("rootPath {} isn't an absolute path", root_path),
+            }
+        }
+        let folder = init_params
+            .workspace_folders
+            .as_ref()
+            .and_then(|x| x.first());
+        if let Some(folder) = folder {
+            return Some(normalize_uri(&folder.uri));
+        }
+        log_warn!("client gave no root, the workspace isn't indexed");
+        None
+    }
+
     pub fn from_init(init_params: &lsp_types::InitializeParams) -> Result<Self, RuntimeError> {
-        // FIXME configure from client capabilities
-        let project_root_val = init_params.root_uri.clone();
+        let project_root = Self::project_root_from_init(init_params);
         // TODO
         // - hover provider
-        // - code action provider
         // - diagnostic provider
-        let capabilities_val = lsp_types::ServerCapabilities {
-            text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Options(
-                lsp_types::TextDocumentSyncOptions {
-                    open_close: Some(true),
-                    change: Some(lsp_types::TextDocumentSyncKind::INCREMENTAL),
-                    will_save: Some(true),
-                    will_save_wait_until: None,
-                    save: None,
-                },
-            )),
-            code_action_provider: Some(lsp_types::CodeActionProviderCapability::Options(
-                lsp_types::CodeActionOptions {
-                    code_action_kinds: Some(vec![lsp_types::CodeActionKind::QUICKFIX]),
-                    work_done_progress_options: lsp_types::WorkDoneProgressOptions {
-                        work_done_progress: None,
-                    },
-                    resolve_provider: None,
-                },
-            )),
-            ..Default::default()
-        };
-        let project_root_path = match &project_root_val {
-            Some(val) => Some(
-                val.to_file_path()
-                    .map_err(|_| RuntimeError::UriToPathError(val.clone()))?,
-            ),
-            None => None,
-        };
-        let project_root = make_rw_send!(project_root_val);
-        let capabilities = make_rw_send!(capabilities_val);
         let open_buffers = make_rw_send!(HashMap::new());
-        let settings = make_rw_send!(Configuration::from_pyproject(&None, &project_root_path,)?);
-        let checks = make_rw_send!(HashMap::new());
-        Ok(Self {
-            settings,
+        let checks = make_rw_send!(CheckRegistries::default());
+        let client_capabilities = make_rw_send!(init_params.capabilities.clone());
+        // malformed options shouldn't prevent initialization, defaults are
+        // used instead, the client being warned once initialized
+        let config = match &init_params.initialization_options {
+            Some(x) => ServerConfig::from_value(x.clone()).unwrap_or_else(|err| {
+                log_warn!("malformed initializationOptions: {}", err);
+                ServerConfig::default()
+            }),
+            None => ServerConfig::default(),
+        };
+        let capabilities = make_rw_send!(server_capabilities(&config, &init_params.capabilities));
+        // the position encoding is negotiated apart from the rest of the
+        // initialize params, so is set once the state is created
+        let config_snapshot = Arc::new(Snapshot::new(ConfigSnapshot {
             project_root,
+            config,
+            ..Default::default()
+        }));
+        let workspace_index = make_rw_send!(WorkspaceIndex::new());
+        let document_status = make_rw_send!(HashMap::new());
+        let notebooks = make_rw_send!(HashMap::new());
+        let shadow_buffers = make_rw_send!(HashMap::new());
+        let cached_diagnostics = make_rw_send!(HashMap::new());
+        let relint_pending = make_rw_send!(false);
+        let ast_cache = make_rw_send!(AstCache::default());
+        let symbol_cache = make_rw_send!(SymbolCache::default());
+        let spilled = make_rw_send!(HashMap::new());
+        let log_limiter = make_rw_send!(RateLimiter::default());
+        Ok(Self {
             capabilities,
             open_buffers,
             checks,
+            client_capabilities,
+            workspace_index,
+            document_status,
+            notebooks,
+            shadow_buffers,
+            cached_diagnostics,
+            relint_pending,
+            ast_cache,
+            symbol_cache,
+            spilled,
+            log_limiter,
+            config_snapshot,
         })
     }
 }
@@ -338,6 +1600,93 @@ impl<T> RwReq<T> {
             Self::Write(x) => RwGuarded::Write(x.write().await),
         }
     }
+
+    /// Locks as [`RwReq::lock`], recording the wait against `field` and the
+    /// handler locking it
+    pub async fn lock_timed(&self, field: &'static str) -> RwGuarded<'_, T> {
+        let start = Instant::now();
+        let guard = self.lock().await;
+        contention::record(field, start.elapsed());
+        guard
+    }
+}
+
+/// Field of the server state, requested by handlers naming it
+///
+/// Snapshot fields are requested as their cell, loaded as the handler runs
+/// if it reads the field and given as the cell itself if it names the field
+/// `mut`, to replace the value
+#[doc(hidden)]
+pub trait StateField {
+    type Req;
+    fn read_req(&self) -> Self::Req;
+    fn write_req(&self) -> Self::Req;
+}
+
+impl<T> StateField for Arc<RwLock<T>> {
+    type Req = RwReq<T>;
+
+    fn read_req(&self) -> Self::Req {
+        RwReq::Read(self.clone())
+    }
+
+    fn write_req(&self) -> Self::Req {
+        RwReq::Write(self.clone())
+    }
+}
+
+impl<T> StateField for Arc<Snapshot<T>> {
+    type Req = Self;
+
+    fn read_req(&self) -> Self::Req {
+        self.clone()
+    }
+
+    fn write_req(&self) -> Self::Req {
+        self.clone()
+    }
+}
+
+/// Handle to a field of the server state, unwrapped into what handlers are
+/// given for the field
+#[doc(hidden)]
+pub trait StateHandle {
+    type ReadHandle;
+    type WriteHandle;
+    fn into_read(self) -> Self::ReadHandle;
+    fn into_write(self) -> Self::WriteHandle;
+}
+
+impl<'a, T> StateHandle for RwGuarded<'a, T> {
+    type ReadHandle = RwLockReadGuard<'a, T>;
+    type WriteHandle = RwLockWriteGuard<'a, T>;
+
+    fn into_read(self) -> Self::ReadHandle {
+        match self {
+            Self::Read(x) => x,
+            _ => unreachable!(),
+        }
+    }
+
+    fn into_write(self) -> Self::WriteHandle {
+        match self {
+            Self::Write(x) => x,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<T> StateHandle for Arc<Snapshot<T>> {
+    type ReadHandle = Arc<T>;
+    type WriteHandle = Self;
+
+    fn into_read(self) -> Self::ReadHandle {
+        self.load()
+    }
+
+    fn into_write(self) -> Self::WriteHandle {
+        self
+    }
 }
 
 #[doc(hidden)]
@@ -365,7 +1714,7 @@ macro_rules! tup_pat_setter {
 #[macro_export]
 macro_rules! create_read_lock {
     ($handle:ident, $name:ident) => {
-        let $name = Some($crate::RwReq::Read($handle.$name.clone()));
+        let $name = Some($crate::StateField::read_req(&$handle.$name));
     };
 }
 
@@ -373,7 +1722,7 @@ macro_rules! create_read_lock {
 #[macro_export]
 macro_rules! create_write_lock {
     ($handle:ident, $name:ident) => {
-        let $name = Some($crate::RwReq::Write($handle.$name.clone()));
+        let $name = Some($crate::StateField::write_req(&$handle.$name));
     };
 }
 
@@ -432,10 +1781,8 @@ macro_rules! create_locks_fut {
 #[macro_export]
 macro_rules! unwrap_write_handle {
     ($handles:ident, $name:ident) => {
-        let mut $name = match $handles.$name.unwrap() {
-            $crate::RwGuarded::Write(x) => x,
-            _ => unreachable!(),
-        };
+        #[allow(unused_mut)]
+        let mut $name = $crate::StateHandle::into_write($handles.$name.unwrap());
     };
 }
 
@@ -443,10 +1790,7 @@ macro_rules! unwrap_write_handle {
 #[macro_export]
 macro_rules! unwrap_read_handle {
     ($handles:ident, $name:ident) => {
-        let $name = match $handles.$name.unwrap() {
-            $crate::RwGuarded::Read(x) => x,
-            _ => unreachable!(),
-        };
+        let $name = $crate::StateHandle::into_read($handles.$name.unwrap());
     };
 }
 
@@ -613,6 +1957,396 @@ if __name__ == '__main__':
         assert_eq!(doc.iter().collect::<String>(), "Some text\n    \n");
     }
 
+    fn content_change(
+        start: (u32, u32),
+        end: (u32, u32),
+        text: &str,
+    ) -> lsp_types::TextDocumentContentChangeEvent {
+        lsp_types::TextDocumentContentChangeEvent {
+            range: Some(lsp_types::Range {
+                start: lsp_types::Position {
+                    line: start.0,
+                    character: start.1,
+                },
+                end: lsp_types::Position {
+                    line: end.0,
+                    character: end.1,
+                },
+            }),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_apply_change_full_document() {
+        let mut doc = DocumentBuffer::from_string(SMALL_PROGRAM.to_string());
+        let change = lsp_types::TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "x = 1\n".to_string(),
+        };
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "x = 1\n");
+        let change = content_change((1, 0), (1, 0), "y = 2\n");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "x = 1\ny = 2\n");
+    }
+
+    fn apply_did_change(doc: &mut DocumentBuffer, params: &str) -> Result<(), DocumentError> {
+        let params =
+            serde_json::from_str::<lsp_types::DidChangeTextDocumentParams>(params).unwrap();
+        doc.apply_content_changes(
+            &params.content_changes,
+            PositionEncoding::Utf16,
+            PositionBounds::Strict,
+        )
+    }
+
+    #[test]
+    fn test_apply_changes_multi_cursor() {
+        // typing at three cursors, sent from the last cursor to the first
+        // such that each position is unaffected by the changes before it
+        let mut doc = DocumentBuffer::from_string("a = 1\nb = 2\nc = 3\n".to_string());
+        let params = r#"{
+            "textDocument": {"uri": "file:///tmp/dummy.py", "version": 2},
+            "contentChanges": [
+                {"range": {"start": {"line": 2, "character": 5}, "end": {"line": 2, "character": 5}}, "rangeLength": 0, "text": "0"},
+                {"range": {"start": {"line": 1, "character": 5}, "end": {"line": 1, "character": 5}}, "rangeLength": 0, "text": "0"},
+                {"range": {"start": {"line": 0, "character": 5}, "end": {"line": 0, "character": 5}}, "rangeLength": 0, "text": "0"}
+            ]
+        }"#;
+        apply_did_change(&mut doc, params).unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "a = 10\nb = 20\nc = 30\n");
+    }
+
+    #[test]
+    fn test_apply_changes_dependent_positions() {
+        // an auto closed bracket followed by typing inside it, the second
+        // change being positioned within the text of the first
+        let mut doc = DocumentBuffer::from_string("print\n".to_string());
+        let params = r#"{
+            "textDocument": {"uri": "file:///tmp/dummy.py", "version": 2},
+            "contentChanges": [
+                {"range": {"start": {"line": 0, "character": 5}, "end": {"line": 0, "character": 5}}, "rangeLength": 0, "text": "()"},
+                {"range": {"start": {"line": 0, "character": 6}, "end": {"line": 0, "character": 6}}, "rangeLength": 0, "text": "'\ud83d\ude00'"},
+                {"range": {"start": {"line": 0, "character": 10}, "end": {"line": 0, "character": 11}}, "rangeLength": 1, "text": ")\nprint()"}
+            ]
+        }"#;
+        apply_did_change(&mut doc, params).unwrap();
+        assert_eq!(
+            doc.iter().collect::<String>(),
+            "print('\u{1f600}')\nprint()\n"
+        );
+        // a change positioned past the end of the document as left by the
+        // changes before it is rejected
+        let mut doc = DocumentBuffer::from_string("x\n".to_string());
+        let params = r#"{
+            "textDocument": {"uri": "file:///tmp/dummy.py", "version": 2},
+            "contentChanges": [
+                {"range": {"start": {"line": 0, "character": 0}, "end": {"line": 1, "character": 0}}, "rangeLength": 2, "text": ""},
+                {"range": {"start": {"line": 0, "character": 1}, "end": {"line": 0, "character": 1}}, "rangeLength": 0, "text": "y"}
+            ]
+        }"#;
+        assert!(apply_did_change(&mut doc, params).is_err());
+    }
+
+    #[test]
+    fn test_map_position_overlapping_edits() {
+        let mut doc = DocumentBuffer::from_string("abc\ndef\n".to_string());
+        // a line broken after `a`, then a deletion spanning the inserted
+        // text and the `b` following it
+        let params = r#"{
+            "textDocument": {"uri": "file:///tmp/dummy.py", "version": 2},
+            "contentChanges": [
+                {"range": {"start": {"line": 0, "character": 1}, "end": {"line": 0, "character": 1}}, "rangeLength": 0, "text": "XY\n"},
+                {"range": {"start": {"line": 0, "character": 2}, "end": {"line": 1, "character": 1}}, "rangeLength": 3, "text": ""}
+            ]
+        }"#;
+        apply_did_change(&mut doc, params).unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "aXc\ndef\n");
+        assert_eq!(doc.map_position((0, 0), 0), Some((0, 0)));
+        // the deleted `b` is moved to the start of the deletion
+        assert_eq!(doc.map_position((0, 1), 0), Some((0, 2)));
+        assert_eq!(doc.map_position((0, 2), 0), Some((0, 2)));
+        assert_eq!(doc.map_position((1, 0), 0), Some((1, 0)));
+        // `c` as it was once the line was broken
+        assert_eq!(doc.map_position((1, 1), 2), Some((0, 2)));
+        let revision = doc.revision();
+        assert_eq!(doc.edits_since(revision).unwrap().count(), 0);
+        assert_eq!(doc.map_position((1, 2), revision), Some((1, 2)));
+        assert_eq!(doc.map_position((1, 2), revision + 1), None);
+        let params = r#"{
+            "textDocument": {"uri": "file:///tmp/dummy.py", "version": 3},
+            "contentChanges": [{"text": "x = 1\n"}]
+        }"#;
+        apply_did_change(&mut doc, params).unwrap();
+        assert_eq!(doc.revision(), revision + 1);
+        assert_eq!(doc.map_position((0, 0), revision), None);
+    }
+
+    #[test]
+    fn test_apply_changes_mixed_full_document() {
+        // changes before a full document change are superseded, changes
+        // after it are positioned against the new content
+        let mut doc = DocumentBuffer::from_string("a = 1\n".to_string());
+        let params = r#"{
+            "textDocument": {"uri": "file:///tmp/dummy.py", "version": 3},
+            "contentChanges": [
+                {"range": {"start": {"line": 9, "character": 0}, "end": {"line": 9, "character": 0}}, "text": "unreachable"},
+                {"text": "b = 2\nc = 3\n"},
+                {"range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 1}}, "rangeLength": 1, "text": "d"}
+            ]
+        }"#;
+        apply_did_change(&mut doc, params).unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "b = 2\nd = 3\n");
+    }
+
+    #[test]
+    fn test_apply_change_end_of_line() {
+        let mut doc = DocumentBuffer::from_string("a = 1\nb = 2\n".to_string());
+        let change = content_change((0, 5), (0, 5), "0");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "a = 10\nb = 2\n");
+        // joining lines by deleting the line break
+        let change = content_change((0, 6), (1, 0), "; ");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "a = 10; b = 2\n");
+    }
+
+    #[test]
+    fn test_apply_change_end_of_file() {
+        let mut doc = DocumentBuffer::from_string("a = 1\n".to_string());
+        let change = content_change((1, 0), (1, 0), "b = 2");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "a = 1\nb = 2");
+        let change = content_change((1, 5), (1, 5), "\n");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "a = 1\nb = 2\n");
+        let change = content_change((0, 0), (2, 0), "");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "");
+    }
+
+    #[test]
+    fn test_apply_change_surrogate_pairs() {
+        // the emoji occupies 2 utf16 code units, 4 utf8 bytes and 1 char
+        let text = "s = '\u{1f600}'\n";
+        let mut doc = DocumentBuffer::from_string(text.to_string());
+        let change = content_change((0, 7), (0, 8), "\"");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "s = '\u{1f600}\"\n");
+        let mut doc = DocumentBuffer::from_string(text.to_string());
+        let change = content_change((0, 5), (0, 7), "x");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "s = 'x'\n");
+        let mut doc = DocumentBuffer::from_string(text.to_string());
+        let change = content_change((0, 5), (0, 9), "x");
+        doc.apply_content_change(&change, PositionEncoding::Utf8, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "s = 'x'\n");
+        let mut doc = DocumentBuffer::from_string(text.to_string());
+        let change = content_change((0, 5), (0, 6), "x");
+        doc.apply_content_change(&change, PositionEncoding::Utf32, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "s = 'x'\n");
+    }
+
+    #[test]
+    fn test_row_lens() {
+        let assert_row_lens = |doc: &DocumentBuffer| {
+            let text = doc.iter().collect::<String>();
+            let rows = text.split_inclusive('\n').collect::<Vec<_>>();
+            for (row, line) in rows.iter().enumerate() {
+                assert_eq!(doc.char_len(row), Some(line.chars().count()));
+                assert_eq!(doc.utf16_len(row), Some(line.encode_utf16().count()));
+            }
+        };
+        let mut doc = DocumentBuffer::from_string("x = '🐍'\ny = 'é'\n".to_string());
+        assert_row_lens(&doc);
+        assert_eq!(doc.char_len(0), Some(8));
+        assert_eq!(doc.utf16_len(0), Some(9));
+        doc.insert_text("🦀\nz = '", (1, 5)).unwrap();
+        assert_row_lens(&doc);
+        doc.delete_range((0, 5), (1, 5)).unwrap();
+        assert_row_lens(&doc);
+        assert_eq!(doc.iter().collect::<String>(), "x = '🦀\nz = 'é'\n");
+        // positions on rows without surrogate pairs are taken as is
+        let position = lsp_types::Position::new(1, 6);
+        assert_eq!(
+            doc.row_col_from_position(&position, PositionEncoding::Utf16, PositionBounds::Strict)
+                .unwrap(),
+            (1, 6)
+        );
+        let position = lsp_types::Position::new(0, 7);
+        assert_eq!(
+            doc.row_col_from_position(&position, PositionEncoding::Utf16, PositionBounds::Strict)
+                .unwrap(),
+            (0, 6)
+        );
+        assert_eq!(doc.utf16_len(3), None);
+        // rows beyond the BMP split either side of the edit
+        doc.insert_text("a", (0, 1)).unwrap();
+        doc.insert_text("b", (0, 7)).unwrap();
+        assert_row_lens(&doc);
+        assert_eq!(doc.iter().collect::<String>(), "xa = '🦀b\nz = 'é'\n");
+    }
+
+    #[test]
+    fn test_negotiate_position_encoding() {
+        let negotiate = |x: &[&str]| PositionEncoding::negotiate(x.iter().copied());
+        assert_eq!(negotiate(&[]), PositionEncoding::Utf16);
+        assert_eq!(negotiate(&["utf-8", "utf-16"]), PositionEncoding::Utf16);
+        assert_eq!(negotiate(&["utf-16", "utf-32"]), PositionEncoding::Utf32);
+        assert_eq!(PositionEncoding::Utf32.kind(), "utf-32");
+    }
+
+    #[test]
+    fn test_apply_change_invalid_positions() {
+        let mut doc = DocumentBuffer::from_string("s = '\u{1f600}'\n".to_string());
+        // splitting the surrogate pair
+        let change = content_change((0, 6), (0, 6), "x");
+        assert!(matches!(
+            doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict),
+            Err(DocumentError::SplitCharacter)
+        ));
+        let change = content_change((0, 20), (0, 20), "x");
+        assert!(matches!(
+            doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict),
+            Err(DocumentError::ColOutOfBounds)
+        ));
+        let change = content_change((5, 1), (5, 1), "x");
+        assert!(matches!(
+            doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict),
+            Err(DocumentError::RowOutOfBounds)
+        ));
+        assert_eq!(doc.iter().collect::<String>(), "s = '\u{1f600}'\n");
+    }
+
+    #[test]
+    fn test_column_past_line_ending() {
+        let mut doc = DocumentBuffer::from_string("ab\ncd\r\nef".to_string());
+        // positions after the line ending belong to the next row
+        assert!(matches!(
+            doc.insert_text("x", (0, 3)),
+            Err(DocumentError::ColOutOfBounds)
+        ));
+        assert!(matches!(
+            doc.delete_range((0, 0), (1, 3)),
+            Err(DocumentError::ColOutOfBounds)
+        ));
+        assert!(matches!(
+            doc.delete_range((1, 4), (2, 0)),
+            Err(DocumentError::ColOutOfBounds)
+        ));
+        // the last row has no line ending
+        doc.insert_text("g", (2, 2)).unwrap();
+        doc.insert_text("!", (1, 2)).unwrap();
+        doc.insert_text("!", (0, 2)).unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "ab!\ncd!\r\nefg");
+        let position = lsp_types::Position {
+            line: 0,
+            character: 4,
+        };
+        assert!(matches!(
+            doc.row_col_from_position(&position, PositionEncoding::Utf16, PositionBounds::Strict),
+            Err(DocumentError::ColOutOfBounds)
+        ));
+        assert_eq!(
+            doc.row_col_from_position(&position, PositionEncoding::Utf16, PositionBounds::Clamp)
+                .unwrap(),
+            (0, 3)
+        );
+    }
+
+    #[test]
+    fn test_apply_change_past_end() {
+        for encoding in [PositionEncoding::Utf16, PositionEncoding::Utf32] {
+            // appending at the row past the last of a document without a
+            // final newline
+            let mut doc = DocumentBuffer::from_string("import os".to_string());
+            let change = content_change((1, 0), (1, 0), "\nimport sys\n");
+            doc.apply_content_change(&change, encoding, PositionBounds::Clamp)
+                .unwrap();
+            assert_eq!(doc.iter().collect::<String>(), "import os\nimport sys\n");
+            // the final newline leaves an empty last row to append to
+            let change = content_change((2, 0), (2, 0), "x = 1");
+            doc.apply_content_change(&change, encoding, PositionBounds::Clamp)
+                .unwrap();
+            assert_eq!(
+                doc.iter().collect::<String>(),
+                "import os\nimport sys\nx = 1"
+            );
+            // columns past the end of the line
+            let change = content_change((2, 3), (2, 40), " 2");
+            doc.apply_content_change(&change, encoding, PositionBounds::Clamp)
+                .unwrap();
+            assert_eq!(
+                doc.iter().collect::<String>(),
+                "import os\nimport sys\nx = 2"
+            );
+            let change = content_change((0, 12), (1, 20), "");
+            doc.apply_content_change(&change, encoding, PositionBounds::Clamp)
+                .unwrap();
+            assert_eq!(doc.iter().collect::<String>(), "import os\nx = 2");
+            let change = content_change((5, 3), (9, 0), "\n");
+            doc.apply_content_change(&change, encoding, PositionBounds::Clamp)
+                .unwrap();
+            assert_eq!(doc.iter().collect::<String>(), "import os\nx = 2\n");
+            // strictly, only the rows of the document are valid
+            let change = content_change((3, 0), (3, 0), "y");
+            assert!(matches!(
+                doc.apply_content_change(&change, encoding, PositionBounds::Strict),
+                Err(DocumentError::RowOutOfBounds)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_neovim_edit_streams() {
+        let mut doc = DocumentBuffer::from_string("def f():\n    pass\n".to_string());
+        // `o` on the last line with autoindent
+        let change = content_change((1, 8), (1, 8), "\n    ");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        let change = content_change((2, 4), (2, 4), "return");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(
+            doc.iter().collect::<String>(),
+            "def f():\n    pass\n    return\n"
+        );
+        // `dd` on the middle line
+        let change = content_change((1, 0), (2, 0), "");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "def f():\n    return\n");
+        // `J` joining the lines, removing the indent
+        let change = content_change((0, 8), (1, 4), " ");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "def f(): return\n");
+        // `A` then backspace over the final character
+        let change = content_change((0, 14), (0, 15), "");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "def f(): retur\n");
+        // `dd` on the only remaining line
+        let change = content_change((0, 0), (1, 0), "");
+        doc.apply_content_change(&change, PositionEncoding::Utf16, PositionBounds::Strict)
+            .unwrap();
+        assert_eq!(doc.iter().collect::<String>(), "");
+    }
+
     #[test]
     fn test_edit_example() {
         let mut doc = DocumentBuffer::from_string(SMALL_PROGRAM.to_string());
@@ -971,4 +2705,199 @@ if __name__ == '__main__':
 "#;
         assert_eq!(doc.iter().collect::<String>(), expected);
     }
+
+    #[test]
+    fn test_project_root_from_init() {
+        let root = std::env::temp_dir();
+        let root_uri = normalize_uri(&path_to_uri(&root).unwrap());
+        let init = |params: serde_json::Value| {
+            let params = serde_json::from_value(params).unwrap();
+            ServerState::project_root_from_init(&params)
+        };
+        assert_eq!(
+            init(serde_json::json!({
+                "capabilities": {},
+                "rootUri": root_uri,
+                "rootPath": "/elsewhere"
+            })),
+            Some(root_uri.clone())
+        );
+        assert_eq!(
+            init(serde_json::json!({"capabilities": {}, "rootPath": root})),
+            Some(root_uri.clone())
+        );
+        assert_eq!(
+            init(serde_json::json!({
+                "capabilities": {},
+                "rootPath": "",
+                "workspaceFolders": [{"uri": root_uri, "name": "root"}]
+            })),
+            Some(root_uri)
+        );
+        assert_eq!(init(serde_json::json!({"capabilities": {}})), None);
+    }
+
+    #[test]
+    fn test_document_status_saves() {
+        let mut status = DocumentStatus::opened(1);
+        assert!(!status.dirty);
+        status.changed(2);
+        assert!(status.dirty);
+        // save without willSave
+        assert!(status.saved());
+        assert_eq!(status.saved_version, Some(2));
+        assert!(!status.dirty);
+        status.changed(3);
+        status.will_save();
+        assert!(status.saved());
+        // edits between willSave and didSave
+        status.changed(4);
+        status.will_save();
+        status.changed(5);
+        assert!(!status.saved());
+        assert_eq!(status.saved_version, Some(5));
+        assert_eq!(status.pending_save, None);
+    }
+
+    #[test]
+    fn test_ast_cache() {
+        let uri = |x: &str| lsp_types::Url::parse(&format!("file:///tmp/{}.py", x)).unwrap();
+        let mut cache = AstCache::new(24);
+        let source = "import os\n";
+        let suite = cache.get_or_parse(&uri("a"), 1, source).unwrap();
+        assert!(Arc::ptr_eq(&suite, &cache.get(&uri("a"), 1).unwrap()));
+        assert!(cache.get(&uri("a"), 2).is_none());
+        cache.get_or_parse(&uri("b"), 1, source).unwrap();
+        assert_eq!(cache.size(), 20);
+        // a is used more recently than b, so b is evicted to fit c
+        cache.get(&uri("a"), 1).unwrap();
+        cache.get_or_parse(&uri("c"), 1, source).unwrap();
+        assert!(cache.get(&uri("b"), 1).is_none());
+        assert!(cache.get(&uri("a"), 1).is_some());
+        // a newer version replaces the module of the document
+        cache.get_or_parse(&uri("a"), 2, source).unwrap();
+        assert!(cache.get(&uri("a"), 1).is_none());
+        assert_eq!(cache.size(), 20);
+        cache.invalidate(&uri("a"));
+        assert_eq!(cache.size(), 10);
+        // sources larger than the cache aren't kept
+        cache.get_or_parse(&uri("d"), 1, &source.repeat(3)).unwrap();
+        assert!(cache.get(&uri("d"), 1).is_none());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let now = Arc::new(std::sync::Mutex::new(start));
+        let clock_now = now.clone();
+        let clock: Clock = Arc::new(move || *clock_now.lock().unwrap());
+        let mut limiter = RateLimiter::new(Duration::from_secs(10), clock);
+        assert_eq!(limiter.admit("DocumentError"), Some(0));
+        assert_eq!(limiter.admit("EditUnopenedDocument"), Some(0));
+        for secs in 1..4 {
+            *now.lock().unwrap() = start + Duration::from_secs(secs);
+            assert_eq!(limiter.admit("DocumentError"), None);
+        }
+        *now.lock().unwrap() = start + Duration::from_secs(10);
+        assert_eq!(limiter.admit("DocumentError"), Some(3));
+        assert_eq!(limiter.admit("DocumentError"), None);
+        assert_eq!(limiter.admit("EditUnopenedDocument"), Some(0));
+    }
+
+    #[test]
+    fn test_symbol_cache_evicts_least_recent() {
+        let cache = SymbolCache::new(2);
+        let uri = |x: &str| lsp_types::Url::parse(&format!("file:///tmp/{}.py", x)).unwrap();
+        let symbols = Arc::new(vec![]);
+        cache.insert(uri("a"), 1, symbols.clone());
+        cache.insert(uri("b"), 1, symbols.clone());
+        assert!(cache.get(&uri("a"), 1).is_some());
+        cache.insert(uri("c"), 1, symbols);
+        assert!(cache.get(&uri("b"), 1).is_none());
+        assert!(cache.get(&uri("a"), 1).is_some());
+        // changed content isn't served from the cache
+        assert!(cache.get(&uri("c"), 2).is_none());
+        // clones share their entries
+        assert!(cache.clone().get(&uri("a"), 1).is_some());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let text = "import os\n\u{e9}\u{1f600}\n".repeat(1000);
+        let mut doc = DocumentBuffer::from_string(text.clone());
+        assert_eq!(doc.content_hash(), content_hash(&text));
+        doc.insert_text("x", (0, 0)).unwrap();
+        assert_ne!(doc.content_hash(), content_hash(&text));
+        doc.delete_range((0, 0), (0, 1)).unwrap();
+        assert_eq!(doc.content_hash(), content_hash(&text));
+        assert_eq!(DocumentBuffer::new().content_hash(), content_hash(""));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let snapshot = Snapshot::new(ConfigSnapshot::default());
+        let loaded = snapshot.load();
+        snapshot.update(|x| x.position_encoding = PositionEncoding::Utf8);
+        snapshot.update(|x| x.config.lint_on_will_save = !x.config.lint_on_will_save);
+        // values already loaded are unaffected by updates
+        assert_eq!(loaded.position_encoding, PositionEncoding::default());
+        let current = snapshot.load();
+        assert_eq!(current.position_encoding, PositionEncoding::Utf8);
+        assert_ne!(
+            current.config.lint_on_will_save,
+            loaded.config.lint_on_will_save
+        );
+    }
+
+    #[test]
+    fn test_result_id() {
+        let registry = || CheckRegistry::from_iter([]).with_content_hash(Some(1));
+        assert!(CheckRegistry::from_iter([]).result_id().is_none());
+        assert_eq!(registry().result_id(), registry().result_id());
+        // the same content under changed settings is identified apart
+        assert_ne!(
+            registry().result_id(),
+            registry().with_generation(1).result_id()
+        );
+    }
+
+    #[test]
+    fn test_check_registry_order() {
+        use ruff::checks::CheckKind;
+        let check = |kind: CheckKind, row, col| Check {
+            kind,
+            location: Location::new(row, col),
+            end_location: Location::new(row, col + 1),
+            fix: None,
+        };
+        let unused_variable = || CheckKind::UnusedVariable("x".to_string());
+        let unused_import = || CheckKind::UnusedImport(vec!["os".to_string()]);
+        let mut registry = CheckRegistry::from_iter([
+            check(unused_variable(), 3, 0),
+            check(unused_variable(), 1, 4),
+            check(unused_import(), 3, 0),
+        ]);
+        let order = |registry: &CheckRegistry| {
+            registry
+                .iter()
+                .map(|x| (x.location.row(), x.kind.code().as_ref().to_string()))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            (1, "F841".to_string()),
+            (3, "F401".to_string()),
+            (3, "F841".to_string()),
+        ];
+        assert_eq!(order(&registry), expected);
+        // checks moved onto the same position are ordered by code
+        registry.retain_mut(|x| {
+            x.location = Location::new(1, 4);
+            x.end_location = Location::new(1, 5);
+            true
+        });
+        assert_eq!(
+            order(&registry)[..2],
+            [(1, "F401".to_string()), (1, "F841".to_string())]
+        );
+    }
 }
@@ -0,0 +1,72 @@
+//! Spawning of tasks named after the work they do, such that hung and
+//! leaked tasks can be told apart in tokio-console
+//!
+//! Tasks are only named when built with the `console` feature and
+//! `RUSTFLAGS="--cfg tokio_unstable"`, the closures naming them otherwise
+//! not being called
+use std::future::Future;
+use tokio::task::{self, JoinHandle};
+
+#[cfg(all(feature = "console", not(tokio_unstable)))]
+compile_error!("the console feature requires building with RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+/// Spawns `fut` as a task named by calling `name`
+pub fn spawn_named<F, N>(name: N, fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+    N: FnOnce() -> String,
+{
+    #[cfg(all(feature = "console", tokio_unstable))]
+    {
+        task::Builder::new()
+            .name(&name())
+            .spawn(fut)
+            .expect("failed spawning task")
+    }
+    #[cfg(not(all(feature = "console", tokio_unstable)))]
+    {
+        let _ = name;
+        task::spawn(fut)
+    }
+}
+
+/// Runs `f` on a blocking thread as a task named by calling `name`
+pub fn spawn_blocking_named<F, R, N>(name: N, f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+    N: FnOnce() -> String,
+{
+    #[cfg(all(feature = "console", tokio_unstable))]
+    {
+        task::Builder::new()
+            .name(&name())
+            .spawn_blocking(f)
+            .expect("failed spawning task")
+    }
+    #[cfg(not(all(feature = "console", tokio_unstable)))]
+    {
+        let _ = name;
+        task::spawn_blocking(f)
+    }
+}
+
+/// Runs `fut` as a task named by calling `name`, or awaits it in place if
+/// `inline`
+///
+/// Handlers of deterministic sessions run their sub-tasks inline, such that
+/// the work they schedule is ordered ahead of the next message rather than
+/// racing it. Such sub-tasks mustn't await the client, as the session
+/// doesn't handle its messages until the handler returns
+pub async fn spawn_or_inline<F, N>(inline: bool, name: N, fut: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+    N: FnOnce() -> String,
+{
+    if inline {
+        fut.await;
+    } else {
+        spawn_named(name, fut);
+    }
+}
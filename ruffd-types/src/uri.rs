@@ -0,0 +1,245 @@
+//! Normalisation of document uris and their conversion to paths
+//!
+//! Clients spell the same file differently, VS Code for instance
+//! lowercases windows drive letters and percent-encodes their colon while
+//! others send `file:///C:/...`. Uris are normalised as they're received
+//! such that each file has a single key in the server state, and converted
+//! to paths here such that ruff sees the same path whatever the spelling
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::path::{Path, PathBuf};
+
+/// Characters percent-encoded in normalised uri paths, matching those
+/// encoded by `lsp_types::Url::from_file_path`
+const PATH_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Style of path a uri is converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathStyle {
+    Posix,
+    Windows,
+}
+
+impl PathStyle {
+    /// Style of paths on the platform the server is running on
+    pub const fn native() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Posix
+        }
+    }
+}
+
+/// Splits the drive letter from a decoded uri path such as `/C:/src`,
+/// returning the letter and the remainder of the path
+fn split_drive(path: &str) -> Option<(char, &str)> {
+    let mut chars = path.chars();
+    let letter = match (chars.next(), chars.next(), chars.next()) {
+        (Some('/'), Some(x), Some(':')) if x.is_ascii_alphabetic() => x,
+        _ => return None,
+    };
+    let rest = &path[3..];
+    if rest.is_empty() || rest.starts_with('/') {
+        Some((letter, rest))
+    } else {
+        None
+    }
+}
+
+/// Decodes the path of a `file` uri, returning `None` for other schemes or
+/// paths which don't decode to utf-8
+///
+/// An encoded separator or nul can't be expressed in a path, so is also
+/// rejected
+fn decoded_path(uri: &lsp_types::Url) -> Option<String> {
+    if uri.scheme() != "file" {
+        return None;
+    }
+    let encoded = uri.path();
+    let lowered = encoded.to_ascii_lowercase();
+    if lowered.contains("%2f") || lowered.contains("%5c") || lowered.contains("%00") {
+        return None;
+    }
+    percent_decode_str(encoded)
+        .decode_utf8()
+        .ok()
+        .map(|x| x.into_owned())
+}
+
+/// Normalises a `file` uri such that differently spelt uris of the same
+/// file compare equal
+///
+/// Drive letters are lowercased and percent-encoding is made consistent,
+/// decoding characters which needn't be encoded. The host of a UNC path is
+/// lowercased on parsing, and `localhost` dropped. Other uris are returned
+/// unchanged
+pub fn normalize_uri(uri: &lsp_types::Url) -> lsp_types::Url {
+    let decoded = match decoded_path(uri) {
+        Some(x) => x,
+        None => return uri.clone(),
+    };
+    let path = match split_drive(&decoded) {
+        Some((letter, rest)) => format!("/{}:{}", letter.to_ascii_lowercase(), rest),
+        None => decoded,
+    };
+    let mut rv = uri.clone();
+    rv.set_path(&utf8_percent_encode(&path, PATH_ENCODE_SET).to_string());
+    rv
+}
+
+/// Converts a `file` uri to a path of the given style
+///
+/// Drive letters are uppercased as windows compares path prefixes case
+/// sensitively. A uri with a host is a UNC path, which only windows can
+/// express
+pub fn file_path(uri: &lsp_types::Url, style: PathStyle) -> Option<String> {
+    let decoded = decoded_path(uri)?;
+    let host = uri
+        .host_str()
+        .filter(|x| !x.is_empty() && *x != "localhost");
+    match style {
+        PathStyle::Posix => match host {
+            Some(_) => None,
+            None => Some(decoded),
+        },
+        PathStyle::Windows => {
+            if let Some(host) = host {
+                return Some(format!(r"\\{}{}", host, decoded.replace('/', "\\")));
+            }
+            let (letter, rest) = split_drive(&decoded)?;
+            let rest = if rest.is_empty() { "/" } else { rest };
+            Some(format!(
+                "{}:{}",
+                letter.to_ascii_uppercase(),
+                rest.replace('/', "\\")
+            ))
+        }
+    }
+}
+
+/// Converts a `file` uri to a path on this platform
+pub fn uri_to_path(uri: &lsp_types::Url) -> Option<PathBuf> {
+    file_path(uri, PathStyle::native()).map(PathBuf::from)
+}
+
+/// Converts an absolute path to a normalised `file` uri
+pub fn path_to_uri(path: &Path) -> Option<lsp_types::Url> {
+    lsp_types::Url::from_file_path(path)
+        .ok()
+        .map(|x| normalize_uri(&x))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(val: &str) -> lsp_types::Url {
+        lsp_types::Url::parse(val).unwrap()
+    }
+
+    #[test]
+    fn test_normalize_drive_letters() {
+        let expected = url("file:///c:/src/a.py");
+        for val in [
+            "file:///C:/src/a.py",
+            "file:///c%3A/src/a.py",
+            "file:///C%3a/src/a.py",
+        ] {
+            assert_eq!(normalize_uri(&url(val)), expected, "{}", val);
+        }
+        // not a drive letter
+        let uri = url("file:///cd:/a.py");
+        assert_eq!(normalize_uri(&uri), uri);
+    }
+
+    #[test]
+    fn test_normalize_encoding() {
+        let expected = url("file:///src/with%20space/caf%C3%A9.py");
+        for val in [
+            "file:///src/with%20space/caf%C3%A9.py",
+            "file:///src/with%20space/caf%c3%a9.py",
+            "file:///src/with space/café.py",
+            "file:///%73rc/with%20space/caf%C3%A9.py",
+        ] {
+            assert_eq!(normalize_uri(&url(val)), expected, "{}", val);
+        }
+        // a literal percent stays encoded
+        let uri = url("file:///src/100%25.py");
+        assert_eq!(normalize_uri(&uri), uri);
+        assert_eq!(
+            normalize_uri(&url("file://localhost/src/a.py")),
+            url("file:///src/a.py")
+        );
+        assert_eq!(
+            normalize_uri(&url("file://Server/Share/a.py")),
+            url("file://server/Share/a.py")
+        );
+        let uri = url("untitled:Untitled-1");
+        assert_eq!(normalize_uri(&uri), uri);
+    }
+
+    #[test]
+    fn test_posix_path() {
+        let cases = [
+            (
+                "file:///src/with%20space/a.py",
+                Some("/src/with space/a.py"),
+            ),
+            ("file:///src/caf%C3%A9.py", Some("/src/café.py")),
+            ("file://localhost/src/a.py", Some("/src/a.py")),
+            ("file://server/share/a.py", None),
+            ("file:///src/a%2Fb.py", None),
+            ("file:///src/%FF.py", None),
+            ("untitled:Untitled-1", None),
+        ];
+        for (val, expected) in cases {
+            assert_eq!(
+                file_path(&url(val), PathStyle::Posix).as_deref(),
+                expected,
+                "{}",
+                val
+            );
+        }
+    }
+
+    #[test]
+    fn test_windows_path() {
+        let cases = [
+            ("file:///c%3A/src/a.py", Some(r"C:\src\a.py")),
+            (
+                "file:///C:/src/with%20space/a.py",
+                Some(r"C:\src\with space\a.py"),
+            ),
+            ("file:///d:", Some(r"D:\")),
+            ("file://server/share/a.py", Some(r"\\server\share\a.py")),
+            ("file:///src/a.py", None),
+            ("file:///c:/src/a%5Cb.py", None),
+        ];
+        for (val, expected) in cases {
+            assert_eq!(
+                file_path(&url(val), PathStyle::Windows).as_deref(),
+                expected,
+                "{}",
+                val
+            );
+        }
+    }
+
+    #[test]
+    fn test_path_round_trip() {
+        let path = std::env::temp_dir().join("with space").join("a.py");
+        let uri = path_to_uri(&path).unwrap();
+        assert_eq!(normalize_uri(&uri), uri);
+        assert_eq!(uri_to_path(&uri).unwrap(), path);
+    }
+}
@@ -1,6 +1,23 @@
 use clap::Parser;
-use ruffd_core::server::{StdioServer, TcpServer};
-use ruffd_types::tokio;
+use ruffd_core::server::{Backoff, StdioServer, TcpServer};
+use ruffd_core::{lint, spill};
+use ruffd_core::{
+    Service, SessionOutcome, TimingMiddleware, TracingMiddleware, DEFAULT_MAX_MESSAGE_SIZE,
+    DEFAULT_SERVER_REQUEST_TIMEOUT,
+};
+use ruffd_types::layered::{ConfigLayer, ResolvedConfig};
+use ruffd_types::project::{LogLevel, ProjectConfig, RunMode};
+use ruffd_types::tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use ruffd_types::{log_error, log_warn, logging, tokio, RUFF_VERSION};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[cfg(feature = "otlp")]
+mod otel;
+
+/// Blocking threads reserved for file io beside those running lints
+const DEFAULT_IO_THREADS: usize = 16;
 
 #[derive(Parser, Debug)]
 struct PipeArg {
@@ -51,12 +68,27 @@ enum CommMode {
         /// Port number to connect to client
         #[command(flatten)]
         port: PortArg,
+        /// Connect to the client again whenever the connection is lost,
+        /// serving each connection with a fresh state
+        #[arg(long)]
+        reconnect: bool,
     },
     Pipe {
         /// Pipe name or socket filename
         #[command(flatten)]
         pipe: PipeArg,
     },
+    /// Dump buffers spilled by servers that didn't shut down cleanly
+    Recover {
+        /// Remove the spilled buffers of servers no longer running once
+        /// dumped
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Print the settings resolved from the environment, the command line
+    /// and the pyproject of the working directory, along with where each
+    /// was taken from
+    Doctor,
 }
 
 #[derive(Parser, Debug)]
@@ -64,30 +96,262 @@ enum CommMode {
 struct Cli {
     #[command(subcommand)]
     comm_mode: Option<CommMode>,
+    /// Largest message in bytes accepted from the client
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_MESSAGE_SIZE)]
+    max_message_size: usize,
+    /// Log every message exchanged with the client and the time taken to
+    /// respond to each request
+    #[arg(long, global = true)]
+    trace_messages: bool,
+    /// Number of threads handling messages, defaulting to the available
+    /// parallelism
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    threads: Option<u16>,
+    /// Handle messages one at a time on a single thread, such that
+    /// replaying a session yields identical output
+    #[arg(long, global = true, conflicts_with_all = ["threads", "lint_jobs"])]
+    deterministic: bool,
+    /// Number of lints run at once, defaulting to the available parallelism
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    lint_jobs: Option<u16>,
+    /// Seconds the client has to answer a request from the server before
+    /// it's cancelled, 0 waiting indefinitely
+    #[arg(long, global = true, value_name = "SECS", default_value_t = DEFAULT_SERVER_REQUEST_TIMEOUT.as_secs())]
+    server_request_timeout: u64,
+    /// Times a request from the server is resent once it times out
+    #[arg(long, global = true, default_value_t = 0)]
+    server_request_retries: usize,
+    /// Directory in which the workspace index and diagnostics are kept
+    /// between runs, such that large workspaces start warm
+    #[arg(long, global = true, value_name = "DIR")]
+    warm_cache: Option<PathBuf>,
+    /// Configuration profile activated on initialization, unless the
+    /// client's settings name one
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+    /// Maximum level logged, one of off, error, warn, info or debug. Takes
+    /// precedence over the client and pyproject, but not `RUFFD_LOG`
+    #[arg(long, global = true, value_name = "LEVEL")]
+    log_level: Option<LogLevel>,
+    /// Pyproject read for the `[tool.ruffd]` table rather than that at the
+    /// project root, relative to the project root. Takes precedence over
+    /// the client, but not `RUFFD_CONFIG`
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// When documents are linted, onType or onSave. Takes precedence over
+    /// the client and pyproject, but not `RUFFD_RUN_MODE`
+    #[arg(long, global = true, value_name = "MODE")]
+    run_mode: Option<RunMode>,
+    /// Export spans of message handling to the OTLP collector at the given
+    /// grpc endpoint
+    #[cfg(feature = "otlp")]
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+}
+
+/// Options applied to the service whichever the transport
+struct ServiceOptions {
+    max_message_size: usize,
+    trace_messages: bool,
+    warm_cache: Option<PathBuf>,
+    deterministic: bool,
+    server_request_timeout: Option<Duration>,
+    server_request_retries: usize,
+    profile: Option<String>,
+}
+
+impl ServiceOptions {
+    fn apply<R, W>(&self, service: &mut Service<R, W>)
+    where
+        R: AsyncBufReadExt + AsyncReadExt + Unpin + Send + 'static,
+        W: AsyncWriteExt + Unpin + Send + 'static,
+    {
+        service.set_max_message_size(self.max_message_size);
+        if self.trace_messages {
+            service.add_middleware(Box::new(TracingMiddleware));
+            service.add_middleware(Box::new(TimingMiddleware::default()));
+        }
+        if let Some(dir) = &self.warm_cache {
+            service.set_warm_cache_dir(dir.clone());
+        }
+        service.set_deterministic(self.deterministic);
+        service.set_server_request_timeout(self.server_request_timeout);
+        service.set_server_request_retries(self.server_request_retries);
+        service.set_profile(self.profile.clone());
+    }
 }
 
-async fn run_stdio_server() {
+/// Exit code of the process once the session ends, being 0 only if the
+/// client shut the server down and asked it to exit
+fn exit_code(outcome: SessionOutcome) -> ExitCode {
+    match outcome {
+        SessionOutcome::Exit => ExitCode::SUCCESS,
+        SessionOutcome::ExitWithoutShutdown => {
+            log_warn!("client asked the server to exit without shutting it down");
+            ExitCode::from(1)
+        }
+        SessionOutcome::Disconnected => {
+            log_warn!("client disconnected without asking the server to exit");
+            ExitCode::from(1)
+        }
+        SessionOutcome::Fatal(err) => {
+            log_error!("{}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+async fn run_stdio_server(options: ServiceOptions) -> ExitCode {
     let mut server = StdioServer::default();
-    server.get_service_mut().run().await;
+    let service = server.get_service_mut();
+    options.apply(service);
+    exit_code(service.run().await)
+}
+
+async fn run_tcp_server(port: u64, reconnect: bool, options: ServiceOptions) -> ExitCode {
+    let addr = format!("127.0.0.1:{}", port);
+    let outcome = if reconnect {
+        let setup = |service: &mut _| options.apply(service);
+        TcpServer::run_reconnecting(addr, Backoff::default(), setup).await
+    } else {
+        match TcpServer::connect(addr).await {
+            Ok(mut server) => {
+                let service = server.get_service_mut();
+                options.apply(service);
+                Ok(service.run().await)
+            }
+            Err(err) => Err(err),
+        }
+    };
+    match outcome {
+        Ok(x) => exit_code(x),
+        Err(err) => {
+            log_error!("failed connecting to client: {}", err);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-async fn run_tcp_server(port: u64) {
-    let mut server = TcpServer::connect(format!("127.0.0.1:{}", port))
-        .await
-        .unwrap();
-    server.get_service_mut().run().await;
+fn recover(clear: bool) {
+    let root = spill::spill_root();
+    let buffers = match spill::read_spilled_buffers(&root) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("failed reading spilled buffers: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if buffers.is_empty() {
+        eprintln!("no spilled buffers found in {}", root.display());
+    }
+    for buffer in buffers {
+        println!("==> {} ({}) <==", buffer.uri, buffer.path.display());
+        println!("{}", buffer.text);
+    }
+    // spills of running servers are still being written
+    if clear {
+        if let Err(err) = spill::clear_stale(&root) {
+            eprintln!("failed clearing spilled buffers: {}", err);
+            std::process::exit(1);
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    let cli = Cli::parse();
-    if let Some(comm_mode) = cli.comm_mode {
-        match comm_mode {
-            CommMode::Stdio => run_stdio_server().await,
-            CommMode::Socket { port } => run_tcp_server(port.into()).await,
-            _ => unimplemented!(),
+/// Prints the settings resolved as a server started in the working
+/// directory would, before any client settings
+fn doctor() {
+    let root = std::env::current_dir().ok();
+    let client = ConfigLayer::default();
+    let resolved = ResolvedConfig::resolve(&client, &ProjectConfig::default());
+    let project = match resolved.pyproject(root.as_deref()) {
+        Some(path) => {
+            println!("pyproject: {}", path.display());
+            ProjectConfig::from_pyproject(&path).unwrap_or_else(|err| {
+                eprintln!("invalid [tool.ruffd] settings: {}", err);
+                std::process::exit(1);
+            })
         }
+        None => ProjectConfig::default(),
+    };
+    let resolved = ResolvedConfig::resolve(&client, &project);
+    println!("ruffd {}", env!("CARGO_PKG_VERSION"));
+    println!("ruff {}", RUFF_VERSION);
+    println!(
+        "{}",
+        ruffd_types::serde_json::to_string_pretty(&resolved).unwrap()
+    );
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    logging::bridge_log_crate();
+    ConfigLayer::set_cli(ConfigLayer {
+        log_level: cli.log_level,
+        config: cli.config.clone(),
+        run_mode: cli.run_mode,
+    });
+    // the level of `RUFFD_LOG` or the command line, as the client and
+    // pyproject are yet to be read
+    ResolvedConfig::resolve(&ConfigLayer::default(), &ProjectConfig::default()).apply_log_level();
+    let mut builder = if cli.deterministic {
+        tokio::runtime::Builder::new_current_thread()
     } else {
-        run_stdio_server().await;
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    if let Some(threads) = cli.threads {
+        builder.worker_threads(threads as usize);
     }
+    let jobs = match (cli.deterministic, cli.lint_jobs) {
+        (true, _) => 1,
+        (false, Some(x)) => x as usize,
+        (false, None) => lint::default_lint_jobs(),
+    };
+    lint::set_lint_jobs(jobs);
+    // lints hold blocking threads, which are otherwise shared with file io
+    builder.max_blocking_threads(jobs + DEFAULT_IO_THREADS);
+    let runtime = builder.enable_all().build().unwrap();
+    runtime.block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> ExitCode {
+    // both install the global subscriber, so spans are only exported over
+    // OTLP when not serving tokio-console
+    #[cfg(feature = "console")]
+    console_subscriber::init();
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &cli.otlp_endpoint {
+        if let Err(err) = otel::init(endpoint) {
+            ruffd_types::log_error!("failed exporting spans to {}: {}", endpoint, err);
+        }
+    }
+    let options = ServiceOptions {
+        max_message_size: cli.max_message_size,
+        trace_messages: cli.trace_messages,
+        warm_cache: cli.warm_cache,
+        deterministic: cli.deterministic,
+        server_request_timeout: match cli.server_request_timeout {
+            0 => None,
+            x => Some(Duration::from_secs(x)),
+        },
+        server_request_retries: cli.server_request_retries,
+        profile: cli.profile,
+    };
+    let code = match cli.comm_mode {
+        Some(CommMode::Socket { port, reconnect }) => {
+            run_tcp_server(port.into(), reconnect, options).await
+        }
+        Some(CommMode::Recover { clear }) => {
+            recover(clear);
+            ExitCode::SUCCESS
+        }
+        Some(CommMode::Doctor) => {
+            doctor();
+            ExitCode::SUCCESS
+        }
+        Some(CommMode::Pipe { .. }) => unimplemented!(),
+        Some(CommMode::Stdio) | None => run_stdio_server(options).await,
+    };
+    #[cfg(feature = "otlp")]
+    otel::shutdown();
+    code
 }
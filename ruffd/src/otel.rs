@@ -0,0 +1,50 @@
+//! Export of the spans traced while handling messages over OTLP
+//!
+//! Each client message is traced under an `rpc` span, with `read`, `parse`,
+//! `lock`, `execute` and `respond` spans for the phases of its handling
+//! when logging at debug level, such that latency in real editor sessions
+//! can be broken down in a collector such as Jaeger. The `trace` field of
+//! an `rpc` span matches the trace tagging the records logged handling it
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use ruffd_core::{PKG_NAME, PKG_VERSION};
+use ruffd_types::logging::{self, Level};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Installs a subscriber exporting spans to the OTLP collector listening
+/// for grpc at `endpoint`
+///
+/// Spans are exported in batches from the tokio runtime, so this must be
+/// called within it
+pub fn init(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let resource = Resource::new(vec![
+        KeyValue::new("service.name", PKG_NAME),
+        KeyValue::new("service.version", PKG_VERSION),
+    ]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(trace::config().with_resource(resource))
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    let level = if logging::enabled(Level::Debug) {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::INFO
+    };
+    tracing_subscriber::registry()
+        .with(level)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    Ok(())
+}
+
+/// Exports spans yet to be sent
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}